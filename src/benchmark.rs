@@ -0,0 +1,79 @@
+//! Measures the overhead of mizumochi itself per operation in `Speed::PassThrough`, i.e., the noise floor of
+//! the measurements taken through it apart from the configured delays.
+//!
+//! Each operation calls the engine as a read in `Mizumochi` does (`Engine::on_operated`,
+//! `Engine::injected_error` and `Engine::speed`) without the actual I/O, and the calls are timed together.
+use atomic_immut::AtomicImmut;
+use config::{Condition, Config, Operation, Speed};
+use engine::Engine;
+use state::State;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The number of operations to measure by default.
+pub const ITERATIONS: u32 = 1_000_000;
+
+/// `Overhead` is the time which the operations took in the engine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Overhead {
+    pub iterations: u32,
+    pub total: Duration,
+}
+
+impl Overhead {
+    /// Returns the mean time per operation, which is zero without operations.
+    pub fn per_operation(&self) -> Duration {
+        self.total.checked_div(self.iterations).unwrap_or_default()
+    }
+}
+
+impl fmt::Display for Overhead {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.1} ns/op in PassThrough ({} operations in {:.3} ms)",
+            self.per_operation().as_secs_f64() * 1e9,
+            self.iterations,
+            self.total.as_secs_f64() * 1e3
+        )
+    }
+}
+
+/// Runs `iterations` no-op operations through the engine in `Speed::PassThrough` and returns the time they took.
+/// The unstable period is always active so that the operations go through the whole checks.
+pub fn measure_overhead(iterations: u32) -> Overhead {
+    let config = Arc::new(AtomicImmut::new(Config {
+        speed: Speed::PassThrough,
+        condition: Condition::Always(State::Unstable),
+        ..Default::default()
+    }));
+    let mut engine = Engine::with_seed(config, 0);
+    engine.init();
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        let _ = engine.on_operated(Operation::Read);
+        let _ = engine.injected_error(Operation::Read, 0);
+        let _ = engine.speed(Operation::Read);
+    }
+    let total = start.elapsed();
+
+    Overhead { iterations, total }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure_overhead() {
+        let overhead = measure_overhead(1000);
+        assert_eq!(1000, overhead.iterations);
+        assert!(overhead.per_operation() <= overhead.total);
+        assert!(overhead.per_operation() < Duration::from_millis(10));
+        assert!(overhead.to_string().contains("ns/op"), "{}", overhead);
+
+        assert_eq!(Duration::new(0, 0), measure_overhead(0).per_operation());
+    }
+}
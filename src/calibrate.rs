@@ -0,0 +1,125 @@
+//! Measures the throughput of the underlying device as the baseline of `Speed::Percent`.
+//!
+//! The calibration writes a temporary file of `CALIBRATION_SIZE` bytes in the original directory,
+//! flushes it to the device by `fsync` and divides the size by the elapsed time.
+//! The reads are not measured because they are likely served from the page cache.
+//! It runs once at startup only if `Speed::Percent` is configured without `Config::baseline`.
+use config::{Config, Speed};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+use std::time::Instant;
+
+/// The number of bytes to write to measure the throughput.
+pub const CALIBRATION_SIZE: usize = 64 << 20;
+
+/// Returns true if `Speed::Percent` is configured at any of `Config::speeds` and its baseline has to be measured.
+pub fn is_required(config: &Config) -> bool {
+    config.baseline.is_none() && config.speeds().any(|s| matches!(*s, Speed::Percent(_)))
+}
+
+/// Writes `size` bytes in `dir` and returns the measured throughput in bytes per second.
+/// The temporary file is removed after the measurement.
+pub fn measure_throughput(dir: &Path, size: usize) -> io::Result<usize> {
+    let path = dir.join(format!(".mizumochi-calibration-{}", process::id()));
+    let result = write_and_sync(&path, size);
+    let _ = fs::remove_file(&path);
+
+    let secs = result?;
+    Ok(((size as f64 / secs.max(1e-9)) as usize).max(1))
+}
+
+/// Returns the elapsed time in seconds to write and sync the file.
+fn write_and_sync(path: &Path, size: usize) -> io::Result<f64> {
+    let chunk = vec![0xA5; 1 << 20];
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+
+    let start = Instant::now();
+    let mut rest = size;
+    while 0 < rest {
+        let n = rest.min(chunk.len());
+        file.write_all(&chunk[..n])?;
+        rest -= n;
+    }
+    file.sync_all()?;
+
+    let elapsed = start.elapsed();
+    Ok(elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1e-9)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{Condition, Profile, ScriptWindow};
+    use std::env;
+    use std::time::Duration;
+
+    #[test]
+    fn test_is_required() {
+        assert!(!is_required(&Config::default()));
+
+        let mut config = Config {
+            speed: Speed::Percent(10),
+            ..Default::default()
+        };
+        assert!(is_required(&config));
+        config.baseline = Some(200 << 20);
+        assert!(!is_required(&config));
+
+        let mut config = Config::default();
+        config
+            .per_operation
+            .insert(::config::Operation::Write, Speed::Percent(10));
+        assert!(is_required(&config));
+
+        let mut config = Config::default();
+        config
+            .extension_speeds
+            .insert("mp4".to_string(), Speed::Percent(10));
+        assert!(is_required(&config));
+
+        let config = Config {
+            append_speed: Some(Speed::Percent(10)),
+            ..Default::default()
+        };
+        assert!(is_required(&config));
+
+        let config = Config {
+            condition: Condition::Script {
+                windows: vec![ScriptWindow {
+                    offset: Duration::from_secs(0),
+                    length: Duration::from_secs(1),
+                    speed: Speed::Percent(10),
+                }],
+                repeat: false,
+            },
+            ..Default::default()
+        };
+        assert!(is_required(&config));
+
+        let config = Config {
+            profiles: vec![Profile {
+                speed: Speed::Percent(10),
+                operations: vec![::config::Operation::Read],
+                duration: Duration::from_secs(1),
+                frequency: Duration::from_secs(2),
+            }],
+            ..Default::default()
+        };
+        assert!(is_required(&config));
+    }
+
+    #[test]
+    fn test_measure_throughput() {
+        let dir = env::temp_dir().join(format!("mizumochi-calibrate-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let bps = measure_throughput(&dir, 1 << 20).unwrap();
+        assert!(0 < bps);
+        // The temporary file is removed.
+        assert_eq!(0, fs::read_dir(&dir).unwrap().count());
+
+        fs::remove_dir(&dir).unwrap();
+    }
+}
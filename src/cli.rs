@@ -0,0 +1,204 @@
+//! The clap integration to parse the command line options straight into the types of `config`.
+//! The values are parsed by `FromStr` of the types, so they accept the same syntax as the config.
+use clap::{Arg, ArgMatches, Error, ErrorKind};
+use config::{Operation, Speed};
+use std::fmt;
+
+/// The names of `Operation` accepted by `operations_arg` (case-insensitively).
+pub const OPERATION_VALUES: &[&str] = &["read", "write", "stat", "open", "readdir", "fsync"];
+
+/// Returns `--operation <OPERATION>`, which can be given multiple times (e.g., `--operation read --operation write`).
+/// The possible values are listed in the help.
+pub fn operations_arg<'a, 'b>(name: &'a str) -> Arg<'a, 'b> {
+    Arg::with_name(name)
+        .long("operation")
+        .value_name("OPERATION")
+        .help("Limits the throttling to the operation (can be given multiple times)")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .possible_values(OPERATION_VALUES)
+        .case_insensitive(true)
+}
+
+/// Validates the speed as `Arg::validator`, so the error is reported with the usage.
+pub fn validate_speed(s: String) -> Result<(), String> {
+    Speed::from_str_clamped(&s)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// Returns the speed of the argument, or `None` if it is not given.
+/// The suffixes are interpreted in SI units if `si` is true (see `Speed::from_str_si`).
+/// The rates which overflow are clamped (see `Speed::from_str_clamped` and `speed_warning_of`).
+///
+/// # Examples
+/// ```
+/// extern crate clap;
+/// extern crate mizumochi;
+///
+/// use clap::{App, Arg};
+/// use mizumochi::cli;
+/// use mizumochi::config::Speed;
+///
+/// # fn main() {
+/// let matches = App::new("test")
+///     .arg(Arg::with_name("SPEED").long("speed").takes_value(true))
+///     .get_matches_from(vec!["test", "--speed", "1MBps"]);
+/// assert_eq!(
+///     Some(Speed::Bps(1 << 20)),
+///     cli::speed_of(&matches, "SPEED", false).unwrap()
+/// );
+/// # }
+/// ```
+pub fn speed_of(matches: &ArgMatches, name: &str, si: bool) -> Result<Option<Speed>, Error> {
+    Ok(clamped_speed_of(matches, name, si)?.map(|(speed, _)| speed))
+}
+
+/// Returns the warning to show if the speed of the argument is clamped by `speed_of`.
+pub fn speed_warning_of(matches: &ArgMatches, name: &str, si: bool) -> Option<String> {
+    clamped_speed_of(matches, name, si)
+        .ok()
+        .and_then(|speed| speed.and_then(|(_, warning)| warning))
+}
+
+fn clamped_speed_of(
+    matches: &ArgMatches,
+    name: &str,
+    si: bool,
+) -> Result<Option<(Speed, Option<String>)>, Error> {
+    let value = match matches.value_of(name) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+
+    let speed = if si {
+        Speed::from_str_si_clamped(value)
+    } else {
+        Speed::from_str_clamped(value)
+    };
+    speed.map(Some).map_err(|e| invalid_value(name, &e))
+}
+
+/// Returns the operations of the argument in the given order without the duplicates,
+/// or `None` if it is not given.
+pub fn operations_of(matches: &ArgMatches, name: &str) -> Result<Option<Vec<Operation>>, Error> {
+    let values = match matches.values_of(name) {
+        Some(values) => values,
+        None => return Ok(None),
+    };
+
+    let mut operations = Vec::new();
+    for value in values {
+        let op: Operation = value.parse().map_err(|e| invalid_value(name, &e))?;
+        if !operations.contains(&op) {
+            operations.push(op);
+        }
+    }
+
+    Ok(Some(operations))
+}
+
+fn invalid_value<E: fmt::Display>(name: &str, e: &E) -> Error {
+    Error::with_description(
+        &format!("Invalid value for '{}': {}", name, e),
+        ErrorKind::InvalidValue,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::App;
+    use config::PartialConfig;
+
+    fn app<'a, 'b>() -> App<'a, 'b> {
+        App::new("test")
+            .arg(
+                Arg::with_name("SPEED")
+                    .long("speed")
+                    .takes_value(true)
+                    .validator(validate_speed),
+            )
+            .arg(Arg::with_name("SI").long("si"))
+            .arg(operations_arg("OPERATION"))
+    }
+
+    fn parse(args: &[&str]) -> Result<PartialConfig, Error> {
+        let matches =
+            app().get_matches_from_safe(Some("test").into_iter().chain(args.iter().cloned()))?;
+        let si = matches.is_present("SI");
+        Ok(PartialConfig {
+            speed: speed_of(&matches, "SPEED", si)?,
+            operations: operations_of(&matches, "OPERATION")?,
+            warnings: speed_warning_of(&matches, "SPEED", si)
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let partial = parse(&[
+            "--speed",
+            "1MBps",
+            "--operation",
+            "read",
+            "--operation",
+            "write",
+        ])
+        .unwrap();
+        assert_eq!(Some(Speed::Bps(1 << 20)), partial.speed);
+        assert_eq!(
+            Some(vec![Operation::Read, Operation::Write]),
+            partial.operations
+        );
+
+        // Case-insensitive, and the duplicates are removed.
+        let partial = parse(&[
+            "--operation",
+            "Fsync",
+            "--operation",
+            "FSYNC",
+            "--si",
+            "--speed",
+            "1MBps",
+        ])
+        .unwrap();
+        assert_eq!(Some(Speed::Bps(1_000_000)), partial.speed);
+        assert_eq!(Some(vec![Operation::Fsync]), partial.operations);
+
+        let partial = parse(&["--speed", "latency:50ms"]).unwrap();
+        assert!(partial.speed.is_some());
+        assert_eq!(None, partial.operations);
+
+        assert_eq!(PartialConfig::default(), parse(&[]).unwrap());
+
+        // The overflowing rate is clamped with the warning.
+        let partial = parse(&["--speed", "99999999999GBps"]).unwrap();
+        assert_eq!(Some(Speed::Bps(u64::MAX)), partial.speed);
+        assert_eq!(1, partial.warnings.len());
+        assert!(partial.warnings[0].contains("99999999999GBps"));
+        let partial = parse(&["--si", "--speed", "99999999999GBps"]).unwrap();
+        assert_eq!(Some(Speed::Bps(u64::MAX)), partial.speed);
+        assert_eq!(1, partial.warnings.len());
+        assert!(parse(&["--speed", "1MBps"]).unwrap().warnings.is_empty());
+    }
+
+    #[test]
+    fn test_parse_args_invalid() {
+        assert_eq!(
+            ErrorKind::ValueValidation,
+            parse(&["--speed", "fast"]).unwrap_err().kind
+        );
+
+        // The possible values are listed in the error.
+        let e = parse(&["--operation", "rename"]).unwrap_err();
+        assert_eq!(ErrorKind::InvalidValue, e.kind);
+        assert!(e.message.contains("readdir"), "{}", e.message);
+
+        // One value for each occurrence.
+        assert!(parse(&["--operation", "read", "write"]).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use shutdown::Shutdown;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// `Clock` is the source of the time to throttle the operations.
+/// It is replaced with `MockClock` to test the timing without actual waits.
+/// The time is `Instant`, which is monotonic for `SystemClock`, but the throttle tolerates the clocks which
+/// jump backward (see `MockClock::rewind`).
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    fn sleep(&self, d: Duration);
+
+    /// Same as `sleep` but wakes when `shutdown` is triggered.
+    /// The default implementation sleeps in the real time.
+    fn sleep_unless_shutdown(&self, d: Duration, shutdown: &Shutdown) {
+        shutdown.sleep(d)
+    }
+}
+
+/// `SystemClock` is the real clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, d: Duration) {
+        thread::sleep(d)
+    }
+}
+
+/// `MockClock` is the clock of which time advances only by `advance` or `sleep`.
+#[derive(Debug)]
+pub struct MockClock {
+    base: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+impl MockClock {
+    pub fn new() -> MockClock {
+        MockClock {
+            base: Instant::now(),
+            elapsed: Mutex::new(Duration::from_secs(0)),
+        }
+    }
+
+    pub fn advance(&self, d: Duration) {
+        *self.elapsed.lock().unwrap() += d;
+    }
+
+    /// Moves the time backward (but not before the creation) to simulate a jump of the clock,
+    /// e.g., a step of NTP, which `SystemClock` never does.
+    pub fn rewind(&self, d: Duration) {
+        let mut elapsed = self.elapsed.lock().unwrap();
+        *elapsed = elapsed.saturating_sub(d);
+    }
+
+    /// Returns the duration advanced since created.
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> MockClock {
+        MockClock::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.base + self.elapsed()
+    }
+
+    fn sleep(&self, d: Duration) {
+        self.advance(d)
+    }
+
+    fn sleep_unless_shutdown(&self, d: Duration, shutdown: &Shutdown) {
+        if !shutdown.is_triggered() {
+            self.advance(d)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        assert_eq!(start, clock.now());
+
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(Duration::from_secs(10), clock.now() - start);
+
+        clock.sleep(Duration::from_millis(500));
+        assert_eq!(Duration::from_millis(10_500), clock.now() - start);
+        assert_eq!(Duration::from_millis(10_500), clock.elapsed());
+
+        clock.rewind(Duration::from_secs(10));
+        assert_eq!(Duration::from_millis(500), clock.now() - start);
+        clock.rewind(Duration::from_secs(10));
+        assert_eq!(start, clock.now());
+    }
+}
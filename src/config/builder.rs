@@ -0,0 +1,421 @@
+use super::{
+    ActiveWindow, Config, ErrorInjection, Operation, PartialConfig, Profile, ShortIo, Speed,
+    SpeedCombine, ThrottleScope, Trigger, WindowBurst,
+};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// `ConfigBuilder` builds `Config` incrementally.
+/// The fields which are not set fall back to `Config::default()`.
+///
+/// # Examples
+/// ```
+/// use mizumochi::config::{ConfigBuilder, Operation, Speed};
+///
+/// let config = ConfigBuilder::new()
+///     .speed(Speed::Bps(1024))
+///     .operations(vec![Operation::Write])
+///     .build();
+/// assert_eq!(Speed::Bps(1024), config.speed);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ConfigBuilder {
+    partial: PartialConfig,
+}
+
+impl ConfigBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn speed(&mut self, speed: Speed) -> &mut Self {
+        self.partial.speed = Some(speed);
+        self
+    }
+
+    pub fn operations(&mut self, operations: Vec<Operation>) -> &mut Self {
+        self.partial.operations = Some(operations);
+        self
+    }
+
+    /// Throttles the operation partly by the weight (see `Config::operation_weights`).
+    pub fn operation_weight(&mut self, op: Operation, weight: u32) -> &mut Self {
+        self.partial
+            .operation_weights
+            .get_or_insert_with(HashMap::new)
+            .insert(op, weight);
+        self
+    }
+
+    /// Throttles the operation at the speed instead of `speed` (see `Config::per_operation`).
+    ///
+    /// # Examples
+    /// ```
+    /// use mizumochi::config::{ConfigBuilder, Operation, Speed};
+    ///
+    /// let config = ConfigBuilder::new()
+    ///     .speed(Speed::Bps(1 << 20))
+    ///     .speed_for(Operation::Write, Speed::Bps(64 << 10))
+    ///     .build();
+    /// assert_eq!(&Speed::Bps(64 << 10), config.speed_for(&Operation::Write));
+    /// assert_eq!(&Speed::Bps(1 << 20), config.speed_for(&Operation::Read));
+    /// ```
+    pub fn speed_for(&mut self, op: Operation, speed: Speed) -> &mut Self {
+        self.partial
+            .per_operation
+            .get_or_insert_with(HashMap::new)
+            .insert(op, speed);
+        self
+    }
+
+    /// Sets the duration of `Condition::Periodic`.
+    pub fn duration(&mut self, duration: Duration) -> &mut Self {
+        self.partial.duration = Some(duration);
+        self
+    }
+
+    /// Sets the frequency of `Condition::Periodic`.
+    pub fn frequency(&mut self, frequency: Duration) -> &mut Self {
+        self.partial.frequency = Some(frequency);
+        self
+    }
+
+    /// Sets the probability that an unstable period slows down the operations.
+    pub fn probability(&mut self, probability: f64) -> &mut Self {
+        self.partial.probability = Some(probability);
+        self
+    }
+
+    /// Sets the jitter of the frequency of `Condition::Periodic`.
+    pub fn frequency_jitter(&mut self, jitter: Duration) -> &mut Self {
+        self.partial.frequency_jitter = Some(jitter);
+        self
+    }
+
+    /// Sets the time of day when the operations can be throttled.
+    pub fn active_window(&mut self, window: ActiveWindow) -> &mut Self {
+        self.partial.active_window = Some(window);
+        self
+    }
+
+    /// Makes the operations fail during unstable periods.
+    pub fn error_injection(&mut self, error_injection: ErrorInjection) -> &mut Self {
+        self.partial.error_injection = Some(error_injection);
+        self
+    }
+
+    /// Makes reads and writes transfer fewer bytes than requested during unstable periods.
+    pub fn short_io(&mut self, short_io: ShortIo) -> &mut Self {
+        self.partial.short_io = Some(short_io);
+        self
+    }
+
+    /// Sets the glob patterns of the files to throttle.
+    pub fn include_globs(&mut self, globs: Vec<String>) -> &mut Self {
+        self.partial.include_globs = Some(globs);
+        self
+    }
+
+    /// Sets the glob patterns of the files not to throttle.
+    pub fn exclude_globs(&mut self, globs: Vec<String>) -> &mut Self {
+        self.partial.exclude_globs = Some(globs);
+        self
+    }
+
+    /// Sets the minimum size of the files to throttle.
+    pub fn min_file_size(&mut self, size: u64) -> &mut Self {
+        self.partial.min_file_size = Some(size);
+        self
+    }
+
+    /// Limits the byte rates by token buckets of the capacity.
+    pub fn burst(&mut self, burst: usize) -> &mut Self {
+        self.partial.burst = Some(burst);
+        self
+    }
+
+    /// Starts the token buckets of `burst` at the fraction of the capacity in each unstable period.
+    pub fn burst_start_fill(&mut self, fill: f64) -> &mut Self {
+        self.partial.burst_start_fill = Some(fill);
+        self
+    }
+
+    /// Stops the unstable periods after the number of them engage.
+    pub fn max_activations(&mut self, n: u32) -> &mut Self {
+        self.partial.max_activations = Some(n);
+        self
+    }
+
+    /// Ends each unstable period once the number of the target operations are serviced in it.
+    pub fn window_operations(&mut self, n: u64) -> &mut Self {
+        self.partial.window_operations = Some(n);
+        self
+    }
+
+    /// Sets the baseline throughput in bytes per second for `Speed::Percent` instead of measuring it.
+    pub fn baseline(&mut self, bps: usize) -> &mut Self {
+        self.partial.baseline = Some(bps);
+        self
+    }
+
+    /// Ramps the speed down over the duration at the start of each unstable period.
+    pub fn ramp(&mut self, ramp: Duration) -> &mut Self {
+        self.partial.ramp = Some(ramp);
+        self
+    }
+
+    /// Sets whether the threads share the token buckets.
+    pub fn scope(&mut self, scope: ThrottleScope) -> &mut Self {
+        self.partial.scope = Some(scope);
+        self
+    }
+
+    /// Sets how the speeds of the overlapping rules are combined (see `SpeedCombine`).
+    pub fn speed_combine(&mut self, speed_combine: SpeedCombine) -> &mut Self {
+        self.partial.speed_combine = Some(speed_combine);
+        self
+    }
+
+    /// Only logs the delays, the errors and the short I/O instead of applying them.
+    pub fn dry_run(&mut self, dry_run: bool) -> &mut Self {
+        self.partial.dry_run = Some(dry_run);
+        self
+    }
+
+    /// Passes the first operation on each opened file handle through.
+    pub fn passthrough_first_op(&mut self, b: bool) -> &mut Self {
+        self.partial.passthrough_first_op = Some(b);
+        self
+    }
+
+    /// Keeps an unstable period for the duration at least.
+    pub fn min_active(&mut self, d: Duration) -> &mut Self {
+        self.partial.min_active = Some(d);
+        self
+    }
+
+    /// Keeps a stable period for the duration at least.
+    pub fn min_idle(&mut self, d: Duration) -> &mut Self {
+        self.partial.min_idle = Some(d);
+        self
+    }
+
+    /// Delays the first unstable period by the duration after the start.
+    pub fn warmup(&mut self, d: Duration) -> &mut Self {
+        self.partial.warmup = Some(d);
+        self
+    }
+
+    /// Scales the speed of each unstable period by the factor from the previous one.
+    pub fn speed_decay(&mut self, decay: f64) -> &mut Self {
+        self.partial.speed_decay = Some(decay);
+        self
+    }
+
+    /// Throttles the operations out of the unstable periods instead of in them.
+    pub fn inverted(&mut self, inverted: bool) -> &mut Self {
+        self.partial.inverted = Some(inverted);
+        self
+    }
+
+    /// Passes the beginning of each unstable period through.
+    pub fn window_burst(&mut self, burst: WindowBurst) -> &mut Self {
+        self.partial.window_burst = Some(burst);
+        self
+    }
+
+    /// Passes the first bytes of each unstable period through and throttles the rest of the period
+    /// (e.g., a cache which fills up) as `window_burst(WindowBurst::Bytes(bytes))` does.
+    pub fn throttle_after_bytes(&mut self, bytes: u64) -> &mut Self {
+        self.partial.throttle_after_bytes = Some(bytes);
+        self
+    }
+
+    /// Charges the sequential reads on a handle within the duration as one transfer.
+    pub fn read_coalesce(&mut self, window: Duration) -> &mut Self {
+        self.partial.read_coalesce = Some(window);
+        self
+    }
+
+    /// Shuts down after running for the duration.
+    pub fn run_for(&mut self, d: Duration) -> &mut Self {
+        self.partial.run_for = Some(d);
+        self
+    }
+
+    /// Delays each random access on a file handle by the penalty.
+    pub fn seek_penalty(&mut self, penalty: Duration) -> &mut Self {
+        self.partial.seek_penalty = Some(penalty);
+        self
+    }
+
+    /// Delays each write beyond the end of the file (i.e., which allocates a new region) by the penalty.
+    pub fn allocation_penalty(&mut self, penalty: Duration) -> &mut Self {
+        self.partial.allocation_penalty = Some(penalty);
+        self
+    }
+
+    /// Throttles the appends (i.e., the writes at the end of the file) at the speed instead of the others.
+    pub fn append_speed(&mut self, speed: Speed) -> &mut Self {
+        self.partial.append_speed = Some(speed);
+        self
+    }
+
+    /// Caps each delay of an operation at the duration.
+    pub fn max_op_delay(&mut self, d: Duration) -> &mut Self {
+        self.partial.max_op_delay = Some(d);
+        self
+    }
+
+    /// Throttles the files of the extension at the speed (see `Config::extension_speeds`).
+    pub fn extension_speed(&mut self, extension: &str, speed: Speed) -> &mut Self {
+        self.partial
+            .extension_speeds
+            .get_or_insert_with(HashMap::new)
+            .insert(extension.to_string(), speed);
+        self
+    }
+
+    /// Divides the speed among the opened file handles.
+    pub fn share_among_handles(&mut self, b: bool) -> &mut Self {
+        self.partial.share_among_handles = Some(b);
+        self
+    }
+
+    /// Sets the mountpoint of `Mizumochi`.
+    pub fn target<P: Into<PathBuf>>(&mut self, target: P) -> &mut Self {
+        self.partial.target = Some(target.into());
+        self
+    }
+
+    /// Forces the unstable periods while the file of the trigger exists.
+    pub fn trigger(&mut self, trigger: Trigger) -> &mut Self {
+        self.partial.trigger = Some(trigger);
+        self
+    }
+
+    /// Adds a profile to `Config::profiles`.
+    pub fn profile(&mut self, profile: Profile) -> &mut Self {
+        self.partial
+            .profiles
+            .get_or_insert_with(Vec::new)
+            .push(profile);
+        self
+    }
+
+    /// Sets the seed of the RNG to make the random decisions reproducible.
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.partial.seed = Some(seed);
+        self
+    }
+
+    pub fn build(&self) -> Config {
+        Config::merge(Config::default(), self.partial.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::Condition;
+
+    #[test]
+    fn test_config_builder_default() {
+        let config = ConfigBuilder::new().build();
+        let default = Config::default();
+
+        assert_eq!(default.speed, config.speed);
+        assert_eq!(default.operations, config.operations);
+        assert_eq!(default.condition, config.condition);
+        assert_eq!(default.per_operation, config.per_operation);
+        assert_eq!(default.extension_speeds, config.extension_speeds);
+        assert_eq!(default.probability, config.probability);
+        assert_eq!(default.seed, config.seed);
+        assert_eq!(default.frequency_jitter, config.frequency_jitter);
+        assert_eq!(default.active_window, config.active_window);
+        assert_eq!(default.error_injection, config.error_injection);
+        assert_eq!(default.short_io, config.short_io);
+        assert_eq!(default.include_globs, config.include_globs);
+        assert_eq!(default.exclude_globs, config.exclude_globs);
+        assert_eq!(default.min_file_size, config.min_file_size);
+        assert_eq!(default.burst, config.burst);
+        assert_eq!(default.burst_start_fill, config.burst_start_fill);
+        assert_eq!(default.max_activations, config.max_activations);
+        assert_eq!(default.window_operations, config.window_operations);
+        assert_eq!(default.baseline, config.baseline);
+        assert_eq!(default.ramp, config.ramp);
+        assert_eq!(default.scope, config.scope);
+        assert_eq!(default.speed_combine, config.speed_combine);
+        assert_eq!(default.dry_run, config.dry_run);
+        assert_eq!(default.passthrough_first_op, config.passthrough_first_op);
+        assert_eq!(default.min_active, config.min_active);
+        assert_eq!(default.min_idle, config.min_idle);
+        assert_eq!(default.warmup, config.warmup);
+        assert_eq!(default.speed_decay, config.speed_decay);
+        assert_eq!(default.inverted, config.inverted);
+        assert_eq!(default.window_burst, config.window_burst);
+        assert_eq!(default.throttle_after_bytes, config.throttle_after_bytes);
+        assert_eq!(default.read_coalesce, config.read_coalesce);
+        assert_eq!(default.run_for, config.run_for);
+        assert_eq!(default.seek_penalty, config.seek_penalty);
+        assert_eq!(default.allocation_penalty, config.allocation_penalty);
+        assert_eq!(default.append_speed, config.append_speed);
+        assert_eq!(default.max_op_delay, config.max_op_delay);
+        assert_eq!(default.share_among_handles, config.share_among_handles);
+        assert_eq!(default.target, config.target);
+        assert_eq!(default.trigger, config.trigger);
+    }
+
+    #[test]
+    fn test_config_builder() {
+        let config = ConfigBuilder::new()
+            .speed(Speed::Bps(1024))
+            .operations(vec![Operation::Write])
+            .frequency(Duration::from_secs(60 * 60))
+            .build();
+
+        assert_eq!(Speed::Bps(1024), config.speed);
+        assert_eq!(vec![Operation::Write], config.operations);
+        assert_eq!(
+            Condition::Periodic {
+                duration: Duration::from_secs(10 * 60),
+                frequency: Duration::from_secs(60 * 60),
+            },
+            config.condition
+        );
+
+        let config = ConfigBuilder::new()
+            .duration(Duration::from_secs(5))
+            .frequency(Duration::from_secs(10))
+            .build();
+        assert_eq!(
+            Condition::Periodic {
+                duration: Duration::from_secs(5),
+                frequency: Duration::from_secs(10),
+            },
+            config.condition
+        );
+
+        let config = ConfigBuilder::new().throttle_after_bytes(64 << 20).build();
+        assert_eq!(Some(64 << 20), config.throttle_after_bytes);
+        assert_eq!(None, config.window_burst);
+
+        let config = ConfigBuilder::new()
+            .speed(Speed::Bps(1024))
+            .speed_for(Operation::Write, Speed::Bps(64))
+            .speed_for(Operation::Stat, Speed::Iops(10))
+            .speed_for(Operation::Write, Speed::Bps(128))
+            .build();
+        // The same operation is overridden.
+        assert_eq!(2, config.per_operation.len());
+        assert_eq!(&Speed::Bps(128), config.speed_for(&Operation::Write));
+        assert_eq!(&Speed::Iops(10), config.speed_for(&Operation::Stat));
+        assert_eq!(&Speed::Bps(1024), config.speed_for(&Operation::Read));
+
+        let config = ConfigBuilder::new()
+            .extension_speed("db", Speed::Bps(64))
+            .build();
+        assert_eq!(Some(&Speed::Bps(64)), config.extension_speeds.get("db"));
+    }
+}
@@ -0,0 +1,84 @@
+use super::Speed;
+use std::fmt;
+use std::str::FromStr;
+
+/// `SpeedCombine` decides the speed when the rules overlap, i.e., `Config::speed`, `Config::per_operation`
+/// and `Config::extension_speeds` for the same operation (see `Config::speed_for_extension`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SpeedCombine {
+    /// The more specific rule overrides the others (the extension, then the operation, then the global speed).
+    #[default]
+    LastWins,
+    /// The slowest of the rules applies (see `Speed::min_speed`).
+    MostRestrictive,
+}
+
+impl SpeedCombine {
+    /// Returns the speed of `specific` layered over `general` by the policy.
+    pub fn combine<'a>(self, general: &'a Speed, specific: &'a Speed) -> &'a Speed {
+        match self {
+            SpeedCombine::LastWins => specific,
+            SpeedCombine::MostRestrictive => Speed::min_speed(general, specific),
+        }
+    }
+}
+
+impl FromStr for SpeedCombine {
+    type Err = String;
+
+    /// Parses "last_wins" or "most_restrictive" as `SpeedCombine`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "last_wins" | "LastWins" => Ok(SpeedCombine::LastWins),
+            "most_restrictive" | "MostRestrictive" => Ok(SpeedCombine::MostRestrictive),
+            _ => Err(format!(
+                "speed_combine accepts last_wins or most_restrictive: {}",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SpeedCombine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SpeedCombine::LastWins => write!(f, "last_wins"),
+            SpeedCombine::MostRestrictive => write!(f, "most_restrictive"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_speed_combine_from_str() {
+        assert_eq!(
+            Ok(SpeedCombine::LastWins),
+            SpeedCombine::from_str("last_wins")
+        );
+        assert_eq!(
+            Ok(SpeedCombine::MostRestrictive),
+            SpeedCombine::from_str("MostRestrictive")
+        );
+        assert!(SpeedCombine::from_str("").is_err());
+        assert!(SpeedCombine::from_str("most-restrictive").is_err());
+
+        for s in ["last_wins", "most_restrictive"].iter() {
+            assert_eq!(*s, SpeedCombine::from_str(s).unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn test_speed_combine_combine() {
+        let (slow, fast) = (Speed::Bps(1024), Speed::Bps(4096));
+        assert_eq!(&fast, SpeedCombine::LastWins.combine(&slow, &fast));
+        assert_eq!(&slow, SpeedCombine::MostRestrictive.combine(&slow, &fast));
+        assert_eq!(&slow, SpeedCombine::MostRestrictive.combine(&fast, &slow));
+        assert_eq!(
+            &slow,
+            SpeedCombine::MostRestrictive.combine(&Speed::PassThrough, &slow)
+        );
+    }
+}
@@ -1,13 +1,60 @@
+use super::duration::{format_duration, format_rate};
+use super::Speed;
 use state::State;
+use std::fmt;
 use std::time::Duration;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Condition {
     Periodic {
+        // Zero durations are rejected at the deserialization.
+        #[serde(with = "super::duration::nonzero")]
         duration: Duration,
+        // It accepts the rates too (e.g., "2/h").
+        #[serde(with = "super::duration::frequency")]
         frequency: Duration,
     },
     Always(State),
+    // The unstable periods are replayed verbatim from `init` (see `ScriptWindow`), e.g., to reproduce an exact
+    // sequence of slowdowns. The randomness and the limits of `Condition::Periodic` (the probability, the jitter,
+    // the hysteresis, the warmup and `max_activations`) are not applied. The script restarts at the end of
+    // the last window if `repeat`, and stays stable after it otherwise.
+    Script {
+        windows: Vec<ScriptWindow>,
+        #[serde(default)]
+        repeat: bool,
+    },
+}
+
+/// `ScriptWindow` is an unstable period of `Condition::Script` which begins `offset` after the start of the script
+/// and lasts `length` at `speed`. The speed takes precedence over `Config::speed`, `Config::per_operation`
+/// and `Config::extension_speeds` in the window.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScriptWindow {
+    #[serde(with = "super::duration::human")]
+    pub offset: Duration,
+    #[serde(with = "super::duration::nonzero")]
+    pub length: Duration,
+    pub speed: Speed,
+}
+
+impl ScriptWindow {
+    /// Returns the offset of the end of the window, which is out of it.
+    pub fn end(&self) -> Duration {
+        self.offset + self.length
+    }
+}
+
+impl fmt::Display for ScriptWindow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at {} for {}",
+            self.speed,
+            format_duration(&self.offset),
+            format_duration(&self.length)
+        )
+    }
 }
 
 impl Condition {
@@ -17,4 +64,128 @@ impl Condition {
             frequency: Duration::from_secs(30 * 60),
         }
     }
+
+    /// Returns the windows of `Condition::Script`, which are empty for the other conditions.
+    pub fn script_windows(&self) -> &[ScriptWindow] {
+        match *self {
+            Condition::Script { ref windows, .. } => windows,
+            _ => &[],
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Condition::Periodic {
+                ref duration,
+                ref frequency,
+            } => {
+                write!(
+                    f,
+                    "Periodic {{duration: {}, frequency: {}",
+                    format_duration(duration),
+                    format_duration(frequency)
+                )?;
+                // The alternate form shows the rate too (e.g., "30m (2/h)").
+                match format_rate(frequency) {
+                    Some(ref rate) if f.alternate() => write!(f, " ({})}}", rate),
+                    _ => write!(f, "}}"),
+                }
+            }
+            Condition::Always(ref s) => write!(f, "Always({})", s),
+            Condition::Script {
+                ref windows,
+                repeat,
+            } => write!(
+                f,
+                "Script {{windows: [{}], repeat: {}}}",
+                windows
+                    .iter()
+                    .map(|w| w.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                repeat
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn test_condition_periodic_rate() {
+        let condition: Condition =
+            serde_json::from_str(r#"{"Periodic": {"duration": "10m", "frequency": "2/h"}}"#)
+                .unwrap();
+        let expected = Condition::Periodic {
+            duration: Duration::from_secs(10 * 60),
+            frequency: Duration::from_secs(30 * 60),
+        };
+        assert_eq!(expected, condition);
+        assert!(serde_json::from_str::<Condition>(
+            r#"{"Periodic": {"duration": "10m", "frequency": "0/h"}}"#
+        )
+        .is_err());
+
+        assert_eq!(
+            "Periodic {duration: 10m, frequency: 30m}",
+            condition.to_string()
+        );
+        assert_eq!(
+            "Periodic {duration: 10m, frequency: 30m (2/h)}",
+            format!("{:#}", condition)
+        );
+        let condition = Condition::Periodic {
+            duration: Duration::from_secs(60),
+            frequency: Duration::from_secs(7 * 60),
+        };
+        assert_eq!(
+            "Periodic {duration: 1m, frequency: 7m}",
+            format!("{:#}", condition)
+        );
+    }
+
+    #[test]
+    fn test_condition_script_serde() {
+        let condition = Condition::Script {
+            windows: vec![
+                ScriptWindow {
+                    offset: Duration::from_secs(60),
+                    length: Duration::from_secs(30),
+                    speed: Speed::Bps(1 << 20),
+                },
+                ScriptWindow {
+                    offset: Duration::from_secs(5 * 60),
+                    length: Duration::from_secs(60),
+                    speed: Speed::Latency(Duration::from_millis(50)),
+                },
+            ],
+            repeat: true,
+        };
+        assert_eq!(
+            "Script {windows: [1MBps at 1m for 30s, latency:50ms at 5m for 1m], repeat: true}",
+            condition.to_string()
+        );
+        assert_eq!(
+            Duration::from_secs(6 * 60),
+            condition.script_windows()[1].end()
+        );
+
+        let json = serde_json::to_string(&condition).unwrap();
+        assert_eq!(condition, serde_json::from_str(&json).unwrap());
+
+        // `repeat` is false if omitted, and the zero length is rejected.
+        let json = r#"{"Script":{"windows":[{"offset":"0s","length":"10s","speed":"1KBps"}]}}"#;
+        match serde_json::from_str(json).unwrap() {
+            Condition::Script { repeat: false, .. } => {}
+            c => panic!("{:?}", c),
+        }
+        let json = r#"{"Script":{"windows":[{"offset":"0s","length":"0s","speed":"1KBps"}]}}"#;
+        assert!(serde_json::from_str::<Condition>(json).is_err());
+        assert!(Condition::default_periodic().script_windows().is_empty());
+    }
 }
@@ -0,0 +1,267 @@
+use super::duration::format_duration;
+use super::Config;
+use std::fmt;
+
+/// `ConfigDiff` is the fields which differ between two configs.
+/// It is displayed as a line per field like "speed: PassThrough -> 1MBps".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigDiff {
+    // The field names with the values of the base and the other.
+    pub fields: Vec<(&'static str, String, String)>,
+}
+
+impl ConfigDiff {
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+impl fmt::Display for ConfigDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, &(name, ref old, ref new)) in self.fields.iter().enumerate() {
+            if 0 < i {
+                writeln!(f)?;
+            }
+            write!(f, "{}: {} -> {}", name, old, new)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Returns the fields which differ from `base`.
+    pub fn diff(&self, base: &Config) -> ConfigDiff {
+        let fields = render(base)
+            .into_iter()
+            .zip(render(self))
+            .filter(|&((_, ref old), (_, ref new))| old != new)
+            .map(|((name, old), (_, new))| (name, old, new))
+            .collect();
+
+        ConfigDiff { fields }
+    }
+
+    /// Returns the fields which differ from `Config::default()`.
+    pub fn diff_from_default(&self) -> ConfigDiff {
+        self.diff(&Config::default())
+    }
+}
+
+/// Renders all fields of the config in the order of the declaration.
+fn render(config: &Config) -> Vec<(&'static str, String)> {
+    let mut per_operation = config
+        .per_operation
+        .iter()
+        .map(|(op, speed)| format!("{}: {}", op, speed))
+        .collect::<Vec<_>>();
+    per_operation.sort();
+    let mut extension_speeds = config
+        .extension_speeds
+        .iter()
+        .map(|(ext, speed)| format!("{}: {}", ext, speed))
+        .collect::<Vec<_>>();
+    extension_speeds.sort();
+    let mut operation_weights = config
+        .operation_weights
+        .iter()
+        .map(|(op, w)| format!("{}: {}", op, w))
+        .collect::<Vec<_>>();
+    operation_weights.sort();
+
+    vec![
+        ("version", config.version.to_string()),
+        ("speed", config.speed.to_string()),
+        ("operations", join(&config.operations, ":")),
+        (
+            "operation_weights",
+            format!("{{{}}}", operation_weights.join(", ")),
+        ),
+        ("condition", config.condition.to_string()),
+        ("per_operation", format!("{{{}}}", per_operation.join(", "))),
+        (
+            "extension_speeds",
+            format!("{{{}}}", extension_speeds.join(", ")),
+        ),
+        ("probability", option(&config.probability)),
+        ("seed", option(&config.seed)),
+        (
+            "frequency_jitter",
+            format_duration(&config.frequency_jitter),
+        ),
+        ("active_window", option(&config.active_window)),
+        (
+            "error_injection",
+            match config.error_injection {
+                Some(ref e) => format!(
+                    "{{probability: {}, operations: {}, disk_full_after: {}, errors: [{}], continuous: {}, errors_per_window: {}}}",
+                    e.probability,
+                    join(&e.operations, ":"),
+                    option(&e.disk_full_after),
+                    join(&e.errors, ", "),
+                    e.continuous,
+                    option(&e.errors_per_window)
+                ),
+                None => "none".to_string(),
+            },
+        ),
+        ("short_io", option(&config.short_io)),
+        (
+            "include_globs",
+            format!("[{}]", config.include_globs.join(", ")),
+        ),
+        (
+            "exclude_globs",
+            format!("[{}]", config.exclude_globs.join(", ")),
+        ),
+        ("min_file_size", option(&config.min_file_size)),
+        ("burst", option(&config.burst)),
+        ("burst_start_fill", config.burst_start_fill.to_string()),
+        ("max_activations", option(&config.max_activations)),
+        ("window_operations", option(&config.window_operations)),
+        ("baseline", option(&config.baseline)),
+        (
+            "ramp",
+            config
+                .ramp
+                .as_ref()
+                .map_or("none".to_string(), format_duration),
+        ),
+        ("scope", config.scope.to_string()),
+        ("speed_combine", config.speed_combine.to_string()),
+        ("dry_run", config.dry_run.to_string()),
+        (
+            "passthrough_first_op",
+            config.passthrough_first_op.to_string(),
+        ),
+        ("min_active", format_duration(&config.min_active)),
+        ("min_idle", format_duration(&config.min_idle)),
+        ("warmup", format_duration(&config.warmup)),
+        ("speed_decay", config.speed_decay.to_string()),
+        ("inverted", config.inverted.to_string()),
+        ("window_burst", option(&config.window_burst)),
+        ("throttle_after_bytes", option(&config.throttle_after_bytes)),
+        (
+            "read_coalesce",
+            config
+                .read_coalesce
+                .as_ref()
+                .map_or("none".to_string(), format_duration),
+        ),
+        (
+            "run_for",
+            config
+                .run_for
+                .as_ref()
+                .map_or("none".to_string(), format_duration),
+        ),
+        (
+            "seek_penalty",
+            config
+                .seek_penalty
+                .as_ref()
+                .map_or("none".to_string(), format_duration),
+        ),
+        (
+            "allocation_penalty",
+            config
+                .allocation_penalty
+                .as_ref()
+                .map_or("none".to_string(), format_duration),
+        ),
+        ("append_speed", option(&config.append_speed)),
+        (
+            "max_op_delay",
+            config
+                .max_op_delay
+                .as_ref()
+                .map_or("none".to_string(), format_duration),
+        ),
+        (
+            "share_among_handles",
+            config.share_among_handles.to_string(),
+        ),
+        (
+            "target",
+            config
+                .target
+                .as_ref()
+                .map_or("none".to_string(), |target| target.display().to_string()),
+        ),
+        (
+            "trigger",
+            match config.trigger {
+                Some(ref t) => format!(
+                    "{{path: {}, poll_interval: {}, idle_when_absent: {}}}",
+                    t.path.display(),
+                    format_duration(&t.poll_interval),
+                    t.idle_when_absent
+                ),
+                None => "none".to_string(),
+            },
+        ),
+        ("profiles", format!("[{}]", join(&config.profiles, ", "))),
+    ]
+}
+
+fn option<T: fmt::Display>(x: &Option<T>) -> String {
+    match *x {
+        Some(ref x) => x.to_string(),
+        None => "none".to_string(),
+    }
+}
+
+fn join<T: fmt::Display>(xs: &[T], sep: &str) -> String {
+    xs.iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>()
+        .join(sep)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{Operation, Speed};
+
+    #[test]
+    fn test_diff_from_default() {
+        assert!(Config::default().diff_from_default().is_empty());
+        assert_eq!("", Config::default().diff_from_default().to_string());
+
+        let config = Config {
+            speed: Speed::Bps(1 << 20),
+            ..Default::default()
+        };
+        assert_eq!(
+            "speed: PassThrough -> 1MBps",
+            config.diff_from_default().to_string()
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let base = Config::default();
+        let mut config = Config {
+            operations: vec![Operation::Read],
+            burst: Some(4096),
+            ..Default::default()
+        };
+        config
+            .per_operation
+            .insert(Operation::Write, Speed::Bps(1024));
+
+        assert_eq!(
+            vec![
+                "operations: Read:Write -> Read",
+                "per_operation: {} -> {Write: 1KBps}",
+                "burst: none -> 4096",
+            ],
+            config.diff(&base).to_string().lines().collect::<Vec<_>>()
+        );
+        // The reversed diff.
+        assert_eq!(
+            "burst: 4096 -> none",
+            base.diff(&config).to_string().lines().last().unwrap()
+        );
+    }
+}
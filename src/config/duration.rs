@@ -0,0 +1,329 @@
+use super::ConfigError;
+use std::time::Duration;
+
+/// Parses the given string as duration.
+/// The number has to be followed by a suffix `ms` (milliseconds), `s` (seconds), `m` (minutes) or `h` (hours)
+/// (e.g., "500ms", "45s", "10m").
+/// The units can be combined and the result is the sum of them (e.g., "1h30m", "1m30s500ms").
+pub fn parse_duration(s: &str) -> Result<Duration, ConfigError> {
+    parse(s).map_err(ConfigError::InvalidDuration)
+}
+
+/// Same as `parse_duration` but the error is the message for the other parsers to wrap.
+pub fn parse(s: &str) -> Result<Duration, String> {
+    if s.is_empty() {
+        return Err("duration is empty".to_string());
+    }
+
+    let mut total = Duration::from_secs(0);
+    let mut rest = s;
+    while !rest.is_empty() {
+        let (n, tail) = match rest.find(|c: char| !c.is_ascii_digit()) {
+            Some(i) => rest.split_at(i),
+            None => return Err(format!("time suffix is missing: {}", s)),
+        };
+        let i = tail
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(i);
+        rest = tail;
+
+        let t: u64 = n.parse().map_err(|e| format!("{}: {}", e, s))?;
+        let d = match unit {
+            "ms" => Some(Duration::from_millis(t)),
+            "s" => Some(Duration::from_secs(t)),
+            "m" => t.checked_mul(60).map(Duration::from_secs),
+            "h" => t.checked_mul(60 * 60).map(Duration::from_secs),
+            _ => return Err("time suffix accepts ms, s, m or h".to_string()),
+        };
+        total = d
+            .and_then(|d| total.checked_add(d))
+            .ok_or_else(|| format!("overflow: {}", s))?;
+    }
+
+    Ok(total)
+}
+
+/// Parses the given string as frequency, i.e., the interval of a cycle: a duration as `parse_duration`, or a rate
+/// `<N>/<unit>` of a positive integer and a unit `s`, `m`, `h` or `d` (e.g., "2/h" is every 30 minutes and "4/d"
+/// is every 6 hours). Zero is rejected as `nonzero::parse` does.
+pub fn parse_frequency(s: &str) -> Result<Duration, ConfigError> {
+    frequency::parse(s).map_err(ConfigError::InvalidDuration)
+}
+
+/// Formats the given frequency as the rate per hour (or per day if longer), e.g., "2/h" for 30 minutes.
+/// It is `None` if the frequency is not an exact rate of them (e.g., 7 minutes) or is zero.
+pub fn format_rate(d: &Duration) -> Option<String> {
+    let nanos = d.as_nanos();
+    if nanos == 0 {
+        return None;
+    }
+
+    [(60 * 60u64, "h"), (24 * 60 * 60, "d")]
+        .iter()
+        .find_map(|&(secs, unit)| {
+            let per = u128::from(secs) * 1_000_000_000;
+            if nanos <= per && per.is_multiple_of(nanos) {
+                Some(format!("{}/{}", per / nanos, unit))
+            } else {
+                None
+            }
+        })
+}
+
+/// Formats the given duration in the largest unit which represents it exactly (e.g., "10m", "90s", "1500ms").
+/// The result can be parsed by `parse_duration`.
+/// Note that the precision below milliseconds is truncated.
+pub fn format_duration(d: &Duration) -> String {
+    let secs = d.as_secs();
+
+    if d.subsec_millis() != 0 {
+        format!("{}ms", secs * 1000 + u64::from(d.subsec_millis()))
+    } else if secs != 0 && secs.is_multiple_of(60 * 60) {
+        format!("{}h", secs / (60 * 60))
+    } else if secs != 0 && secs.is_multiple_of(60) {
+        format!("{}m", secs / 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// Serializes/deserializes `Duration` as human-readable string like "10m".
+/// The deserializer also accepts the `{secs, nanos}` form for backward compatibility.
+pub mod human {
+    use super::{format_duration, parse_duration};
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Human(String),
+        Raw(Duration),
+    }
+
+    pub fn serialize<S: Serializer>(d: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format_duration(d))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        match Repr::deserialize(deserializer)? {
+            Repr::Human(s) => parse_duration(&s).map_err(D::Error::custom),
+            Repr::Raw(d) => Ok(d),
+        }
+    }
+}
+
+/// Same as `human` for `Option<Duration>`, of which `None` is null.
+pub mod human_option {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    #[derive(Deserialize)]
+    struct Human(#[serde(with = "super::human")] Duration);
+
+    pub fn serialize<S: Serializer>(
+        d: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match *d {
+            Some(ref d) => super::human::serialize(d, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<Human>::deserialize(deserializer)?.map(|d| d.0))
+    }
+}
+
+/// Same as `human` but rejects zero, which makes `Condition::Periodic` pathological
+/// (e.g., the scheduler spins with a zero frequency).
+pub mod nonzero {
+    pub use super::human::serialize;
+    use serde::de::{Error, Unexpected};
+    use serde::Deserializer;
+    use std::time::Duration;
+
+    const EXPECTED: &str = "a non-zero duration (e.g., \"10m\")";
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let d = super::human::deserialize(deserializer)?;
+        if d == Duration::default() {
+            return Err(D::Error::invalid_value(
+                Unexpected::Other("zero duration"),
+                &EXPECTED,
+            ));
+        }
+
+        Ok(d)
+    }
+
+    /// Parses the duration by `parse_duration` and rejects zero in the same way.
+    pub fn parse(s: &str) -> Result<Duration, String> {
+        match super::parse(s)? {
+            d if d == Duration::default() => Err(format!(
+                "invalid value: zero duration, expected {}",
+                EXPECTED
+            )),
+            d => Ok(d),
+        }
+    }
+}
+
+/// Same as `nonzero` but accepts the rates of `parse_frequency` too. It is serialized as the duration.
+pub mod frequency {
+    pub use super::human::serialize;
+    use serde::de::{Error, Unexpected};
+    use serde::{Deserialize, Deserializer};
+    use std::time::Duration;
+
+    const EXPECTED: &str = "a non-zero duration or rate (e.g., \"30m\" or \"2/h\")";
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Human(String),
+        Raw(Duration),
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        match Repr::deserialize(deserializer)? {
+            Repr::Human(s) => parse(&s).map_err(D::Error::custom),
+            Repr::Raw(d) if d == Duration::default() => Err(D::Error::invalid_value(
+                Unexpected::Other("zero duration"),
+                &EXPECTED,
+            )),
+            Repr::Raw(d) => Ok(d),
+        }
+    }
+
+    /// Parses the frequency as `parse_frequency`.
+    pub fn parse(s: &str) -> Result<Duration, String> {
+        let i = match s.find('/') {
+            Some(i) => i,
+            None => return super::nonzero::parse(s),
+        };
+
+        let n: u32 = s[..i].parse().map_err(|e| format!("{}: {}", e, s))?;
+        if n == 0 {
+            return Err(format!("invalid value: zero rate, expected {}", EXPECTED));
+        }
+        let secs = match &s[i + 1..] {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => return Err("rate unit accepts s, m, h or d".to_string()),
+        };
+        match Duration::from_secs(secs) / n {
+            d if d == Duration::default() => Err(format!("rate is too high: {}", s)),
+            d => Ok(d),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(Ok(Duration::from_secs(45)), parse_duration("45s"));
+        assert_eq!(Ok(Duration::from_secs(10 * 60)), parse_duration("10m"));
+        assert_eq!(Ok(Duration::from_secs(2 * 60 * 60)), parse_duration("2h"));
+        assert_eq!(Ok(Duration::from_millis(500)), parse_duration("500ms"));
+
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("10").is_err());
+        assert!(parse_duration("10x").is_err());
+        assert!(parse_duration("10ns").is_err());
+        assert!(parse_duration("-10s").is_err());
+
+        match parse_duration("10") {
+            Err(ConfigError::InvalidDuration(_)) => {}
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_parse_duration_combined() {
+        assert_eq!(Ok(Duration::from_secs(90 * 60)), parse_duration("1h30m"));
+        assert_eq!(Ok(Duration::from_secs(90)), parse_duration("1m30s"));
+        assert_eq!(
+            Ok(Duration::from_millis(((60 + 1) * 60 + 1) * 1000 + 500)),
+            parse_duration("1h1m1s500ms")
+        );
+        assert_eq!(Ok(Duration::from_secs(90)), parse_duration("30s1m"));
+
+        assert!(parse_duration("1h30").is_err());
+        assert!(parse_duration("1h30x").is_err());
+        assert!(parse_duration("1hm").is_err());
+        assert!(parse_duration("1h 30m").is_err());
+        assert!(parse_duration("18446744073709551615h").is_err());
+    }
+
+    #[test]
+    fn test_parse_frequency() {
+        assert_eq!(Ok(Duration::from_secs(30 * 60)), parse_frequency("2/h"));
+        assert_eq!(Ok(Duration::from_secs(6 * 60 * 60)), parse_frequency("4/d"));
+        assert_eq!(Ok(Duration::from_millis(100)), parse_frequency("10/s"));
+        assert_eq!(Ok(Duration::from_secs(20)), parse_frequency("3/m"));
+        // The durations are accepted as they are.
+        assert_eq!(Ok(Duration::from_secs(30 * 60)), parse_frequency("30m"));
+
+        assert!(parse_frequency("0/h").is_err());
+        assert!(parse_frequency("-2/h").is_err());
+        assert!(parse_frequency("0s").is_err());
+        assert!(parse_frequency("2/").is_err());
+        assert!(parse_frequency("/h").is_err());
+        assert!(parse_frequency("2/w").is_err());
+        assert!(parse_frequency("1.5/h").is_err());
+        assert!(parse_frequency("2/h/h").is_err());
+        assert!(parse_frequency("4000000000/s").is_err());
+        match parse_frequency("2/x") {
+            Err(ConfigError::InvalidDuration(_)) => {}
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_format_rate() {
+        assert_eq!(
+            Some("2/h".to_string()),
+            format_rate(&Duration::from_secs(30 * 60))
+        );
+        assert_eq!(
+            Some("1/h".to_string()),
+            format_rate(&Duration::from_secs(60 * 60))
+        );
+        assert_eq!(
+            Some("4/d".to_string()),
+            format_rate(&Duration::from_secs(6 * 60 * 60))
+        );
+        assert_eq!(None, format_rate(&Duration::from_secs(7 * 60)));
+        assert_eq!(None, format_rate(&Duration::from_secs(48 * 60 * 60)));
+        assert_eq!(None, format_rate(&Duration::default()));
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!("0s", format_duration(&Duration::from_secs(0)));
+        assert_eq!("45s", format_duration(&Duration::from_secs(45)));
+        assert_eq!("90s", format_duration(&Duration::from_secs(90)));
+        assert_eq!("10m", format_duration(&Duration::from_secs(10 * 60)));
+        assert_eq!("90m", format_duration(&Duration::from_secs(90 * 60)));
+        assert_eq!("2h", format_duration(&Duration::from_secs(2 * 60 * 60)));
+        assert_eq!("50ms", format_duration(&Duration::from_millis(50)));
+        assert_eq!("1500ms", format_duration(&Duration::from_millis(1500)));
+
+        for secs in [0, 1, 59, 60, 61, 3599, 3600, 3601, 86400].iter() {
+            let d = Duration::from_secs(*secs);
+            assert_eq!(Ok(d), parse_duration(&format_duration(&d)));
+        }
+    }
+}
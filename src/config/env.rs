@@ -0,0 +1,209 @@
+use super::duration::{frequency, nonzero};
+use super::{parse_operations, ConfigError, PartialConfig, Speed};
+use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// The environment variables which `Config::from_env` reads.
+pub const SPEED: &str = "MIZUMOCHI_SPEED";
+pub const OPERATIONS: &str = "MIZUMOCHI_OPERATIONS";
+pub const DURATION: &str = "MIZUMOCHI_DURATION";
+pub const FREQUENCY: &str = "MIZUMOCHI_FREQUENCY";
+pub const TARGET: &str = "MIZUMOCHI_TARGET";
+
+/// Reads the config from the environment variables of the process.
+pub fn from_env() -> Result<PartialConfig, ConfigError> {
+    from_vars(|name| match env::var(name) {
+        Ok(v) => Ok(Some(v)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(e) => Err(ConfigError::Syntax(format!("{}: {}", name, e))),
+    })
+}
+
+/// Reads the config from the variables which `var` looks up.
+/// The result has only the fields of which variables are set.
+pub fn from_vars<F>(var: F) -> Result<PartialConfig, ConfigError>
+where
+    F: Fn(&str) -> Result<Option<String>, ConfigError>,
+{
+    // Name the variable in the errors.
+    let with_name = |name: &'static str| move |e: String| format!("{}: {}", name, e);
+    let mut config = PartialConfig::default();
+
+    if let Some(speed) = var(SPEED)? {
+        config.speed = Some(Speed::from_str(&speed).map_err(|e| e.with_name(SPEED))?);
+    }
+
+    if let Some(operations) = var(OPERATIONS)? {
+        // The operations are separated by commas or colons (e.g., "Read,Write").
+        config.operations =
+            Some(parse_operations(&operations).map_err(|e| e.with_name(OPERATIONS))?);
+    }
+
+    if let Some(duration) = var(DURATION)? {
+        config.duration = Some(
+            nonzero::parse(&duration)
+                .map_err(with_name(DURATION))
+                .map_err(ConfigError::InvalidDuration)?,
+        );
+    }
+
+    if let Some(frequency) = var(FREQUENCY)? {
+        config.frequency = Some(
+            frequency::parse(&frequency)
+                .map_err(with_name(FREQUENCY))
+                .map_err(ConfigError::InvalidDuration)?,
+        );
+    }
+
+    if let Some(target) = var(TARGET)? {
+        config.target = Some(PathBuf::from(target));
+    }
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{Condition, Config, Operation};
+    use std::collections::HashMap;
+    use std::sync::{Mutex, MutexGuard};
+    use std::time::Duration;
+
+    // The tests which change the environment variables of the process take it,
+    // since the other tests would see the variables in parallel.
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    const NAMES: &[&str] = &[SPEED, OPERATIONS, DURATION, FREQUENCY, TARGET];
+
+    /// Sets only the given variables of `NAMES` while the returned guard lives, and then removes them.
+    fn set_vars(vars: &[(&str, &str)]) -> impl Drop {
+        struct Scope {
+            _lock: MutexGuard<'static, ()>,
+        }
+        impl Drop for Scope {
+            fn drop(&mut self) {
+                for name in NAMES {
+                    env::remove_var(name);
+                }
+            }
+        }
+
+        // The others go on even if a test failed holding the lock.
+        let scope = Scope {
+            _lock: ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner()),
+        };
+        for name in NAMES {
+            env::remove_var(name);
+        }
+        for &(name, value) in vars {
+            env::set_var(name, value);
+        }
+        scope
+    }
+
+    fn from_map(vars: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        let vars = vars
+            .iter()
+            .map(|&(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>();
+        let partial = from_vars(|name| Ok(vars.get(name).cloned()))?;
+        Ok(Config::merge(Config::default(), partial))
+    }
+
+    #[test]
+    fn test_from_vars() {
+        let config = from_map(&[
+            (SPEED, "512KBps"),
+            (OPERATIONS, "Read, Stat"),
+            (DURATION, "5m"),
+            (FREQUENCY, "1h"),
+            (TARGET, "/mnt/slow"),
+        ])
+        .unwrap();
+        assert_eq!(Some(PathBuf::from("/mnt/slow")), config.target);
+        assert_eq!(Speed::Bps(512 << 10), config.speed);
+        assert_eq!(vec![Operation::Read, Operation::Stat], config.operations);
+        assert_eq!(
+            Condition::Periodic {
+                duration: Duration::from_secs(5 * 60),
+                frequency: Duration::from_secs(60 * 60),
+            },
+            config.condition
+        );
+    }
+
+    #[test]
+    fn test_from_vars_fallback() {
+        let default = Config::default();
+
+        let config = from_map(&[]).unwrap();
+        assert_eq!(default.speed, config.speed);
+        assert_eq!(default.operations, config.operations);
+        assert_eq!(default.condition, config.condition);
+        assert_eq!(default.target, config.target);
+
+        // The other one of the periodic condition is the default.
+        let config = from_map(&[(FREQUENCY, "1h")]).unwrap();
+        assert_eq!(
+            Condition::Periodic {
+                duration: Duration::from_secs(10 * 60),
+                frequency: Duration::from_secs(60 * 60),
+            },
+            config.condition
+        );
+        assert_eq!(default.speed, config.speed);
+    }
+
+    #[test]
+    fn test_from_vars_error() {
+        for &(name, value) in &[
+            (SPEED, "fast"),
+            (OPERATIONS, "Read,Remove"),
+            (DURATION, "5"),
+            (FREQUENCY, "soon"),
+            (FREQUENCY, "0s"),
+        ] {
+            let e = from_map(&[(name, value)]).unwrap_err().to_string();
+            assert!(e.contains(name), "{}", e);
+        }
+
+        // The variable is named keeping the kind of the error.
+        match from_map(&[(SPEED, "fast")]) {
+            Err(ConfigError::InvalidSpeed(ref e)) if e.starts_with(SPEED) => {}
+            r => panic!("{:?}", r),
+        }
+        match from_map(&[(OPERATIONS, "Read,Remove")]) {
+            Err(ConfigError::InvalidOperation(ref e)) if e.starts_with(OPERATIONS) => {}
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_from_env() {
+        let _vars = set_vars(&[(SPEED, "1MBps"), (FREQUENCY, "2h")]);
+
+        let config = Config::from_env().unwrap();
+        assert_eq!(Speed::Bps(1 << 20), config.speed);
+        assert_eq!(
+            Condition::Periodic {
+                duration: Duration::from_secs(10 * 60),
+                frequency: Duration::from_secs(2 * 60 * 60),
+            },
+            config.condition
+        );
+        assert_eq!(Config::default().operations, config.operations);
+    }
+
+    #[test]
+    fn test_from_env_unset() {
+        {
+            let _vars = set_vars(&[]);
+            assert_eq!(PartialConfig::default(), from_env().unwrap());
+        }
+
+        let _vars = set_vars(&[(TARGET, "/mnt/slow")]);
+        assert_eq!(Some(PathBuf::from("/mnt/slow")), from_env().unwrap().target);
+    }
+}
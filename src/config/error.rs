@@ -0,0 +1,97 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::mem;
+
+/// `ConfigError` represents errors while loading config.
+/// The parsers of the values (e.g., `Speed::from_str` and `parse_duration`) return it too,
+/// so that the callers can tell the kinds of the errors.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    // The document is not valid TOML or does not match to the config format.
+    Syntax(String),
+    InvalidSpeed(String),
+    InvalidDuration(String),
+    InvalidOperation(String),
+    InvalidWindow(String),
+    InvalidSize(String),
+    InvalidShortIo(String),
+    InvalidScope(String),
+    InvalidErrno(String),
+    InvalidWindowBurst(String),
+    // The number (e.g., of a speed or a byte size) exceeds the range of the type.
+    Overflow(String),
+    // The version of the config is newer than `CONFIG_VERSION` or unknown, so it cannot be migrated.
+    UnsupportedVersion(String),
+    // The config is well-formed but its values are not acceptable (see `Config::validate`).
+    ValidationFailed(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref e) => write!(f, "cannot read config: {}", e),
+            ConfigError::Syntax(ref e) => write!(f, "invalid config: {}", e),
+            ConfigError::InvalidSpeed(ref e) => write!(f, "invalid speed: {}", e),
+            ConfigError::InvalidDuration(ref e) => write!(f, "invalid duration: {}", e),
+            ConfigError::InvalidOperation(ref e) => write!(f, "invalid operation: {}", e),
+            ConfigError::InvalidWindow(ref e) => write!(f, "invalid active window: {}", e),
+            ConfigError::InvalidSize(ref e) => write!(f, "invalid byte size: {}", e),
+            ConfigError::InvalidShortIo(ref e) => write!(f, "invalid short io: {}", e),
+            ConfigError::InvalidScope(ref e) => write!(f, "invalid scope: {}", e),
+            ConfigError::InvalidErrno(ref e) => write!(f, "invalid errno: {}", e),
+            ConfigError::InvalidWindowBurst(ref e) => write!(f, "invalid window burst: {}", e),
+            ConfigError::Overflow(ref e) => write!(f, "overflow: {}", e),
+            ConfigError::UnsupportedVersion(ref e) => {
+                write!(f, "unsupported config version: {}", e)
+            }
+            ConfigError::ValidationFailed(ref e) => write!(f, "validation failed: {}", e),
+        }
+    }
+}
+
+impl ConfigError {
+    /// Prefixes the message by the name of the source (e.g., an environment variable) keeping the kind.
+    pub fn with_name(self, name: &str) -> ConfigError {
+        let prefix = |e: String| format!("{}: {}", name, e);
+        match self {
+            ConfigError::Io(e) => ConfigError::Io(io::Error::new(e.kind(), prefix(e.to_string()))),
+            ConfigError::Syntax(e) => ConfigError::Syntax(prefix(e)),
+            ConfigError::InvalidSpeed(e) => ConfigError::InvalidSpeed(prefix(e)),
+            ConfigError::InvalidDuration(e) => ConfigError::InvalidDuration(prefix(e)),
+            ConfigError::InvalidOperation(e) => ConfigError::InvalidOperation(prefix(e)),
+            ConfigError::InvalidWindow(e) => ConfigError::InvalidWindow(prefix(e)),
+            ConfigError::InvalidSize(e) => ConfigError::InvalidSize(prefix(e)),
+            ConfigError::InvalidShortIo(e) => ConfigError::InvalidShortIo(prefix(e)),
+            ConfigError::InvalidScope(e) => ConfigError::InvalidScope(prefix(e)),
+            ConfigError::InvalidErrno(e) => ConfigError::InvalidErrno(prefix(e)),
+            ConfigError::InvalidWindowBurst(e) => ConfigError::InvalidWindowBurst(prefix(e)),
+            ConfigError::Overflow(e) => ConfigError::Overflow(prefix(e)),
+            ConfigError::UnsupportedVersion(e) => ConfigError::UnsupportedVersion(prefix(e)),
+            ConfigError::ValidationFailed(e) => ConfigError::ValidationFailed(prefix(e)),
+        }
+    }
+}
+
+/// The errors are equal if they are of the same kind with the same message.
+impl PartialEq for ConfigError {
+    fn eq(&self, other: &ConfigError) -> bool {
+        mem::discriminant(self) == mem::discriminant(other) && self.to_string() == other.to_string()
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ConfigError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> ConfigError {
+        ConfigError::Io(e)
+    }
+}
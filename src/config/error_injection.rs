@@ -0,0 +1,30 @@
+use super::{Operation, WeightedError};
+
+/// `ErrorInjection` makes the operations fail during unstable periods.
+/// Each operation in `operations` fails in `probability` (0.0 to 1.0) independently of the speed,
+/// with an error chosen from `errors` by the weights (EIO if empty).
+/// The writes fail with ENOSPC once the written bytes in an unstable period exceed `disk_full_after`.
+///
+/// If `continuous`, `probability` applies in the stable periods too (e.g., to model the flaky device which
+/// fails even when it is fast), while `disk_full_after` is still only for the unstable periods.
+///
+/// `errors_per_window` replaces `probability` in the unstable periods with the budget of the errors:
+/// exactly that many operations in `operations` fail in each unstable period, at random among as many
+/// operations as the last period had, scaled by the lengths of the periods (e.g., of the script windows).
+/// The first K operations fail if it had K or fewer (e.g., in the first period), and so do the operations
+/// beyond the estimate until the budget is spent. A period which has fewer operations than K cannot reach it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorInjection {
+    #[serde(default)]
+    pub probability: f64,
+    #[serde(default)]
+    pub operations: Vec<Operation>,
+    #[serde(default)]
+    pub disk_full_after: Option<usize>,
+    #[serde(default)]
+    pub errors: Vec<WeightedError>,
+    #[serde(default)]
+    pub continuous: bool,
+    #[serde(default)]
+    pub errors_per_window: Option<u32>,
+}
@@ -0,0 +1,1371 @@
+use super::duration::{frequency, nonzero};
+use super::{
+    format_duration, parse_byte_size, parse_duration, ActiveWindow, Condition, Config, ConfigError,
+    ErrorInjection, Operation, PartialConfig, Profile, ScriptWindow, ShortIo, Speed, SpeedCombine,
+    ThrottleScope, Trigger, WeightedError, WindowBurst, CONFIG_VERSION,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use toml;
+
+/// `ConfigFile` is the format of config files.
+/// All fields are optional and the missing fields fall back to `Config::default()`.
+///
+/// `version` is `CONFIG_VERSION` if omitted, and the older versions are migrated by `migrate`.
+///
+/// # Examples
+/// ```toml
+/// version = 1
+/// speed = "1MBps"
+/// operations = ["Read", "Write", "Stat:3", "Open:1"]
+/// duration = "10m"
+/// frequency = "30m"
+/// probability = 0.5
+/// seed = 42
+/// frequency_jitter = "5m"
+/// active_window = "09:00-17:00"
+/// short_io = "random"
+/// include_globs = ["data/**"]
+/// exclude_globs = ["**/*.log"]
+/// min_file_size = "1MB"
+/// burst = "4MB"
+/// burst_start_fill = 0.0
+/// max_activations = 1
+/// window_operations = 1000
+/// baseline = "200MBps"
+/// ramp = "30s"
+/// scope = "per_thread"
+/// speed_combine = "most_restrictive"
+/// dry_run = false
+/// passthrough_first_op = true
+/// min_active = "5m"
+/// min_idle = "15m"
+/// warmup = "1m"
+/// speed_decay = 0.5
+/// inverted = false
+/// window_burst = "64MB"
+/// read_coalesce = "10ms"
+/// run_for = "2h"
+/// seek_penalty = "8ms"
+/// allocation_penalty = "4ms"
+/// append_speed = "PassThrough"
+/// max_op_delay = "30s"
+/// share_among_handles = false
+/// target = "/mnt/slow"
+///
+/// [[profiles]]
+/// speed = "64KBps"
+/// operations = ["Fsync"]
+/// duration = "1m"
+/// frequency = "15m"
+///
+/// [per_operation]
+/// Write = "64KBps"
+///
+/// [extension_speeds]
+/// db = "16KBps"
+///
+/// [error_injection]
+/// probability = 0.1
+/// operations = ["Read"]
+/// disk_full_after = "100MB"
+/// errors = ["EIO", "EAGAIN:3"]
+/// continuous = false
+/// errors_per_window = 3
+///
+/// [trigger]
+/// path = "/tmp/mizumochi-slow"
+/// poll_interval = "1s"
+/// idle_when_absent = false
+/// ```
+///
+/// `[[script]]` replaces `duration` and `frequency` with the windows of `Condition::Script`,
+/// and `script_repeat` replays them:
+/// ```toml
+/// script_repeat = true
+///
+/// [[script]]
+/// offset = "10s"
+/// length = "5s"
+/// speed = "1KBps"
+///
+/// [[script]]
+/// offset = "30s"
+/// length = "10s"
+/// speed = "latency:50ms"
+/// ```
+///
+/// `throttle_after_bytes` (e.g., `throttle_after_bytes = "64MB"`) is the same as `window_burst` of the bytes,
+/// so they cannot be combined (see `Config::validate`).
+// The tables are declared last because TOML requires the values before them.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ConfigFile {
+    version: Option<u32>,
+    speed: Option<String>,
+    operations: Option<Vec<String>>,
+    duration: Option<String>,
+    frequency: Option<String>,
+    probability: Option<f64>,
+    seed: Option<u64>,
+    frequency_jitter: Option<String>,
+    active_window: Option<String>,
+    short_io: Option<String>,
+    include_globs: Option<Vec<String>>,
+    exclude_globs: Option<Vec<String>>,
+    min_file_size: Option<String>,
+    burst: Option<String>,
+    burst_start_fill: Option<f64>,
+    max_activations: Option<u32>,
+    window_operations: Option<u64>,
+    baseline: Option<String>,
+    ramp: Option<String>,
+    scope: Option<String>,
+    speed_combine: Option<String>,
+    dry_run: Option<bool>,
+    passthrough_first_op: Option<bool>,
+    min_active: Option<String>,
+    min_idle: Option<String>,
+    warmup: Option<String>,
+    speed_decay: Option<f64>,
+    inverted: Option<bool>,
+    window_burst: Option<String>,
+    throttle_after_bytes: Option<String>,
+    read_coalesce: Option<String>,
+    run_for: Option<String>,
+    seek_penalty: Option<String>,
+    allocation_penalty: Option<String>,
+    append_speed: Option<String>,
+    max_op_delay: Option<String>,
+    share_among_handles: Option<bool>,
+    script_repeat: Option<bool>,
+    target: Option<PathBuf>,
+    profiles: Option<Vec<ProfileFile>>,
+    script: Option<Vec<ScriptWindowFile>>,
+    per_operation: Option<BTreeMap<String, String>>,
+    extension_speeds: Option<BTreeMap<String, String>>,
+    error_injection: Option<ErrorInjectionFile>,
+    trigger: Option<Trigger>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ProfileFile {
+    speed: String,
+    operations: Vec<String>,
+    duration: String,
+    frequency: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ScriptWindowFile {
+    offset: String,
+    length: String,
+    speed: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct ErrorInjectionFile {
+    probability: f64,
+    operations: Vec<String>,
+    disk_full_after: Option<String>,
+    errors: Vec<String>,
+    continuous: bool,
+    errors_per_window: Option<u32>,
+}
+
+/// Migrates the config file of its version to `CONFIG_VERSION`, which is the only place to handle
+/// the older schemas. The files without the version are of the current version.
+/// The newer (or unknown) versions are rejected rather than misread.
+fn migrate(file: ConfigFile) -> Result<ConfigFile, ConfigError> {
+    match file.version.unwrap_or(CONFIG_VERSION) {
+        // Version 1 is the first schema, so nothing to migrate yet.
+        // Add the steps from each old version here (e.g., renaming the fields) when the schema changes.
+        CONFIG_VERSION => Ok(ConfigFile {
+            version: Some(CONFIG_VERSION),
+            ..file
+        }),
+        v => Err(ConfigError::UnsupportedVersion(format!(
+            "{} (the supported version is {})",
+            v, CONFIG_VERSION
+        ))),
+    }
+}
+
+fn parse_operations(operations: &[String]) -> Result<Vec<Operation>, ConfigError> {
+    operations
+        .iter()
+        .map(|op| Operation::from_str(op))
+        .collect()
+}
+
+fn to_strings<T: fmt::Display>(xs: &[T]) -> Vec<String> {
+    xs.iter().map(|x| x.to_string()).collect()
+}
+
+/// Writes all fields of the config in the format which `from_toml_str` reads.
+/// `Condition::Always` is not representable in the format.
+pub fn to_toml_string(config: &Config) -> Result<String, ConfigError> {
+    let (duration, frequency, script, script_repeat) = match config.condition {
+        Condition::Periodic {
+            ref duration,
+            ref frequency,
+        } => (
+            Some(format_duration(duration)),
+            Some(format_duration(frequency)),
+            None,
+            None,
+        ),
+        Condition::Always(ref s) => {
+            return Err(ConfigError::Syntax(format!(
+                "condition Always({:?}) is not representable",
+                s
+            )))
+        }
+        Condition::Script {
+            ref windows,
+            repeat,
+        } => {
+            let windows = windows
+                .iter()
+                .map(|w| ScriptWindowFile {
+                    offset: format_duration(&w.offset),
+                    length: format_duration(&w.length),
+                    speed: w.speed.to_string(),
+                })
+                .collect();
+            (None, None, Some(windows), Some(repeat))
+        }
+    };
+
+    let file = ConfigFile {
+        version: Some(config.version),
+        speed: Some(config.speed.to_string()),
+        operations: Some(
+            config
+                .operations
+                .iter()
+                .map(|op| match config.operation_weights.get(op) {
+                    Some(w) => format!("{}:{}", op, w),
+                    None => op.to_string(),
+                })
+                .collect(),
+        ),
+        duration,
+        frequency,
+        probability: config.probability,
+        seed: config.seed,
+        frequency_jitter: Some(format_duration(&config.frequency_jitter)),
+        active_window: config.active_window.as_ref().map(|w| w.to_string()),
+        short_io: config.short_io.as_ref().map(|s| s.to_string()),
+        include_globs: Some(config.include_globs.clone()),
+        exclude_globs: Some(config.exclude_globs.clone()),
+        min_file_size: config.min_file_size.map(|n| n.to_string()),
+        burst: config.burst.map(|n| n.to_string()),
+        burst_start_fill: Some(config.burst_start_fill),
+        max_activations: config.max_activations,
+        window_operations: config.window_operations,
+        baseline: config.baseline.map(|n| n.to_string()),
+        ramp: config.ramp.as_ref().map(format_duration),
+        scope: Some(config.scope.to_string()),
+        speed_combine: Some(config.speed_combine.to_string()),
+        dry_run: Some(config.dry_run),
+        passthrough_first_op: Some(config.passthrough_first_op),
+        min_active: Some(format_duration(&config.min_active)),
+        min_idle: Some(format_duration(&config.min_idle)),
+        warmup: Some(format_duration(&config.warmup)),
+        speed_decay: Some(config.speed_decay),
+        inverted: Some(config.inverted),
+        window_burst: config.window_burst.as_ref().map(|b| b.to_string()),
+        throttle_after_bytes: config.throttle_after_bytes.map(|n| n.to_string()),
+        read_coalesce: config.read_coalesce.as_ref().map(format_duration),
+        run_for: config.run_for.as_ref().map(format_duration),
+        seek_penalty: config.seek_penalty.as_ref().map(format_duration),
+        allocation_penalty: config.allocation_penalty.as_ref().map(format_duration),
+        append_speed: config.append_speed.as_ref().map(|s| s.to_string()),
+        max_op_delay: config.max_op_delay.as_ref().map(format_duration),
+        share_among_handles: Some(config.share_among_handles),
+        script_repeat,
+        target: config.target.clone(),
+        profiles: Some(
+            config
+                .profiles
+                .iter()
+                .map(|p| ProfileFile {
+                    speed: p.speed.to_string(),
+                    operations: to_strings(&p.operations),
+                    duration: format_duration(&p.duration),
+                    frequency: format_duration(&p.frequency),
+                })
+                .collect(),
+        ),
+        script,
+        per_operation: Some(
+            config
+                .per_operation
+                .iter()
+                .map(|(op, speed)| (op.to_string(), speed.to_string()))
+                .collect(),
+        ),
+        extension_speeds: Some(
+            config
+                .extension_speeds
+                .iter()
+                .map(|(ext, speed)| (ext.clone(), speed.to_string()))
+                .collect(),
+        ),
+        error_injection: config.error_injection.as_ref().map(|e| ErrorInjectionFile {
+            probability: e.probability,
+            operations: to_strings(&e.operations),
+            disk_full_after: e.disk_full_after.map(|n| n.to_string()),
+            errors: to_strings(&e.errors),
+            continuous: e.continuous,
+            errors_per_window: e.errors_per_window,
+        }),
+        trigger: config.trigger.clone(),
+    };
+
+    toml::to_string(&file).map_err(|e| ConfigError::Syntax(e.to_string()))
+}
+
+/// The comments of the top-level keys in `default_template` in the order of `ConfigFile`,
+/// and the examples of the keys which are unset by default.
+const TEMPLATE_KEYS: &[(&str, &str, Option<&str>)] = &[
+    ("version", "The version of the schema of this file.", None),
+    (
+        "speed",
+        "The speed in the unstable periods, e.g., \"1MBps\", \"1MBps..5MBps\", \"latency:50ms\", \"1MBps+50ms\" or \"100iops\".",
+        None,
+    ),
+    (
+        "operations",
+        "The operations to throttle. The weighted ones (e.g., \"Stat:3\") are chosen one per unstable period.",
+        None,
+    ),
+    ("duration", "The length of each unstable period.", None),
+    ("frequency", "The cycle of the unstable periods, or their rate (e.g., \"2/h\").", None),
+    (
+        "probability",
+        "The probability that an unstable period actually slows down the operations.",
+        Some("0.5"),
+    ),
+    ("seed", "The seed to make the random decisions reproducible.", Some("42")),
+    ("frequency_jitter", "Randomizes each cycle within frequency +/- frequency_jitter.", None),
+    (
+        "active_window",
+        "The time of day when the operations can be throttled.",
+        Some("\"09:00-17:00\""),
+    ),
+    (
+        "short_io",
+        "Reads and writes transfer fewer bytes than requested in the unstable periods.",
+        Some("\"random\""),
+    ),
+    ("include_globs", "The files to throttle (all files if empty).", None),
+    ("exclude_globs", "The files not to throttle, which take precedence over include_globs.", None),
+    ("min_file_size", "Only the files of this size or larger are throttled.", Some("\"1MB\"")),
+    ("burst", "The capacity of the token buckets of the byte rates.", Some("\"4MB\"")),
+    ("burst_start_fill", "The fraction of burst in the token buckets at the start of each unstable period.", None),
+    ("max_activations", "Stops after this number of the unstable periods.", Some("1")),
+    (
+        "window_operations",
+        "Ends each unstable period after this number of the operations in it.",
+        Some("1000"),
+    ),
+    ("baseline", "The throughput of the device which the percentages are relative to.", Some("\"200MBps\"")),
+    ("ramp", "The speed ramps down over this duration at the start of each unstable period.", Some("\"30s\"")),
+    ("scope", "Whether the threads share the token buckets (\"global\" or \"per_thread\").", None),
+    (
+        "speed_combine",
+        "Whether the more specific speeds override the others (\"last_wins\") or the slowest applies (\"most_restrictive\").",
+        None,
+    ),
+    ("dry_run", "Only logs the delays, the errors and the short I/O without applying them.", None),
+    ("passthrough_first_op", "Never delays the first operation on each opened file.", None),
+    ("min_active", "The shortest unstable period.", None),
+    ("min_idle", "The shortest stable period.", None),
+    ("warmup", "Extends the first stable period.", None),
+    ("speed_decay", "Scales the speed of the N-th unstable period by speed_decay^N.", None),
+    ("inverted", "Throttles out of the unstable periods instead of in them.", None),
+    (
+        "window_burst",
+        "The beginning of each unstable period which passes through (bytes or a duration).",
+        Some("\"64MB\""),
+    ),
+    (
+        "throttle_after_bytes",
+        "The bytes of each unstable period which pass through before the speed applies.",
+        Some("\"64MB\""),
+    ),
+    ("read_coalesce", "Charges the continued reads within this duration as one transfer.", Some("\"10ms\"")),
+    ("run_for", "Stops throttling and unmounts after this duration.", Some("\"2h\"")),
+    ("seek_penalty", "The extra delay of each random access.", Some("\"8ms\"")),
+    (
+        "allocation_penalty",
+        "The extra delay of each write beyond the end of the file.",
+        Some("\"4ms\""),
+    ),
+    (
+        "append_speed",
+        "The speed of the writes at the end of the file, which overrides the others.",
+        Some("\"PassThrough\""),
+    ),
+    ("max_op_delay", "The cap of each delay of an operation.", Some("\"30s\"")),
+    ("share_among_handles", "Divides the speed among the opened files.", None),
+    (
+        "target",
+        "The mountpoint, which the command line argument overrides.",
+        Some("\"/mnt/slow\""),
+    ),
+    (
+        "profiles",
+        "The extra slowdowns with their own cycles as [[profiles]] tables (speed, operations, duration and frequency).",
+        None,
+    ),
+];
+
+/// The commented example of `[error_injection]`, which is unset by default.
+const TEMPLATE_ERROR_INJECTION: &str = "# The errors to inject in the unstable periods.
+# [error_injection]
+# probability = 0.1
+# operations = [\"Read\"]
+# disk_full_after = \"100MB\"
+# errors = [\"EIO\", \"EAGAIN:3\"]
+# continuous = false
+# errors_per_window = 3
+";
+
+/// The commented example of `[trigger]`, which is unset by default.
+const TEMPLATE_TRIGGER: &str = "# The file which forces the unstable periods while it exists.
+# [trigger]
+# path = \"/tmp/mizumochi-slow\"
+# poll_interval = \"1s\"
+# idle_when_absent = false
+";
+
+/// Writes `Config::default()` as a TOML document with the comments of the keys, which `from_toml_str` reads back
+/// to the default config. The keys which are unset by default are commented out with examples.
+pub fn default_template() -> String {
+    let toml = to_toml_string(&Config::default()).expect("the default config is representable");
+    // The top-level keys precede the tables.
+    let (keys, tables) = match toml.find("\n[") {
+        Some(i) => toml.split_at(i + 1),
+        None => (&toml[..], ""),
+    };
+    let lines = keys
+        .lines()
+        .filter_map(|l| l.find(" = ").map(|i| (&l[..i], l)))
+        .collect::<HashMap<_, _>>();
+
+    let mut s = String::from(
+        "# The config of mizumochi, which is loaded by --config.\n\
+         # The commented keys are unset by default.\n",
+    );
+    for &(key, comment, example) in TEMPLATE_KEYS {
+        s.push_str(&format!("\n# {}\n", comment));
+        match (lines.get(key), example) {
+            (Some(line), _) => s.push_str(&format!("{}\n", line)),
+            (None, Some(example)) => s.push_str(&format!("# {} = {}\n", key, example)),
+            (None, None) => {}
+        }
+    }
+
+    for line in tables.lines() {
+        if line == "[per_operation]" {
+            s.push_str("\n# The speeds of the specific operations, which override speed.\n");
+            s.push_str(&format!("{}\n# Write = \"64KBps\"\n", line));
+        } else if line == "[extension_speeds]" {
+            s.push_str(
+                "\n# The speeds of the files of the extensions, which override per_operation.\n",
+            );
+            s.push_str(&format!("{}\n# db = \"16KBps\"\n", line));
+        } else if !line.is_empty() {
+            s.push_str(&format!("{}\n", line));
+        }
+    }
+    s.push('\n');
+    s.push_str(TEMPLATE_ERROR_INJECTION);
+    s.push('\n');
+    s.push_str(TEMPLATE_TRIGGER);
+    s
+}
+
+/// Parses the operations which may have the weights like "Write:3" (see `Config::operation_weights`).
+fn parse_weighted_operations(
+    operations: &[String],
+) -> Result<(Vec<Operation>, HashMap<Operation, u32>), ConfigError> {
+    let mut ops = Vec::with_capacity(operations.len());
+    let mut weights = HashMap::new();
+    for s in operations {
+        let (op, weight) = match s.find(':') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (&s[..], None),
+        };
+        let op = Operation::from_str(op)?;
+        if let Some(weight) = weight {
+            let weight = weight.parse().map_err(|e| {
+                ConfigError::InvalidOperation(format!("invalid weight of {}: {}", s, e))
+            })?;
+            weights.insert(op.clone(), weight);
+        }
+        ops.push(op);
+    }
+
+    Ok((ops, weights))
+}
+
+/// Parses the speed by `Speed::from_str_clamped` and keeps the warning if it is clamped.
+fn clamped_speed(s: &str, warnings: &mut Vec<String>) -> Result<Speed, ConfigError> {
+    let (speed, warning) = Speed::from_str_clamped(s)?;
+    warnings.extend(warning);
+    Ok(speed)
+}
+
+pub fn from_toml_str(s: &str) -> Result<Config, ConfigError> {
+    Ok(Config::merge(Config::default(), partial_from_toml_str(s)?))
+}
+
+/// Parses the config file as `PartialConfig`, which has only the fields in the file.
+pub fn partial_from_toml_str(s: &str) -> Result<PartialConfig, ConfigError> {
+    let file: ConfigFile = toml::from_str(s).map_err(|e| ConfigError::Syntax(e.to_string()))?;
+    let file = migrate(file)?;
+    let mut config = PartialConfig::default();
+
+    if let Some(speed) = file.speed {
+        config.speed = Some(clamped_speed(&speed, &mut config.warnings)?);
+    }
+
+    if let Some(operations) = file.operations {
+        let (operations, weights) = parse_weighted_operations(&operations)?;
+        config.operations = Some(operations);
+        config.operation_weights = Some(weights);
+    }
+
+    if let Some(duration) = file.duration {
+        config.duration = Some(nonzero::parse(&duration).map_err(ConfigError::InvalidDuration)?);
+    }
+
+    if let Some(frequency) = file.frequency {
+        config.frequency =
+            Some(frequency::parse(&frequency).map_err(ConfigError::InvalidDuration)?);
+    }
+
+    if let Some(window) = file.active_window {
+        config.active_window =
+            Some(ActiveWindow::from_str(&window).map_err(ConfigError::InvalidWindow)?);
+    }
+
+    if let Some(e) = file.error_injection {
+        config.error_injection = Some(ErrorInjection {
+            probability: e.probability,
+            operations: parse_operations(&e.operations)?,
+            disk_full_after: match e.disk_full_after {
+                Some(ref s) => Some(parse_byte_size(s)?),
+                None => None,
+            },
+            errors: e
+                .errors
+                .iter()
+                .map(|e| WeightedError::from_str(e))
+                .collect::<Result<_, _>>()
+                .map_err(ConfigError::InvalidErrno)?,
+            continuous: e.continuous,
+            errors_per_window: e.errors_per_window,
+        });
+    }
+
+    if let Some(burst) = file.window_burst {
+        config.window_burst =
+            Some(WindowBurst::from_str(&burst).map_err(ConfigError::InvalidWindowBurst)?);
+    }
+
+    if let Some(bytes) = file.throttle_after_bytes {
+        config.throttle_after_bytes = Some(parse_byte_size(&bytes)? as u64);
+    }
+
+    if let Some(short_io) = file.short_io {
+        config.short_io = Some(ShortIo::from_str(&short_io).map_err(ConfigError::InvalidShortIo)?);
+    }
+
+    config.include_globs = file.include_globs;
+    config.exclude_globs = file.exclude_globs;
+
+    if let Some(size) = file.min_file_size {
+        config.min_file_size = Some(parse_byte_size(&size)? as u64);
+    }
+
+    if let Some(burst) = file.burst {
+        config.burst = Some(parse_byte_size(&burst)?);
+    }
+
+    if let Some(per_operation) = file.per_operation {
+        let mut speeds = HashMap::new();
+        for (op, speed) in per_operation {
+            let op = Operation::from_str(&op)?;
+            let speed = clamped_speed(&speed, &mut config.warnings)?;
+            speeds.insert(op, speed);
+        }
+        config.per_operation = Some(speeds);
+    }
+
+    if let Some(extension_speeds) = file.extension_speeds {
+        let mut speeds = HashMap::new();
+        for (ext, speed) in extension_speeds {
+            speeds.insert(ext, clamped_speed(&speed, &mut config.warnings)?);
+        }
+        config.extension_speeds = Some(speeds);
+    }
+
+    config.probability = file.probability;
+    config.seed = file.seed;
+    config.max_activations = file.max_activations;
+    config.window_operations = file.window_operations;
+
+    if let Some(baseline) = file.baseline {
+        config.baseline = Some(parse_byte_size(&baseline)?);
+    }
+
+    config.dry_run = file.dry_run;
+    config.passthrough_first_op = file.passthrough_first_op;
+    config.burst_start_fill = file.burst_start_fill;
+    config.speed_decay = file.speed_decay;
+    config.inverted = file.inverted;
+    config.share_among_handles = file.share_among_handles;
+    config.target = file.target;
+    config.trigger = file.trigger;
+
+    if let Some(profiles) = file.profiles {
+        config.profiles = Some(
+            profiles
+                .into_iter()
+                .map(|p| {
+                    Ok(Profile {
+                        speed: clamped_speed(&p.speed, &mut config.warnings)?,
+                        operations: parse_operations(&p.operations)?,
+                        duration: nonzero::parse(&p.duration)
+                            .map_err(ConfigError::InvalidDuration)?,
+                        frequency: frequency::parse(&p.frequency)
+                            .map_err(ConfigError::InvalidDuration)?,
+                    })
+                })
+                .collect::<Result<_, ConfigError>>()?,
+        );
+    }
+
+    if let Some(scope) = file.scope {
+        config.scope = Some(ThrottleScope::from_str(&scope).map_err(ConfigError::InvalidScope)?);
+    }
+
+    if let Some(speed_combine) = file.speed_combine {
+        config.speed_combine =
+            Some(SpeedCombine::from_str(&speed_combine).map_err(ConfigError::InvalidSpeed)?);
+    }
+
+    if let Some(ramp) = file.ramp {
+        config.ramp = Some(parse_duration(&ramp)?);
+    }
+
+    if let Some(window) = file.read_coalesce {
+        config.read_coalesce = Some(parse_duration(&window)?);
+    }
+
+    if let Some(d) = file.run_for {
+        config.run_for = Some(parse_duration(&d)?);
+    }
+
+    if let Some(d) = file.seek_penalty {
+        config.seek_penalty = Some(parse_duration(&d)?);
+    }
+
+    if let Some(d) = file.allocation_penalty {
+        config.allocation_penalty = Some(parse_duration(&d)?);
+    }
+
+    if let Some(speed) = file.append_speed {
+        config.append_speed = Some(clamped_speed(&speed, &mut config.warnings)?);
+    }
+
+    if let Some(d) = file.max_op_delay {
+        config.max_op_delay = Some(parse_duration(&d)?);
+    }
+
+    if let Some(d) = file.min_active {
+        config.min_active = Some(parse_duration(&d)?);
+    }
+
+    if let Some(d) = file.min_idle {
+        config.min_idle = Some(parse_duration(&d)?);
+    }
+
+    if let Some(d) = file.warmup {
+        config.warmup = Some(parse_duration(&d)?);
+    }
+
+    if let Some(jitter) = file.frequency_jitter {
+        config.frequency_jitter = Some(parse_duration(&jitter)?);
+    }
+
+    if let Some(windows) = file.script {
+        if config.duration.is_some() || config.frequency.is_some() {
+            return Err(ConfigError::Syntax(
+                "script cannot be combined with duration and frequency".to_string(),
+            ));
+        }
+        config.script = Some(
+            windows
+                .into_iter()
+                .map(|w| {
+                    Ok(ScriptWindow {
+                        offset: parse_duration(&w.offset)?,
+                        length: nonzero::parse(&w.length).map_err(ConfigError::InvalidDuration)?,
+                        speed: clamped_speed(&w.speed, &mut config.warnings)?,
+                    })
+                })
+                .collect::<Result<_, ConfigError>>()?,
+        );
+    }
+    config.script_repeat = file.script_repeat;
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::InjectedError;
+    use state::State;
+    use std::path::Path;
+    use std::time::Duration;
+
+    #[test]
+    fn test_from_toml_path() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/config.toml");
+        let config = Config::from_toml_path(&path).unwrap();
+
+        assert_eq!(Speed::Bps(1 << 20), config.speed);
+        assert_eq!(vec![Operation::Read, Operation::Write], config.operations);
+        assert_eq!(
+            Condition::Periodic {
+                duration: Duration::from_secs(10 * 60),
+                frequency: Duration::from_secs(30 * 60),
+            },
+            config.condition
+        );
+        assert_eq!(&Speed::Bps(64 << 10), config.speed_for(&Operation::Write));
+
+        assert!(Config::from_toml_path(Path::new("/not/found.toml")).is_err());
+    }
+
+    #[test]
+    fn test_from_toml_v1() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/config-v1.toml");
+        let config = Config::from_toml_path(&path).unwrap();
+        config.validate().unwrap();
+
+        assert_eq!(CONFIG_VERSION, config.version);
+        assert_eq!(Speed::Bps(1 << 20), config.speed);
+        assert_eq!(
+            vec![
+                Operation::Read,
+                Operation::Write,
+                Operation::Stat,
+                Operation::Open
+            ],
+            config.operations
+        );
+        assert_eq!(Some(42), config.seed);
+        assert_eq!(Some(4 << 20), config.burst);
+        assert_eq!(ThrottleScope::PerThread, config.scope);
+        assert_eq!(Some(WindowBurst::Bytes(64 << 20)), config.window_burst);
+        assert_eq!(1, config.profiles.len());
+        assert_eq!(&Speed::Bps(64 << 10), config.speed_for(&Operation::Write));
+        assert_eq!(
+            Some(100 << 20),
+            config.error_injection.and_then(|e| e.disk_full_after)
+        );
+    }
+
+    #[test]
+    fn test_from_toml_version() {
+        // The files without the version are of the current version.
+        assert_eq!(CONFIG_VERSION, from_toml_str("").unwrap().version);
+        assert_eq!(
+            CONFIG_VERSION,
+            from_toml_str(&format!("version = {}", CONFIG_VERSION))
+                .unwrap()
+                .version
+        );
+
+        for version in [0, CONFIG_VERSION + 1].iter() {
+            match from_toml_str(&format!("version = {}", version)) {
+                Err(ConfigError::UnsupportedVersion(ref e))
+                    if e.starts_with(&version.to_string()) => {}
+                r => panic!("unexpected result: {:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_toml_str_fallback() {
+        let config = from_toml_str("").unwrap();
+        let default = Config::default();
+        assert_eq!(default.speed, config.speed);
+        assert_eq!(default.operations, config.operations);
+        assert_eq!(default.condition, config.condition);
+        assert!(config.per_operation.is_empty());
+        assert_eq!(None, config.probability);
+
+        let config = from_toml_str("probability = 0.25").unwrap();
+        assert_eq!(Some(0.25), config.probability);
+
+        let config = from_toml_str("seed = 42").unwrap();
+        assert_eq!(Some(42), config.seed);
+
+        let config = from_toml_str(r#"frequency_jitter = "5m""#).unwrap();
+        assert_eq!(Duration::from_secs(5 * 60), config.frequency_jitter);
+
+        let config = from_toml_str("min_active = \"1m\"\nmin_idle = \"2m\"").unwrap();
+        assert_eq!(Duration::from_secs(60), config.min_active);
+        assert_eq!(Duration::from_secs(2 * 60), config.min_idle);
+
+        let config = from_toml_str(r#"warmup = "30s""#).unwrap();
+        assert_eq!(Duration::from_secs(30), config.warmup);
+
+        let config = from_toml_str(r#"operations = ["Read", "Write:3", "Stat:0"]"#).unwrap();
+        assert_eq!(
+            vec![Operation::Read, Operation::Write, Operation::Stat],
+            config.operations
+        );
+        assert_eq!(
+            vec![(Operation::Write, 3), (Operation::Stat, 0)]
+                .into_iter()
+                .collect::<HashMap<_, _>>(),
+            config.operation_weights
+        );
+
+        let config = from_toml_str(
+            r#"
+            [error_injection]
+            probability = 0.5
+            operations = ["Read", "write"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            Some(ErrorInjection {
+                probability: 0.5,
+                operations: vec![Operation::Read, Operation::Write],
+                disk_full_after: None,
+                errors: vec![],
+                continuous: false,
+                errors_per_window: None,
+            }),
+            config.error_injection
+        );
+
+        let config = from_toml_str(
+            r#"
+            [error_injection]
+            disk_full_after = "100MB"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            Some(ErrorInjection {
+                probability: 0.0,
+                operations: vec![],
+                disk_full_after: Some(100 << 20),
+                errors: vec![],
+                continuous: false,
+                errors_per_window: None,
+            }),
+            config.error_injection
+        );
+
+        let config = from_toml_str(
+            r#"
+            [error_injection]
+            probability = 0.5
+            operations = ["Read"]
+            errors = ["EIO", "eagain:3"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                WeightedError::from(InjectedError::Eio),
+                WeightedError {
+                    error: InjectedError::Eagain,
+                    weight: 3,
+                },
+            ],
+            config.error_injection.unwrap().errors
+        );
+
+        let config = from_toml_str(
+            r#"
+            [error_injection]
+            operations = ["Read"]
+            errors_per_window = 3
+            "#,
+        )
+        .unwrap();
+        assert_eq!(Some(3), config.error_injection.unwrap().errors_per_window);
+
+        let config = from_toml_str(r#"short_io = "0.5""#).unwrap();
+        assert_eq!(Some(ShortIo::Fraction(0.5)), config.short_io);
+
+        let config = from_toml_str(
+            r#"
+            include_globs = ["data/**"]
+            exclude_globs = ["**/*.log"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(vec!["data/**".to_string()], config.include_globs);
+        assert_eq!(vec!["**/*.log".to_string()], config.exclude_globs);
+
+        let config = from_toml_str(r#"min_file_size = "1MB""#).unwrap();
+        assert_eq!(Some(1 << 20), config.min_file_size);
+
+        let config = from_toml_str(
+            r#"
+            [[profiles]]
+            speed = "1KBps"
+            operations = ["Read"]
+            duration = "1m"
+            frequency = "10m"
+
+            [[profiles]]
+            speed = "2KBps"
+            operations = ["Write"]
+            duration = "2m"
+            frequency = "20m"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                Profile {
+                    speed: Speed::Bps(1024),
+                    operations: vec![Operation::Read],
+                    duration: Duration::from_secs(60),
+                    frequency: Duration::from_secs(10 * 60),
+                },
+                Profile {
+                    speed: Speed::Bps(2048),
+                    operations: vec![Operation::Write],
+                    duration: Duration::from_secs(2 * 60),
+                    frequency: Duration::from_secs(20 * 60),
+                },
+            ],
+            config.profiles
+        );
+        assert_eq!(Config::default().condition, config.condition);
+
+        let config = from_toml_str(r#"burst = "4MB""#).unwrap();
+        assert_eq!(Some(4 << 20), config.burst);
+
+        let config = from_toml_str("burst_start_fill = 0.25").unwrap();
+        assert_eq!(0.25, config.burst_start_fill);
+
+        let config = from_toml_str("max_activations = 1").unwrap();
+        assert_eq!(Some(1), config.max_activations);
+
+        let config = from_toml_str("window_operations = 1000").unwrap();
+        assert_eq!(Some(1000), config.window_operations);
+
+        let config = from_toml_str(
+            r#"
+            speed = "10%"
+            baseline = "200MBps"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(Speed::Percent(10), config.speed);
+        assert_eq!(Some(200 << 20), config.baseline);
+
+        let config = from_toml_str(r#"ramp = "30s""#).unwrap();
+        assert_eq!(Some(Duration::from_secs(30)), config.ramp);
+
+        let config = from_toml_str("dry_run = true").unwrap();
+        assert!(config.dry_run);
+
+        let config = from_toml_str("passthrough_first_op = true").unwrap();
+        assert!(config.passthrough_first_op);
+
+        let config = from_toml_str("speed_decay = 0.5").unwrap();
+        assert_eq!(0.5, config.speed_decay);
+
+        let config = from_toml_str("inverted = true").unwrap();
+        assert!(config.inverted);
+
+        let config = from_toml_str(r#"window_burst = "30s""#).unwrap();
+        assert_eq!(
+            Some(WindowBurst::Duration(Duration::from_secs(30))),
+            config.window_burst
+        );
+
+        let config = from_toml_str(r#"throttle_after_bytes = "64MB""#).unwrap();
+        assert_eq!(Some(64 << 20), config.throttle_after_bytes);
+        assert_eq!(
+            config,
+            from_toml_str(&to_toml_string(&config).unwrap()).unwrap()
+        );
+        let config =
+            from_toml_str("window_burst = \"30s\"\nthrottle_after_bytes = \"64MB\"").unwrap();
+        match config.validate() {
+            Err(ConfigError::ValidationFailed(ref e)) if e.contains("throttle_after_bytes") => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        let config = from_toml_str(r#"read_coalesce = "10ms""#).unwrap();
+        assert_eq!(Some(Duration::from_millis(10)), config.read_coalesce);
+
+        let config = from_toml_str(r#"run_for = "2h""#).unwrap();
+        assert_eq!(Some(Duration::from_secs(2 * 60 * 60)), config.run_for);
+
+        let config = from_toml_str(r#"seek_penalty = "8ms""#).unwrap();
+        assert_eq!(Some(Duration::from_millis(8)), config.seek_penalty);
+
+        let config = from_toml_str(r#"allocation_penalty = "4ms""#).unwrap();
+        assert_eq!(Some(Duration::from_millis(4)), config.allocation_penalty);
+
+        let config = from_toml_str(r#"append_speed = "PassThrough""#).unwrap();
+        assert_eq!(Some(Speed::PassThrough), config.append_speed);
+
+        let config = from_toml_str(r#"max_op_delay = "30s""#).unwrap();
+        assert_eq!(Some(Duration::from_secs(30)), config.max_op_delay);
+
+        let config = from_toml_str("share_among_handles = true").unwrap();
+        assert!(config.share_among_handles);
+
+        let config = from_toml_str(r#"target = "/mnt/slow""#).unwrap();
+        assert_eq!(Some(PathBuf::from("/mnt/slow")), config.target);
+
+        let config = from_toml_str(
+            r#"
+            [trigger]
+            path = "/tmp/mizumochi-slow"
+            idle_when_absent = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            Some(Trigger {
+                idle_when_absent: true,
+                ..Trigger::new("/tmp/mizumochi-slow")
+            }),
+            config.trigger
+        );
+
+        let config = from_toml_str("[extension_speeds]\ndb = \"16KBps\"\nLOG = \"1MBps\"").unwrap();
+        assert_eq!(
+            Some(&Speed::Bps(16 << 10)),
+            config.extension_speeds.get("db")
+        );
+        assert_eq!(
+            Some(&Speed::Bps(1 << 20)),
+            config.extension_speeds.get("LOG")
+        );
+
+        let config = from_toml_str(r#"scope = "per_thread""#).unwrap();
+        assert_eq!(ThrottleScope::PerThread, config.scope);
+        match from_toml_str(r#"scope = "per_process""#) {
+            Err(ConfigError::InvalidScope(_)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        let config = from_toml_str(r#"speed_combine = "most_restrictive""#).unwrap();
+        assert_eq!(SpeedCombine::MostRestrictive, config.speed_combine);
+        match from_toml_str(r#"speed_combine = "slowest""#) {
+            Err(ConfigError::InvalidSpeed(_)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        let config = from_toml_str(r#"active_window = "22:00-02:00""#).unwrap();
+        assert_eq!(
+            Some(ActiveWindow {
+                start: 22 * 60 * 60,
+                end: 2 * 60 * 60,
+            }),
+            config.active_window
+        );
+
+        let config = from_toml_str(r#"frequency = "1h""#).unwrap();
+        assert_eq!(default.speed, config.speed);
+        assert_eq!(
+            Condition::Periodic {
+                duration: Duration::from_secs(10 * 60),
+                frequency: Duration::from_secs(60 * 60),
+            },
+            config.condition
+        );
+
+        // The rate is stored as the interval.
+        let config = from_toml_str(r#"frequency = "2/h""#).unwrap();
+        assert_eq!(
+            Condition::Periodic {
+                duration: Duration::from_secs(10 * 60),
+                frequency: Duration::from_secs(30 * 60),
+            },
+            config.condition
+        );
+        assert!(config
+            .to_toml_string()
+            .unwrap()
+            .contains(r#"frequency = "30m""#));
+        match from_toml_str(r#"frequency = "0/h""#) {
+            Err(ConfigError::InvalidDuration(_)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_default_template() {
+        let template = default_template();
+        assert_eq!(Config::default(), from_toml_str(&template).unwrap());
+        // All keys are commented.
+        for line in to_toml_string(&Config::default()).unwrap().lines() {
+            if let Some(i) = line.find(" = ") {
+                assert!(
+                    TEMPLATE_KEYS.iter().any(|&(key, _, _)| key == &line[..i]),
+                    "{}",
+                    line
+                );
+            }
+        }
+
+        // The examples are valid too.
+        let uncommented = template
+            .lines()
+            .map(|l| match l.strip_prefix("# ") {
+                Some(l) if l.contains(" = ") || l.starts_with('[') => l,
+                _ => l,
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let config = from_toml_str(&uncommented).unwrap();
+        assert_eq!(Some(42), config.seed);
+        assert_eq!(Some(Duration::from_secs(30)), config.max_op_delay);
+        assert_eq!(
+            Some(&Speed::Bps(64 << 10)),
+            config.per_operation.get(&Operation::Write)
+        );
+        assert_eq!(
+            Some(&Speed::Bps(16 << 10)),
+            config.extension_speeds.get("db")
+        );
+        assert_eq!(0.1, config.error_injection.unwrap().probability);
+        assert_eq!(Some(Trigger::new("/tmp/mizumochi-slow")), config.trigger);
+    }
+
+    #[test]
+    fn test_to_toml_string() {
+        let mut config = Config {
+            speed: Speed::Range {
+                min: 1 << 10,
+                max: 1 << 20,
+            },
+            operations: vec![Operation::Read, Operation::Stat],
+            condition: Condition::Periodic {
+                duration: Duration::from_millis(1500),
+                frequency: Duration::from_secs(60 * 60),
+            },
+            probability: Some(0.5),
+            seed: Some(42),
+            frequency_jitter: Duration::from_secs(5 * 60),
+            active_window: Some(ActiveWindow::from_str("09:00-17:30:15").unwrap()),
+            error_injection: Some(ErrorInjection {
+                probability: 0.1,
+                operations: vec![Operation::Write],
+                disk_full_after: Some(100 << 20),
+                errors: vec![
+                    InjectedError::Eio.into(),
+                    WeightedError::from_str("EAGAIN:3").unwrap(),
+                ],
+                continuous: true,
+                errors_per_window: Some(3),
+            }),
+            short_io: Some(ShortIo::Random),
+            include_globs: vec!["data/**".to_string()],
+            min_file_size: Some(1 << 20),
+            burst: Some(4 << 20),
+            burst_start_fill: 0.5,
+            max_activations: Some(3),
+            window_operations: Some(1000),
+            baseline: Some(200 << 20),
+            ramp: Some(Duration::from_secs(30)),
+            scope: ThrottleScope::PerThread,
+            speed_combine: SpeedCombine::MostRestrictive,
+            dry_run: true,
+            passthrough_first_op: true,
+            min_active: Duration::from_secs(60),
+            warmup: Duration::from_secs(10),
+            speed_decay: 0.5,
+            inverted: true,
+            window_burst: Some(WindowBurst::Bytes(64 << 20)),
+            read_coalesce: Some(Duration::from_millis(10)),
+            run_for: Some(Duration::from_secs(2 * 60 * 60)),
+            seek_penalty: Some(Duration::from_millis(8)),
+            allocation_penalty: Some(Duration::from_millis(4)),
+            append_speed: Some(Speed::Bps(1 << 20)),
+            max_op_delay: Some(Duration::from_secs(30)),
+            share_among_handles: true,
+            target: Some(PathBuf::from("/mnt/slow")),
+            trigger: Some(Trigger {
+                poll_interval: Duration::from_millis(100),
+                idle_when_absent: true,
+                ..Trigger::new("/tmp/mizumochi-slow")
+            }),
+            profiles: vec![Profile {
+                speed: Speed::Bps(64 << 10),
+                operations: vec![Operation::Fsync],
+                duration: Duration::from_secs(60),
+                frequency: Duration::from_secs(15 * 60),
+            }],
+            ..Default::default()
+        };
+        config
+            .per_operation
+            .insert(Operation::Write, Speed::Bps(64 << 10));
+        config
+            .per_operation
+            .insert(Operation::Read, Speed::Iops(100));
+        config
+            .extension_speeds
+            .insert("db".to_string(), Speed::Bps(16 << 10));
+        config.operation_weights.insert(Operation::Stat, 3);
+
+        let s = config.to_toml_string().unwrap();
+        assert_eq!(config, from_toml_str(&s).unwrap(), "{}", s);
+        // The default is written out too.
+        let s = Config::default().to_toml_string().unwrap();
+        assert_eq!(Config::default(), from_toml_str(&s).unwrap(), "{}", s);
+        // The output is canonical.
+        assert_eq!(s, from_toml_str(&s).unwrap().to_toml_string().unwrap());
+
+        let config = Config {
+            condition: Condition::Always(State::Unstable),
+            ..Default::default()
+        };
+        assert!(config.to_toml_string().is_err());
+    }
+
+    #[test]
+    fn test_script() {
+        let config = from_toml_str(
+            r#"
+            script_repeat = true
+
+            [[script]]
+            offset = "10s"
+            length = "5s"
+            speed = "1KBps"
+
+            [[script]]
+            offset = "30s"
+            length = "10s"
+            speed = "latency:50ms"
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(
+            Condition::Script {
+                windows: vec![
+                    ScriptWindow {
+                        offset: Duration::from_secs(10),
+                        length: Duration::from_secs(5),
+                        speed: Speed::Bps(1024),
+                    },
+                    ScriptWindow {
+                        offset: Duration::from_secs(30),
+                        length: Duration::from_secs(10),
+                        speed: Speed::Latency(Duration::from_millis(50)),
+                    },
+                ],
+                repeat: true,
+            },
+            config.condition
+        );
+
+        // It is written back to the same config.
+        let s = config.to_toml_string().unwrap();
+        assert_eq!(config, from_toml_str(&s).unwrap(), "{}", s);
+
+        // It does not repeat by default.
+        let config =
+            from_toml_str("[[script]]\noffset = \"0s\"\nlength = \"1s\"\nspeed = \"1KBps\"")
+                .unwrap();
+        assert!(matches!(
+            config.condition,
+            Condition::Script { repeat: false, .. }
+        ));
+
+        // It replaces the periodic condition, so they are exclusive.
+        let s =
+            "duration = \"1m\"\n[[script]]\noffset = \"0s\"\nlength = \"1s\"\nspeed = \"1KBps\"";
+        match from_toml_str(s) {
+            Err(ConfigError::Syntax(ref e)) if e.contains("script") => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        let s = "[[script]]\noffset = \"0s\"\nlength = \"0s\"\nspeed = \"1KBps\"";
+        assert!(from_toml_str(s).is_err());
+    }
+
+    #[test]
+    fn test_clamped_speed() {
+        let partial = partial_from_toml_str(
+            "speed = \"99999999999GBps\"\nappend_speed = \"1MBps\"\n[extension_speeds]\nlog = \"1048576PBps\"",
+        )
+        .unwrap();
+        assert_eq!(Some(Speed::Bps(u64::MAX)), partial.speed);
+        assert_eq!(Some(Speed::Bps(1 << 20)), partial.append_speed);
+        assert_eq!(
+            Some(&Speed::Bps(u64::MAX)),
+            partial.extension_speeds.as_ref().unwrap().get("log")
+        );
+        assert_eq!(2, partial.warnings.len());
+        assert!(partial.warnings[0].contains("99999999999GBps"));
+        assert!(partial.warnings[1].contains("1048576PBps"));
+
+        assert!(partial_from_toml_str("speed = \"1MBps\"")
+            .unwrap()
+            .warnings
+            .is_empty());
+    }
+
+    #[test]
+    fn test_from_toml_str_error() {
+        match from_toml_str("speed = ") {
+            Err(ConfigError::Syntax(_)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match from_toml_str(r#"speed = "fast""#) {
+            Err(ConfigError::InvalidSpeed(_)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match from_toml_str(r#"duration = "10x""#) {
+            Err(ConfigError::InvalidDuration(_)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match from_toml_str(r#"frequency = "0s""#) {
+            Err(ConfigError::InvalidDuration(ref e)) if e.contains("non-zero duration") => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match from_toml_str(r#"operations = ["Read", "Delete"]"#) {
+            Err(ConfigError::InvalidOperation(_)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match from_toml_str(r#"operations = ["Read:-1"]"#) {
+            Err(ConfigError::InvalidOperation(ref e)) if e.contains("Read:-1") => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match from_toml_str("[error_injection]\nerrors = [\"EIO\", \"EOOPS\"]") {
+            Err(ConfigError::InvalidErrno(_)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use super::duration::format_duration;
+use super::{Condition, Config, Speed};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// `FmtOptions` selects the units in which `Config::format_with` writes the durations and the speeds.
+/// `FmtOptions::default()` writes the same as `Display` of `Config`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FmtOptions {
+    pub durations: DurationUnit,
+    // `None` means that each byte rate is written in the largest unit below it (e.g., "1.5MBps").
+    pub speed_unit: Option<SpeedUnit>,
+}
+
+/// The unit of the durations in the formatted config.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DurationUnit {
+    // The largest unit which represents the duration exactly (e.g., "10m").
+    #[default]
+    Human,
+    // Always in seconds (e.g., "600s" and "1.5s").
+    Seconds,
+}
+
+/// The unit to which all byte rates in the formatted config are forced.
+/// The units are binary (i.e., 1KBps is 1024Bps) as same as `Speed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpeedUnit {
+    B,
+    KB,
+    MB,
+    GB,
+    TB,
+    PB,
+}
+
+impl FromStr for DurationUnit {
+    type Err = String;
+
+    /// Parses "human" or "seconds" as `DurationUnit`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(DurationUnit::Human),
+            "seconds" => Ok(DurationUnit::Seconds),
+            _ => Err(format!("duration unit accepts human or seconds: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for DurationUnit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DurationUnit::Human => write!(f, "human"),
+            DurationUnit::Seconds => write!(f, "seconds"),
+        }
+    }
+}
+
+impl FromStr for SpeedUnit {
+    type Err = String;
+
+    /// Parses the unit with or without "ps" (e.g., "MB" and "MBps").
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_suffix("ps").unwrap_or(s) {
+            "B" => Ok(SpeedUnit::B),
+            "KB" => Ok(SpeedUnit::KB),
+            "MB" => Ok(SpeedUnit::MB),
+            "GB" => Ok(SpeedUnit::GB),
+            "TB" => Ok(SpeedUnit::TB),
+            "PB" => Ok(SpeedUnit::PB),
+            _ => Err(format!("unknown speed unit: {}", s)),
+        }
+    }
+}
+
+/// Formats the unit as the suffix of the speeds (e.g., "MBps").
+impl fmt::Display for SpeedUnit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.suffix())
+    }
+}
+
+impl SpeedUnit {
+    fn scale(self) -> u64 {
+        match self {
+            SpeedUnit::B => 1,
+            SpeedUnit::KB => 1 << 10,
+            SpeedUnit::MB => 1 << 20,
+            SpeedUnit::GB => 1 << 30,
+            SpeedUnit::TB => 1 << 40,
+            SpeedUnit::PB => 1 << 50,
+        }
+    }
+
+    fn suffix(self) -> &'static str {
+        match self {
+            SpeedUnit::B => "Bps",
+            SpeedUnit::KB => "KBps",
+            SpeedUnit::MB => "MBps",
+            SpeedUnit::GB => "GBps",
+            SpeedUnit::TB => "TBps",
+            SpeedUnit::PB => "PBps",
+        }
+    }
+}
+
+impl Config {
+    /// Formats the config like `Display` but in the units of `opts`.
+    ///
+    /// # Examples
+    /// ```
+    /// use mizumochi::config::{Config, DurationUnit, FmtOptions, Speed, SpeedUnit};
+    ///
+    /// let config = Config {
+    ///     speed: Speed::Bps(1 << 20),
+    ///     ..Default::default()
+    /// };
+    /// let opts = FmtOptions {
+    ///     durations: DurationUnit::Seconds,
+    ///     speed_unit: Some(SpeedUnit::KB),
+    /// };
+    /// assert_eq!(
+    ///     "config {speed: 1024KBps, operations: Read:Write, condition: Periodic {duration: 600s, frequency: 1800s}, per_operation: {}}",
+    ///     config.format_with(opts)
+    /// );
+    /// ```
+    pub fn format_with(&self, opts: FmtOptions) -> String {
+        let ops = self
+            .operations
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>()
+            .join(":");
+        let mut per_operation = self
+            .per_operation
+            .iter()
+            .map(|(op, speed)| format!("{}: {}", op, speed_with(speed, opts)))
+            .collect::<Vec<_>>();
+        per_operation.sort();
+
+        format!(
+            "config {{speed: {}, operations: {}, condition: {}, per_operation: {{{}}}}}",
+            speed_with(&self.speed, opts),
+            ops,
+            condition_with(&self.condition, opts),
+            per_operation.join(", ")
+        )
+    }
+}
+
+fn duration_with(d: &Duration, opts: FmtOptions) -> String {
+    match opts.durations {
+        DurationUnit::Human => format_duration(d),
+        DurationUnit::Seconds => {
+            // Truncate below milliseconds as same as `format_duration`.
+            let millis = d.as_secs() as f64 * 1000.0 + f64::from(d.subsec_millis());
+            format!("{}s", millis / 1000.0)
+        }
+    }
+}
+
+fn bps_with(bps: u64, opts: FmtOptions) -> String {
+    match opts.speed_unit {
+        Some(unit) => format!("{}{}", bps as f64 / unit.scale() as f64, unit.suffix()),
+        None => Speed::Bps(bps).to_string(),
+    }
+}
+
+fn speed_with(speed: &Speed, opts: FmtOptions) -> String {
+    match *speed {
+        Speed::Bps(bps) => bps_with(bps, opts),
+        Speed::Range { min, max } => format!("{}..{}", bps_with(min, opts), bps_with(max, opts)),
+        Speed::Latency(ref d) => format!("latency:{}", duration_with(d, opts)),
+        Speed::LatencyRange { ref min, ref max } => format!(
+            "latency:{}..{}",
+            duration_with(min, opts),
+            duration_with(max, opts)
+        ),
+        Speed::ExpLatency(ref d) => format!("latency:exp:{}", duration_with(d, opts)),
+        Speed::PerByte(d) => match Speed::from_per_byte(d) {
+            Speed::Bps(bps) => bps_with(bps, opts),
+            _ => format!("{}/B", duration_with(&d, opts)),
+        },
+        Speed::BpsWithLatency { bps, ref latency } => {
+            format!("{}+{}", bps_with(bps, opts), duration_with(latency, opts))
+        }
+        ref speed => speed.to_string(),
+    }
+}
+
+fn condition_with(condition: &Condition, opts: FmtOptions) -> String {
+    match *condition {
+        Condition::Periodic {
+            ref duration,
+            ref frequency,
+        } => format!(
+            "Periodic {{duration: {}, frequency: {}}}",
+            duration_with(duration, opts),
+            duration_with(frequency, opts)
+        ),
+        ref condition => condition.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::Operation;
+
+    fn config() -> Config {
+        let mut config = Config {
+            speed: Speed::Range {
+                min: 512 << 10,
+                max: 3 << 19,
+            },
+            condition: Condition::Periodic {
+                duration: Duration::from_millis(1500),
+                frequency: Duration::from_secs(60 * 60),
+            },
+            ..Default::default()
+        };
+        config.per_operation.insert(
+            Operation::Write,
+            Speed::BpsWithLatency {
+                bps: 1 << 30,
+                latency: Duration::from_secs(2 * 60),
+            },
+        );
+        config
+    }
+
+    #[test]
+    fn test_format_with_default() {
+        let config = config();
+        assert_eq!(
+            config.to_string(),
+            config.format_with(FmtOptions::default())
+        );
+        assert_eq!(
+            "config {speed: 512KBps..1.5MBps, operations: Read:Write, condition: Periodic {duration: 1500ms, frequency: 1h}, per_operation: {Write: 1GBps+2m}}",
+            config.format_with(FmtOptions::default())
+        );
+    }
+
+    #[test]
+    fn test_format_with_units() {
+        let config = config();
+        assert_eq!(
+            "config {speed: 0.5MBps..1.5MBps, operations: Read:Write, condition: Periodic {duration: 1.5s, frequency: 3600s}, per_operation: {Write: 1024MBps+120s}}",
+            config.format_with(FmtOptions {
+                durations: DurationUnit::Seconds,
+                speed_unit: Some(SpeedUnit::MB),
+            })
+        );
+        // The human durations with the forced unit.
+        assert_eq!(
+            "config {speed: 524288Bps..1572864Bps, operations: Read:Write, condition: Periodic {duration: 1500ms, frequency: 1h}, per_operation: {Write: 1073741824Bps+2m}}",
+            config.format_with(FmtOptions {
+                speed_unit: Some(SpeedUnit::B),
+                ..Default::default()
+            })
+        );
+        // The other speeds are not affected.
+        let config = Config {
+            speed: Speed::Iops(100),
+            ..Default::default()
+        };
+        assert!(config
+            .format_with(FmtOptions {
+                speed_unit: Some(SpeedUnit::KB),
+                ..Default::default()
+            })
+            .starts_with("config {speed: 100iops,"));
+    }
+
+    #[test]
+    fn test_units_from_str() {
+        assert_eq!(Ok(DurationUnit::Seconds), DurationUnit::from_str("seconds"));
+        assert!(DurationUnit::from_str("minutes").is_err());
+        for unit in [DurationUnit::Human, DurationUnit::Seconds].iter() {
+            assert_eq!(Ok(*unit), DurationUnit::from_str(&unit.to_string()));
+        }
+
+        assert_eq!(Ok(SpeedUnit::MB), SpeedUnit::from_str("MB"));
+        assert_eq!(Ok(SpeedUnit::MB), SpeedUnit::from_str("MBps"));
+        assert!(SpeedUnit::from_str("Mbps").is_err());
+        assert!(SpeedUnit::from_str("ps").is_err());
+        let units = [
+            SpeedUnit::B,
+            SpeedUnit::KB,
+            SpeedUnit::MB,
+            SpeedUnit::GB,
+            SpeedUnit::TB,
+            SpeedUnit::PB,
+        ];
+        for unit in units.iter() {
+            assert_eq!(Ok(*unit), SpeedUnit::from_str(&unit.to_string()));
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use libc;
+use std::fmt;
+use std::os::raw::c_int;
+use std::str::FromStr;
+
+/// `InjectedError` is the errno which the operations fail with.
+/// They are POSIX and available on Linux and macOS, but the numbers differ between the platforms
+/// (e.g., EAGAIN is 11 on Linux and 35 on macOS), so they are given by the names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum InjectedError {
+    Eio,
+    Eacces,
+    Eagain,
+    Ebusy,
+    Eintr,
+    Enospc,
+    Eperm,
+    Erofs,
+    Etimedout,
+}
+
+const ALL: [InjectedError; 9] = [
+    InjectedError::Eio,
+    InjectedError::Eacces,
+    InjectedError::Eagain,
+    InjectedError::Ebusy,
+    InjectedError::Eintr,
+    InjectedError::Enospc,
+    InjectedError::Eperm,
+    InjectedError::Erofs,
+    InjectedError::Etimedout,
+];
+
+impl InjectedError {
+    /// Returns the errno of the platform.
+    pub fn code(self) -> c_int {
+        match self {
+            InjectedError::Eio => libc::EIO,
+            InjectedError::Eacces => libc::EACCES,
+            InjectedError::Eagain => libc::EAGAIN,
+            InjectedError::Ebusy => libc::EBUSY,
+            InjectedError::Eintr => libc::EINTR,
+            InjectedError::Enospc => libc::ENOSPC,
+            InjectedError::Eperm => libc::EPERM,
+            InjectedError::Erofs => libc::EROFS,
+            InjectedError::Etimedout => libc::ETIMEDOUT,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            InjectedError::Eio => "EIO",
+            InjectedError::Eacces => "EACCES",
+            InjectedError::Eagain => "EAGAIN",
+            InjectedError::Ebusy => "EBUSY",
+            InjectedError::Eintr => "EINTR",
+            InjectedError::Enospc => "ENOSPC",
+            InjectedError::Eperm => "EPERM",
+            InjectedError::Erofs => "EROFS",
+            InjectedError::Etimedout => "ETIMEDOUT",
+        }
+    }
+}
+
+impl FromStr for InjectedError {
+    type Err = String;
+
+    /// Parses the name of the errno like "EIO" case-insensitively.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ALL.iter()
+            .find(|e| e.name().eq_ignore_ascii_case(s))
+            .cloned()
+            .ok_or_else(|| format!("unsupported errno: {}", s))
+    }
+}
+
+impl fmt::Display for InjectedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// `WeightedError` is a candidate of the errors to inject, which is chosen in proportion to `weight`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WeightedError {
+    pub error: InjectedError,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+impl From<InjectedError> for WeightedError {
+    fn from(error: InjectedError) -> WeightedError {
+        WeightedError {
+            error,
+            weight: default_weight(),
+        }
+    }
+}
+
+impl FromStr for WeightedError {
+    type Err = String;
+
+    /// Parses "EAGAIN" (of which weight is one) or "EAGAIN:3".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (error, weight) = match s.find(':') {
+            Some(i) => (
+                &s[..i],
+                s[i + 1..]
+                    .parse()
+                    .map_err(|e| format!("invalid weight: {}: {}", e, s))?,
+            ),
+            None => (s, default_weight()),
+        };
+
+        Ok(WeightedError {
+            error: error.parse()?,
+            weight,
+        })
+    }
+}
+
+impl fmt::Display for WeightedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.weight == default_weight() {
+            write!(f, "{}", self.error)
+        } else {
+            write!(f, "{}:{}", self.error, self.weight)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_injected_error_from_str() {
+        for e in ALL.iter() {
+            assert_eq!(Ok(*e), InjectedError::from_str(&e.to_string()));
+        }
+        assert_eq!(Ok(InjectedError::Eagain), InjectedError::from_str("eagain"));
+        assert_eq!(libc::ETIMEDOUT, InjectedError::Etimedout.code());
+        assert!(InjectedError::from_str("EWHATEVER").is_err());
+        assert!(InjectedError::from_str("5").is_err());
+
+        assert_eq!(
+            Ok(WeightedError {
+                error: InjectedError::Eagain,
+                weight: 3,
+            }),
+            WeightedError::from_str("EAGAIN:3")
+        );
+        assert_eq!(
+            Ok(WeightedError::from(InjectedError::Eio)),
+            WeightedError::from_str("EIO")
+        );
+        assert!(WeightedError::from_str("EIO:heavy").is_err());
+        for s in ["EIO", "EAGAIN:3"].iter() {
+            assert_eq!(*s, WeightedError::from_str(s).unwrap().to_string());
+        }
+    }
+}
@@ -1,42 +1,1738 @@
+use serde_json;
+use slog::Logger;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-pub use self::condition::Condition;
-pub use self::operation::Operation;
-pub use self::speed::Speed;
+pub use self::builder::ConfigBuilder;
+pub use self::combine::SpeedCombine;
+pub use self::condition::{Condition, ScriptWindow};
+pub use self::diff::ConfigDiff;
+pub use self::duration::{format_duration, format_rate, parse_duration, parse_frequency};
+pub use self::error::ConfigError;
+pub use self::error_injection::ErrorInjection;
+pub use self::format::{DurationUnit, FmtOptions, SpeedUnit};
+pub use self::injected_error::{InjectedError, WeightedError};
+pub use self::operation::{parse_operations, Operation};
+pub use self::partial::PartialConfig;
+pub use self::path_filter::PathFilter;
+pub use self::profile::Profile;
+pub use self::scope::ThrottleScope;
+pub use self::short_io::ShortIo;
+pub use self::snapshot::ConfigSnapshot;
+pub use self::speed::{parse_byte_size, Speed};
+pub use self::trigger::Trigger;
+pub use self::window::ActiveWindow;
+pub use self::window_burst::WindowBurst;
 
+mod builder;
+mod combine;
 mod condition;
+mod diff;
+mod duration;
+mod env;
+mod error;
+mod error_injection;
+mod file;
+mod format;
+mod injected_error;
 mod operation;
+mod partial;
+mod path_filter;
+mod profile;
+mod scope;
+mod short_io;
+mod snapshot;
 mod speed;
+mod trigger;
+mod window;
+mod window_burst;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// The version of the schema of `Config` and the config files.
+/// It is bumped only by the incompatible changes, and the config files of the older versions are migrated
+/// to the current one when they are loaded (see `Config::partial_from_toml_path`).
+pub const CONFIG_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Config {
+    // The version of the schema, which is `CONFIG_VERSION` if omitted.
+    #[serde(default = "default_version")]
+    pub version: u32,
     pub speed: Speed,
+    // The empty operations disable the throttling (i.e., all operations pass through as if no unstable periods).
+    // The unknown ones (e.g., of a newer version) are skipped in deserializing (see `Config::from_json_str`).
+    #[serde(deserialize_with = "operation::deserialize_known")]
     pub operations: Vec<Operation>,
+    // The weights of the operations in `operations` which are throttled partly.
+    // At the beginning of each unstable period, each weighted operation is chosen independently with the probability
+    // of its share of the total weight (e.g., 3 of a total 4 is 75%), and the ones not chosen are not throttled
+    // in the period. Any subset of them can be throttled together. The operations without weights are always throttled.
+    #[serde(default)]
+    pub operation_weights: HashMap<Operation, u32>,
     pub condition: Condition,
+    // The speeds override `speed` for the specific operations.
+    // The map is reloaded along with the whole config, so the lookups never see a partial update (see `Engine`).
+    #[serde(default)]
+    pub per_operation: HashMap<Operation, Speed>,
+    // The speeds override `per_operation` and `speed` for the files of the extensions (e.g., "db" for "a.DB"),
+    // which are matched case-insensitively and without the leading dot. The files are selected by the globs
+    // before, and the unstable profiles take precedence over them as over `per_operation`.
+    #[serde(default)]
+    pub extension_speeds: HashMap<String, Speed>,
+    // The probability (0.0 to 1.0) that an unstable period of `Condition::Periodic` actually slows down the operations.
+    // `None` means 1.0 (i.e., every unstable period slows down).
+    #[serde(default)]
+    pub probability: Option<f64>,
+    // The seed of the RNG to make the random decisions reproducible.
+    // `None` means the RNG is seeded with entropy.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    // Each stable period of `Condition::Periodic` lasts randomly within `frequency ± frequency_jitter`.
+    // Zero means the frequency is fixed.
+    #[serde(default, with = "self::duration::human")]
+    pub frequency_jitter: Duration,
+    // The operations are not throttled out of the window regardless of the condition.
+    // `None` means that there is no restriction.
+    #[serde(default)]
+    pub active_window: Option<ActiveWindow>,
+    // `None` means that no operations fail intentionally.
+    #[serde(default)]
+    pub error_injection: Option<ErrorInjection>,
+    // Reads and writes transfer fewer bytes than requested during unstable periods.
+    // It can be combined with `speed` and `error_injection`.
+    #[serde(default)]
+    pub short_io: Option<ShortIo>,
+    // The glob patterns to select the files to throttle by the paths relative to the original directory.
+    // The empty `include_globs` selects all files and `exclude_globs` takes precedence over `include_globs`.
+    #[serde(default)]
+    pub include_globs: Vec<String>,
+    #[serde(default)]
+    pub exclude_globs: Vec<String>,
+    // Only the files of this size in bytes or larger are throttled.
+    // `None` means that all files are throttled regardless of their sizes.
+    #[serde(default)]
+    pub min_file_size: Option<u64>,
+    // The byte rates are limited by token buckets of this capacity in bytes if set.
+    // A burst up to the capacity passes immediately whereas the rest proceeds at the rate.
+    // `None` keeps the legacy throttle, which sleeps for each operation in proportion to its size.
+    #[serde(default)]
+    pub burst: Option<usize>,
+    // The fraction of `burst` which the token buckets hold at the start of each unstable period, e.g., 0.0 starts
+    // them empty so that the first operations are throttled right away instead of passing as a free burst after
+    // the idle stable period. 1.0 starts them full. `window_burst` passes its budget before the buckets.
+    #[serde(default = "default_burst_start_fill")]
+    pub burst_start_fill: f64,
+    // The unstable periods of `Condition::Periodic` stop after this number of them engage if set.
+    // `None` repeats them forever.
+    #[serde(default)]
+    pub max_activations: Option<u32>,
+    // Each unstable period (of `Condition::Periodic` or `Condition::Script`) ends early once this number of the
+    // target operations are serviced in it, i.e., the next one begins the stable period, so that a window lasts
+    // "for the next N operations" or its duration, whichever is shorter. `None` ends them by the duration only.
+    #[serde(default)]
+    pub window_operations: Option<u64>,
+    // The throughput of the underlying device in bytes per second which `Speed::Percent` is relative to.
+    // `None` means that it is measured at startup (see `calibrate`), and set it for reproducible tests.
+    #[serde(default)]
+    pub baseline: Option<usize>,
+    // The speed ramps from `PassThrough` down to `speed` over this duration at the start of each unstable period.
+    // `None` drops the speed at once.
+    #[serde(default, with = "self::duration::human_option")]
+    pub ramp: Option<Duration>,
+    // Whether the threads share the token buckets or each thread has its own buckets.
+    #[serde(default)]
+    pub scope: ThrottleScope,
+    // Whether `per_operation` and `extension_speeds` override the more general speeds or the slowest of them applies.
+    #[serde(default)]
+    pub speed_combine: SpeedCombine,
+    // The delays, the errors and the short I/O are only logged and never applied.
+    #[serde(default)]
+    pub dry_run: bool,
+    // The first operation on each opened file handle is never delayed (e.g., a stat right after an open).
+    #[serde(default)]
+    pub passthrough_first_op: bool,
+    // The hysteresis of `Condition::Periodic`: an unstable period lasts `min_active` at least and
+    // a stable period lasts `min_idle` at least even if `duration` or the jittered frequency is shorter.
+    // Zero means no hysteresis.
+    #[serde(default, with = "self::duration::human")]
+    pub min_active: Duration,
+    #[serde(default, with = "self::duration::human")]
+    pub min_idle: Duration,
+    // The first stable period of `Condition::Periodic` (and the profiles) is extended by `warmup` so that
+    // the first unstable period begins at `warmup + frequency` after the start. Zero means no warmup.
+    #[serde(default, with = "self::duration::human")]
+    pub warmup: Duration,
+    // The N-th unstable period (from zero) throttles at the speed scaled by `speed_decay^N` to model
+    // the degrading (below 1.0) or recovering (above 1.0) devices. 1.0 is the constant speed.
+    // It does not apply to the profiles.
+    #[serde(default = "default_speed_decay")]
+    pub speed_decay: f64,
+    // The stable and the unstable periods of `condition` are swapped: the operations are throttled out of
+    // the unstable periods and pass through in them (e.g., to test the recovery of the normally slow device).
+    // It does not apply to the profiles.
+    #[serde(default)]
+    pub inverted: bool,
+    // The beginning of each unstable period which passes through before the speed applies.
+    // It is for the byte rates of `speed` and `per_operation` (not for the profiles). `None` means no burst.
+    #[serde(default)]
+    pub window_burst: Option<WindowBurst>,
+    // The bytes read/written at the beginning of each unstable period which pass through before the speed applies
+    // (e.g., a cache which fills up), i.e., `window_burst` of the bytes. It cannot be combined with `window_burst`.
+    #[serde(default)]
+    pub throttle_after_bytes: Option<u64>,
+    // The reads on the same file handle which continue the previous read (e.g., by the read-ahead of the kernel)
+    // within this duration after the first one completes (i.e., after its delay) are charged as one transfer:
+    // only the first one waits, and the bytes of the others are owed to the following transfers.
+    // `None` charges every read.
+    #[serde(default, with = "self::duration::human_option")]
+    pub read_coalesce: Option<Duration>,
+    // The total time to run from `Engine::init`, after which the engine shuts down (i.e., the operations pass through)
+    // and `Mizumochi::mount` unmounts to exit. `None` runs until unmounted.
+    #[serde(default, with = "self::duration::human_option")]
+    pub run_for: Option<Duration>,
+    // The extra delay of each random access, i.e., a read/write on a file handle which does not begin at the end
+    // of the last one on the handle, while the operation is throttled. The sequential accesses pay only the speed.
+    // `None` never penalizes the seeks.
+    #[serde(default, with = "self::duration::human_option")]
+    pub seek_penalty: Option<Duration>,
+    // The extra delay of each allocating write, i.e., a write on a file handle which ends beyond the high-water
+    // offset of the handle (the size of the file when opened, raised by the writes since), while the operation is
+    // throttled. It models the cost of allocating the new regions (e.g., of a sparse file), so the overwrites
+    // in place pay only the speed. `None` never penalizes the allocations.
+    #[serde(default, with = "self::duration::human_option")]
+    pub allocation_penalty: Option<Duration>,
+    // The speed of the appends, i.e., the writes on a file handle which begin at its high-water offset (as
+    // `allocation_penalty` tracks), which overrides `speed`, `per_operation` and `extension_speeds` for them
+    // (e.g., to throttle the logs of a workload differently from its random writes).
+    // `Speed::PassThrough` exempts the appends. `None` throttles them as the other writes.
+    #[serde(default)]
+    pub append_speed: Option<Speed>,
+    // The cap of each delay of an operation (e.g., a large write under a very low byte rate), which keeps
+    // the operation from tripping the timeouts of the clients. The bytes beyond the cap are still owed by the
+    // bucket or the debt, so the throughput is slowed overall. `None` caps the delays only at `engine::MAX_OP_DELAY`,
+    // which caps the longer ones too.
+    #[serde(default, with = "self::duration::human_option")]
+    pub max_op_delay: Option<Duration>,
+    // The speed is divided among the opened file handles (i.e., `Engine::on_opened` and not yet released)
+    // to model the contention, e.g., each of 4 readers gets a quarter of the rate. The latencies are not divided.
+    #[serde(default)]
+    pub share_among_handles: bool,
+    // The mountpoint of `Mizumochi`, which the command line argument overrides, to set up the mount from
+    // the config alone. It is ignored by `Engine` and the throttled adapters. `None` requires the argument.
+    #[serde(default)]
+    pub target: Option<PathBuf>,
+    // The file which forces the unstable period at `speed` while it exists (see `Trigger`).
+    // `None` follows the schedule only.
+    #[serde(default)]
+    pub trigger: Option<Trigger>,
+    // The extra slowdowns which have their own cycles independent of `condition`.
+    // An operation is throttled at the speed of the first unstable profile of it, then at `speed` by `condition`.
+    #[serde(default)]
+    pub profiles: Vec<Profile>,
+}
+
+impl Config {
+    /// Creates the config of `Condition::Periodic` with the defaults for the other fields, and validates it.
+    /// It is recommended over the struct literal, which is not validated until the engine uses it.
+    ///
+    /// # Examples
+    /// ```
+    /// use mizumochi::config::{Config, Operation, Speed};
+    /// use std::time::Duration;
+    ///
+    /// let config = Config::new(
+    ///     Duration::from_secs(60),
+    ///     Duration::from_secs(10 * 60),
+    ///     vec![Operation::Read],
+    ///     Speed::Bps(1 << 20),
+    /// )
+    /// .unwrap();
+    /// assert_eq!(Speed::Bps(1 << 20), config.speed);
+    ///
+    /// // The unstable period is longer than the cycle.
+    /// assert!(Config::new(
+    ///     Duration::from_secs(60),
+    ///     Duration::from_secs(30),
+    ///     vec![Operation::Read],
+    ///     Speed::Bps(1 << 20),
+    /// )
+    /// .is_err());
+    /// ```
+    pub fn new(
+        duration: Duration,
+        frequency: Duration,
+        operations: Vec<Operation>,
+        speed: Speed,
+    ) -> Result<Config, ConfigError> {
+        let mut config = Config {
+            speed,
+            operations,
+            condition: Condition::Periodic {
+                duration,
+                frequency,
+            },
+            ..Default::default()
+        };
+        config.canonicalize();
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Loads config from the given TOML file.
+    /// The missing fields fall back to `Config::default()`.
+    pub fn from_toml_path(path: &Path) -> Result<Config, ConfigError> {
+        let s = fs::read_to_string(path)?;
+        file::from_toml_str(&s)
+    }
+
+    /// Loads only the fields in the given TOML file to merge it with the other sources.
+    pub fn partial_from_toml_path(path: &Path) -> Result<PartialConfig, ConfigError> {
+        let s = fs::read_to_string(path)?;
+        file::partial_from_toml_str(&s)
+    }
+
+    /// Writes the config as a TOML document which `from_toml_path` reads back to the same config.
+    /// The durations are in milliseconds at most, and it fails for `Condition::Always`.
+    pub fn to_toml_string(&self) -> Result<String, ConfigError> {
+        file::to_toml_string(self)
+    }
+
+    /// Returns the TOML document of `Config::default()` with the comments of all keys, which is a starter
+    /// of the config files. The keys which are unset by default are commented out with examples.
+    pub fn default_template() -> String {
+        file::default_template()
+    }
+
+    /// Returns the config as a JSON object in a line, which `serde_json` reads back to the same config.
+    /// The keys are sorted so that the same config is always the same string.
+    pub fn to_summary_json(&self) -> String {
+        // `Value` sorts the keys including the ones of the maps.
+        let value = serde_json::to_value(self).expect("Config is always serializable");
+        value.to_string()
+    }
+
+    /// Reads the config from the JSON as `to_summary_json` writes (e.g., of `PUT /config`), and canonicalizes
+    /// and validates it. The unknown operations are skipped with a warning each instead of failing the whole config.
+    pub fn from_json_str(s: &str, logger: &Logger) -> Result<Config, ConfigError> {
+        let syntax = |e: serde_json::Error| ConfigError::Syntax(e.to_string());
+        let value: serde_json::Value = serde_json::from_str(s).map_err(syntax)?;
+        for op in operation::unknown_operations(&value["operations"]) {
+            warn!(logger, "skipped the unknown operation: {}", op);
+        }
+
+        let mut config: Config = serde_json::from_value(value).map_err(syntax)?;
+        config.canonicalize();
+        config.validate()?;
+        for warning in config.warnings() {
+            warn!(logger, "{}", warning);
+        }
+        Ok(config)
+    }
+
+    /// Loads config from the environment variables below.
+    /// The unset variables fall back to `Config::default()`.
+    ///
+    /// - `MIZUMOCHI_SPEED` (e.g., "512KBps")
+    /// - `MIZUMOCHI_OPERATIONS` separated by commas (e.g., "Read,Write")
+    /// - `MIZUMOCHI_DURATION` and `MIZUMOCHI_FREQUENCY` of `Condition::Periodic` (e.g., "10m")
+    /// - `MIZUMOCHI_TARGET` of the mountpoint (e.g., "/mnt/slow")
+    pub fn from_env() -> Result<Config, ConfigError> {
+        Ok(Config::merge(Config::default(), env::from_env()?))
+    }
+
+    /// Same as `Config::from_env` but yields only the fields of which variables are set.
+    pub fn partial_from_env() -> Result<PartialConfig, ConfigError> {
+        env::from_env()
+    }
+
+    /// Checks the config is acceptable.
+    ///
+    /// The following configs are rejected:
+    /// - `version` is not `CONFIG_VERSION` (the config files are migrated before).
+    /// - `duration` is equal to or longer than `frequency` in `Condition::Periodic`.
+    /// - `frequency_jitter` is equal to or longer than `frequency` in `Condition::Periodic`.
+    /// - `Condition::Script` has no windows, or the windows overlap, touch or are out of the order of the offsets.
+    ///   The first one has to begin after the start if `repeat` so that the cycles are separated too.
+    /// - `operation_weights` has an operation which is not in `operations`, or the weights are all zero.
+    /// - `Speed::Bps(0)` (or a range including zero) is given because it would stall the operations forever
+    ///   (see `Speed::is_stalled`).
+    /// - `Speed::Percent` is zero or more than 100.
+    /// - `Speed::PerByte` is zero.
+    /// - `Speed::Range` or `Speed::LatencyRange` is inverted (i.e., `min` is faster or longer than `max`).
+    /// - `probability` is out of `[0, 1]`.
+    /// - `active_window` is empty or out of a day.
+    /// - `probability` of `error_injection` is out of `[0, 1]`.
+    /// - `error_injection` has neither `operations` nor `disk_full_after`.
+    /// - The weights of `errors` of `error_injection` are all zero.
+    /// - `errors_per_window` of `error_injection` is zero.
+    /// - The fraction of `short_io` is out of `(0, 1]`.
+    /// - `include_globs` or `exclude_globs` has an invalid pattern.
+    /// - `burst` is zero.
+    /// - `burst_start_fill` is out of `[0, 1]`.
+    /// - `max_activations` is zero.
+    /// - `window_operations` is zero.
+    /// - `window_burst` is zero.
+    /// - `throttle_after_bytes` is zero or combined with `window_burst`.
+    /// - `read_coalesce` is zero.
+    /// - `run_for` is zero.
+    /// - `seek_penalty` is zero.
+    /// - `allocation_penalty` is zero.
+    /// - `max_op_delay` is zero.
+    /// - `target` is empty.
+    /// - The path of `trigger` is empty or its `poll_interval` is zero.
+    /// - An extension of `extension_speeds` is empty or the same as another one ignoring the case.
+    /// - `speed_decay` is not positive or not finite.
+    /// - A profile has the invalid cycle, the empty operations or the speed above.
+    ///
+    /// The other combinations of the speeds are legal: each operation is throttled by its own speed
+    /// (e.g., `Speed::Iops` for `Stat` in `per_operation` along with `Speed::Bps` for the others),
+    /// and the unstable profiles of an operation take precedence over `speed` and `per_operation`.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.version != CONFIG_VERSION {
+            return Err(ConfigError::UnsupportedVersion(format!(
+                "{} (the supported version is {})",
+                self.version, CONFIG_VERSION
+            )));
+        }
+
+        if let Condition::Periodic {
+            ref duration,
+            ref frequency,
+        } = self.condition
+        {
+            if frequency <= duration {
+                return Err(ConfigError::ValidationFailed(format!(
+                    "duration ({:?}) has to be shorter than frequency ({:?})",
+                    duration, frequency
+                )));
+            }
+            if *frequency <= self.frequency_jitter {
+                return Err(ConfigError::ValidationFailed(format!(
+                    "frequency_jitter ({:?}) has to be shorter than frequency ({:?})",
+                    self.frequency_jitter, frequency
+                )));
+            }
+        }
+
+        if let Condition::Script {
+            ref windows,
+            repeat,
+        } = self.condition
+        {
+            if windows.is_empty() {
+                return Err(ConfigError::ValidationFailed(
+                    "windows of the script are empty".to_string(),
+                ));
+            }
+            if repeat && windows[0].offset == Duration::from_secs(0) {
+                return Err(ConfigError::ValidationFailed(format!(
+                    "the first window of the repeated script ({}) has to begin after the start",
+                    windows[0]
+                )));
+            }
+            if let Some(w) = windows.windows(2).find(|w| w[1].offset <= w[0].end()) {
+                return Err(ConfigError::ValidationFailed(format!(
+                    "window of the script ({}) has to begin after the end of the last one ({})",
+                    w[1], w[0]
+                )));
+            }
+        }
+
+        for profile in &self.profiles {
+            if profile.frequency <= profile.duration {
+                return Err(ConfigError::ValidationFailed(format!(
+                    "duration of profile ({}) has to be shorter than frequency",
+                    profile
+                )));
+            }
+            if profile.operations.is_empty() {
+                return Err(ConfigError::ValidationFailed(format!(
+                    "operations of profile ({}) is empty",
+                    profile
+                )));
+            }
+        }
+
+        if let Some(op) = self
+            .operation_weights
+            .keys()
+            .find(|op| !self.operations.contains(op))
+        {
+            return Err(ConfigError::ValidationFailed(format!(
+                "weighted operation {} is not in operations",
+                op
+            )));
+        }
+        if !self.operation_weights.is_empty() && self.operation_weights.values().all(|w| *w == 0) {
+            return Err(ConfigError::ValidationFailed(
+                "weights of operations are all zero".to_string(),
+            ));
+        }
+
+        for speed in self.speeds() {
+            match *speed {
+                _ if speed.is_stalled() => {
+                    return Err(ConfigError::ValidationFailed(format!(
+                        "speed {} stalls the operations",
+                        speed
+                    )));
+                }
+                Speed::Percent(p) if 100 < p => {
+                    return Err(ConfigError::ValidationFailed(format!(
+                        "speed {} exceeds the baseline",
+                        speed
+                    )));
+                }
+                Speed::PerByte(d) if d == Duration::from_secs(0) => {
+                    return Err(ConfigError::ValidationFailed(format!(
+                        "speed {} has to take time per byte",
+                        speed
+                    )));
+                }
+                Speed::Range { min, max } if max < min => {
+                    return Err(ConfigError::ValidationFailed(format!(
+                        "speed {} has the inverted range",
+                        speed
+                    )));
+                }
+                Speed::LatencyRange { min, max } if max < min => {
+                    return Err(ConfigError::ValidationFailed(format!(
+                        "speed {} has the inverted range",
+                        speed
+                    )));
+                }
+                _ => {}
+            }
+        }
+
+        if self.baseline == Some(0) {
+            return Err(ConfigError::ValidationFailed(
+                "baseline has to be positive".to_string(),
+            ));
+        }
+
+        if let Some(p) = self.probability {
+            if !(0.0..=1.0).contains(&p) {
+                return Err(ConfigError::ValidationFailed(format!(
+                    "probability ({}) has to be in [0, 1]",
+                    p
+                )));
+            }
+        }
+
+        if let Some(w) = self.active_window {
+            if w.start == w.end
+                || window::SECONDS_PER_DAY <= w.start
+                || window::SECONDS_PER_DAY <= w.end
+            {
+                return Err(ConfigError::ValidationFailed(format!(
+                    "active_window ({:?}) is invalid",
+                    w
+                )));
+            }
+        }
+
+        if let Some(ref e) = self.error_injection {
+            if !(0.0..=1.0).contains(&e.probability) {
+                return Err(ConfigError::ValidationFailed(format!(
+                    "probability of error_injection ({}) has to be in [0, 1]",
+                    e.probability
+                )));
+            }
+            if e.operations.is_empty() && e.disk_full_after.is_none() {
+                return Err(ConfigError::ValidationFailed(
+                    "error_injection injects nothing".to_string(),
+                ));
+            }
+            if !e.errors.is_empty() && e.errors.iter().all(|e| e.weight == 0) {
+                return Err(ConfigError::ValidationFailed(
+                    "weights of errors of error_injection are all zero".to_string(),
+                ));
+            }
+            if e.errors_per_window == Some(0) {
+                return Err(ConfigError::ValidationFailed(
+                    "errors_per_window of error_injection has to be positive".to_string(),
+                ));
+            }
+        }
+
+        if let Some(ShortIo::Fraction(x)) = self.short_io {
+            if !(0.0 < x && x <= 1.0) {
+                return Err(ConfigError::ValidationFailed(format!(
+                    "fraction of short_io ({}) has to be in (0, 1]",
+                    x
+                )));
+            }
+        }
+
+        self.path_filter()?;
+
+        if self.burst == Some(0) {
+            return Err(ConfigError::ValidationFailed(
+                "burst has to be positive".to_string(),
+            ));
+        }
+
+        if !(0.0 <= self.burst_start_fill && self.burst_start_fill <= 1.0) {
+            return Err(ConfigError::ValidationFailed(format!(
+                "burst_start_fill ({}) has to be in [0, 1]",
+                self.burst_start_fill
+            )));
+        }
+
+        if self.max_activations == Some(0) {
+            return Err(ConfigError::ValidationFailed(
+                "max_activations has to be positive".to_string(),
+            ));
+        }
+
+        if self.window_operations == Some(0) {
+            return Err(ConfigError::ValidationFailed(
+                "window_operations has to be positive".to_string(),
+            ));
+        }
+
+        match self.window_burst {
+            Some(WindowBurst::Bytes(0)) => {
+                return Err(ConfigError::ValidationFailed(
+                    "window_burst has to be positive".to_string(),
+                ))
+            }
+            Some(WindowBurst::Duration(d)) if d == Duration::from_secs(0) => {
+                return Err(ConfigError::ValidationFailed(
+                    "window_burst has to be positive".to_string(),
+                ))
+            }
+            _ => {}
+        }
+
+        match (self.throttle_after_bytes, self.window_burst) {
+            (Some(0), _) => {
+                return Err(ConfigError::ValidationFailed(
+                    "throttle_after_bytes has to be positive".to_string(),
+                ))
+            }
+            (Some(_), Some(_)) => {
+                return Err(ConfigError::ValidationFailed(
+                    "throttle_after_bytes cannot be combined with window_burst".to_string(),
+                ))
+            }
+            _ => {}
+        }
+
+        if self.read_coalesce == Some(Duration::from_secs(0)) {
+            return Err(ConfigError::ValidationFailed(
+                "read_coalesce has to be positive".to_string(),
+            ));
+        }
+
+        if self.run_for == Some(Duration::from_secs(0)) {
+            return Err(ConfigError::ValidationFailed(
+                "run_for has to be positive".to_string(),
+            ));
+        }
+
+        if self.seek_penalty == Some(Duration::from_secs(0)) {
+            return Err(ConfigError::ValidationFailed(
+                "seek_penalty has to be positive".to_string(),
+            ));
+        }
+
+        if self.allocation_penalty == Some(Duration::from_secs(0)) {
+            return Err(ConfigError::ValidationFailed(
+                "allocation_penalty has to be positive".to_string(),
+            ));
+        }
+
+        if self.max_op_delay == Some(Duration::from_secs(0)) {
+            return Err(ConfigError::ValidationFailed(
+                "max_op_delay has to be positive".to_string(),
+            ));
+        }
+
+        if self
+            .target
+            .as_ref()
+            .is_some_and(|target| target.as_os_str().is_empty())
+        {
+            return Err(ConfigError::ValidationFailed(
+                "target has to be non-empty".to_string(),
+            ));
+        }
+
+        if let Some(ref trigger) = self.trigger {
+            if trigger.path.as_os_str().is_empty() {
+                return Err(ConfigError::ValidationFailed(
+                    "path of trigger has to be non-empty".to_string(),
+                ));
+            }
+            if trigger.poll_interval == Duration::from_secs(0) {
+                return Err(ConfigError::ValidationFailed(
+                    "poll_interval of trigger has to be positive".to_string(),
+                ));
+            }
+        }
+
+        let mut extensions = HashSet::new();
+        for ext in self.extension_speeds.keys() {
+            let normalized = ext.trim_start_matches('.').to_lowercase();
+            if normalized.is_empty() {
+                return Err(ConfigError::ValidationFailed(format!(
+                    "extension {:?} of extension_speeds is empty",
+                    ext
+                )));
+            }
+            if !extensions.insert(normalized) {
+                return Err(ConfigError::ValidationFailed(format!(
+                    "extension {} of extension_speeds is given twice",
+                    ext
+                )));
+            }
+        }
+
+        if !(self.speed_decay.is_finite() && 0.0 < self.speed_decay) {
+            return Err(ConfigError::ValidationFailed(format!(
+                "speed_decay ({}) has to be positive and finite",
+                self.speed_decay
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the warnings of the legal but ineffective combinations:
+    ///
+    /// - `burst` is set but none of the speeds (see `speeds`) is a byte rate, which the buckets limit.
+    /// - `baseline` is set but none of the speeds is `Speed::Percent`, which is relative to it.
+    ///
+    /// They are not errors since a speed can be set to a byte rate or a percentage later (e.g., by the control socket).
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if let Some(burst) = self.burst {
+            if !self
+                .speeds()
+                .any(|s| s.bytes_per_sec().is_some() || matches!(*s, Speed::Percent(_)))
+            {
+                warnings.push(format!(
+                    "burst ({}) has no byte rate to limit: none of speed, per_operation, extension_speeds, \
+                     the script windows, append_speed and the profiles is a byte rate",
+                    burst
+                ));
+            }
+        }
+        if let Some(baseline) = self.baseline {
+            if !self.speeds().any(|s| matches!(*s, Speed::Percent(_))) {
+                warnings.push(format!(
+                    "baseline ({}) is unused: none of speed, per_operation, extension_speeds, \
+                     the script windows, append_speed and the profiles is a percentage",
+                    baseline
+                ));
+            }
+        }
+        warnings
+    }
+
+    /// Returns all speeds which can apply: `speed`, `per_operation`, `extension_speeds`, the windows of
+    /// `Condition::Script`, `append_speed` and the profiles.
+    pub fn speeds<'a>(&'a self) -> impl Iterator<Item = &'a Speed> + 'a {
+        Some(&self.speed)
+            .into_iter()
+            .chain(self.per_operation.values())
+            .chain(self.extension_speeds.values())
+            .chain(self.condition.script_windows().iter().map(|w| &w.speed))
+            .chain(self.append_speed.iter())
+            .chain(self.profiles.iter().map(|p| &p.speed))
+    }
+
+    /// Compiles `include_globs` and `exclude_globs`.
+    pub fn path_filter(&self) -> Result<PathFilter, ConfigError> {
+        PathFilter::new(&self.include_globs, &self.exclude_globs)
+            .map_err(ConfigError::ValidationFailed)
+    }
+
+    /// Returns the speed for the given operation.
+    /// The speed in `per_operation` is used if exists (or the slower of it and `speed` by `speed_combine`),
+    /// otherwise `speed` is used.
+    pub fn speed_for(&self, op: &Operation) -> &Speed {
+        self.per_operation
+            .get(op)
+            .map_or(&self.speed, |s| self.speed_combine.combine(&self.speed, s))
+    }
+
+    /// Returns the speed for the given operation on the file of the extension (e.g., "db").
+    /// The speed in `extension_speeds` is used if exists (or the slower of it and the one of `speed_for`
+    /// by `speed_combine`), otherwise the one of `speed_for` is used.
+    pub fn speed_for_extension(&self, op: &Operation, extension: Option<&str>) -> &Speed {
+        extension
+            .and_then(|ext| {
+                self.extension_speeds
+                    .iter()
+                    .find(|(k, _)| k.trim_start_matches('.').eq_ignore_ascii_case(ext))
+            })
+            .map_or_else(
+                || self.speed_for(op),
+                |(_, s)| self.speed_combine.combine(self.speed_for(op), s),
+            )
+    }
+
+    /// Removes the repeated operations in `operations` (and in `error_injection`).
+    /// The first occurrences are kept in the order.
+    pub fn canonicalize(&mut self) {
+        dedup_operations(&mut self.operations);
+        if let Some(ref mut e) = self.error_injection {
+            dedup_operations(&mut e.operations);
+        }
+    }
+}
+
+fn default_version() -> u32 {
+    CONFIG_VERSION
+}
+
+fn default_burst_start_fill() -> f64 {
+    1.0
+}
+
+fn default_speed_decay() -> f64 {
+    1.0
+}
+
+fn dedup_operations(operations: &mut Vec<Operation>) {
+    let mut seen = HashSet::new();
+    operations.retain(|op| seen.insert(op.clone()));
 }
 
 impl Default for Config {
     fn default() -> Config {
         Config {
+            version: CONFIG_VERSION,
             speed: Speed::PassThrough,
             operations: vec![Operation::Read, Operation::Write],
+            operation_weights: HashMap::new(),
             condition: Condition::default_periodic(),
+            per_operation: HashMap::new(),
+            extension_speeds: HashMap::new(),
+            probability: None,
+            seed: None,
+            frequency_jitter: Duration::from_secs(0),
+            active_window: None,
+            error_injection: None,
+            short_io: None,
+            include_globs: Vec::new(),
+            exclude_globs: Vec::new(),
+            min_file_size: None,
+            burst: None,
+            burst_start_fill: default_burst_start_fill(),
+            max_activations: None,
+            window_operations: None,
+            baseline: None,
+            ramp: None,
+            scope: ThrottleScope::Global,
+            speed_combine: SpeedCombine::LastWins,
+            dry_run: false,
+            passthrough_first_op: false,
+            min_active: Duration::from_secs(0),
+            min_idle: Duration::from_secs(0),
+            warmup: Duration::from_secs(0),
+            speed_decay: default_speed_decay(),
+            inverted: false,
+            window_burst: None,
+            throttle_after_bytes: None,
+            read_coalesce: None,
+            run_for: None,
+            seek_penalty: None,
+            allocation_penalty: None,
+            append_speed: None,
+            max_op_delay: None,
+            share_among_handles: false,
+            target: None,
+            trigger: None,
+            profiles: Vec::new(),
         }
     }
 }
 
 impl fmt::Display for Config {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        let ops = self
-            .operations
-            .iter()
-            .map(|x| x.to_string())
-            .collect::<Vec<_>>()
-            .join(":");
-        write!(
-            fmt,
-            "config {{speed: {}, operations: {}, condition: {:?}}}",
-            ops, self.speed, self.condition
+        fmt.write_str(&self.format_with(FmtOptions::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+    use state::State;
+
+    #[test]
+    fn test_config_serde_with_metadata_operations() {
+        let config = Config {
+            operations: vec![Operation::Read, Operation::Readdir],
+            ..Default::default()
+        };
+
+        let json = serde_json::to_string(&config).unwrap();
+        let decoded: Config = serde_json::from_str(&json).unwrap();
+        assert_eq!(config.operations, decoded.operations);
+        assert_eq!(config.to_string(), decoded.to_string());
+
+        // The default is kept to throttle read and write only.
+        assert_eq!(
+            vec![Operation::Read, Operation::Write],
+            Config::default().operations
+        );
+    }
+
+    #[test]
+    fn test_config_serde_target() {
+        let config = Config {
+            target: Some(PathBuf::from("/mnt/slow")),
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(json.contains(r#""target":"/mnt/slow""#), "{}", json);
+        let decoded: Config = serde_json::from_str(&json).unwrap();
+        assert_eq!(config, decoded);
+        assert!(decoded.validate().is_ok());
+
+        // It is optional.
+        let mut value = serde_json::to_value(Config::default()).unwrap();
+        value.as_object_mut().unwrap().remove("target");
+        let decoded: Config = serde_json::from_value(value).unwrap();
+        assert_eq!(None, decoded.target);
+    }
+
+    #[test]
+    fn test_config_serde_version() {
+        let mut value = serde_json::to_value(Config::default()).unwrap();
+        assert_eq!(Some(CONFIG_VERSION as u64), value["version"].as_u64());
+
+        // The JSON which omits the version is of the current version.
+        value.as_object_mut().unwrap().remove("version");
+        let decoded: Config = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(CONFIG_VERSION, decoded.version);
+
+        value["version"] = (CONFIG_VERSION + 1).into();
+        let decoded: Config = serde_json::from_value(value).unwrap();
+        match decoded.validate() {
+            Err(ConfigError::UnsupportedVersion(_)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_config_validate() {
+        assert!(Config::default().validate().is_ok());
+
+        let config = Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Always(State::Unstable),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        for p in [0.0, 0.5, 1.0].iter() {
+            let config = Config {
+                probability: Some(*p),
+                ..Default::default()
+            };
+            assert!(config.validate().is_ok());
+        }
+
+        // No operations are throttled.
+        let config = Config {
+            speed: Speed::Bps(1024),
+            operations: vec![],
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        let profile = Profile {
+            speed: Speed::Bps(1024),
+            operations: vec![Operation::Read],
+            duration: Duration::from_secs(10),
+            frequency: Duration::from_secs(30),
+        };
+        let config = Config {
+            profiles: vec![profile.clone()],
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        let window = |offset, length| ScriptWindow {
+            offset: Duration::from_secs(offset),
+            length: Duration::from_secs(length),
+            speed: Speed::Bps(1024),
+        };
+        let script = |windows, repeat| Config {
+            condition: Condition::Script { windows, repeat },
+            ..Default::default()
+        };
+        assert!(script(vec![window(0, 10), window(20, 10)], false)
+            .validate()
+            .is_ok());
+        assert!(script(vec![window(10, 10), window(30, 10)], true)
+            .validate()
+            .is_ok());
+
+        let invalid_configs = vec![
+            script(vec![], false),
+            // Overlapping, touching and out of the order.
+            script(vec![window(0, 10), window(5, 10)], false),
+            script(vec![window(0, 10), window(10, 10)], false),
+            script(vec![window(20, 10), window(0, 10)], false),
+            // The cycles touch.
+            script(vec![window(0, 10), window(20, 10)], true),
+            Config {
+                condition: Condition::Script {
+                    windows: vec![ScriptWindow {
+                        speed: Speed::Bps(0),
+                        ..window(0, 10)
+                    }],
+                    repeat: false,
+                },
+                ..Default::default()
+            },
+            Config {
+                profiles: vec![Profile {
+                    duration: Duration::from_secs(30),
+                    ..profile.clone()
+                }],
+                ..Default::default()
+            },
+            Config {
+                profiles: vec![Profile {
+                    operations: vec![],
+                    ..profile.clone()
+                }],
+                ..Default::default()
+            },
+            Config {
+                profiles: vec![Profile {
+                    speed: Speed::Bps(0),
+                    ..profile.clone()
+                }],
+                ..Default::default()
+            },
+            Config {
+                condition: Condition::Periodic {
+                    duration: Duration::from_secs(30),
+                    frequency: Duration::from_secs(30),
+                },
+                ..Default::default()
+            },
+            Config {
+                condition: Condition::Periodic {
+                    duration: Duration::from_secs(60),
+                    frequency: Duration::from_secs(30),
+                },
+                ..Default::default()
+            },
+            Config {
+                speed: Speed::Bps(0),
+                ..Default::default()
+            },
+            Config {
+                speed: Speed::Iops(0),
+                ..Default::default()
+            },
+            Config {
+                append_speed: Some(Speed::Bps(0)),
+                ..Default::default()
+            },
+            Config {
+                speed: Speed::Range { min: 0, max: 1024 },
+                ..Default::default()
+            },
+            Config {
+                per_operation: vec![(Operation::Write, Speed::Bps(0))]
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            },
+            Config {
+                frequency_jitter: Duration::from_secs(30 * 60),
+                ..Default::default()
+            },
+            Config {
+                operation_weights: vec![(Operation::Stat, 1)].into_iter().collect(),
+                ..Default::default()
+            },
+            Config {
+                operation_weights: vec![(Operation::Read, 0), (Operation::Write, 0)]
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            },
+            Config {
+                active_window: Some(ActiveWindow { start: 60, end: 60 }),
+                ..Default::default()
+            },
+            Config {
+                active_window: Some(ActiveWindow {
+                    start: 0,
+                    end: 24 * 60 * 60,
+                }),
+                ..Default::default()
+            },
+            Config {
+                error_injection: Some(ErrorInjection {
+                    probability: 1.0,
+                    operations: vec![Operation::Read],
+                    disk_full_after: None,
+                    errors: vec![WeightedError {
+                        error: InjectedError::Eagain,
+                        weight: 0,
+                    }],
+                    continuous: false,
+                    errors_per_window: None,
+                }),
+                ..Default::default()
+            },
+            Config {
+                error_injection: Some(ErrorInjection {
+                    probability: 0.0,
+                    operations: vec![Operation::Read],
+                    disk_full_after: None,
+                    errors: vec![],
+                    continuous: false,
+                    errors_per_window: Some(0),
+                }),
+                ..Default::default()
+            },
+            Config {
+                error_injection: Some(ErrorInjection {
+                    probability: 1.1,
+                    operations: vec![Operation::Read],
+                    disk_full_after: None,
+                    errors: vec![],
+                    continuous: false,
+                    errors_per_window: None,
+                }),
+                ..Default::default()
+            },
+            Config {
+                error_injection: Some(ErrorInjection {
+                    probability: 0.5,
+                    operations: vec![],
+                    disk_full_after: None,
+                    errors: vec![],
+                    continuous: false,
+                    errors_per_window: None,
+                }),
+                ..Default::default()
+            },
+            Config {
+                short_io: Some(ShortIo::Fraction(0.0)),
+                ..Default::default()
+            },
+            Config {
+                short_io: Some(ShortIo::Fraction(1.5)),
+                ..Default::default()
+            },
+            Config {
+                include_globs: vec!["data/[".to_string()],
+                ..Default::default()
+            },
+            Config {
+                exclude_globs: vec!["***".to_string()],
+                ..Default::default()
+            },
+            Config {
+                burst: Some(0),
+                ..Default::default()
+            },
+            Config {
+                burst_start_fill: -0.5,
+                ..Default::default()
+            },
+            Config {
+                burst_start_fill: f64::NAN,
+                ..Default::default()
+            },
+            Config {
+                max_activations: Some(0),
+                ..Default::default()
+            },
+            Config {
+                window_operations: Some(0),
+                ..Default::default()
+            },
+            Config {
+                speed: Speed::Percent(0),
+                ..Default::default()
+            },
+            Config {
+                speed: Speed::Percent(101),
+                ..Default::default()
+            },
+            Config {
+                baseline: Some(0),
+                ..Default::default()
+            },
+            Config {
+                probability: Some(1.5),
+                ..Default::default()
+            },
+            Config {
+                probability: Some(-0.1),
+                ..Default::default()
+            },
+            Config {
+                probability: Some(f64::NAN),
+                ..Default::default()
+            },
+            Config {
+                speed_decay: 0.0,
+                ..Default::default()
+            },
+            Config {
+                window_burst: Some(WindowBurst::Bytes(0)),
+                ..Default::default()
+            },
+            Config {
+                window_burst: Some(WindowBurst::Duration(Duration::from_secs(0))),
+                ..Default::default()
+            },
+            Config {
+                throttle_after_bytes: Some(0),
+                ..Default::default()
+            },
+            Config {
+                throttle_after_bytes: Some(1024),
+                window_burst: Some(WindowBurst::Bytes(1024)),
+                ..Default::default()
+            },
+            Config {
+                read_coalesce: Some(Duration::from_secs(0)),
+                ..Default::default()
+            },
+            Config {
+                run_for: Some(Duration::from_secs(0)),
+                ..Default::default()
+            },
+            Config {
+                seek_penalty: Some(Duration::from_secs(0)),
+                ..Default::default()
+            },
+            Config {
+                allocation_penalty: Some(Duration::from_secs(0)),
+                ..Default::default()
+            },
+            Config {
+                max_op_delay: Some(Duration::from_secs(0)),
+                ..Default::default()
+            },
+            Config {
+                target: Some(PathBuf::new()),
+                ..Default::default()
+            },
+            Config {
+                trigger: Some(Trigger::new("")),
+                ..Default::default()
+            },
+            Config {
+                trigger: Some(Trigger {
+                    poll_interval: Duration::from_secs(0),
+                    ..Trigger::new("/tmp/slow")
+                }),
+                ..Default::default()
+            },
+            Config {
+                extension_speeds: vec![(".".to_string(), Speed::Bps(1024))]
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            },
+            Config {
+                extension_speeds: vec![
+                    ("db".to_string(), Speed::Bps(1024)),
+                    (".DB".to_string(), Speed::Bps(2048)),
+                ]
+                .into_iter()
+                .collect(),
+                ..Default::default()
+            },
+            Config {
+                extension_speeds: vec![("db".to_string(), Speed::Bps(0))]
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            },
+            Config {
+                speed: Speed::PerByte(Duration::from_secs(0)),
+                ..Default::default()
+            },
+            Config {
+                speed: Speed::LatencyRange {
+                    min: Duration::from_secs(2),
+                    max: Duration::from_secs(1),
+                },
+                ..Default::default()
+            },
+            Config {
+                speed: Speed::Range {
+                    min: 2048,
+                    max: 1024,
+                },
+                ..Default::default()
+            },
+            Config {
+                speed_decay: f64::INFINITY,
+                ..Default::default()
+            },
+        ];
+        for config in invalid_configs {
+            match config.validate() {
+                Err(ConfigError::ValidationFailed(_)) => {}
+                r => panic!("unexpected result: {:?} for {}", r, config),
+            }
+        }
+    }
+
+    #[test]
+    fn test_config_validate_inverted_range() {
+        // Every speed of the config is checked as `speed`, so none of them panics in `Speed::sample`.
+        let inverted = || Speed::Range {
+            min: 2048,
+            max: 1024,
+        };
+        let mut configs = vec![Config::default(); 5];
+        configs[0].per_operation.insert(Operation::Read, inverted());
+        configs[1]
+            .extension_speeds
+            .insert("db".to_string(), inverted());
+        configs[2].append_speed = Some(inverted());
+        configs[3].profiles.push(Profile {
+            speed: inverted(),
+            operations: vec![Operation::Stat],
+            duration: Duration::from_secs(10),
+            frequency: Duration::from_secs(60),
+        });
+        configs[4].condition = Condition::Script {
+            windows: vec![ScriptWindow {
+                offset: Duration::from_secs(10),
+                length: Duration::from_secs(5),
+                speed: inverted(),
+            }],
+            repeat: false,
+        };
+        for config in configs {
+            assert_eq!(
+                Err(ConfigError::ValidationFailed(
+                    "speed 2KBps..1KBps has the inverted range".to_string()
+                )),
+                config.validate(),
+                "{}",
+                config
+            );
+        }
+    }
+
+    #[test]
+    fn test_config_validate_speed_modes() {
+        // The speeds of the different modes are mixed.
+        let mut config = Config {
+            speed: Speed::Iops(100),
+            burst: Some(4096),
+            ..Default::default()
+        };
+        config
+            .per_operation
+            .insert(Operation::Write, Speed::Bps(1024));
+        assert!(config.validate().is_ok());
+
+        assert!(config.warnings().is_empty());
+
+        // The unused burst and baseline are warned but legal.
+        config.per_operation.clear();
+        assert!(config.validate().is_ok());
+        let warnings = config.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("burst (4096)"), "{}", warnings[0]);
+        config.append_speed = Some(Speed::Bps(1024));
+        assert!(config.warnings().is_empty());
+
+        let config = Config {
+            speed: Speed::Bps(1024),
+            baseline: Some(1 << 20),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+        let warnings = config.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("baseline"), "{}", warnings[0]);
+        let config = Config {
+            speed: Speed::Percent(50),
+            ..config
+        };
+        assert!(config.validate().is_ok());
+        assert!(config.warnings().is_empty());
+
+        // The zero rate is rejected with the reason.
+        let config = Config {
+            speed: Speed::Bps(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            Err(ConfigError::ValidationFailed(
+                "speed 0Bps stalls the operations".to_string()
+            )),
+            config.validate()
+        );
+    }
+
+    #[test]
+    fn test_config_to_summary_json() {
+        let mut config = Config {
+            speed: Speed::Range {
+                min: 1 << 10,
+                max: 1 << 20,
+            },
+            operations: vec![Operation::Read, Operation::Stat],
+            condition: Condition::Periodic {
+                duration: Duration::from_millis(1500),
+                frequency: Duration::from_secs(60 * 60),
+            },
+            probability: Some(0.3),
+            ramp: Some(Duration::from_secs(30)),
+            error_injection: Some(ErrorInjection {
+                probability: 0.1,
+                operations: vec![Operation::Write],
+                disk_full_after: Some(100 << 20),
+                errors: vec![InjectedError::Eagain.into()],
+                continuous: false,
+                errors_per_window: None,
+            }),
+            short_io: Some(ShortIo::Fraction(0.5)),
+            warmup: Duration::from_secs(10),
+            ..Default::default()
+        };
+        for op in &[
+            Operation::Read,
+            Operation::Write,
+            Operation::Stat,
+            Operation::Open,
+        ] {
+            config.per_operation.insert(op.clone(), Speed::Iops(100));
+        }
+        config.operation_weights.insert(Operation::Stat, 3);
+
+        let json = config.to_summary_json();
+        assert!(!json.contains('\n'));
+        assert!(json.contains(r#""speed":"1KBps..1MBps""#), "{}", json);
+        assert!(json.contains(r#""warmup":"10s""#), "{}", json);
+        let decoded: Config = serde_json::from_str(&json).unwrap();
+        assert_eq!(config, decoded);
+        // Stable regardless of the order of the maps.
+        assert_eq!(json, decoded.to_summary_json());
+        assert_eq!(
+            serde_json::from_str::<Config>(&Config::default().to_summary_json()).unwrap(),
+            Config::default()
+        );
+    }
+
+    #[test]
+    fn test_config_serde_duration() {
+        let config = Config::default();
+
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(
+            json.contains(r#"{"Periodic":{"duration":"10m","frequency":"30m"}}"#),
+            "{}",
+            json
+        );
+
+        let decoded: Config = serde_json::from_str(&json).unwrap();
+        assert_eq!(config.condition, decoded.condition);
+
+        // The raw form is still accepted.
+        let json = r#"{"speed":"PassThrough","operations":[],"condition":{"Periodic":{"duration":{"secs":60,"nanos":0},"frequency":"2h"}}}"#;
+        let decoded: Config = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            Condition::Periodic {
+                duration: Duration::from_secs(60),
+                frequency: Duration::from_secs(2 * 60 * 60),
+            },
+            decoded.condition
+        );
+
+        let json = r#"{"speed":"PassThrough","operations":[],"condition":{"Periodic":{"duration":"10x","frequency":"2h"}}}"#;
+        assert!(serde_json::from_str::<Config>(json).is_err());
+    }
+
+    #[test]
+    fn test_config_from_json_str_unknown_operation() {
+        use slog::{Drain, Never, OwnedKVList, Record};
+        use std::sync::{Arc, Mutex};
+
+        struct Collect(Arc<Mutex<Vec<String>>>);
+        impl Drain for Collect {
+            type Ok = ();
+            type Err = Never;
+            fn log(&self, record: &Record, _: &OwnedKVList) -> Result<(), Never> {
+                self.0.lock().unwrap().push(record.msg().to_string());
+                Ok(())
+            }
+        }
+
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let logger = Logger::root(Collect(Arc::clone(&messages)), o!());
+        let json = r#"{"speed":"PassThrough","operations":["Read","Rename"],"condition":{"Always":"Unstable"}}"#;
+        let config = Config::from_json_str(json, &logger).unwrap();
+        assert_eq!(vec![Operation::Read], config.operations);
+        assert_eq!(
+            vec!["skipped the unknown operation: Rename".to_string()],
+            *messages.lock().unwrap()
+        );
+
+        // The syntax errors are still rejected.
+        let json = r#"{"operations":["Read"],"speed":"fast","condition":{"Always":"Unstable"}}"#;
+        match Config::from_json_str(json, &logger) {
+            Err(ConfigError::Syntax(_)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert_eq!(1, messages.lock().unwrap().len());
+    }
+
+    #[test]
+    fn test_config_json_throttle_after_bytes() {
+        let logger = Logger::root(slog::Discard, o!());
+        let json = r#"{"speed":"1MBps","operations":["Read"],"condition":{"Always":"Unstable"},"throttle_after_bytes":8589934592}"#;
+        let config = Config::from_json_str(json, &logger).unwrap();
+        assert_eq!(Some(8 << 30), config.throttle_after_bytes);
+        assert_eq!(
+            config,
+            Config::from_json_str(&config.to_summary_json(), &logger).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_config_serde_zero_duration() {
+        for condition in &[
+            r#"{"Periodic":{"duration":"10m","frequency":"0s"}}"#,
+            r#"{"Periodic":{"duration":"0ms","frequency":"2h"}}"#,
+            r#"{"Periodic":{"duration":"10m","frequency":{"secs":0,"nanos":0}}}"#,
+        ] {
+            let json = format!(
+                r#"{{"speed":"PassThrough","operations":[],"condition":{}}}"#,
+                condition
+            );
+            let e = serde_json::from_str::<Config>(&json).unwrap_err();
+            assert!(e.to_string().contains("non-zero duration"), "{}", e);
+        }
+    }
+
+    #[test]
+    fn test_config_per_operation() {
+        let mut config = Config {
+            speed: Speed::Bps(1024),
+            ..Default::default()
+        };
+        config
+            .per_operation
+            .insert(Operation::Write, Speed::Bps(64));
+
+        // Override.
+        assert_eq!(&Speed::Bps(64), config.speed_for(&Operation::Write));
+        // Fallback.
+        assert_eq!(&Speed::Bps(1024), config.speed_for(&Operation::Read));
+        assert_eq!(&Speed::Bps(1024), config.speed_for(&Operation::Stat));
+
+        assert_eq!(
+            "config {speed: 1KBps, operations: Read:Write, condition: Periodic {duration: 10m, frequency: 30m}, per_operation: {Write: 64Bps}}",
+            config.to_string()
+        );
+
+        let json = serde_json::to_string(&config).unwrap();
+        let decoded: Config = serde_json::from_str(&json).unwrap();
+        assert_eq!(config.per_operation, decoded.per_operation);
+
+        // The old config without the overrides.
+        let json =
+            r#"{"speed":"PassThrough","operations":["Read"],"condition":{"Always":"Unstable"}}"#;
+        let decoded: Config = serde_json::from_str(json).unwrap();
+        assert!(decoded.per_operation.is_empty());
+        assert_eq!(&Speed::PassThrough, decoded.speed_for(&Operation::Read));
+    }
+
+    #[test]
+    fn test_config_extension_speeds() {
+        let mut config = Config {
+            speed: Speed::Bps(1024),
+            ..Default::default()
+        };
+        config
+            .per_operation
+            .insert(Operation::Write, Speed::Bps(64));
+        config
+            .extension_speeds
+            .insert("db".to_string(), Speed::Bps(16));
+        config
+            .extension_speeds
+            .insert(".log".to_string(), Speed::Bps(1 << 20));
+        assert!(config.validate().is_ok());
+
+        // It overrides both of `speed` and `per_operation`, ignoring the case and the leading dot.
+        let read = Operation::Read;
+        let write = Operation::Write;
+        assert_eq!(
+            &Speed::Bps(16),
+            config.speed_for_extension(&read, Some("db"))
+        );
+        assert_eq!(
+            &Speed::Bps(16),
+            config.speed_for_extension(&write, Some("DB"))
+        );
+        assert_eq!(
+            &Speed::Bps(1 << 20),
+            config.speed_for_extension(&write, Some("log"))
+        );
+        // Fallback.
+        assert_eq!(
+            &Speed::Bps(1024),
+            config.speed_for_extension(&read, Some("txt"))
+        );
+        assert_eq!(&Speed::Bps(64), config.speed_for_extension(&write, None));
+
+        let json = serde_json::to_string(&config).unwrap();
+        let decoded: Config = serde_json::from_str(&json).unwrap();
+        assert_eq!(config.extension_speeds, decoded.extension_speeds);
+    }
+
+    #[test]
+    fn test_config_speed_combine() {
+        let mut config = Config {
+            speed: Speed::Bps(1024),
+            ..Default::default()
+        };
+        config
+            .per_operation
+            .insert(Operation::Write, Speed::Bps(4096));
+        config.per_operation.insert(Operation::Read, Speed::Bps(64));
+        config
+            .extension_speeds
+            .insert("db".to_string(), Speed::Bps(1 << 20));
+        config
+            .extension_speeds
+            .insert("log".to_string(), Speed::Bps(16));
+        let read = Operation::Read;
+        let write = Operation::Write;
+
+        // The more specific rules win even if they are faster.
+        assert_eq!(SpeedCombine::LastWins, config.speed_combine);
+        assert_eq!(&Speed::Bps(4096), config.speed_for(&write));
+        assert_eq!(&Speed::Bps(64), config.speed_for(&read));
+        assert_eq!(
+            &Speed::Bps(1 << 20),
+            config.speed_for_extension(&read, Some("db"))
+        );
+        assert_eq!(
+            &Speed::Bps(16),
+            config.speed_for_extension(&write, Some("log"))
+        );
+
+        // The slowest of the overlapping rules applies.
+        config.speed_combine = SpeedCombine::MostRestrictive;
+        assert_eq!(&Speed::Bps(1024), config.speed_for(&write));
+        assert_eq!(&Speed::Bps(64), config.speed_for(&read));
+        assert_eq!(&Speed::Bps(1024), config.speed_for(&Operation::Stat));
+        assert_eq!(
+            &Speed::Bps(64),
+            config.speed_for_extension(&read, Some("db"))
+        );
+        assert_eq!(
+            &Speed::Bps(1024),
+            config.speed_for_extension(&write, Some("db"))
+        );
+        assert_eq!(
+            &Speed::Bps(16),
+            config.speed_for_extension(&write, Some("log"))
+        );
+        assert_eq!(
+            &Speed::Bps(1024),
+            config.speed_for_extension(&write, Some("txt"))
+        );
+
+        let json = serde_json::to_string(&config).unwrap();
+        let decoded: Config = serde_json::from_str(&json).unwrap();
+        assert_eq!(SpeedCombine::MostRestrictive, decoded.speed_combine);
+    }
+
+    #[test]
+    fn test_config_new() {
+        let secs = Duration::from_secs;
+        let config = Config::new(
+            secs(60),
+            secs(10 * 60),
+            vec![Operation::Write, Operation::Read, Operation::Write],
+            Speed::Bps(1024),
         )
+        .unwrap();
+        assert_eq!(
+            Config {
+                speed: Speed::Bps(1024),
+                operations: vec![Operation::Write, Operation::Read],
+                condition: Condition::Periodic {
+                    duration: secs(60),
+                    frequency: secs(10 * 60),
+                },
+                ..Default::default()
+            },
+            config
+        );
+
+        for &(duration, frequency) in &[(secs(60), secs(60)), (secs(60), secs(30))] {
+            match Config::new(duration, frequency, vec![Operation::Read], Speed::Bps(1024)) {
+                Err(ConfigError::ValidationFailed(_)) => {}
+                r => panic!("unexpected result: {:?}", r),
+            }
+        }
+        // The other fields are validated too.
+        assert!(Config::new(secs(60), secs(120), vec![], Speed::Bps(0)).is_err());
+    }
+
+    #[test]
+    fn test_config_canonicalize() {
+        use self::Operation::*;
+
+        let mut config = Config {
+            operations: vec![Write, Read, Write, Stat, Read],
+            error_injection: Some(ErrorInjection {
+                probability: 1.0,
+                operations: vec![Fsync, Fsync],
+                disk_full_after: None,
+                errors: vec![],
+                continuous: false,
+                errors_per_window: None,
+            }),
+            ..Default::default()
+        };
+        config.canonicalize();
+        // The first occurrences are kept in the order.
+        assert_eq!(vec![Write, Read, Stat], config.operations);
+        assert_eq!(vec![Fsync], config.error_injection.unwrap().operations);
+
+        // The merged configs are canonicalized.
+        let config = Config::merge(
+            Config::default(),
+            PartialConfig {
+                operations: Some(vec![Read, Read, Write]),
+                ..Default::default()
+            },
+        );
+        assert_eq!(vec![Read, Write], config.operations);
+        assert!(config.to_string().contains("operations: Read:Write,"));
+
+        // Empty is kept to disable the throttling.
+        let mut config = Config {
+            operations: Vec::new(),
+            ..Default::default()
+        };
+        config.canonicalize();
+        assert!(config.operations.is_empty());
+        assert!(config.validate().is_ok());
     }
 }
@@ -1,9 +1,90 @@
+use super::ConfigError;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use serde_json::{self, Value};
 use std::fmt;
+use std::str::FromStr;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Operation {
     Read,
     Write,
+    Stat,
+    Open,
+    Readdir,
+    Fsync,
+}
+
+impl FromStr for Operation {
+    type Err = ConfigError;
+
+    /// Parses the given string as operation case-insensitively.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "read" => Ok(Operation::Read),
+            "write" => Ok(Operation::Write),
+            "stat" => Ok(Operation::Stat),
+            "open" => Ok(Operation::Open),
+            "readdir" => Ok(Operation::Readdir),
+            "fsync" => Ok(Operation::Fsync),
+            _ => Err(ConfigError::InvalidOperation(s.to_string())),
+        }
+    }
+}
+
+/// Parses the operations separated by `:` (as `Display` of `Config` prints) or `,` (e.g., "Read:Write" and
+/// "read, write"). The operations are trimmed and the duplicates are removed keeping the first ones.
+pub fn parse_operations(s: &str) -> Result<Vec<Operation>, ConfigError> {
+    let mut operations = Vec::new();
+    for token in s.split([':', ',']) {
+        let token = token.trim();
+        if token.is_empty() {
+            return Err(ConfigError::InvalidOperation(format!(
+                "empty operation in {:?}",
+                s
+            )));
+        }
+
+        let op = Operation::from_str(token)?;
+        if !operations.contains(&op) {
+            operations.push(op);
+        }
+    }
+
+    Ok(operations)
+}
+
+/// Deserializes the operations skipping the unknown variants (e.g., those added in a newer version)
+/// instead of failing the whole config, so that a forward-compatible config still loads.
+/// The values which are not strings are still rejected. The skipped ones are not reported, so see
+/// `unknown_operations` to log them (as `Config::from_json_str` does).
+pub fn deserialize_known<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Operation>, D::Error> {
+    let mut operations = Vec::new();
+    for value in Vec::<Value>::deserialize(deserializer)? {
+        let is_name = value.is_string();
+        match serde_json::from_value(value) {
+            Ok(op) => operations.push(op),
+            Err(_) if is_name => {}
+            Err(e) => return Err(D::Error::custom(e)),
+        }
+    }
+    Ok(operations)
+}
+
+/// Returns the names in the JSON array of the operations which `deserialize_known` skips.
+pub fn unknown_operations(operations: &Value) -> Vec<String> {
+    let names = match *operations {
+        Value::Array(ref values) => values,
+        _ => return Vec::new(),
+    };
+    names
+        .iter()
+        .filter_map(|v| v.as_str())
+        .filter(|name| serde_json::from_value::<Operation>(Value::from(*name)).is_err())
+        .map(|name| name.to_string())
+        .collect()
 }
 
 impl fmt::Display for Operation {
@@ -11,6 +92,98 @@ impl fmt::Display for Operation {
         match *self {
             Operation::Read => write!(f, "Read"),
             Operation::Write => write!(f, "Write"),
+            Operation::Stat => write!(f, "Stat"),
+            Operation::Open => write!(f, "Open"),
+            Operation::Readdir => write!(f, "Readdir"),
+            Operation::Fsync => write!(f, "Fsync"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operation_from_str() {
+        assert_eq!(Ok(Operation::Read), Operation::from_str("Read"));
+        assert_eq!(Ok(Operation::Write), Operation::from_str("Write"));
+        assert_eq!(Ok(Operation::Read), Operation::from_str("read"));
+        assert_eq!(Ok(Operation::Write), Operation::from_str("WRITE"));
+        assert_eq!(Ok(Operation::Write), Operation::from_str("wRiTe"));
+        assert_eq!(Ok(Operation::Stat), Operation::from_str("stat"));
+        assert_eq!(Ok(Operation::Open), Operation::from_str("Open"));
+        assert_eq!(Ok(Operation::Readdir), Operation::from_str("readdir"));
+        assert_eq!(Ok(Operation::Fsync), Operation::from_str("FSYNC"));
+
+        assert_eq!(
+            Err(ConfigError::InvalidOperation("Delete".to_string())),
+            Operation::from_str("Delete")
+        );
+        assert!(Operation::from_str("").is_err());
+        assert!(Operation::from_str(" read").is_err());
+
+        // It mirrors the display.
+        let ops = "Read:Write"
+            .split(':')
+            .map(Operation::from_str)
+            .collect::<Result<Vec<_>, _>>();
+        assert_eq!(Ok(vec![Operation::Read, Operation::Write]), ops);
+        let all = [
+            Operation::Read,
+            Operation::Write,
+            Operation::Stat,
+            Operation::Open,
+            Operation::Readdir,
+            Operation::Fsync,
+        ];
+        for op in all.iter() {
+            assert_eq!(Ok(op.clone()), Operation::from_str(&op.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_deserialize_known() {
+        #[derive(Deserialize)]
+        struct Operations(#[serde(deserialize_with = "deserialize_known")] Vec<Operation>);
+
+        let ops: Operations = serde_json::from_str(r#"["Write", "Rename", "Fsync"]"#).unwrap();
+        assert_eq!(vec![Operation::Write, Operation::Fsync], ops.0);
+        assert_eq!(
+            vec!["Rename".to_string()],
+            unknown_operations(&serde_json::from_str(r#"["Write", "Rename", "Fsync"]"#).unwrap())
+        );
+        assert!(unknown_operations(&Value::Null).is_empty());
+
+        // Only the unknown names are skipped.
+        assert!(serde_json::from_str::<Operations>(r#"["Read", 1]"#).is_err());
+        assert!(serde_json::from_str::<Operations>(r#""Read""#).is_err());
+    }
+
+    #[test]
+    fn test_parse_operations() {
+        let read_write = vec![Operation::Read, Operation::Write];
+        assert_eq!(Ok(read_write.clone()), parse_operations("Read:Write"));
+        assert_eq!(Ok(read_write.clone()), parse_operations("read, write"));
+        assert_eq!(
+            Ok(read_write.clone()),
+            parse_operations(" Read ,Write:read ")
+        );
+        assert_eq!(Ok(vec![Operation::Fsync]), parse_operations("FSYNC"));
+
+        for s in &["", "Read:", "Read,,Write", ":Write", " , "] {
+            assert!(
+                parse_operations(s)
+                    .unwrap_err()
+                    .to_string()
+                    .contains("empty operation"),
+                "{}",
+                s
+            );
         }
+        assert_eq!(
+            Err(ConfigError::InvalidOperation("Delete".to_string())),
+            parse_operations("Read:Delete")
+        );
     }
 }
@@ -0,0 +1,369 @@
+use super::{
+    ActiveWindow, Condition, Config, ErrorInjection, Operation, Profile, ScriptWindow, ShortIo,
+    Speed, SpeedCombine, ThrottleScope, Trigger, WindowBurst,
+};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// `PartialConfig` is the config of which fields are optional.
+/// Each source of the config (e.g., a config file, the environment variables and the command line options)
+/// yields `PartialConfig`, and then they are merged over `Config::default()` in the priority order.
+///
+/// # Examples
+/// ```
+/// use mizumochi::config::{Config, Operation, PartialConfig, Speed};
+///
+/// let file = PartialConfig {
+///     speed: Some(Speed::Bps(1024)),
+///     operations: Some(vec![Operation::Write]),
+///     ..Default::default()
+/// };
+/// let cli = PartialConfig {
+///     speed: Some(Speed::Bps(2048)),
+///     ..Default::default()
+/// };
+///
+/// let config = vec![file, cli].into_iter().fold(Config::default(), Config::merge);
+/// assert_eq!(Speed::Bps(2048), config.speed);
+/// assert_eq!(vec![Operation::Write], config.operations);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PartialConfig {
+    pub speed: Option<Speed>,
+    pub operations: Option<Vec<Operation>>,
+    // The weights replace `Config::operation_weights` as a whole.
+    pub operation_weights: Option<HashMap<Operation, u32>>,
+    // The duration and the frequency of `Condition::Periodic`.
+    // The condition turns into `Condition::default_periodic()` if it is not periodic.
+    pub duration: Option<Duration>,
+    pub frequency: Option<Duration>,
+    // The windows replace the condition with `Condition::Script`, which repeats by `script_repeat`.
+    // `script_repeat` alone sets the repeat of the condition only if it is a script.
+    pub script: Option<Vec<ScriptWindow>>,
+    pub script_repeat: Option<bool>,
+    // The speeds are added to `Config::per_operation` (the same operations are overridden).
+    pub per_operation: Option<HashMap<Operation, Speed>>,
+    // The speeds are added to `Config::extension_speeds` like `per_operation`.
+    pub extension_speeds: Option<HashMap<String, Speed>>,
+    pub probability: Option<f64>,
+    pub seed: Option<u64>,
+    pub frequency_jitter: Option<Duration>,
+    pub active_window: Option<ActiveWindow>,
+    pub error_injection: Option<ErrorInjection>,
+    pub short_io: Option<ShortIo>,
+    pub include_globs: Option<Vec<String>>,
+    pub exclude_globs: Option<Vec<String>>,
+    pub min_file_size: Option<u64>,
+    pub burst: Option<usize>,
+    pub burst_start_fill: Option<f64>,
+    pub max_activations: Option<u32>,
+    pub window_operations: Option<u64>,
+    pub baseline: Option<usize>,
+    pub ramp: Option<Duration>,
+    pub scope: Option<ThrottleScope>,
+    pub speed_combine: Option<SpeedCombine>,
+    pub dry_run: Option<bool>,
+    pub passthrough_first_op: Option<bool>,
+    pub min_active: Option<Duration>,
+    pub min_idle: Option<Duration>,
+    pub warmup: Option<Duration>,
+    pub speed_decay: Option<f64>,
+    pub inverted: Option<bool>,
+    pub window_burst: Option<WindowBurst>,
+    pub throttle_after_bytes: Option<u64>,
+    pub read_coalesce: Option<Duration>,
+    pub run_for: Option<Duration>,
+    pub seek_penalty: Option<Duration>,
+    pub allocation_penalty: Option<Duration>,
+    pub append_speed: Option<Speed>,
+    pub max_op_delay: Option<Duration>,
+    pub share_among_handles: Option<bool>,
+    pub target: Option<PathBuf>,
+    pub trigger: Option<Trigger>,
+    // The profiles replace `Config::profiles` as a whole.
+    pub profiles: Option<Vec<Profile>>,
+    // The warnings of parsing the source (e.g., the clamped speeds), which are not merged into `Config`.
+    pub warnings: Vec<String>,
+}
+
+impl Config {
+    /// Overrides the fields of `base` by the fields which are set in `overlay`.
+    pub fn merge(base: Config, overlay: PartialConfig) -> Config {
+        let mut config = base;
+
+        if let Some(speed) = overlay.speed {
+            config.speed = speed;
+        }
+
+        if let Some(operations) = overlay.operations {
+            config.operations = operations;
+        }
+
+        if let Some(weights) = overlay.operation_weights {
+            config.operation_weights = weights;
+        }
+
+        if overlay.duration.is_some() || overlay.frequency.is_some() {
+            if !matches!(config.condition, Condition::Periodic { .. }) {
+                config.condition = Condition::default_periodic();
+            }
+            if let Condition::Periodic {
+                ref mut duration,
+                ref mut frequency,
+            } = config.condition
+            {
+                *duration = overlay.duration.unwrap_or(*duration);
+                *frequency = overlay.frequency.unwrap_or(*frequency);
+            }
+        }
+
+        if let Some(windows) = overlay.script {
+            config.condition = Condition::Script {
+                windows,
+                repeat: overlay.script_repeat.unwrap_or(false),
+            };
+        } else if let (Some(b), &mut Condition::Script { ref mut repeat, .. }) =
+            (overlay.script_repeat, &mut config.condition)
+        {
+            *repeat = b;
+        }
+
+        if let Some(per_operation) = overlay.per_operation {
+            config.per_operation.extend(per_operation);
+        }
+
+        if let Some(extension_speeds) = overlay.extension_speeds {
+            config.extension_speeds.extend(extension_speeds);
+        }
+
+        if overlay.probability.is_some() {
+            config.probability = overlay.probability;
+        }
+
+        if overlay.seed.is_some() {
+            config.seed = overlay.seed;
+        }
+
+        if let Some(jitter) = overlay.frequency_jitter {
+            config.frequency_jitter = jitter;
+        }
+
+        if overlay.active_window.is_some() {
+            config.active_window = overlay.active_window;
+        }
+
+        if overlay.error_injection.is_some() {
+            config.error_injection = overlay.error_injection;
+        }
+
+        if overlay.short_io.is_some() {
+            config.short_io = overlay.short_io;
+        }
+
+        if let Some(globs) = overlay.include_globs {
+            config.include_globs = globs;
+        }
+
+        if let Some(globs) = overlay.exclude_globs {
+            config.exclude_globs = globs;
+        }
+
+        if overlay.min_file_size.is_some() {
+            config.min_file_size = overlay.min_file_size;
+        }
+
+        if overlay.burst.is_some() {
+            config.burst = overlay.burst;
+        }
+
+        if let Some(fill) = overlay.burst_start_fill {
+            config.burst_start_fill = fill;
+        }
+
+        if overlay.max_activations.is_some() {
+            config.max_activations = overlay.max_activations;
+        }
+
+        if overlay.window_operations.is_some() {
+            config.window_operations = overlay.window_operations;
+        }
+
+        if overlay.baseline.is_some() {
+            config.baseline = overlay.baseline;
+        }
+
+        if overlay.ramp.is_some() {
+            config.ramp = overlay.ramp;
+        }
+
+        if let Some(scope) = overlay.scope {
+            config.scope = scope;
+        }
+
+        if let Some(speed_combine) = overlay.speed_combine {
+            config.speed_combine = speed_combine;
+        }
+
+        if let Some(dry_run) = overlay.dry_run {
+            config.dry_run = dry_run;
+        }
+
+        if let Some(b) = overlay.passthrough_first_op {
+            config.passthrough_first_op = b;
+        }
+
+        if let Some(d) = overlay.min_active {
+            config.min_active = d;
+        }
+
+        if let Some(d) = overlay.min_idle {
+            config.min_idle = d;
+        }
+
+        if let Some(d) = overlay.warmup {
+            config.warmup = d;
+        }
+
+        if let Some(decay) = overlay.speed_decay {
+            config.speed_decay = decay;
+        }
+
+        if let Some(inverted) = overlay.inverted {
+            config.inverted = inverted;
+        }
+
+        if overlay.window_burst.is_some() {
+            config.window_burst = overlay.window_burst;
+        }
+
+        if overlay.throttle_after_bytes.is_some() {
+            config.throttle_after_bytes = overlay.throttle_after_bytes;
+        }
+
+        if overlay.read_coalesce.is_some() {
+            config.read_coalesce = overlay.read_coalesce;
+        }
+
+        if overlay.run_for.is_some() {
+            config.run_for = overlay.run_for;
+        }
+
+        if overlay.seek_penalty.is_some() {
+            config.seek_penalty = overlay.seek_penalty;
+        }
+
+        if overlay.allocation_penalty.is_some() {
+            config.allocation_penalty = overlay.allocation_penalty;
+        }
+
+        if overlay.append_speed.is_some() {
+            config.append_speed = overlay.append_speed;
+        }
+
+        if overlay.max_op_delay.is_some() {
+            config.max_op_delay = overlay.max_op_delay;
+        }
+
+        if let Some(b) = overlay.share_among_handles {
+            config.share_among_handles = b;
+        }
+
+        if overlay.target.is_some() {
+            config.target = overlay.target;
+        }
+
+        if overlay.trigger.is_some() {
+            config.trigger = overlay.trigger;
+        }
+
+        if let Some(profiles) = overlay.profiles {
+            config.profiles = profiles;
+        }
+
+        config.canonicalize();
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use state::State;
+
+    #[test]
+    fn test_merge_priority() {
+        let file = PartialConfig {
+            speed: Some(Speed::Bps(1024)),
+            operations: Some(vec![Operation::Write]),
+            frequency: Some(Duration::from_secs(60 * 60)),
+            ..Default::default()
+        };
+        let env = PartialConfig {
+            duration: Some(Duration::from_secs(5 * 60)),
+            ..Default::default()
+        };
+        let cli = PartialConfig {
+            speed: Some(Speed::Bps(2048)),
+            ..Default::default()
+        };
+
+        let config = vec![file, env, cli]
+            .into_iter()
+            .fold(Config::default(), Config::merge);
+
+        // The CLI beats the file.
+        assert_eq!(Speed::Bps(2048), config.speed);
+        // The fields which the CLI does not set survive.
+        assert_eq!(vec![Operation::Write], config.operations);
+        assert_eq!(
+            Condition::Periodic {
+                duration: Duration::from_secs(5 * 60),
+                frequency: Duration::from_secs(60 * 60),
+            },
+            config.condition
+        );
+        assert_eq!(Config::default().probability, config.probability);
+    }
+
+    #[test]
+    fn test_merge_empty() {
+        let base = Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Always(State::Unstable),
+            burst: Some(4096),
+            ..Default::default()
+        };
+        let config = Config::merge(base.clone(), PartialConfig::default());
+
+        assert_eq!(base.speed, config.speed);
+        assert_eq!(base.condition, config.condition);
+        assert_eq!(base.burst, config.burst);
+    }
+
+    #[test]
+    fn test_merge_per_operation() {
+        let mut base = Config::default();
+        base.per_operation.insert(Operation::Read, Speed::Bps(1));
+        base.per_operation.insert(Operation::Write, Speed::Bps(2));
+
+        let mut per_operation = HashMap::new();
+        per_operation.insert(Operation::Write, Speed::Bps(3));
+        let config = Config::merge(
+            base,
+            PartialConfig {
+                per_operation: Some(per_operation),
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(
+            Some(&Speed::Bps(1)),
+            config.per_operation.get(&Operation::Read)
+        );
+        assert_eq!(
+            Some(&Speed::Bps(3)),
+            config.per_operation.get(&Operation::Write)
+        );
+    }
+}
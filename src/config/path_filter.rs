@@ -0,0 +1,95 @@
+use glob::{MatchOptions, Pattern};
+use std::path::Path;
+
+/// `PathFilter` selects the files to throttle by glob patterns.
+/// A path is selected if it matches any of `include` (or `include` is empty) and none of `exclude`.
+/// That is, `exclude` takes precedence over `include`.
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl PathFilter {
+    /// Compiles the given patterns.
+    pub fn new(include: &[String], exclude: &[String]) -> Result<PathFilter, String> {
+        Ok(PathFilter {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+        })
+    }
+
+    /// Returns true if the given path is selected.
+    /// `path` is relative to the original directory (e.g., "data/a.bin").
+    pub fn matches(&self, path: &Path) -> bool {
+        let options = MatchOptions {
+            // "*" does not cross the directories whereas "**" does.
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        let is_match = |p: &Pattern| p.matches_path_with(path, options);
+
+        (self.include.is_empty() || self.include.iter().any(&is_match))
+            && !self.exclude.iter().any(&is_match)
+    }
+}
+
+fn compile(patterns: &[String]) -> Result<Vec<Pattern>, String> {
+    patterns
+        .iter()
+        .map(|p| Pattern::new(p).map_err(|e| format!("{}: {}", e, p)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str]) -> PathFilter {
+        let to_vec = |x: &[&str]| x.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        PathFilter::new(&to_vec(include), &to_vec(exclude)).unwrap()
+    }
+
+    #[test]
+    fn test_path_filter_empty() {
+        let f = filter(&[], &[]);
+        assert!(f.matches(Path::new("a.txt")));
+        assert!(f.matches(Path::new("data/a.bin")));
+    }
+
+    #[test]
+    fn test_path_filter_include() {
+        let f = filter(&["data/**"], &[]);
+        assert!(f.matches(Path::new("data/a.bin")));
+        assert!(f.matches(Path::new("data/sub/a.bin")));
+        assert!(!f.matches(Path::new("a.txt")));
+        assert!(!f.matches(Path::new("other/a.bin")));
+
+        let f = filter(&["*.bin"], &[]);
+        assert!(f.matches(Path::new("a.bin")));
+        assert!(!f.matches(Path::new("data/a.bin")));
+    }
+
+    #[test]
+    fn test_path_filter_exclude() {
+        let f = filter(&[], &["**/*.log"]);
+        assert!(f.matches(Path::new("data/a.bin")));
+        assert!(!f.matches(Path::new("a.log")));
+        assert!(!f.matches(Path::new("data/a.log")));
+    }
+
+    #[test]
+    fn test_path_filter_precedence() {
+        // The exclude patterns win if the both match.
+        let f = filter(&["data/**"], &["data/tmp/**"]);
+        assert!(f.matches(Path::new("data/a.bin")));
+        assert!(!f.matches(Path::new("data/tmp/a.bin")));
+        assert!(!f.matches(Path::new("a.bin")));
+    }
+
+    #[test]
+    fn test_path_filter_invalid() {
+        assert!(PathFilter::new(&["data/[".to_string()], &[]).is_err());
+        assert!(PathFilter::new(&[], &["***".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,67 @@
+use super::duration::format_duration;
+use super::{Condition, Operation, Speed};
+use std::fmt;
+use std::time::Duration;
+
+/// `Profile` is an extra slowdown of `operations` which is unstable for `duration` every `frequency`.
+/// Each profile has its own cycle independent of `Config::condition` and the other profiles.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profile {
+    pub speed: Speed,
+    pub operations: Vec<Operation>,
+    #[serde(with = "super::duration::nonzero")]
+    pub duration: Duration,
+    #[serde(with = "super::duration::frequency")]
+    pub frequency: Duration,
+}
+
+impl Profile {
+    /// Returns the periodic condition of the cycle.
+    pub fn condition(&self) -> Condition {
+        Condition::Periodic {
+            duration: self.duration,
+            frequency: self.frequency,
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operations = self
+            .operations
+            .iter()
+            .map(|op| op.to_string())
+            .collect::<Vec<_>>();
+        write!(
+            f,
+            "{} on {} for {} every {}",
+            self.speed,
+            operations.join(":"),
+            format_duration(&self.duration),
+            format_duration(&self.frequency)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_display() {
+        let profile = Profile {
+            speed: Speed::Bps(1 << 20),
+            operations: vec![Operation::Read, Operation::Stat],
+            duration: Duration::from_secs(10 * 60),
+            frequency: Duration::from_secs(60 * 60),
+        };
+        assert_eq!("1MBps on Read:Stat for 10m every 1h", profile.to_string());
+        assert_eq!(
+            Condition::Periodic {
+                duration: Duration::from_secs(10 * 60),
+                frequency: Duration::from_secs(60 * 60),
+            },
+            profile.condition()
+        );
+    }
+}
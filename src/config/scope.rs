@@ -0,0 +1,55 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// `ThrottleScope` decides which operations share the token buckets (see `Config::burst` and `Speed::Iops`),
+/// or the reserved delays of the byte rates without `Config::burst`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ThrottleScope {
+    /// All threads share a bucket, so the rate is the total of them.
+    #[default]
+    Global,
+    /// Each thread has its own bucket, so the total rate scales with the number of the threads.
+    PerThread,
+}
+
+impl FromStr for ThrottleScope {
+    type Err = String;
+
+    /// Parses "global" or "per_thread" as `ThrottleScope`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "global" | "Global" => Ok(ThrottleScope::Global),
+            "per_thread" | "PerThread" => Ok(ThrottleScope::PerThread),
+            _ => Err(format!("scope accepts global or per_thread: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for ThrottleScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ThrottleScope::Global => write!(f, "global"),
+            ThrottleScope::PerThread => write!(f, "per_thread"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle_scope_from_str() {
+        assert_eq!(Ok(ThrottleScope::Global), ThrottleScope::from_str("global"));
+        assert_eq!(
+            Ok(ThrottleScope::PerThread),
+            ThrottleScope::from_str("PerThread")
+        );
+        assert!(ThrottleScope::from_str("").is_err());
+        assert!(ThrottleScope::from_str("per-thread").is_err());
+
+        for s in ["global", "per_thread"].iter() {
+            assert_eq!(*s, ThrottleScope::from_str(s).unwrap().to_string());
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// `ShortIo` makes reads and writes transfer fewer bytes than requested during unstable periods.
+/// A non-empty request always transfers one byte at least.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ShortIo {
+    /// Transfers the fraction (0.0 exclusive to 1.0 inclusive) of the requested bytes.
+    Fraction(f64),
+    /// Transfers bytes randomly between one and the requested size.
+    Random,
+}
+
+impl FromStr for ShortIo {
+    type Err = String;
+
+    /// Parses "random" or a fraction like "0.5" as `ShortIo`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "random" || s == "Random" {
+            return Ok(ShortIo::Random);
+        }
+
+        s.parse()
+            .map(ShortIo::Fraction)
+            .map_err(|_| format!("short io accepts random or a fraction: {}", s))
+    }
+}
+
+impl fmt::Display for ShortIo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ShortIo::Fraction(x) => write!(f, "{}", x),
+            ShortIo::Random => write!(f, "random"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_io_from_str() {
+        assert_eq!(Ok(ShortIo::Random), ShortIo::from_str("random"));
+        assert_eq!(Ok(ShortIo::Fraction(0.5)), ShortIo::from_str("0.5"));
+        assert_eq!(Ok(ShortIo::Fraction(1.0)), ShortIo::from_str("1"));
+        assert!(ShortIo::from_str("").is_err());
+        assert!(ShortIo::from_str("half").is_err());
+
+        for s in ["random", "0.25"].iter() {
+            assert_eq!(*s, ShortIo::from_str(s).unwrap().to_string());
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use super::{Config, Speed, ThrottleScope, WindowBurst};
+use std::ops::Deref;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// `ConfigSnapshot` is a loaded `Config` and the copies of its fields which are looked up per operation.
+/// It is cheap to clone, and reading it never takes the lock of `AtomicImmut`, so `Engine` caches it
+/// and refreshes it only when the config is replaced (i.e., reloaded).
+/// The other fields are read through `Deref` from the same config, so all of them are consistent.
+///
+/// # Examples
+/// ```
+/// use mizumochi::config::{Config, ConfigSnapshot};
+/// use std::sync::Arc;
+///
+/// let config = Arc::new(Config {
+///     dry_run: true,
+///     ..Default::default()
+/// });
+/// let snapshot = ConfigSnapshot::new(Arc::clone(&config));
+/// assert!(snapshot.dry_run);
+/// assert!(snapshot.is_of(&config));
+/// assert_eq!(config.speed, snapshot.speed);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConfigSnapshot {
+    config: Arc<Config>,
+    pub burst: Option<usize>,
+    pub burst_start_fill: f64,
+    pub scope: ThrottleScope,
+    pub dry_run: bool,
+    pub passthrough_first_op: bool,
+    pub window_burst: Option<WindowBurst>,
+    pub throttle_after_bytes: Option<u64>,
+    pub read_coalesce: Option<Duration>,
+    pub seek_penalty: Option<Duration>,
+    pub allocation_penalty: Option<Duration>,
+    pub append_speed: Option<Speed>,
+    pub max_op_delay: Option<Duration>,
+    pub share_among_handles: bool,
+}
+
+impl ConfigSnapshot {
+    pub fn new(config: Arc<Config>) -> ConfigSnapshot {
+        ConfigSnapshot {
+            burst: config.burst,
+            burst_start_fill: config.burst_start_fill,
+            scope: config.scope,
+            dry_run: config.dry_run,
+            passthrough_first_op: config.passthrough_first_op,
+            window_burst: config.window_burst,
+            throttle_after_bytes: config.throttle_after_bytes,
+            read_coalesce: config.read_coalesce,
+            seek_penalty: config.seek_penalty,
+            allocation_penalty: config.allocation_penalty,
+            append_speed: config.append_speed.clone(),
+            max_op_delay: config.max_op_delay,
+            share_among_handles: config.share_among_handles,
+            config,
+        }
+    }
+
+    /// Returns the config from which it is taken.
+    pub fn config(&self) -> &Arc<Config> {
+        &self.config
+    }
+
+    /// Returns true if it is taken from `config` itself, i.e., the config is not replaced since.
+    pub fn is_of(&self, config: &Arc<Config>) -> bool {
+        Arc::ptr_eq(&self.config, config)
+    }
+}
+
+impl Deref for ConfigSnapshot {
+    type Target = Config;
+
+    fn deref(&self) -> &Config {
+        &self.config
+    }
+}
+
+impl Default for ConfigSnapshot {
+    fn default() -> ConfigSnapshot {
+        ConfigSnapshot::new(Arc::new(Config::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_snapshot() {
+        let config = Arc::new(Config {
+            burst: Some(4096),
+            burst_start_fill: 0.5,
+            scope: ThrottleScope::PerThread,
+            dry_run: true,
+            passthrough_first_op: true,
+            window_burst: Some(WindowBurst::Bytes(1024)),
+            read_coalesce: Some(Duration::from_millis(10)),
+            seek_penalty: Some(Duration::from_millis(20)),
+            allocation_penalty: Some(Duration::from_millis(30)),
+            append_speed: Some(Speed::PassThrough),
+            max_op_delay: Some(Duration::from_secs(1)),
+            share_among_handles: true,
+            ..Default::default()
+        });
+        let snapshot = ConfigSnapshot::new(Arc::clone(&config));
+
+        assert_eq!(config.burst, snapshot.burst);
+        assert_eq!(config.burst_start_fill, snapshot.burst_start_fill);
+        assert_eq!(config.scope, snapshot.scope);
+        assert_eq!(config.dry_run, snapshot.dry_run);
+        assert_eq!(config.passthrough_first_op, snapshot.passthrough_first_op);
+        assert_eq!(config.window_burst, snapshot.window_burst);
+        assert_eq!(config.read_coalesce, snapshot.read_coalesce);
+        assert_eq!(config.seek_penalty, snapshot.seek_penalty);
+        assert_eq!(config.allocation_penalty, snapshot.allocation_penalty);
+        assert_eq!(config.append_speed, snapshot.append_speed);
+        assert_eq!(config.max_op_delay, snapshot.max_op_delay);
+        assert_eq!(config.share_among_handles, snapshot.share_among_handles);
+        assert_eq!(*config, *snapshot);
+
+        // An equal config which is loaded again is another one.
+        assert!(snapshot.is_of(&config));
+        assert!(snapshot.clone().is_of(&config));
+        assert!(!snapshot.is_of(&Arc::new((*config).clone())));
+    }
+}
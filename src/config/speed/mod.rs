@@ -0,0 +1,1465 @@
+pub use self::parse::parse_byte_size;
+
+mod parse;
+
+use self::parse::{parse_clamped, parse_speed, speed_error};
+use super::duration::format_duration;
+use super::ConfigError;
+use rand::distributions::{Exp1, Uniform};
+use rand::Rng;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::{Ordering, Reverse};
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::{Div, Mul};
+use std::str::FromStr;
+use std::time::Duration;
+use throttle::compute_delay;
+
+/// `Speed` is serialized as the string of `Speed::from_str` (e.g., "1MBps").
+/// It is deserialized from the string, a bare integer as `Speed::Bps` or the tagged form (e.g., `{"Bps": 1024}`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Speed {
+    Bps(u64),
+    /// A speed is picked randomly between `min` and `max` (inclusive) on each activation.
+    Range {
+        min: u64,
+        max: u64,
+    },
+    /// Each operation is delayed by the fixed duration before it is serviced regardless of its size.
+    /// It is exclusive with `Speed::Bps` (i.e., the byte rate is not limited).
+    Latency(Duration),
+    /// Each operation is delayed by the duration drawn uniformly between `min` and `max` (inclusive)
+    /// like `Speed::Latency` (e.g., "latency:10ms..200ms").
+    LatencyRange {
+        min: Duration,
+        max: Duration,
+    },
+    /// Each operation is delayed by the duration drawn from the exponential distribution of the mean
+    /// like `Speed::Latency` (e.g., "latency:exp:50ms"), so that a few operations have the long tail.
+    ExpLatency(Duration),
+    /// Each operation is delayed by the fixed `latency` and then the byte rate is limited by `bps`.
+    BpsWithLatency {
+        bps: u64,
+        latency: Duration,
+    },
+    /// The number of the operations per second is limited regardless of their sizes.
+    /// It is exclusive with the byte rates (i.e., the bytes are not limited).
+    Iops(u64),
+    /// The byte rate is the percentage of the baseline throughput of the underlying device.
+    /// The baseline is `Config::baseline` if set, otherwise it is measured at startup (see `calibrate`).
+    Percent(u8),
+    /// Each byte takes the duration (e.g., "2s/B") for the byte rates below one byte per second, which
+    /// `Speed::Bps` cannot represent. The faster ones are `Speed::Bps` (see `Speed::from_per_byte`).
+    /// The token buckets of `Config::burst` do not limit it.
+    PerByte(Duration),
+    PassThrough,
+}
+
+impl Speed {
+    /// A 3.5-inch HD floppy disk drive (500kbit/s).
+    pub const FLOPPY: Speed = Speed::Bps(62_500);
+    /// The signaling rate of USB 2.0 Hi-Speed (480Mbit/s). The actual throughput is less than this.
+    pub const USB2: Speed = Speed::Bps(60_000_000);
+    /// The sequential access of a typical 7200rpm hard disk drive.
+    pub const HDD_7200: Speed = Speed::mbps(120);
+
+    /// Creates `Speed::Bps` of `n` KBps (i.e., `n` * 1024 bytes per second as `Speed::from_str` does).
+    /// These helpers saturate at `u64::MAX`.
+    pub const fn kbps(n: u64) -> Speed {
+        Speed::Bps(n.saturating_mul(1 << 10))
+    }
+
+    /// Creates `Speed::Bps` of `n` MBps.
+    pub const fn mbps(n: u64) -> Speed {
+        Speed::Bps(n.saturating_mul(1 << 20))
+    }
+
+    /// Creates `Speed::Bps` of `n` GBps.
+    pub const fn gbps(n: u64) -> Speed {
+        Speed::Bps(n.saturating_mul(1 << 30))
+    }
+
+    /// Picks a concrete speed.
+    /// `Speed::Range` yields `Speed::Bps` randomly in the range and the others yield themselves.
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Speed {
+        match *self {
+            // The bounds are ordered in case the range is not validated (see `Config::validate`).
+            Speed::Range { min, max } => {
+                Speed::Bps(rng.sample(Uniform::new_inclusive(min.min(max), min.max(max))))
+            }
+            ref s => s.clone(),
+        }
+    }
+
+    /// Returns the slower (i.e., the more restrictive) of the speeds by `Ord`, where `PassThrough` is the fastest.
+    /// It returns `a` if they are equal.
+    ///
+    /// # Examples
+    /// ```
+    /// use mizumochi::config::Speed;
+    ///
+    /// let (slow, fast) = (Speed::Bps(1024), Speed::Bps(4096));
+    /// assert_eq!(&slow, Speed::min_speed(&slow, &fast));
+    /// assert_eq!(&fast, Speed::min_speed(&fast, &Speed::PassThrough));
+    /// ```
+    pub fn min_speed<'a>(a: &'a Speed, b: &'a Speed) -> &'a Speed {
+        if b < a {
+            b
+        } else {
+            a
+        }
+    }
+
+    /// Returns the faster (i.e., the less restrictive) of the speeds by `Ord`, where `PassThrough` is the fastest.
+    /// It returns `a` if they are equal.
+    pub fn max_speed<'a>(a: &'a Speed, b: &'a Speed) -> &'a Speed {
+        if a < b {
+            b
+        } else {
+            a
+        }
+    }
+
+    /// Returns true if the operations are never throttled at this speed.
+    pub fn is_passthrough(&self) -> bool {
+        *self == Speed::PassThrough
+    }
+
+    /// Returns true if the rate is zero (e.g., `Speed::Bps(0)` or a range from zero), which would stall
+    /// the operations forever. `Config::validate` rejects them, and `Engine` passes the operations through
+    /// at them instead of hanging if the config is not validated.
+    pub fn is_stalled(&self) -> bool {
+        matches!(
+            *self,
+            Speed::Bps(0)
+                | Speed::Iops(0)
+                | Speed::Range { min: 0, .. }
+                | Speed::BpsWithLatency { bps: 0, .. }
+                | Speed::Percent(0)
+        )
+    }
+
+    /// Returns the byte rate to limit if exists.
+    pub fn bps(&self) -> Option<u64> {
+        match *self {
+            Speed::Bps(bps) | Speed::BpsWithLatency { bps, .. } => Some(bps),
+            _ => None,
+        }
+    }
+
+    /// Returns the nominal byte rate, which is the slowest rate for `Speed::Range` (as `compute_delay` waits).
+    /// It is `None` for the speeds which are not byte rates (i.e., `PassThrough`, `Latency`, `Iops` and `Percent`)
+    /// and for `PerByte` of which rate is below one byte per second.
+    pub fn bytes_per_sec(&self) -> Option<u64> {
+        match *self {
+            Speed::Range { min, .. } => Some(min),
+            ref s => s.bps(),
+        }
+    }
+
+    /// Resolves `Speed::Percent` into `Speed::Bps` of the percentage of `baseline` bytes per second.
+    /// The result is one byte per second at least. The other speeds are kept as they are.
+    pub fn resolve(&self, baseline: u64) -> Speed {
+        match *self {
+            Speed::Percent(p) => {
+                let bps = (baseline as u128 * u128::from(p) / 100) as u64;
+                Speed::Bps(bps.max(1))
+            }
+            ref s => s.clone(),
+        }
+    }
+
+    /// Returns the speed at `progress` (from 0 to 1) of the ramp from `Speed::PassThrough` to this speed.
+    /// The delays (i.e., the time per byte, per operation and the latency) are interpolated linearly,
+    /// so the rates are divided by `progress` (e.g., `Speed::Bps(1024)` is `Speed::Bps(2048)` at 0.5).
+    /// It is `Speed::PassThrough` at 0 and this speed itself at 1 or later.
+    pub fn interpolate(&self, progress: f64) -> Speed {
+        if 1.0 <= progress {
+            return self.clone();
+        }
+        if progress <= 0.0 {
+            return Speed::PassThrough;
+        }
+
+        let rate = |x: u64| {
+            let x = (x as f64 / progress).round();
+            if u64::MAX as f64 <= x {
+                u64::MAX
+            } else {
+                x as u64
+            }
+        };
+        let latency = |d: Duration| d.mul_f64(progress);
+        match *self {
+            Speed::Latency(d) => Speed::Latency(latency(d)),
+            Speed::LatencyRange { min, max } => Speed::LatencyRange {
+                min: latency(min),
+                max: latency(max),
+            },
+            Speed::ExpLatency(d) => Speed::ExpLatency(latency(d)),
+            Speed::PerByte(d) => Speed::from_per_byte(latency(d)),
+            Speed::BpsWithLatency { bps, latency: d } => Speed::BpsWithLatency {
+                bps: rate(bps),
+                latency: latency(d),
+            },
+            ref s => s.clone().map_rates(rate),
+        }
+    }
+
+    /// Returns the speed of which each byte takes `d`.
+    /// It is `Speed::PerByte` below one byte per second, otherwise `Speed::Bps` of the rate rounded to the nearest
+    /// whole byte as the decimal rates are (e.g., 300ms is 3Bps). The zero is kept for `Config::validate` to reject.
+    pub fn from_per_byte(d: Duration) -> Speed {
+        let nanos = d.as_nanos();
+        if nanos == 0 || 1_000_000_000 < nanos {
+            Speed::PerByte(d)
+        } else {
+            Speed::Bps(((1_000_000_000 + nanos / 2) / nanos) as u64)
+        }
+    }
+
+    /// Returns the time per byte of `Speed::PerByte`.
+    pub fn per_byte(&self) -> Option<Duration> {
+        match *self {
+            Speed::PerByte(d) => Some(d),
+            _ => None,
+        }
+    }
+
+    /// Returns the fixed delay per operation if exists.
+    /// It is `None` for the random latencies, which `sample_latency` draws.
+    pub fn latency(&self) -> Option<Duration> {
+        match *self {
+            Speed::Latency(d) | Speed::BpsWithLatency { latency: d, .. } => Some(d),
+            _ => None,
+        }
+    }
+
+    /// Returns the delay of an operation: the fixed latency, or the one drawn for `Speed::LatencyRange`
+    /// and `Speed::ExpLatency`. It is `None` for the speeds without latencies.
+    /// The inverted range (which `Config::validate` rejects) is drawn as if it is not inverted.
+    pub fn sample_latency<R: Rng>(&self, rng: &mut R) -> Option<Duration> {
+        match *self {
+            Speed::LatencyRange { min, max } => {
+                let nanos = |d: Duration| u64::try_from(d.as_nanos()).unwrap_or(u64::MAX);
+                Some(Duration::from_nanos(rng.sample(Uniform::new_inclusive(
+                    nanos(min.min(max)),
+                    nanos(min.max(max)),
+                ))))
+            }
+            Speed::ExpLatency(mean) => {
+                let x: f64 = rng.sample(Exp1);
+                Some(Duration::try_from_secs_f64(mean.as_secs_f64() * x).unwrap_or(Duration::MAX))
+            }
+            ref s => s.latency(),
+        }
+    }
+
+    /// Parses the given string as same as `Speed::from_str` except the scale suffixes.
+    /// This interprets the suffixes in SI (base-1000) units (e.g., 1KBps = 1000Bps, 1MBps = 1000000Bps)
+    /// whereas `Speed::from_str` interprets them in binary (base-1024) units.
+    pub fn from_str_si(s: &str) -> Result<Self, ConfigError> {
+        parse_speed(s, 1000, false).map_err(|e| speed_error(s, e))
+    }
+
+    /// Parses the given string as same as `Speed::from_str` but the rates which overflow are clamped to
+    /// `u64::MAX` (e.g., "99999999999GBps") instead of the error, because it is as fast as `PassThrough`.
+    /// The bit rates are clamped to `u64::MAX` bits per second.
+    /// Returns the warning to show with the speed if it is clamped.
+    /// The config file and the command line options are parsed by it.
+    pub fn from_str_clamped(s: &str) -> Result<(Self, Option<String>), ConfigError> {
+        parse_clamped(s, 1 << 10)
+    }
+
+    /// Parses the given string as same as `Speed::from_str_clamped` but in SI units like `Speed::from_str_si`.
+    pub fn from_str_si_clamped(s: &str) -> Result<(Self, Option<String>), ConfigError> {
+        parse_clamped(s, 1000)
+    }
+
+    /// Scales the rates by `factor` in the precision of 1/1024 with `Mul` and `Div`.
+    /// The factor below 1/1024 is rounded up to it.
+    pub fn scale(self, factor: f64) -> Speed {
+        if factor == 1.0 {
+            return self;
+        }
+
+        let scaled = (factor * 1024.0).round().max(1.0) as u64;
+        self * scaled / 1024
+    }
+
+    /// Clamps the speed between `min` and `max` in the order of `Ord` (e.g., to keep the speeds of the generated
+    /// configs within the approved bounds): `min` if it is slower than `min`, and `max` if faster than `max`.
+    /// `Speed::PassThrough` is the fastest of all, so it is clamped to `max` unless `max` is `PassThrough` too.
+    /// Both ends of `Speed::Range` are clamped each if the bounds are `Speed::Bps`, so that the range is kept
+    /// within them.
+    ///
+    /// # Panics
+    /// Panics if `min` is faster than `max` as `Ord::clamp` does.
+    pub fn clamp(self, min: Speed, max: Speed) -> Speed {
+        assert!(
+            min <= max,
+            "min ({}) has to be slower than max ({})",
+            min,
+            max
+        );
+        if let (&Speed::Range { min: lo, max: hi }, &Speed::Bps(a), &Speed::Bps(b)) =
+            (&self, &min, &max)
+        {
+            return Speed::Range {
+                min: lo.clamp(a, b),
+                max: hi.clamp(a, b),
+            };
+        }
+
+        if self < min {
+            min
+        } else if max < self {
+            max
+        } else {
+            self
+        }
+    }
+
+    /// Returns how long a transfer of `bytes` bytes takes at the speed as one operation, i.e., the delay
+    /// of `compute_delay` without any tokens: `Speed::Range` is at its slowest rate and the latencies
+    /// are added once. It saturates at `Duration::MAX` (e.g., for a rate of zero).
+    /// It is `None` for `Speed::PassThrough`, which never waits, and for `Speed::Percent`, which has to be
+    /// resolved by `Speed::resolve` before.
+    ///
+    /// # Examples
+    /// ```
+    /// use mizumochi::config::Speed;
+    /// use std::time::Duration;
+    ///
+    /// assert_eq!(
+    ///     Some(Duration::from_secs(60)),
+    ///     Speed::mbps(1).time_for_bytes(60 << 20)
+    /// );
+    /// assert_eq!(None, Speed::PassThrough.time_for_bytes(1024));
+    /// ```
+    pub fn time_for_bytes(&self, bytes: u64) -> Option<Duration> {
+        match *self {
+            Speed::PassThrough | Speed::Percent(_) => None,
+            ref s => Some(compute_delay(
+                s,
+                usize::try_from(bytes).unwrap_or(usize::MAX),
+                0.0,
+            )),
+        }
+    }
+
+    /// Applies `f` to the rates (i.e., the byte rates and the IOPS) and keeps the others.
+    /// `Speed::PerByte` is kept too because its rate is below one byte per second.
+    fn map_rates<F: Fn(u64) -> u64>(self, f: F) -> Speed {
+        match self {
+            Speed::Bps(bps) => Speed::Bps(f(bps)),
+            Speed::Range { min, max } => Speed::Range {
+                min: f(min),
+                max: f(max),
+            },
+            Speed::BpsWithLatency { bps, latency } => Speed::BpsWithLatency {
+                bps: f(bps),
+                latency,
+            },
+            Speed::Iops(n) => Speed::Iops(f(n)),
+            s @ Speed::Latency(_)
+            | s @ Speed::LatencyRange { .. }
+            | s @ Speed::ExpLatency(_)
+            | s @ Speed::Percent(_)
+            | s @ Speed::PerByte(_)
+            | s @ Speed::PassThrough => s,
+        }
+    }
+}
+
+/// Orders the speeds from the slowest to the fastest.
+///
+/// The byte rates (`Speed::Bps`, `Speed::Range` and `Speed::BpsWithLatency`) are ordered by their slowest rate
+/// (i.e., `min` of `Speed::Range`). For the same rate, `Speed::BpsWithLatency` is slower than `Speed::Bps`
+/// (the longer latency is the slower), which is slower than `Speed::Range`.
+/// `Speed::PerByte` is ordered between 0Bps and 1Bps (e.g., "2s/B" is between them) because its rate is below
+/// one byte per second.
+///
+/// The speeds of the different kinds are not comparable actually, so they are ordered by the kinds as below.
+/// `PassThrough` is the maximum (i.e., the fastest) of all.
+///
+/// `Speed::Percent` is relative to the baseline, so it is ordered after all absolute byte rates.
+///
+/// `Speed::Latency` (the longer is the slower) < `Speed::Iops` < the byte rates < `Speed::Percent`
+/// < `Speed::PassThrough`
+///
+/// The random latencies are ordered with `Speed::Latency` by their longest (i.e., `max` of `Speed::LatencyRange`)
+/// or mean (of `Speed::ExpLatency`) latencies. For the same latency, `Speed::Latency` is the slowest,
+/// then `Speed::LatencyRange` (the longer `min` is the slower) and `Speed::ExpLatency`.
+impl Ord for Speed {
+    fn cmp(&self, other: &Speed) -> Ordering {
+        order_key(self).cmp(&order_key(other))
+    }
+}
+
+impl PartialOrd for Speed {
+    fn partial_cmp(&self, other: &Speed) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Returns the key of `Ord` for `Speed`. It is unique for each speed to be consistent with `Eq`.
+fn order_key(s: &Speed) -> (u8, u64, u8, Reverse<Duration>, u64) {
+    let zero = Duration::from_secs(0);
+    // The longer latency is the smaller.
+    let latency = |d: Duration| u64::MAX - u64::try_from(d.as_nanos()).unwrap_or(u64::MAX);
+    match *s {
+        Speed::Latency(d) => (0, latency(d), 0, Reverse(d), 0),
+        Speed::LatencyRange { min, max } => (0, latency(max), 1, Reverse(min), 0),
+        Speed::ExpLatency(mean) => (0, latency(mean), 2, Reverse(mean), 0),
+        Speed::Iops(n) => (1, n, 0, Reverse(zero), 0),
+        Speed::BpsWithLatency { bps, latency } => (2, bps, 0, Reverse(latency), 0),
+        Speed::Bps(bps) => (2, bps, 1, Reverse(zero), 0),
+        Speed::Range { min, max } => (2, min, 2, Reverse(zero), max),
+        Speed::PerByte(d) => {
+            let rate = 1_000_000_000u128
+                .checked_div(d.as_nanos())
+                .map_or(u64::MAX, |r| u64::try_from(r).unwrap_or(u64::MAX));
+            (2, rate, 3, Reverse(d), 0)
+        }
+        Speed::Percent(p) => (3, u64::from(p), 0, Reverse(zero), 0),
+        Speed::PassThrough => (4, 0, 0, Reverse(zero), 0),
+    }
+}
+
+/// Scales the rates of the speed.
+/// The rates saturate at `u64::MAX` instead of overflowing.
+/// The time per byte of `Speed::PerByte` is divided instead, which is one nanosecond at least.
+/// `Speed::Latency`, `Speed::Percent` and `Speed::PassThrough` are kept as they are.
+impl Mul<u64> for Speed {
+    type Output = Speed;
+
+    fn mul(self, rhs: u64) -> Speed {
+        match self {
+            Speed::PerByte(d) if rhs != 0 => {
+                Speed::from_per_byte(nanos_saturating((d.as_nanos() / u128::from(rhs)).max(1)))
+            }
+            s => s.map_rates(|x| x.saturating_mul(rhs)),
+        }
+    }
+}
+
+/// Scales down the rates of the speed.
+/// The rates are rounded down but one at least so that the result is still valid.
+/// The time per byte of `Speed::PerByte` is multiplied instead.
+/// Dividing by zero keeps the speed as it is instead of panicking.
+/// `Speed::Latency`, `Speed::Percent` and `Speed::PassThrough` are kept as they are.
+impl Div<u64> for Speed {
+    type Output = Speed;
+
+    fn div(self, rhs: u64) -> Speed {
+        if rhs == 0 {
+            return self;
+        }
+
+        match self {
+            Speed::PerByte(d) => Speed::from_per_byte(nanos_saturating(
+                d.as_nanos().saturating_mul(u128::from(rhs)),
+            )),
+            s => s.map_rates(|x| (x / rhs).max(1)),
+        }
+    }
+}
+
+fn nanos_saturating(nanos: u128) -> Duration {
+    Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+}
+
+impl FromStr for Speed {
+    type Err = ConfigError;
+
+    /// Parses the given string as speed.
+    /// The scale suffixes are interpreted in binary (base-1024) units (e.g., 1KBps = 1024Bps).
+    /// Use `Speed::from_str_si` for SI (base-1000) units.
+    ///
+    /// The units of the rates accept the aliases:
+    ///
+    /// | | byte per second | bit per second |
+    /// |---|---|---|
+    /// | unit | `Bps`, `B/s` | `bps`, `b/s` |
+    /// | scale | `K`, `k`, `Ki`, `ki` (and so on for `M`, `G`, `T` and `P`) | the same |
+    ///
+    /// The case of the scale does not matter, but the case of the unit does: "B" is a byte and "b" is a bit
+    /// (e.g., "1kB/s" is 1KBps, but "1kbps" is 1Kbps, i.e., 128Bps).
+    /// The binary scales with "i" (e.g., "KiBps") are base-1024 even in SI units.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_speed(s, 1 << 10, false).map_err(|e| speed_error(s, e))
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Speed::Bps(bps) if bps < 1 << 10 => write!(f, "{}Bps", bps),
+            Speed::Bps(bps) if bps < 1 << 20 => write_scaled(f, bps, 1 << 10, "KBps"),
+            Speed::Bps(bps) if bps < 1 << 30 => write_scaled(f, bps, 1 << 20, "MBps"),
+            Speed::Bps(bps) if bps < 1 << 40 => write_scaled(f, bps, 1 << 30, "GBps"),
+            Speed::Bps(bps) if bps < 1 << 50 => write_scaled(f, bps, 1 << 40, "TBps"),
+            Speed::Bps(bps) => write_scaled(f, bps, 1 << 50, "PBps"),
+            Speed::Range { min, max } => write!(f, "{}..{}", Speed::Bps(min), Speed::Bps(max)),
+            Speed::Latency(ref d) => write!(f, "latency:{}", format_duration(d)),
+            Speed::LatencyRange { ref min, ref max } => write!(
+                f,
+                "latency:{}..{}",
+                format_duration(min),
+                format_duration(max)
+            ),
+            Speed::ExpLatency(ref d) => write!(f, "latency:exp:{}", format_duration(d)),
+            Speed::BpsWithLatency { bps, ref latency } => {
+                write!(f, "{}+{}", Speed::Bps(bps), format_duration(latency))
+            }
+            Speed::Iops(n) => write!(f, "{}iops", n),
+            Speed::Percent(p) => write!(f, "{}%", p),
+            // The time per byte is printed only below one byte per second, and the faster one as the byte rate.
+            Speed::PerByte(d) => match Speed::from_per_byte(d) {
+                Speed::Bps(bps) => write!(f, "{}", Speed::Bps(bps)),
+                _ => write!(f, "{}/B", format_duration(&d)),
+            },
+            Speed::PassThrough => write!(f, "PassThrough"),
+        }
+    }
+}
+
+impl Serialize for Speed {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+// The tagged form which the derived `Deserialize` accepted.
+#[derive(Deserialize)]
+enum Tagged {
+    Bps(u64),
+    Range { min: u64, max: u64 },
+    Latency(Duration),
+    LatencyRange { min: Duration, max: Duration },
+    ExpLatency(Duration),
+    BpsWithLatency { bps: u64, latency: Duration },
+    Iops(u64),
+    Percent(u8),
+    PerByte(Duration),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Repr {
+    Str(String),
+    Int(u64),
+    Tagged(Tagged),
+}
+
+impl<'de> Deserialize<'de> for Speed {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Speed, D::Error> {
+        match Repr::deserialize(deserializer)? {
+            // "PassThrough" is the tagged form too.
+            Repr::Str(s) => parse_speed(&s, 1 << 10, false)
+                .map_err(|e| D::Error::custom(format!("invalid speed {:?}: {}", s, e))),
+            Repr::Int(bps) => Ok(Speed::Bps(bps)),
+            Repr::Tagged(t) => Ok(match t {
+                Tagged::Bps(bps) => Speed::Bps(bps),
+                Tagged::Range { min, max } => Speed::Range { min, max },
+                Tagged::Latency(d) => Speed::Latency(d),
+                Tagged::LatencyRange { min, max } => Speed::LatencyRange { min, max },
+                Tagged::ExpLatency(d) => Speed::ExpLatency(d),
+                Tagged::BpsWithLatency { bps, latency } => Speed::BpsWithLatency { bps, latency },
+                Tagged::Iops(n) => Speed::Iops(n),
+                Tagged::Percent(p) => Speed::Percent(p),
+                Tagged::PerByte(d) => Speed::from_per_byte(d),
+            }),
+        }
+    }
+}
+
+/// Writes `bps` in the given scale so that `Speed::from_str` can parse it back to the same value.
+fn write_scaled(f: &mut fmt::Formatter, bps: u64, scale: u64, suffix: &str) -> fmt::Result {
+    // If `bps` is exactly representable in f64, `bps / scale` is exact too because `scale` is a power of two.
+    // Then, it is printed in the shortest form which parses back to the same f64.
+    // Otherwise, fall back to Bps.
+    if (bps as f64) as u128 == bps as u128 {
+        write!(f, "{}{}", bps as f64 / scale as f64, suffix)
+    } else {
+        write!(f, "{}Bps", bps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_speed_new() {
+        assert!(Speed::from_str("").is_err());
+        assert!(Speed::from_str("alskjaslkdfjhasjdhfb").is_err());
+        assert!(Speed::from_str("Bps").is_err());
+        assert_eq!(
+            Err(ConfigError::InvalidSpeed(
+                "invalid digit found in string".to_string()
+            )),
+            Speed::from_str("alskjaslkdfjhasjdhfb")
+        );
+        assert_eq!(
+            Err(ConfigError::InvalidSpeed(
+                "cannot parse integer from empty string".to_string()
+            )),
+            Speed::from_str("")
+        );
+        assert_eq!(Ok(Speed::Bps(1 << 10)), Speed::from_str("1024"));
+        assert_eq!(Ok(Speed::Bps(1 << 10)), Speed::from_str("1024Bps"));
+        assert_eq!(Ok(Speed::Bps(1 << 20)), Speed::from_str("1024KBps"));
+        assert_eq!(Ok(Speed::Bps(1 << 30)), Speed::from_str("1024MBps"));
+        assert_eq!(Ok(Speed::Bps(1 << 40)), Speed::from_str("1024GBps"));
+    }
+
+    #[test]
+    fn test_speed_unit_aliases() {
+        for input in [
+            "1KBps", "1kBps", "1KiBps", "1kiBps", "1KB/s", "1kB/s", "1KiB/s", "1 kiB/s",
+        ]
+        .iter()
+        {
+            assert_eq!(Ok(Speed::Bps(1 << 10)), Speed::from_str(input), "{}", input);
+        }
+        for input in ["2MBps", "2mBps", "2MiBps", "2MiB/s", "2miB/s"].iter() {
+            assert_eq!(Ok(Speed::Bps(2 << 20)), Speed::from_str(input), "{}", input);
+        }
+
+        // The lowercase "b" is still a bit.
+        for input in ["1Kbps", "1kbps", "1Kibps", "1kib/s"].iter() {
+            assert_eq!(Ok(Speed::Bps(128)), Speed::from_str(input), "{}", input);
+        }
+
+        // The binary scales are base-1024 in SI units too.
+        assert_eq!(Ok(Speed::Bps(1000)), Speed::from_str_si("1kB/s"));
+        assert_eq!(Ok(Speed::Bps(1 << 10)), Speed::from_str_si("1KiBps"));
+
+        assert!(Speed::from_str("1iBps").is_err());
+        assert!(Speed::from_str("1KiiBps").is_err());
+        assert!(Speed::from_str("1KB/m").is_err());
+        // The canonical form is kept.
+        assert_eq!("1KBps", Speed::from_str("1kiB/s").unwrap().to_string());
+    }
+
+    #[test]
+    fn test_speed_large_scale() {
+        // The rates are u64 on all targets including 32-bit ones.
+        assert_eq!(Ok(Speed::Bps(4 << 30)), Speed::from_str("4GBps"));
+        assert_eq!(Ok(Speed::Bps(2 << 40)), Speed::from_str("2TBps"));
+        assert_eq!(Ok(Speed::Bps(3 << 50)), Speed::from_str("3PBps"));
+        assert_eq!(Ok(Speed::Bps(1 << 37)), Speed::from_str("1Tbps"));
+        assert_eq!(
+            Err(ConfigError::Overflow("1048576PBps".to_string())),
+            Speed::from_str("1048576PBps")
+        );
+
+        assert_eq!("2TBps", Speed::Bps(2 << 40).to_string());
+        assert_eq!("1PBps", Speed::Bps(1 << 50).to_string());
+        assert_eq!("1024PBps", Speed::Bps(1 << 60).to_string());
+    }
+
+    #[test]
+    fn test_speed_range() {
+        assert_eq!(
+            Ok(Speed::Range {
+                min: 1 << 20,
+                max: 5 << 20,
+            }),
+            Speed::from_str("1MBps..5MBps")
+        );
+        assert_eq!(
+            Ok(Speed::Range { min: 512, max: 512 }),
+            Speed::from_str("512..0.5KBps")
+        );
+        assert_eq!(
+            "1MBps..5MBps",
+            Speed::from_str("1MBps..5MBps").unwrap().to_string()
+        );
+
+        assert!(Speed::from_str("5MBps..1MBps").is_err());
+        assert!(Speed::from_str("1MBps..").is_err());
+        assert!(Speed::from_str("..1MBps").is_err());
+        assert!(Speed::from_str("1MBps..pass_through").is_err());
+        assert!(Speed::from_str("1MBps..2MBps..3MBps").is_err());
+    }
+
+    #[test]
+    fn test_speed_sample() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let range = Speed::Range { min: 10, max: 20 };
+        for _ in 0..1000 {
+            match range.sample(&mut rng) {
+                Speed::Bps(bps) => assert!((10..=20).contains(&bps), "{}", bps),
+                s => panic!("unexpected speed: {}", s),
+            }
+        }
+
+        // The inverted range does not panic.
+        let inverted = Speed::Range { min: 20, max: 10 };
+        for _ in 0..1000 {
+            match inverted.sample(&mut rng) {
+                Speed::Bps(bps) => assert!((10..=20).contains(&bps), "{}", bps),
+                s => panic!("unexpected speed: {}", s),
+            }
+        }
+
+        assert_eq!(Speed::Bps(10), Speed::Bps(10).sample(&mut rng));
+        assert_eq!(Speed::PassThrough, Speed::PassThrough.sample(&mut rng));
+    }
+
+    #[test]
+    fn test_speed_latency() {
+        assert_eq!(
+            Ok(Speed::Latency(Duration::from_millis(50))),
+            Speed::from_str("latency:50ms")
+        );
+        assert_eq!(
+            Ok(Speed::Latency(Duration::from_secs(1))),
+            Speed::from_str("latency:1s")
+        );
+        assert!(Speed::from_str("latency:").is_err());
+        assert!(Speed::from_str("latency:50").is_err());
+        assert!(Speed::from_str("latency:fast").is_err());
+
+        for input in ["latency:50ms", "latency:1500ms", "latency:2s", "latency:1m"].iter() {
+            let speed = Speed::from_str(input).unwrap();
+            assert_eq!(*input, speed.to_string());
+            assert_eq!(Ok(speed.clone()), Speed::from_str(&speed.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_speed_random_latency() {
+        let ms = Duration::from_millis;
+        assert_eq!(
+            Ok(Speed::LatencyRange {
+                min: ms(10),
+                max: ms(200),
+            }),
+            Speed::from_str("latency:10ms..200ms")
+        );
+        assert_eq!(
+            Ok(Speed::ExpLatency(ms(50))),
+            Speed::from_str("latency:exp:50ms")
+        );
+        assert!(Speed::from_str("latency:200ms..10ms").is_err());
+        assert!(Speed::from_str("latency:10ms..").is_err());
+        assert!(Speed::from_str("latency:exp:").is_err());
+        for input in ["latency:10ms..200ms", "latency:exp:50ms"].iter() {
+            assert_eq!(*input, Speed::from_str(input).unwrap().to_string());
+        }
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let n = 10000;
+        let range = Speed::LatencyRange {
+            min: ms(10),
+            max: ms(200),
+        };
+        let mut total = Duration::default();
+        for _ in 0..n {
+            let d = range.sample_latency(&mut rng).unwrap();
+            assert!(ms(10) <= d && d <= ms(200), "{:?}", d);
+            total += d;
+        }
+        let mean = (total / n).as_secs_f64();
+        assert!((mean - 0.105).abs() < 0.005, "{}", mean);
+
+        let exp = Speed::ExpLatency(ms(50));
+        let samples = (0..n)
+            .map(|_| exp.sample_latency(&mut rng).unwrap())
+            .collect::<Vec<_>>();
+        let mean = (samples.iter().sum::<Duration>() / n).as_secs_f64();
+        assert!((mean - 0.05).abs() < 0.0025, "{}", mean);
+        // The tail is longer than the mean.
+        assert!(samples.iter().any(|d| ms(200) < *d));
+
+        // The fixed latencies are not drawn, and the others have no latency.
+        let latency = Speed::Latency(ms(50));
+        assert_eq!(Some(ms(50)), latency.sample_latency(&mut rng));
+        assert_eq!(None, range.latency());
+        assert_eq!(None, Speed::Bps(1).sample_latency(&mut rng));
+        // The random ones are slower than the fixed one of the mean.
+        assert!(range < Speed::Latency(ms(105)));
+        assert!(Speed::Latency(ms(50)) < exp);
+    }
+
+    #[test]
+    fn test_speed_bps_with_latency() {
+        let speed = Speed::BpsWithLatency {
+            bps: 1 << 20,
+            latency: Duration::from_millis(50),
+        };
+        assert_eq!(Ok(speed.clone()), Speed::from_str("1MBps+50ms"));
+        assert_eq!("1MBps+50ms", speed.to_string());
+        assert_eq!(Some(1 << 20), speed.bps());
+        assert_eq!(Some(Duration::from_millis(50)), speed.latency());
+
+        assert_eq!(None, Speed::Bps(1).latency());
+        assert_eq!(None, Speed::Latency(Duration::from_millis(1)).bps());
+
+        assert!(Speed::from_str("1MBps+").is_err());
+        assert!(Speed::from_str("+50ms").is_err());
+        assert!(Speed::from_str("1MBps+50").is_err());
+        assert!(Speed::from_str("1MBps..2MBps+50ms").is_err());
+        assert!(Speed::from_str("latency:50ms+1MBps").is_err());
+    }
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(Ok(100), parse_byte_size("100"));
+        assert_eq!(Ok(100), parse_byte_size("100B"));
+        assert_eq!(Ok(1 << 10), parse_byte_size("1KB"));
+        assert_eq!(Ok(100 << 20), parse_byte_size("100MB"));
+        assert_eq!(Ok(100 << 20), parse_byte_size("100MBps"));
+        assert_eq!(Ok(1536), parse_byte_size("1.5KB"));
+
+        assert!(parse_byte_size("").is_err());
+        assert!(parse_byte_size("B").is_err());
+        assert!(parse_byte_size("MB").is_err());
+        assert!(parse_byte_size("100Mb").is_err());
+        assert!(parse_byte_size("100XB").is_err());
+
+        // The kinds of the errors.
+        match parse_byte_size("100XB") {
+            Err(ConfigError::InvalidSize(_)) => {}
+            r => panic!("{:?}", r),
+        }
+        assert_eq!(
+            Err(ConfigError::Overflow("99999999PB".to_string())),
+            parse_byte_size("99999999PB")
+        );
+        match Speed::from_str_si("fast") {
+            Err(ConfigError::InvalidSpeed(_)) => {}
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_speed_is_stalled() {
+        assert!(Speed::Bps(0).is_stalled());
+        assert!(Speed::Range { min: 0, max: 1 }.is_stalled());
+        assert!(Speed::Percent(0).is_stalled());
+        assert!(!Speed::Bps(1).is_stalled());
+        assert!(!Speed::Latency(Duration::from_secs(0)).is_stalled());
+        assert!(!Speed::PassThrough.is_stalled());
+    }
+
+    #[test]
+    fn test_speed_per_byte() {
+        // The integer rates are byte rates.
+        assert_eq!(Ok(Speed::Bps(10)), Speed::from_str("100ms/B"));
+        assert_eq!(Ok(Speed::Bps(1)), Speed::from_str("1s/B"));
+        assert_eq!(
+            Ok(Speed::PerByte(Duration::from_secs(2))),
+            Speed::from_str("2s/B")
+        );
+        // The rates of one byte per second or faster are rounded to the nearest whole byte.
+        assert_eq!(Ok(Speed::Bps(3)), Speed::from_str(" 300ms/B "));
+        assert_eq!(Ok(Speed::Bps(2)), Speed::from_str("600ms/B"));
+        assert_eq!(
+            Ok(Speed::PerByte(Duration::from_secs(90))),
+            Speed::from_str("1m30s/B")
+        );
+
+        for s in &["0s/B", "-1s/B", "/B", "100/B", "100ms/b"] {
+            assert!(Speed::from_str(s).is_err(), "{}", s);
+        }
+
+        // The speeds below 1Bps are printed in the time per byte.
+        assert_eq!("2s/B", Speed::PerByte(Duration::from_secs(2)).to_string());
+        assert_eq!("1m/B", Speed::PerByte(Duration::from_secs(60)).to_string());
+        for s in &["2s/B", "90s/B", "1h/B"] {
+            assert_eq!(*s, Speed::from_str(s).unwrap().to_string());
+        }
+        // The faster ones are printed in the byte rate even if built directly.
+        assert_eq!(
+            "3Bps",
+            Speed::PerByte(Duration::from_millis(300)).to_string()
+        );
+        assert_eq!("1Bps", Speed::PerByte(Duration::from_secs(1)).to_string());
+
+        // Slower than 1Bps, and between the integer rates of it.
+        let per_byte = |ms| Speed::PerByte(Duration::from_millis(ms));
+        assert!(per_byte(2000) < Speed::Bps(1));
+        assert!(per_byte(3000) < per_byte(2000));
+        assert!(Speed::Bps(0) < per_byte(2000));
+
+        // The rates are scaled by the time per byte, and they are byte rates once one byte per second or faster.
+        assert_eq!(per_byte(1500), per_byte(3000) * 2);
+        assert_eq!(Speed::Bps(2), per_byte(2000) * 4);
+        assert_eq!(per_byte(8000), per_byte(2000) / 4);
+        assert_eq!(Speed::Bps(1), per_byte(2000).interpolate(0.5));
+        assert_eq!(None, per_byte(2000).bps());
+    }
+
+    #[test]
+    fn test_speed_iops() {
+        assert_eq!(Ok(Speed::Iops(100)), Speed::from_str("100iops"));
+        assert_eq!(Ok(Speed::Iops(100)), Speed::from_str_si("100iops"));
+        assert_eq!("100iops", Speed::Iops(100).to_string());
+        assert_eq!(None, Speed::Iops(100).bps());
+
+        assert!(Speed::from_str("iops").is_err());
+        assert!(Speed::from_str("1.5iops").is_err());
+        assert!(Speed::from_str("100iops..200iops").is_err());
+    }
+
+    #[test]
+    fn test_speed_round_trip() {
+        let mut inputs = vec![0, 1, 1023, 1024, 1025, 1_500_000, 1 << 30, (1 << 30) + 1];
+        inputs.extend(1..5000);
+        inputs.extend((0..64).map(|i| 7919 * i * i * i));
+        inputs.extend((0..u64::MAX.count_ones()).map(|i| 1 << i));
+        inputs.extend((0..u64::MAX.count_ones()).map(|i| (1 << i) - 1));
+        inputs.push(u64::MAX);
+
+        for bps in inputs {
+            let speed = Speed::Bps(bps);
+            assert_eq!(
+                Ok(speed.clone()),
+                Speed::from_str(&speed.to_string()),
+                "{}",
+                speed
+            );
+        }
+
+        assert_eq!(
+            Ok(Speed::PassThrough),
+            Speed::from_str(&Speed::PassThrough.to_string())
+        );
+    }
+
+    #[test]
+    fn test_speed_si() {
+        assert_eq!(Ok(Speed::Bps(1024)), Speed::from_str_si("1024"));
+        assert_eq!(Ok(Speed::Bps(1000)), Speed::from_str_si("1KBps"));
+        assert_eq!(Ok(Speed::Bps(1_000_000)), Speed::from_str_si("1MBps"));
+        assert_eq!(Ok(Speed::Bps(1_000_000_000)), Speed::from_str_si("1GBps"));
+        assert_eq!(Ok(Speed::Bps(1_500_000)), Speed::from_str_si("1.5MBps"));
+        assert_eq!(Ok(Speed::Bps(125_000)), Speed::from_str_si("1Mbps"));
+        assert!(Speed::from_str_si("").is_err());
+
+        // The both paths diverge for the same input.
+        for input in ["1KBps", "1MBps", "1GBps", "1Mbps"].iter() {
+            assert_ne!(
+                Speed::from_str(input),
+                Speed::from_str_si(input),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_speed_bit_per_second() {
+        assert_eq!(Ok(Speed::Bps(1)), Speed::from_str("8bps"));
+        assert_eq!(Ok(Speed::Bps(0)), Speed::from_str("7bps"));
+        assert_eq!(Ok(Speed::Bps(1)), Speed::from_str("15bps"));
+        assert_eq!(Ok(Speed::Bps(128)), Speed::from_str("1Kbps"));
+        assert_eq!(Ok(Speed::Bps(131_072)), Speed::from_str("1Mbps"));
+        assert_eq!(Ok(Speed::Bps(134_217_728)), Speed::from_str("1Gbps"));
+        assert!(Speed::from_str("bps").is_err());
+        assert!(Speed::from_str("Kbps").is_err());
+        assert!(Speed::from_str("xyzbps").is_err());
+    }
+
+    #[test]
+    fn test_speed_decimal() {
+        let cases = [
+            ("1.5MBps", 1_572_864),
+            ("0.5KBps", 512),
+            (".5KBps", 512),
+            ("1.25KBps", 1280),
+            ("1.250KBps", 1280),
+            ("2.0MBps", 2 << 20),
+            ("1.5", 2),
+            ("0.4Bps", 0),
+        ];
+        for &(input, expected) in cases.iter() {
+            assert_eq!(
+                Ok(Speed::Bps(expected)),
+                Speed::from_str(input),
+                "{}",
+                input
+            );
+        }
+
+        assert!(Speed::from_str("1.5.5KBps").is_err());
+        assert!(Speed::from_str(".KBps").is_err());
+        assert!(Speed::from_str("-1.5KBps").is_err());
+        assert_eq!(
+            Err(ConfigError::Overflow(
+                "99999999999999999999.5GBps".to_string()
+            )),
+            Speed::from_str("99999999999999999999.5GBps")
+        );
+    }
+
+    #[test]
+    fn test_speed_scale() {
+        assert_eq!(Speed::Bps(2048), Speed::Bps(1024) * 2);
+        assert_eq!(Speed::Bps(256), Speed::Bps(1024) / 4);
+        assert_eq!(
+            Speed::Range { min: 20, max: 40 },
+            Speed::Range { min: 10, max: 20 } * 2
+        );
+        assert_eq!(
+            Speed::BpsWithLatency {
+                bps: 512,
+                latency: Duration::from_millis(50),
+            },
+            Speed::BpsWithLatency {
+                bps: 1024,
+                latency: Duration::from_millis(50),
+            } / 2
+        );
+        assert_eq!(Speed::Iops(300), Speed::Iops(100) * 3);
+
+        // Not scaled.
+        assert_eq!(Speed::PassThrough, Speed::PassThrough * 2);
+        assert_eq!(Speed::PassThrough, Speed::PassThrough / 2);
+        let latency = Speed::Latency(Duration::from_millis(50));
+        assert_eq!(latency, latency.clone() * 2);
+
+        // Saturated.
+        assert_eq!(Speed::Bps(u64::MAX), Speed::Bps(u64::MAX / 2 + 1) * 2);
+        assert_eq!(Speed::Bps(u64::MAX), Speed::Bps(1) * u64::MAX);
+        assert_eq!(Speed::Bps(1), Speed::Bps(3) / 4);
+
+        // Dividing by zero keeps the speed.
+        assert_eq!(Speed::Bps(1024), Speed::Bps(1024) / 0);
+
+        // By the factors.
+        assert_eq!(Speed::Bps(512), Speed::Bps(1024).scale(0.5));
+        assert_eq!(Speed::Bps(1536), Speed::Bps(1024).scale(1.5));
+        assert_eq!(Speed::Iops(25), Speed::Iops(100).scale(0.25));
+        assert_eq!(Speed::Bps(1), Speed::Bps(1024).scale(0.0001));
+        assert_eq!(Speed::Bps(u64::MAX), Speed::Bps(u64::MAX).scale(1.0));
+    }
+
+    #[test]
+    fn test_speed_ord() {
+        assert!(Speed::Bps(1024) < Speed::Bps(2048));
+        assert!(Speed::Bps(2048) < Speed::PassThrough);
+        assert!(Speed::Bps(u64::MAX) < Speed::PassThrough);
+        assert_eq!(
+            Speed::Bps(1024),
+            ::std::cmp::min(Speed::Bps(1024), Speed::PassThrough)
+        );
+
+        let latency = |ms| Speed::Latency(Duration::from_millis(ms));
+        let mut speeds = vec![
+            Speed::PassThrough,
+            Speed::Range {
+                min: 1024,
+                max: 4096,
+            },
+            Speed::Bps(2048),
+            Speed::Iops(100),
+            Speed::Bps(1024),
+            Speed::BpsWithLatency {
+                bps: 1024,
+                latency: Duration::from_millis(10),
+            },
+            latency(10),
+            latency(50),
+            Speed::Iops(10),
+        ];
+        speeds.sort();
+        assert_eq!(
+            vec![
+                latency(50),
+                latency(10),
+                Speed::Iops(10),
+                Speed::Iops(100),
+                Speed::BpsWithLatency {
+                    bps: 1024,
+                    latency: Duration::from_millis(10),
+                },
+                Speed::Bps(1024),
+                Speed::Range {
+                    min: 1024,
+                    max: 4096,
+                },
+                Speed::Bps(2048),
+                Speed::PassThrough,
+            ],
+            speeds
+        );
+    }
+
+    #[test]
+    fn test_speed_clamp() {
+        let (min, max) = (Speed::kbps(1), Speed::mbps(1));
+        // Below the min, above the max and within the bounds.
+        assert_eq!(min, Speed::Bps(10).clamp(min.clone(), max.clone()));
+        assert_eq!(max, Speed::gbps(1).clamp(min.clone(), max.clone()));
+        assert_eq!(
+            Speed::kbps(64),
+            Speed::kbps(64).clamp(min.clone(), max.clone())
+        );
+        assert_eq!(min, min.clone().clamp(min.clone(), max.clone()));
+
+        // `PassThrough` is the fastest.
+        assert_eq!(max, Speed::PassThrough.clamp(min.clone(), max.clone()));
+        assert_eq!(
+            Speed::PassThrough,
+            Speed::PassThrough.clamp(min.clone(), Speed::PassThrough)
+        );
+        assert_eq!(
+            Speed::gbps(1),
+            Speed::gbps(1).clamp(min.clone(), Speed::PassThrough)
+        );
+        assert_eq!(
+            Speed::PassThrough,
+            Speed::Bps(10).clamp(Speed::PassThrough, Speed::PassThrough)
+        );
+
+        // The ends of a range are clamped each.
+        let range = |min, max| Speed::Range { min, max };
+        assert_eq!(
+            range(1 << 10, 1 << 20),
+            range(1, u64::MAX).clamp(min.clone(), max.clone())
+        );
+        assert_eq!(
+            range(4 << 10, 1 << 20),
+            range(4 << 10, 8 << 20).clamp(min.clone(), max.clone())
+        );
+        assert_eq!(
+            range(1 << 20, 1 << 20),
+            range(2 << 20, 8 << 20).clamp(min.clone(), max.clone())
+        );
+
+        // The other kinds are clamped as the whole.
+        assert_eq!(min, Speed::Iops(100).clamp(min.clone(), max.clone()));
+        assert_eq!(max, Speed::Percent(50).clamp(min.clone(), max.clone()));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_speed_clamp_inverted() {
+        Speed::kbps(64).clamp(Speed::PassThrough, Speed::kbps(1));
+    }
+
+    #[test]
+    fn test_speed_min_max() {
+        let speeds = [
+            Speed::Latency(Duration::from_millis(50)),
+            Speed::Iops(100),
+            Speed::Bps(1024),
+            Speed::Bps(4096),
+            Speed::Percent(10),
+            Speed::PassThrough,
+        ];
+        for (i, a) in speeds.iter().enumerate() {
+            for b in &speeds[i..] {
+                assert_eq!(a, Speed::min_speed(a, b));
+                assert_eq!(a, Speed::min_speed(b, a));
+                assert_eq!(b, Speed::max_speed(a, b));
+                assert_eq!(b, Speed::max_speed(b, a));
+            }
+        }
+
+        // The first one is returned for the equal speeds.
+        let (a, b) = (Speed::Bps(1024), Speed::Bps(1024));
+        assert!(::std::ptr::eq(&a, Speed::min_speed(&a, &b)));
+        assert!(::std::ptr::eq(&a, Speed::max_speed(&a, &b)));
+    }
+
+    #[test]
+    fn test_speed_time_for_bytes() {
+        assert_eq!(
+            Some(Duration::from_secs(4)),
+            Speed::Bps(1024).time_for_bytes(4096)
+        );
+        assert_eq!(
+            Some(Duration::from_millis(500)),
+            Speed::Range {
+                min: 1024,
+                max: 4096
+            }
+            .time_for_bytes(512)
+        );
+        assert_eq!(
+            Some(Duration::from_millis(1050)),
+            Speed::BpsWithLatency {
+                bps: 1024,
+                latency: Duration::from_millis(50)
+            }
+            .time_for_bytes(1024)
+        );
+
+        // Zero bytes are free but for the latency.
+        assert_eq!(
+            Some(Duration::default()),
+            Speed::Bps(1024).time_for_bytes(0)
+        );
+        assert_eq!(
+            Some(Duration::from_millis(50)),
+            Speed::Latency(Duration::from_millis(50)).time_for_bytes(0)
+        );
+
+        // Saturates.
+        assert_eq!(Some(Duration::MAX), Speed::Bps(1).time_for_bytes(u64::MAX));
+        assert_eq!(Some(Duration::MAX), Speed::Bps(0).time_for_bytes(1));
+
+        assert_eq!(None, Speed::PassThrough.time_for_bytes(1024));
+        assert_eq!(None, Speed::Percent(10).time_for_bytes(1024));
+    }
+
+    #[test]
+    fn test_speed_helpers() {
+        assert_eq!(Speed::from_str("1KBps").unwrap(), Speed::kbps(1));
+        assert_eq!(Speed::from_str("1MBps").unwrap(), Speed::mbps(1));
+        assert_eq!(Speed::from_str("10MBps").unwrap(), Speed::mbps(10));
+        assert_eq!(Speed::from_str("1GBps").unwrap(), Speed::gbps(1));
+        assert_eq!(Speed::Bps(u64::MAX), Speed::gbps(u64::MAX));
+
+        assert!(Speed::FLOPPY < Speed::USB2);
+        assert!(Speed::USB2 < Speed::HDD_7200);
+        assert_eq!("120MBps", Speed::HDD_7200.to_string());
+
+        assert!(Speed::PassThrough.is_passthrough());
+        assert!(!Speed::Bps(0).is_passthrough());
+        assert!(!Speed::Percent(100).is_passthrough());
+    }
+
+    #[test]
+    fn test_speed_bytes_per_sec() {
+        assert_eq!(Some(1 << 20), Speed::mbps(1).bytes_per_sec());
+        assert_eq!(Some(0), Speed::Bps(0).bytes_per_sec());
+        assert_eq!(None, Speed::PassThrough.bytes_per_sec());
+
+        assert_eq!(
+            Some(1024),
+            Speed::Range {
+                min: 1024,
+                max: 4096
+            }
+            .bytes_per_sec()
+        );
+        assert_eq!(
+            Some(1024),
+            Speed::from_str("1KBps+50ms").unwrap().bytes_per_sec()
+        );
+        assert_eq!(None, Speed::Iops(100).bytes_per_sec());
+        assert_eq!(
+            None,
+            Speed::Latency(Duration::from_millis(5)).bytes_per_sec()
+        );
+        assert_eq!(None, Speed::Percent(50).bytes_per_sec());
+    }
+
+    #[test]
+    fn test_speed_whitespace() {
+        assert_eq!(Ok(Speed::Bps(1 << 20)), Speed::from_str(" 1024 KBps "));
+        assert_eq!(Ok(Speed::Bps(1 << 20)), Speed::from_str("1024 KBps"));
+        assert_eq!(Ok(Speed::Bps(1 << 20)), Speed::from_str("\t1024KBps\n"));
+        assert_eq!(Ok(Speed::Bps(1000)), Speed::from_str_si("1 KBps"));
+        assert_eq!(Ok(Speed::Bps(1024)), Speed::from_str(" 1024 "));
+        assert_eq!(Ok(Speed::Iops(100)), Speed::from_str("100 iops"));
+        assert_eq!(Ok(Speed::PassThrough), Speed::from_str(" PassThrough "));
+        assert_eq!(
+            Ok(Speed::Range {
+                min: 1 << 20,
+                max: 5 << 20,
+            }),
+            Speed::from_str("1 MBps..5 MBps")
+        );
+        assert_eq!(
+            Ok(Speed::BpsWithLatency {
+                bps: 1 << 20,
+                latency: Duration::from_millis(50),
+            }),
+            Speed::from_str("1 MBps + 50ms")
+        );
+        assert_eq!(
+            Ok(Speed::Latency(Duration::from_millis(50))),
+            Speed::from_str(" latency: 50ms")
+        );
+
+        // The interior spaces are still rejected.
+        assert!(Speed::from_str("10 24KBps").is_err());
+        assert!(Speed::from_str("1024  KBps").is_err());
+        assert!(Speed::from_str("1024 K Bps").is_err());
+        assert!(Speed::from_str("1 024").is_err());
+        assert!(Speed::from_str("1024 KB ps").is_err());
+    }
+
+    #[test]
+    fn test_speed_percent() {
+        assert_eq!(Ok(Speed::Percent(10)), Speed::from_str("10%"));
+        assert_eq!(Ok(Speed::Percent(100)), Speed::from_str(" 100% "));
+        assert_eq!(Ok(Speed::Percent(0)), Speed::from_str("0%"));
+        assert!(Speed::from_str("101%").is_err());
+        assert!(Speed::from_str("-1%").is_err());
+        assert!(Speed::from_str("1.5%").is_err());
+        assert!(Speed::from_str("%").is_err());
+        assert_eq!("10%", Speed::Percent(10).to_string());
+
+        // Resolved by the fixed baseline.
+        let baseline = 200 << 20;
+        assert_eq!(Speed::Bps(20 << 20), Speed::Percent(10).resolve(baseline));
+        assert_eq!(Speed::Bps(baseline), Speed::Percent(100).resolve(baseline));
+        assert_eq!(Speed::Bps(1), Speed::Percent(1).resolve(10));
+        assert_eq!(
+            Speed::Bps(u64::MAX / 2),
+            Speed::Percent(50).resolve(u64::MAX)
+        );
+        assert_eq!(Speed::Bps(1024), Speed::Bps(1024).resolve(baseline));
+
+        assert!(Speed::Bps(u64::MAX) < Speed::Percent(1));
+        assert!(Speed::Percent(1) < Speed::Percent(2));
+        assert!(Speed::Percent(100) < Speed::PassThrough);
+    }
+
+    #[test]
+    fn test_speed_interpolate() {
+        let speed = Speed::Bps(1024);
+        assert_eq!(Speed::PassThrough, speed.interpolate(0.0));
+        assert_eq!(Speed::Bps(4096), speed.interpolate(0.25));
+        assert_eq!(Speed::Bps(2048), speed.interpolate(0.5));
+        assert_eq!(Speed::Bps(1024), speed.interpolate(1.0));
+        assert_eq!(Speed::Bps(1024), speed.interpolate(1.5));
+
+        assert_eq!(
+            Speed::BpsWithLatency {
+                bps: 2048,
+                latency: Duration::from_millis(25),
+            },
+            Speed::BpsWithLatency {
+                bps: 1024,
+                latency: Duration::from_millis(50),
+            }
+            .interpolate(0.5)
+        );
+        assert_eq!(
+            Speed::Latency(Duration::from_millis(25)),
+            Speed::Latency(Duration::from_millis(50)).interpolate(0.5)
+        );
+        assert_eq!(Speed::Iops(20), Speed::Iops(10).interpolate(0.5));
+        assert_eq!(Speed::Bps(u64::MAX), Speed::Bps(u64::MAX).interpolate(0.5));
+        assert_eq!(Speed::PassThrough, Speed::PassThrough.interpolate(0.5));
+    }
+
+    #[test]
+    fn test_speed_from_str_clamped() {
+        for s in &["99999999999GBps", "1048576PBps", "99999999999999999999999"] {
+            assert_eq!(
+                Err(ConfigError::Overflow(s.to_string())),
+                Speed::from_str(s),
+                "{}",
+                s
+            );
+
+            let (speed, warning) = Speed::from_str_clamped(s).unwrap();
+            assert_eq!(Speed::Bps(u64::MAX), speed, "{}", s);
+            assert!(warning.unwrap().contains(s));
+        }
+
+        let (speed, warning) = Speed::from_str_clamped(" 1MBps..99999999999GBps ").unwrap();
+        assert_eq!(
+            Speed::Range {
+                min: 1 << 20,
+                max: u64::MAX,
+            },
+            speed
+        );
+        assert_eq!(
+            format!(
+                "speed 1MBps..99999999999GBps overflows and is clamped to 1MBps..{}Bps",
+                u64::MAX
+            ),
+            warning.unwrap()
+        );
+        assert_eq!(
+            Speed::Iops(u64::MAX),
+            Speed::from_str_clamped("99999999999999999999999iops")
+                .unwrap()
+                .0
+        );
+        assert_eq!(
+            Speed::Bps(u64::MAX / 8),
+            Speed::from_str_clamped("99999999999Gbps").unwrap().0
+        );
+
+        // The others are the same as the strict parser.
+        assert_eq!(
+            Ok((Speed::Bps(1 << 20), None)),
+            Speed::from_str_clamped("1MBps")
+        );
+        assert!(Speed::from_str_clamped("fast").is_err());
+        assert!(Speed::from_str("99999999999999999999999iops").is_err());
+
+        assert!(Speed::from_str_si("99999999999GBps").is_err());
+        let (speed, warning) = Speed::from_str_si_clamped("99999999999GBps").unwrap();
+        assert_eq!(Speed::Bps(u64::MAX), speed);
+        assert!(warning.is_some());
+        assert_eq!(
+            Ok((Speed::Bps(1_000_000), None)),
+            Speed::from_str_si_clamped("1MBps")
+        );
+    }
+
+    #[test]
+    fn test_speed_serde() {
+        use serde_json;
+
+        for speed in &[
+            Speed::Bps(1 << 20),
+            Speed::Range {
+                min: 1024,
+                max: 2048,
+            },
+            Speed::Latency(Duration::from_millis(5)),
+            Speed::BpsWithLatency {
+                bps: 1024,
+                latency: Duration::from_millis(5),
+            },
+            Speed::Iops(100),
+            Speed::Percent(10),
+            Speed::PassThrough,
+        ] {
+            let json = serde_json::to_string(speed).unwrap();
+            assert_eq!(format!("\"{}\"", speed), json);
+            assert_eq!(*speed, serde_json::from_str::<Speed>(&json).unwrap());
+        }
+
+        assert_eq!(
+            Speed::Bps(1 << 20),
+            serde_json::from_str::<Speed>(r#""1MBps""#).unwrap()
+        );
+        assert_eq!(
+            Speed::Bps(1024),
+            serde_json::from_str::<Speed>("1024").unwrap()
+        );
+
+        // The tagged form is still accepted.
+        assert_eq!(
+            Speed::Bps(1024),
+            serde_json::from_str::<Speed>(r#"{"Bps":1024}"#).unwrap()
+        );
+        assert_eq!(
+            Speed::Range {
+                min: 1024,
+                max: 2048
+            },
+            serde_json::from_str::<Speed>(r#"{"Range":{"min":1024,"max":2048}}"#).unwrap()
+        );
+        assert_eq!(
+            Speed::PassThrough,
+            serde_json::from_str::<Speed>(r#""PassThrough""#).unwrap()
+        );
+
+        let e = serde_json::from_str::<Speed>(r#""fast""#).unwrap_err();
+        assert!(e.to_string().contains("fast"), "{}", e);
+        assert!(serde_json::from_str::<Speed>("-1").is_err());
+    }
+}
@@ -0,0 +1,382 @@
+//! The parsers of the speed strings for `Speed::from_str`.
+//!
+//! `parse_speed` splits the string by its form (the latency, the time per byte, the byte rate with the latency,
+//! the range or the single rate) and the small parsers below parse the parts.
+use super::{nanos_saturating, Speed};
+use config::duration;
+use config::ConfigError;
+use std::convert::TryFrom;
+use std::num::{IntErrorKind, ParseIntError};
+
+/// The error of the rates which exceed `u64::MAX`.
+pub const OVERFLOW: &str = "overflow";
+
+/// Converts the error of `parse_speed` for the speed string `s` into `ConfigError`.
+pub fn speed_error(s: &str, e: String) -> ConfigError {
+    if e == OVERFLOW {
+        ConfigError::Overflow(s.trim().to_string())
+    } else {
+        ConfigError::InvalidSpeed(e)
+    }
+}
+
+/// Parses the speed string as `parse_speed`, but clamps the rates which overflow and returns the warning of it.
+pub fn parse_clamped(s: &str, kilo: u64) -> Result<(Speed, Option<String>), ConfigError> {
+    match parse_speed(s, kilo, false) {
+        Err(ref e) if e == OVERFLOW => {
+            let speed = parse_speed(s, kilo, true).map_err(|e| speed_error(s, e))?;
+            let warning = format!("speed {} overflows and is clamped to {}", s.trim(), speed);
+            Ok((speed, Some(warning)))
+        }
+        r => r.map(|speed| (speed, None)).map_err(|e| speed_error(s, e)),
+    }
+}
+
+/// Clamps the rate to `u64::MAX` on the overflow if `clamp` is true.
+fn saturate(rate: Result<u64, String>, clamp: bool) -> Result<u64, String> {
+    match rate {
+        Err(ref e) if clamp && e == OVERFLOW => Ok(u64::MAX),
+        r => r,
+    }
+}
+
+fn parse_int_error(e: ParseIntError) -> String {
+    match *e.kind() {
+        IntErrorKind::PosOverflow => OVERFLOW.to_string(),
+        _ => e.to_string(),
+    }
+}
+
+/// Parses the speed string with the given base of the scale suffixes.
+/// The leading and trailing whitespaces are ignored.
+/// The rates which overflow are clamped to `u64::MAX` if `clamp` is true.
+pub fn parse_speed(s: &str, kilo: u64, clamp: bool) -> Result<Speed, String> {
+    let s = s.trim();
+    if let Some(d) = s.strip_prefix("latency:") {
+        return parse_latency(d.trim());
+    }
+
+    if let Some(d) = s.strip_suffix("/B") {
+        return parse_per_byte(d);
+    }
+
+    if let Some((bps, latency)) = s.split_once('+') {
+        return Ok(Speed::BpsWithLatency {
+            bps: parse_bps(bps, kilo, clamp)?,
+            latency: duration::parse(latency.trim())?,
+        });
+    }
+
+    if let Some((lo, hi)) = split_range(s) {
+        let min = parse_bps(lo, kilo, clamp)?;
+        let max = parse_bps(hi, kilo, clamp)?;
+        check_range(lo, hi, &min, &max)?;
+        return Ok(Speed::Range { min, max });
+    }
+
+    parse_single(&remove_unit_space(s), kilo, clamp)
+}
+
+/// Parses the latency after "latency:", which is a duration, a range of them (e.g., "10ms..200ms")
+/// or the mean of the exponential distribution after "exp:".
+fn parse_latency(d: &str) -> Result<Speed, String> {
+    if let Some(mean) = d.strip_prefix("exp:") {
+        return Ok(Speed::ExpLatency(duration::parse(mean.trim())?));
+    }
+
+    if let Some((lo, hi)) = split_range(d) {
+        let min = duration::parse(lo.trim())?;
+        let max = duration::parse(hi.trim())?;
+        check_range(lo, hi, &min, &max)?;
+        return Ok(Speed::LatencyRange { min, max });
+    }
+
+    Ok(Speed::Latency(duration::parse(d)?))
+}
+
+/// Parses the time per byte before "/B". The rates of one byte per second or faster are `Speed::Bps`
+/// (e.g., "100ms/B" is 10Bps, see `Speed::from_per_byte`).
+fn parse_per_byte(d: &str) -> Result<Speed, String> {
+    let nanos = duration::parse(d.trim())?.as_nanos();
+    if nanos == 0 {
+        return Err(format!("time per byte has to be positive: {}/B", d));
+    }
+
+    Ok(Speed::from_per_byte(nanos_saturating(nanos)))
+}
+
+/// Splits the range at the first "..", e.g., "1KBps..2KBps" into "1KBps" and "2KBps".
+fn split_range(s: &str) -> Option<(&str, &str)> {
+    s.split_once("..")
+}
+
+/// Rejects the range of `lo` and `hi`, which are parsed as `min` and `max`, if it is inverted.
+fn check_range<T: PartialOrd>(lo: &str, hi: &str, min: &T, max: &T) -> Result<(), String> {
+    if max < min {
+        return Err(format!("inverted range: {} is greater than {}", lo, hi));
+    }
+
+    Ok(())
+}
+
+/// Parses the speed which is not a range: the pass-through, the percentage, the IOPS or the rate.
+/// The rate without the unit is bytes per second.
+fn parse_single(s: &str, kilo: u64, clamp: bool) -> Result<Speed, String> {
+    if s == "pass_through" || s == "PassThrough" {
+        Ok(Speed::PassThrough)
+    } else if let Some(n) = s.strip_suffix('%') {
+        let p = n.trim().parse::<u8>().map_err(|e| format!("{}", e))?;
+        if 100 < p {
+            return Err(format!("percentage ({}%) has to be 100% or less", p));
+        }
+
+        Ok(Speed::Percent(p))
+    } else if let Some(n) = s.strip_suffix("iops") {
+        Ok(Speed::Iops(saturate(
+            n.parse().map_err(parse_int_error),
+            clamp,
+        )?))
+    } else if let Some((n, is_bit)) = strip_rate_unit(s) {
+        let speed = saturate(parse_scaled(n, kilo), clamp)?;
+        if is_bit {
+            // Round down the bits which do not fill a byte.
+            Ok(Speed::Bps(speed / 8))
+        } else {
+            Ok(Speed::Bps(speed))
+        }
+    } else {
+        Ok(Speed::Bps(saturate(parse_magnitude(s, 1), clamp)?))
+    }
+}
+
+/// Strips the unit of the byte rates ("Bps" or "B/s") or the bit rates ("bps" or "b/s"),
+/// and returns the rest and true if it is of the bit rates.
+fn strip_rate_unit(s: &str) -> Option<(&str, bool)> {
+    [("Bps", false), ("B/s", false), ("bps", true), ("b/s", true)]
+        .iter()
+        .find_map(|&(unit, is_bit)| s.strip_suffix(unit).map(|n| (n, is_bit)))
+}
+
+/// Removes a space between the number and the unit (e.g., "1024 KBps" is "1024KBps").
+/// The other spaces are kept to be rejected.
+fn remove_unit_space(s: &str) -> String {
+    if let Some(i) = s.find(' ') {
+        let (n, unit) = (&s[..i], &s[i + 1..]);
+        let is_number = n.ends_with(|c: char| c.is_ascii_digit());
+        let is_unit = unit.starts_with(|c: char| c.is_ascii_alphabetic());
+        if is_number && is_unit && !unit.contains(' ') {
+            return format!("{}{}", n, unit);
+        }
+    }
+
+    s.to_string()
+}
+
+/// Parses the given string as a number of bytes with the same suffixes as `Speed` without "ps"
+/// (e.g., "100MB" is 100 * 1024 * 1024 bytes).
+/// The suffixes of `Speed` (e.g., "100MBps") are accepted too to reuse the speed strings as byte counts.
+pub fn parse_byte_size(s: &str) -> Result<usize, ConfigError> {
+    let size = match s.strip_suffix("Bps").or_else(|| s.strip_suffix('B')) {
+        Some(n) => parse_scaled(n, 1 << 10),
+        None => parse_magnitude(s, 1),
+    };
+    let size = size.map_err(|e| {
+        if e == OVERFLOW {
+            ConfigError::Overflow(s.to_string())
+        } else {
+            ConfigError::InvalidSize(e)
+        }
+    })?;
+    // The sizes are in memory, so they depend on the target unlike the rates.
+    usize::try_from(size).map_err(|_| ConfigError::Overflow(s.to_string()))
+}
+
+/// Parses `s` which may end with a scale prefix (see `split_scale`) in the given base.
+fn parse_scaled(s: &str, kilo: u64) -> Result<u64, String> {
+    if s.is_empty() {
+        return Err("number is missing".to_string());
+    }
+
+    let (n, scale) = split_scale(s, kilo)?;
+    parse_magnitude(n, scale)
+}
+
+/// Splits the scale prefix (K, M, G, T or P in any case) from `s` and returns the rest and the scale
+/// in the given base (1 without the prefix).
+/// The binary prefixes (e.g., "Ki") are in base-1024 regardless of the given base.
+fn split_scale(s: &str, kilo: u64) -> Result<(&str, u64), String> {
+    let exp_of = |c: char| "KMGTP".find(c.to_ascii_uppercase()).map(|i| i as u32 + 1);
+    let (s, kilo) = match s.strip_suffix(|c| c == 'i' || c == 'I') {
+        Some(n) if n.ends_with(|c| exp_of(c).is_some()) => (n, 1 << 10),
+        _ => (s, kilo),
+    };
+    let (n, exp) = match s.chars().last().and_then(exp_of) {
+        Some(exp) => (&s[..s.len() - 1], exp),
+        None => (s, 0),
+    };
+    let scale = kilo.checked_pow(exp).ok_or(OVERFLOW)?;
+
+    Ok((n, scale))
+}
+
+/// Parses the bound of a range, which has to be a byte per second value.
+fn parse_bps(s: &str, kilo: u64, clamp: bool) -> Result<u64, String> {
+    match parse_speed(s, kilo, clamp)? {
+        Speed::Bps(bps) => Ok(bps),
+        _ => Err(format!("range bound has to be byte per second: {}", s)),
+    }
+}
+
+/// Parses `s` as a number of bytes and multiplies it by `scale`.
+/// A decimal mantissa (e.g., "1.5") is accepted and the result is rounded to the nearest whole byte.
+fn parse_magnitude(s: &str, scale: u64) -> Result<u64, String> {
+    let int_err = match s.parse::<u64>() {
+        Ok(n) => return Ok(n.checked_mul(scale).ok_or(OVERFLOW)?),
+        Err(e) => parse_int_error(e),
+    };
+
+    if !s.contains('.') || !s.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return Err(int_err);
+    }
+
+    let n = s.parse::<f64>().map_err(|_| "invalid decimal number")?;
+    let speed = (n * scale as f64).round();
+    if u64::MAX as f64 <= speed {
+        return Err(OVERFLOW.to_string());
+    }
+
+    Ok(speed as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_latency() {
+        assert_eq!(
+            Ok(Speed::Latency(Duration::from_millis(10))),
+            parse_latency("10ms")
+        );
+        assert_eq!(
+            Ok(Speed::LatencyRange {
+                min: Duration::from_millis(10),
+                max: Duration::from_millis(200),
+            }),
+            parse_latency("10ms .. 200ms")
+        );
+        assert_eq!(
+            Ok(Speed::ExpLatency(Duration::from_millis(50))),
+            parse_latency("exp: 50ms")
+        );
+        assert!(parse_latency("200ms..10ms").is_err());
+        assert!(parse_latency("10KBps").is_err());
+    }
+
+    #[test]
+    fn test_parse_per_byte() {
+        assert_eq!(Ok(Speed::Bps(10)), parse_per_byte("100ms"));
+        assert_eq!(Ok(Speed::Bps(3)), parse_per_byte(" 300ms"));
+        assert_eq!(
+            Ok(Speed::PerByte(Duration::from_millis(1500))),
+            parse_per_byte("1500ms")
+        );
+        assert_eq!(
+            Err("time per byte has to be positive: 0ms/B".to_string()),
+            parse_per_byte("0ms")
+        );
+    }
+
+    #[test]
+    fn test_split_range() {
+        assert_eq!(Some(("1KBps", "2KBps")), split_range("1KBps..2KBps"));
+        assert_eq!(Some(("", "")), split_range(".."));
+        assert_eq!(Some(("1", ".2")), split_range("1...2"));
+        assert_eq!(None, split_range("1.5KBps"));
+    }
+
+    #[test]
+    fn test_check_range() {
+        assert_eq!(Ok(()), check_range("1", "2", &1, &2));
+        assert_eq!(Ok(()), check_range("1", "1", &1, &1));
+        assert_eq!(
+            Err("inverted range: 2 is greater than 1".to_string()),
+            check_range("2", "1", &2, &1)
+        );
+    }
+
+    #[test]
+    fn test_parse_single() {
+        assert_eq!(
+            Ok(Speed::PassThrough),
+            parse_single("pass_through", 1024, false)
+        );
+        assert_eq!(Ok(Speed::Percent(50)), parse_single("50%", 1024, false));
+        assert!(parse_single("101%", 1024, false).is_err());
+        assert_eq!(Ok(Speed::Iops(100)), parse_single("100iops", 1024, false));
+        assert_eq!(Ok(Speed::Bps(2048)), parse_single("2KBps", 1024, false));
+        assert_eq!(Ok(Speed::Bps(128)), parse_single("1Kbps", 1024, false));
+        assert_eq!(Ok(Speed::Bps(100)), parse_single("100", 1024, false));
+        assert_eq!(
+            Err(OVERFLOW.to_string()),
+            parse_single("99999999999PBps", 1024, false)
+        );
+        assert_eq!(
+            Ok(Speed::Bps(u64::MAX)),
+            parse_single("99999999999PBps", 1024, true)
+        );
+    }
+
+    #[test]
+    fn test_strip_rate_unit() {
+        assert_eq!(Some(("1K", false)), strip_rate_unit("1KBps"));
+        assert_eq!(Some(("1K", false)), strip_rate_unit("1KB/s"));
+        assert_eq!(Some(("1K", true)), strip_rate_unit("1Kbps"));
+        assert_eq!(Some(("1K", true)), strip_rate_unit("1Kb/s"));
+        assert_eq!(None, strip_rate_unit("1KB"));
+    }
+
+    #[test]
+    fn test_remove_unit_space() {
+        assert_eq!("1024KBps", remove_unit_space("1024 KBps"));
+        assert_eq!("1024KBps", remove_unit_space("1024KBps"));
+        assert_eq!("1024 K Bps", remove_unit_space("1024 K Bps"));
+        assert_eq!("fast 1KBps", remove_unit_space("fast 1KBps"));
+    }
+
+    #[test]
+    fn test_split_scale() {
+        assert_eq!(Ok(("1", 1)), split_scale("1", 1024));
+        assert_eq!(Ok(("1", 1024)), split_scale("1K", 1024));
+        assert_eq!(Ok(("1", 1000)), split_scale("1k", 1000));
+        assert_eq!(Ok(("1", 1 << 20)), split_scale("1Mi", 1000));
+        assert_eq!(Ok(("1.5", 1 << 50)), split_scale("1.5P", 1024));
+        // "i" is not a prefix without the scale.
+        assert_eq!(Ok(("1i", 1)), split_scale("1i", 1024));
+        assert_eq!(Ok(("", 1024)), split_scale("K", 1024));
+    }
+
+    #[test]
+    fn test_parse_scaled() {
+        assert_eq!(Ok(2048), parse_scaled("2K", 1024));
+        assert_eq!(Ok(1500), parse_scaled("1.5k", 1000));
+        assert_eq!(Err("number is missing".to_string()), parse_scaled("", 1024));
+        assert!(parse_scaled("K", 1024).is_err());
+    }
+
+    #[test]
+    fn test_parse_magnitude() {
+        assert_eq!(Ok(100), parse_magnitude("100", 1));
+        assert_eq!(Ok(1536), parse_magnitude("1.5", 1024));
+        assert_eq!(Ok(2), parse_magnitude("1.5", 1));
+        assert_eq!(Err(OVERFLOW.to_string()), parse_magnitude("2", u64::MAX));
+        assert_eq!(
+            Err(OVERFLOW.to_string()),
+            parse_magnitude("99999999999999999999", 1)
+        );
+        assert!(parse_magnitude("1.5.5", 1).is_err());
+        assert!(parse_magnitude("-1", 1).is_err());
+        assert!(parse_magnitude("1e3", 1).is_err());
+    }
+}
@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// `Trigger` lets an external process start and stop the slowdown by creating and removing a file:
+/// the engine is forced active at `Config::speed` while the file at `path` exists (as `Engine::force_active`).
+/// While it is absent, the engine follows the schedule, or stays idle if `idle_when_absent`.
+///
+/// The path is polled at most once in `poll_interval` on the operations, so the change takes effect
+/// at the first operation after the poll.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Trigger {
+    pub path: PathBuf,
+    #[serde(default = "default_poll_interval", with = "super::duration::human")]
+    pub poll_interval: Duration,
+    #[serde(default)]
+    pub idle_when_absent: bool,
+}
+
+impl Trigger {
+    /// Returns the trigger of the path polled every second.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Trigger {
+        Trigger {
+            path: path.into(),
+            poll_interval: default_poll_interval(),
+            idle_when_absent: false,
+        }
+    }
+}
+
+fn default_poll_interval() -> Duration {
+    Duration::from_secs(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn test_trigger_serde() {
+        let trigger: Trigger = serde_json::from_str(r#"{"path": "/tmp/slow"}"#).unwrap();
+        assert_eq!(Trigger::new("/tmp/slow"), trigger);
+
+        let trigger: Trigger = serde_json::from_str(
+            r#"{"path": "/tmp/slow", "poll_interval": "100ms", "idle_when_absent": true}"#,
+        )
+        .unwrap();
+        assert_eq!(Duration::from_millis(100), trigger.poll_interval);
+        assert!(trigger.idle_when_absent);
+        assert_eq!(
+            trigger,
+            serde_json::from_str(&serde_json::to_string(&trigger).unwrap()).unwrap()
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use std::fmt;
+use std::str::FromStr;
+
+pub const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+
+/// `ActiveWindow` is the time of day when the operations can be unstable.
+/// `start` and `end` are seconds since midnight and `start` is inclusive whereas `end` is exclusive.
+/// The window wraps past midnight if `end` is earlier than `start` (e.g., 22:00-02:00).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActiveWindow {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl ActiveWindow {
+    /// Returns true if the given time of day in seconds since midnight is in the window.
+    pub fn contains(&self, time_of_day: u32) -> bool {
+        if self.start <= self.end {
+            self.start <= time_of_day && time_of_day < self.end
+        } else {
+            // Wrap past midnight.
+            self.start <= time_of_day || time_of_day < self.end
+        }
+    }
+}
+
+impl FromStr for ActiveWindow {
+    type Err = String;
+
+    /// Parses the given string like "09:00-17:00" or "22:00-02:00" as window.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let i = s
+            .find('-')
+            .ok_or_else(|| format!("window has to be <start>-<end>: {}", s))?;
+
+        Ok(ActiveWindow {
+            start: parse_time_of_day(&s[..i])?,
+            end: parse_time_of_day(&s[i + 1..])?,
+        })
+    }
+}
+
+/// Parses "HH:MM" or "HH:MM:SS" as seconds since midnight.
+fn parse_time_of_day(s: &str) -> Result<u32, String> {
+    let fields = s
+        .split(':')
+        .map(|x| x.parse::<u32>().map_err(|e| format!("{}: {}", e, s)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (h, m, sec) = match fields[..] {
+        [h, m] => (h, m, 0),
+        [h, m, sec] => (h, m, sec),
+        _ => return Err(format!("time of day has to be HH:MM or HH:MM:SS: {}", s)),
+    };
+    if 24 <= h || 60 <= m || 60 <= sec {
+        return Err(format!("time of day is out of range: {}", s));
+    }
+
+    Ok(h * 60 * 60 + m * 60 + sec)
+}
+
+impl fmt::Display for ActiveWindow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_time_of_day(f, self.start)?;
+        write!(f, "-")?;
+        write_time_of_day(f, self.end)
+    }
+}
+
+fn write_time_of_day(f: &mut fmt::Formatter, t: u32) -> fmt::Result {
+    write!(f, "{:02}:{:02}", t / (60 * 60), t / 60 % 60)?;
+    if !t.is_multiple_of(60) {
+        write!(f, ":{:02}", t % 60)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(h: u32, m: u32) -> u32 {
+        h * 60 * 60 + m * 60
+    }
+
+    #[test]
+    fn test_active_window_contains() {
+        let window: ActiveWindow = "09:00-17:00".parse().unwrap();
+
+        // In the window.
+        assert!(window.contains(at(9, 0)));
+        assert!(window.contains(at(12, 30)));
+        assert!(window.contains(at(17, 0) - 1));
+
+        // Out of the window.
+        assert!(!window.contains(at(8, 59)));
+        assert!(!window.contains(at(17, 0)));
+        assert!(!window.contains(at(0, 0)));
+        assert!(!window.contains(at(23, 59)));
+    }
+
+    #[test]
+    fn test_active_window_wrap_around() {
+        let window: ActiveWindow = "22:00-02:00".parse().unwrap();
+
+        assert!(window.contains(at(22, 0)));
+        assert!(window.contains(at(23, 59)));
+        assert!(window.contains(at(0, 0)));
+        assert!(window.contains(at(1, 59)));
+
+        assert!(!window.contains(at(2, 0)));
+        assert!(!window.contains(at(12, 0)));
+        assert!(!window.contains(at(21, 59)));
+    }
+
+    #[test]
+    fn test_active_window_parse() {
+        assert_eq!(
+            Ok(ActiveWindow {
+                start: at(9, 0),
+                end: at(17, 30) + 15,
+            }),
+            "09:00-17:30:15".parse()
+        );
+        assert_eq!(
+            "09:00-17:30:15",
+            "9:00-17:30:15".parse::<ActiveWindow>().unwrap().to_string()
+        );
+        assert_eq!(
+            "22:00-02:00",
+            "22:00-02:00".parse::<ActiveWindow>().unwrap().to_string()
+        );
+
+        assert!("".parse::<ActiveWindow>().is_err());
+        assert!("09:00".parse::<ActiveWindow>().is_err());
+        assert!("09:00-".parse::<ActiveWindow>().is_err());
+        assert!("9-17".parse::<ActiveWindow>().is_err());
+        assert!("24:00-02:00".parse::<ActiveWindow>().is_err());
+        assert!("09:60-17:00".parse::<ActiveWindow>().is_err());
+        assert!("09:00-17:00:00:00".parse::<ActiveWindow>().is_err());
+    }
+}
@@ -0,0 +1,73 @@
+use super::duration::format_duration;
+use super::{parse_byte_size, parse_duration};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// `WindowBurst` passes the beginning of each unstable period through before the speed applies
+/// (e.g., a disk which absorbs the writes into its cache until it fills).
+/// The budget is given again at every unstable period unlike the continuous `Config::burst`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WindowBurst {
+    /// The first bytes read/written in the period are not delayed.
+    Bytes(u64),
+    /// The operations are not delayed for the first duration of the period.
+    Duration(Duration),
+}
+
+impl FromStr for WindowBurst {
+    type Err = String;
+
+    /// Parses a duration like "30s" or a byte size like "64MB" (a plain number is bytes).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(d) = parse_duration(s) {
+            return Ok(WindowBurst::Duration(d));
+        }
+
+        parse_byte_size(s)
+            .map(|n| WindowBurst::Bytes(n as u64))
+            .map_err(|e| {
+                format!(
+                    "window burst accepts a byte size or a duration: {}: {}",
+                    s, e
+                )
+            })
+    }
+}
+
+impl fmt::Display for WindowBurst {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WindowBurst::Bytes(n) => write!(f, "{}", n),
+            WindowBurst::Duration(ref d) => write!(f, "{}", format_duration(d)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_burst_from_str() {
+        assert_eq!(
+            Ok(WindowBurst::Duration(Duration::from_secs(30))),
+            WindowBurst::from_str("30s")
+        );
+        assert_eq!(
+            Ok(WindowBurst::Duration(Duration::from_secs(30 * 60))),
+            WindowBurst::from_str("30m")
+        );
+        assert_eq!(
+            Ok(WindowBurst::Bytes(64 << 20)),
+            WindowBurst::from_str("64MB")
+        );
+        assert_eq!(Ok(WindowBurst::Bytes(4096)), WindowBurst::from_str("4096"));
+        assert!(WindowBurst::from_str("").is_err());
+        assert!(WindowBurst::from_str("soon").is_err());
+
+        for s in ["30s", "1500ms", "4096"].iter() {
+            assert_eq!(*s, WindowBurst::from_str(s).unwrap().to_string());
+        }
+    }
+}
@@ -0,0 +1,394 @@
+//! Control server to change the config at runtime over a Unix domain socket.
+//!
+//! The server accepts the line-oriented commands below and replies a line for each command.
+//!
+//! - `set speed <speed>` (e.g., `set speed 512KBps`)
+//! - `set duration <duration>` and `set frequency <duration>` (e.g., `set frequency 10m`)
+//! - `set operations <operation>,...` (e.g., `set operations Read,Write` or `set operations Read:Write`)
+//! - `get config`
+//! - `pause [<operation>]` and `resume [<operation>]` (e.g., `pause Write`), which pause and resume throttling
+//!   the operation, or all operations without it, as `SIGRTMIN+i` and `SIGUSR1` do (see `signal`).
+//!
+//! The reply is `ok` for `set`, `pause` and `resume`, the config for `get` or `error: <reason>` if the command fails.
+use atomic_immut::AtomicImmut;
+use config::{
+    parse_duration, parse_frequency, parse_operations, Condition, Config, ConfigError, Operation,
+};
+use signal::{Controls, CONTROLS};
+use slog::Logger;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+
+/// Starts the control server listening on `path` in a background thread.
+/// A stale socket at `path` (i.e., no server accepts on it) is removed before listening.
+///
+/// The error is recoverable: nothing is left running and the caller may go on without the control server.
+pub fn start_server(
+    logger: Logger,
+    path: &Path,
+    config: Arc<AtomicImmut<Config>>,
+) -> io::Result<()> {
+    remove_stale_socket(&logger, path)?;
+    let listener = UnixListener::bind(path)?;
+    info!(logger, "control socket: {:?}", path);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let logger = logger.clone();
+                    let config = Arc::clone(&config);
+                    thread::spawn(move || {
+                        if let Err(e) = serve(&logger, stream, &config) {
+                            warn!(logger, "control connection error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!(logger, "control accept error: {}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Removes the socket at `path` if it exists and nobody listens on it.
+/// A live socket or the other kinds of files are kept and reported as errors.
+fn remove_stale_socket(logger: &Logger, path: &Path) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{:?} exists and is not a socket", path),
+        ));
+    }
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{:?} is in use by another server", path),
+        ));
+    }
+
+    info!(logger, "removing the stale control socket: {:?}", path);
+    fs::remove_file(path)
+}
+
+fn serve(logger: &Logger, stream: UnixStream, config: &AtomicImmut<Config>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let reply = match handle_command(config, &line) {
+            Ok(reply) => {
+                info!(logger, "control command: {}", line);
+                if line.trim_start().starts_with("set ") {
+                    for warning in config.load().warnings() {
+                        warn!(logger, "{}", warning);
+                    }
+                }
+                reply
+            }
+            Err(e) => {
+                warn!(logger, "control command rejected: {}: {}", line, e);
+                format!("error: {}", e)
+            }
+        };
+        writeln!(writer, "{}", reply)?;
+    }
+
+    Ok(())
+}
+
+/// Executes the command and returns the reply.
+/// The config is replaced atomically only if the new config is valid and differs from the live one.
+pub fn handle_command(config: &AtomicImmut<Config>, line: &str) -> Result<String, String> {
+    execute(config, &CONTROLS, line)
+}
+
+fn execute(
+    config: &AtomicImmut<Config>,
+    controls: &Controls,
+    line: &str,
+) -> Result<String, String> {
+    let words = line.split_whitespace().collect::<Vec<_>>();
+
+    match words[..] {
+        ["pause"] => {
+            controls.set_paused(true);
+            Ok("ok".to_string())
+        }
+        ["resume"] => {
+            controls.set_paused(false);
+            Ok("ok".to_string())
+        }
+        ["pause", op] => {
+            let op: Operation = op.parse().map_err(|e: ConfigError| e.to_string())?;
+            controls.pause_operation(&op);
+            Ok("ok".to_string())
+        }
+        ["resume", op] => {
+            let op: Operation = op.parse().map_err(|e: ConfigError| e.to_string())?;
+            controls.resume_operation(&op);
+            Ok("ok".to_string())
+        }
+        ["get", "config"] => Ok(config.load().to_string()),
+        ["set", key, value] => {
+            let mut new_config = (*config.load()).clone();
+            set(&mut new_config, key, value)?;
+            new_config.canonicalize();
+            new_config.validate().map_err(|e| e.to_string())?;
+
+            // Keep the live config (and the cycles which compare it) if nothing is changed.
+            if *config.load() != new_config {
+                config.store(new_config);
+            }
+            Ok("ok".to_string())
+        }
+        _ => Err(format!("unknown command: {}", line.trim())),
+    }
+}
+
+fn set(config: &mut Config, key: &str, value: &str) -> Result<(), String> {
+    match key {
+        "speed" => config.speed = value.parse().map_err(|e: ConfigError| e.to_string())?,
+        "operations" => config.operations = parse_operations(value).map_err(|e| e.to_string())?,
+        "duration" | "frequency" => {
+            let d = if key == "duration" {
+                parse_duration(value)
+            } else {
+                parse_frequency(value)
+            }
+            .map_err(|e| e.to_string())?;
+            match config.condition {
+                Condition::Periodic {
+                    ref mut duration,
+                    ref mut frequency,
+                } => {
+                    if key == "duration" {
+                        *duration = d;
+                    } else {
+                        *frequency = d;
+                    }
+                }
+                Condition::Always(_) | Condition::Script { .. } => {
+                    return Err("the condition is not periodic".to_string())
+                }
+            }
+        }
+        _ => return Err(format!("unknown key: {}", key)),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{Operation, Speed};
+    use slog::Discard;
+    use std::env;
+    use std::process;
+    use std::time::Duration;
+
+    fn config() -> Arc<AtomicImmut<Config>> {
+        Arc::new(AtomicImmut::new(Config::default()))
+    }
+
+    #[test]
+    fn test_handle_command() {
+        let config = config();
+
+        assert_eq!(
+            Ok("ok".to_string()),
+            handle_command(&config, "set speed 512KBps")
+        );
+        assert_eq!(Speed::Bps(512 << 10), config.load().speed);
+        // The same value keeps the live config.
+        let live = config.load();
+        assert_eq!(
+            Ok("ok".to_string()),
+            handle_command(&config, "set speed 512KBps")
+        );
+        assert!(Arc::ptr_eq(&live, &config.load()));
+
+        assert_eq!(
+            Ok("ok".to_string()),
+            handle_command(&config, "set frequency 1h")
+        );
+        assert_eq!(
+            Condition::Periodic {
+                duration: Duration::from_secs(10 * 60),
+                frequency: Duration::from_secs(60 * 60),
+            },
+            config.load().condition
+        );
+
+        assert_eq!(
+            Ok("ok".to_string()),
+            handle_command(&config, "set frequency 4/h")
+        );
+        assert_eq!(
+            Condition::Periodic {
+                duration: Duration::from_secs(10 * 60),
+                frequency: Duration::from_secs(15 * 60),
+            },
+            config.load().condition
+        );
+        assert!(handle_command(&config, "set frequency 0/h").is_err());
+
+        assert_eq!(
+            Ok("ok".to_string()),
+            handle_command(&config, "set operations Read,Stat")
+        );
+        assert_eq!(
+            vec![Operation::Read, Operation::Stat],
+            config.load().operations
+        );
+
+        assert_eq!(
+            Ok(config.load().to_string()),
+            handle_command(&config, "get config")
+        );
+    }
+
+    #[test]
+    fn test_handle_command_pause() {
+        let config = config();
+        let controls = Controls::new();
+
+        assert_eq!(
+            Ok("ok".to_string()),
+            execute(&config, &controls, "pause Write")
+        );
+        assert!(controls.is_operation_paused(&Operation::Write));
+        assert!(!controls.is_operation_paused(&Operation::Read));
+        // The operation is case-insensitive.
+        assert_eq!(
+            Ok("ok".to_string()),
+            execute(&config, &controls, "resume write")
+        );
+        assert!(!controls.is_operation_paused(&Operation::Write));
+
+        assert_eq!(Ok("ok".to_string()), execute(&config, &controls, "pause"));
+        assert!(controls.is_paused());
+        assert_eq!(Ok("ok".to_string()), execute(&config, &controls, "resume"));
+        assert!(!controls.is_paused());
+
+        assert!(execute(&config, &controls, "pause Delete").is_err());
+        assert!(execute(&config, &controls, "resume Read Write").is_err());
+        // The config is not changed.
+        assert_eq!(Config::default(), *config.load());
+    }
+
+    #[test]
+    fn test_handle_command_unused_burst() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            burst: Some(4096),
+            ..Default::default()
+        }));
+
+        // The burst is unused by the pass-through speed but it is not an error.
+        assert_eq!(
+            Ok("ok".to_string()),
+            handle_command(&config, "set speed PassThrough")
+        );
+        assert_eq!(Speed::PassThrough, config.load().speed);
+        assert_eq!(1, config.load().warnings().len());
+    }
+
+    #[test]
+    fn test_handle_command_error() {
+        let config = config();
+
+        assert!(handle_command(&config, "").is_err());
+        assert!(handle_command(&config, "set speed").is_err());
+        assert!(handle_command(&config, "set speed fast").is_err());
+        assert!(handle_command(&config, "set color red").is_err());
+        assert!(handle_command(&config, "delete config").is_err());
+        // Rejected by the validation.
+        assert!(handle_command(&config, "set speed 0").is_err());
+        assert!(handle_command(&config, "set duration 1h").is_err());
+
+        // The config is kept.
+        assert_eq!(Speed::PassThrough, config.load().speed);
+    }
+
+    #[test]
+    fn test_control_server() {
+        let path = env::temp_dir().join(format!("mizumochi-control-{}.sock", process::id()));
+        let config = config();
+        let logger = Logger::root(Discard, o!());
+        start_server(logger, &path, Arc::clone(&config)).unwrap();
+
+        let stream = UnixStream::connect(&path).unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut request = |command: &str| {
+            writeln!(writer, "{}", command).unwrap();
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            line.trim_end().to_string()
+        };
+
+        assert_eq!("ok", request("set speed 1MBps"));
+        assert_eq!(Speed::Bps(1 << 20), config.load().speed);
+        assert!(request("get config").starts_with("config {speed: 1MBps,"));
+        assert!(request("set speed fast").starts_with("error: "));
+        assert_eq!(Speed::Bps(1 << 20), config.load().speed);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_control_server_stale_socket() {
+        let path = env::temp_dir().join(format!("mizumochi-control-stale-{}.sock", process::id()));
+        let logger = Logger::root(Discard, o!());
+        // The socket file is left after the listener is dropped.
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        start_server(logger.clone(), &path, config()).unwrap();
+        assert!(UnixStream::connect(&path).is_ok());
+
+        // The live socket is not taken over.
+        let e = start_server(logger, &path, config()).unwrap_err();
+        assert_eq!(io::ErrorKind::AddrInUse, e.kind());
+        assert!(UnixStream::connect(&path).is_ok());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_control_server_unbindable() {
+        let logger = Logger::root(Discard, o!());
+
+        // The parent directory does not exist.
+        let path = env::temp_dir()
+            .join(format!("mizumochi-control-missing-{}", process::id()))
+            .join("control.sock");
+        assert!(start_server(logger.clone(), &path, config()).is_err());
+
+        // The regular file is not removed.
+        let path = env::temp_dir().join(format!("mizumochi-control-file-{}", process::id()));
+        fs::write(&path, "data").unwrap();
+        let e = start_server(logger, &path, config()).unwrap_err();
+        assert_eq!(io::ErrorKind::AlreadyExists, e.kind());
+        assert_eq!("data", fs::read_to_string(&path).unwrap());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
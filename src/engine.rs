@@ -0,0 +1,5744 @@
+use atomic_immut::AtomicImmut;
+use clock::{Clock, SystemClock};
+use config::{
+    format_duration, Condition, Config, ConfigError, ConfigSnapshot, ErrorInjection, Operation,
+    PathFilter, Profile, ShortIo, Speed, ThrottleScope, Trigger, WeightedError, WindowBurst,
+};
+use event::{self, Event, EventKind, EventLog, LogLevel};
+use libc;
+use rand::rngs::SmallRng;
+use rand::{FromEntropy, Rng, SeedableRng};
+use shutdown::Shutdown;
+use signal::{Controls, CONTROLS};
+use slog::{Discard, Logger};
+use state::{State, StateManager};
+use stats::{RunSummary, Stats, StatsCounters};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::os::raw::c_int;
+use std::path::Path;
+#[cfg(feature = "async")]
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+use subscription::{self, Subscribers};
+use throttle::{compute_delay, compute_sleep_duration_with_debt, TokenBucket};
+use time;
+
+/// The speed is not throttled (i.e., `Speed::PassThrough`) once `Config::speed_decay` scales it by this or more.
+pub const MAX_SPEED_MULTIPLIER: f64 = 1024.0;
+/// `Config::speed_decay` never scales the speed down by less than this.
+pub const MIN_SPEED_MULTIPLIER: f64 = 1.0 / 1024.0;
+/// The longest delay of an operation, and `Config::max_op_delay` caps it shorter.
+/// The delays of the huge operations at a low rate (e.g., `usize::MAX` bytes at 1Bps) saturate to it
+/// instead of overflowing the deadlines and the clocks.
+pub const MAX_OP_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+// It is mixed into the seed of the fork of the dedicated RNGs so that they differ from `rng`.
+const FORK_SALT: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// `ThrottleState` is the snapshot of the throttling for `Engine::current_state`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ThrottleState {
+    /// The `operations` are throttled at `speed` (the configured one before sampled from `Speed::Range`)
+    /// scaled by `multiplier` of `Config::speed_decay`.
+    /// `remaining` is the time until the unstable period ends and `None` means it never ends.
+    Active {
+        operations: Vec<Operation>,
+        speed: Speed,
+        multiplier: f64,
+        remaining: Option<Duration>,
+    },
+    /// The operations are not throttled.
+    /// `next_activation` is the time until the next unstable period begins and `None` means it is not scheduled.
+    Idle { next_activation: Option<Duration> },
+}
+
+/// Formats the state like "Active: throttling Read,Write at 1MBps for 9m remaining" or
+/// "Idle: next activation in 20m". The multiplier is printed only if it is not 1 (e.g., "at 1MBps x0.5").
+/// The sub-second times are printed in milliseconds, which are rounded up not to show zero while active.
+impl fmt::Display for ThrottleState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ThrottleState::Active {
+                ref operations,
+                ref speed,
+                multiplier,
+                ref remaining,
+            } => {
+                let operations = operations
+                    .iter()
+                    .map(|op| op.to_string())
+                    .collect::<Vec<_>>();
+                write!(
+                    f,
+                    "Active: throttling {} at {}",
+                    operations.join(","),
+                    speed
+                )?;
+                if multiplier != 1.0 {
+                    write!(f, " x{}", multiplier)?;
+                }
+                match *remaining {
+                    Some(ref d) => write!(f, " for {} remaining", format_duration(&ceil_millis(d))),
+                    None => Ok(()),
+                }
+            }
+            ThrottleState::Idle {
+                next_activation: Some(ref d),
+            } => write!(
+                f,
+                "Idle: next activation in {}",
+                format_duration(&ceil_millis(d))
+            ),
+            ThrottleState::Idle {
+                next_activation: None,
+            } => write!(f, "Idle"),
+        }
+    }
+}
+
+fn ceil_millis(d: &Duration) -> Duration {
+    let millis = d.as_nanos().div_ceil(1_000_000);
+    Duration::from_millis(u64::try_from(millis).unwrap_or(u64::MAX))
+}
+
+// The milliseconds of the duration, which saturate instead of being truncated to 64 bits.
+fn saturating_millis(d: Duration) -> u64 {
+    u64::try_from(d.as_millis()).unwrap_or(u64::MAX)
+}
+
+// The state pinned by `Engine::force_active` or `Engine::force_idle`.
+#[derive(Debug, Clone, PartialEq)]
+enum Forced {
+    Active(Speed),
+    Idle,
+}
+
+// The token buckets and the reserved delays are per operation, and per thread too if `ThrottleScope::PerThread`.
+type BucketKey = (Operation, Option<ThreadId>);
+
+/// `Engine` decides whether and how the operations are throttled.
+/// All random decisions (i.e., the engagement of unstable periods and the speeds sampled from ranges) are made
+/// with the RNG in it, so the same seed and config produce the same decisions.
+///
+/// The config is replaced as a whole by `AtomicImmut` (e.g., by the control socket and the HTTP API), and it is
+/// loaded once at the entries of an operation (`on_operated` and `speed`) into `ConfigSnapshot`, which the other
+/// lookups of the operation read without loading it again. So an operation sees either the whole old config or
+/// the whole new one (including `Config::per_operation`) but never a partial update, and the reloads never block
+/// the lookups.
+pub struct Engine {
+    config: Arc<AtomicImmut<Config>>,
+    state_manager: StateManager,
+    // The cycles of `Config::profiles`, which begin again when the profiles are changed.
+    profiles: Vec<(Profile, StateManager)>,
+    clock: Arc<dyn Clock>,
+
+    rng: SmallRng,
+    // The configured speed and the speed sampled from it for the current unstable state.
+    sampled_speeds: HashMap<Operation, (Speed, Speed)>,
+    // The errors are decided with the dedicated RNG so that they do not affect the sampled speeds.
+    error_rng: SmallRng,
+    // The sizes of short reads/writes are decided with the dedicated RNG too.
+    short_io_rng: SmallRng,
+    // The random latencies of `Speed::LatencyRange` and `Speed::ExpLatency` are drawn with the dedicated RNG too.
+    latency_rng: SmallRng,
+    // The operations of `Config::operation_weights` which are throttled in the current unstable period.
+    // They are chosen with `rng` only if the weights are given.
+    chosen_operations: Vec<Operation>,
+    // The number of the operations on each opened file handle for `Config::passthrough_first_op`.
+    // The handles are removed when they are released.
+    handle_operations: HashMap<u64, u32>,
+    // The time when the first read of the last transfer completed (i.e., after its delay) and the end offset
+    // of the transfer on each opened file handle for `Config::read_coalesce`.
+    // The handles are removed when they are released.
+    read_transfers: HashMap<u64, (Instant, u64)>,
+    // The end offset of the last read/write on each opened file handle for `Config::seek_penalty`.
+    // The handles are removed when they are released.
+    access_offsets: HashMap<u64, u64>,
+    // The high-water offset (i.e., the known end of the file) on each opened file handle
+    // for `Config::allocation_penalty` and `Config::append_speed`. The handles are removed when they are released.
+    high_water_offsets: HashMap<u64, u64>,
+    // The opened file handles of which last write recorded by `on_handle_written` is an append
+    // for `Config::append_speed`. The handles are removed when they are released.
+    appending_handles: HashSet<u64>,
+    // The bytes written in the current unstable period to inject ENOSPC.
+    written_bytes: usize,
+    // The target operations serviced in the current unstable period for `Config::window_operations`.
+    window_operation_count: u64,
+    // The errors left to inject by `ErrorInjection::errors_per_window` in the current unstable period,
+    // the target operations in it so far and the estimate of them over which the errors are spread.
+    // The estimate is of the target operations and the length of the last period.
+    window_errors_left: usize,
+    window_error_ops: u64,
+    window_error_estimate: u64,
+    window_length: Option<Duration>,
+    last_window_error_ops: u64,
+    last_window_length: Option<Duration>,
+    // The bytes which passed through by `WindowBurst::Bytes` (or `Config::throttle_after_bytes`) in the current throttling state.
+    window_burst_bytes: u64,
+
+    // The token buckets to limit the byte rates if `Config::burst` is set.
+    // They are filled again at every change of the state.
+    buckets: HashMap<BucketKey, TokenBucket>,
+    // The token buckets for `Speed::Iops`, of which capacity is the rate (i.e., a second of the operations).
+    iops_buckets: HashMap<BucketKey, TokenBucket>,
+    // The bytes which the delays without the token buckets left over by the rounding.
+    delay_debts: HashMap<BucketKey, f64>,
+    // The time until which the delays without the token buckets are reserved, so that the concurrent
+    // operations of a bucket key wait in turn and proceed at the rate in total.
+    delay_reserved_until: HashMap<BucketKey, Instant>,
+
+    // The compiled `Config::include_globs` and `Config::exclude_globs`.
+    // It is compiled again only when the patterns are changed.
+    path_filter: PathFilter,
+    path_filter_globs: (Vec<String>, Vec<String>),
+    // The extension of the file of the current operation by `is_target_file` for `Config::extension_speeds`.
+    // It is kept only while the map is not empty.
+    file_extension: Option<String>,
+
+    // Returns the current time of day in seconds since midnight to check `Config::active_window`.
+    time_of_day: Box<dyn Fn() -> u32 + Send>,
+
+    // The flags to pause the throttling and to begin an unstable period (changed by the signals by default).
+    controls: &'static Controls,
+
+    // The sink of the start/end events of the unstable periods if exists.
+    event_log: Option<EventLog>,
+    log_level: LogLevel,
+    subscribers: Subscribers,
+    unstable_begin_time: Option<Instant>,
+    // The nominal byte rate of the current unstable period for `Stats::last_window`.
+    window_configured_bps: Option<u64>,
+
+    // Shared with the exporter of the metrics (see `stats_counters`).
+    stats: Arc<StatsCounters>,
+
+    // The measured throughput for `Speed::Percent`, which `Config::baseline` overrides.
+    baseline: Option<usize>,
+
+    // Logs the delays and the errors which `Config::dry_run` skips.
+    logger: Logger,
+
+    // Stops throttling and wakes the sleeping operations once triggered.
+    shutdown: Arc<Shutdown>,
+    // When `init` is called, from which `Config::run_for` is measured.
+    started_at: Option<Instant>,
+
+    // True if no operation is throttled, which is computed again only when the config, the pause flag or
+    // the state is changed (i.e., the key is changed) so that the pass-through operations skip the rest of `speed`.
+    passthrough: AtomicBool,
+    passthrough_key: Option<(Arc<Config>, bool, State)>,
+
+    // The state which overrides the schedule until `clear_force`.
+    forced: Option<Forced>,
+    // The last poll of `Config::trigger` (as of the trigger) and whether the file existed then.
+    trigger_polled: Option<(Trigger, Instant, bool)>,
+    // True if `forced` is set by the trigger, which clears it only then.
+    forced_by_trigger: bool,
+
+    // The config which the operations look up without loading `config`.
+    // It is refreshed by the entries of the operations (`on_operated` and `speed`) if the config is replaced.
+    snapshot: ConfigSnapshot,
+}
+
+impl Engine {
+    /// Creates `Engine` with `Config::seed` if exists, otherwise with entropy.
+    pub fn new(config: Arc<AtomicImmut<Config>>) -> Engine {
+        match config.load().seed {
+            Some(seed) => Engine::with_seed(Arc::clone(&config), seed),
+            None => Engine::with_rng(config, SmallRng::from_entropy()),
+        }
+    }
+
+    /// Creates `Engine` with the given seed regardless of `Config::seed`.
+    pub fn with_seed(config: Arc<AtomicImmut<Config>>, seed: u64) -> Engine {
+        Engine::with_rng(config, SmallRng::seed_from_u64(seed))
+    }
+
+    /// Creates `Engine` which never throttles, e.g., to disable it in tests without another code path.
+    /// No unstable periods begin (i.e., it is shut down from the beginning), so the operations always
+    /// pass through and the stats stay zero.
+    pub fn null() -> Engine {
+        let config = Config {
+            speed: Speed::PassThrough,
+            operations: Vec::new(),
+            condition: Condition::Always(State::Stable),
+            ..Default::default()
+        };
+        let engine = Engine::with_seed(Arc::new(AtomicImmut::new(config)), 0);
+        engine.shutdown();
+        engine
+    }
+
+    fn with_rng(config: Arc<AtomicImmut<Config>>, mut rng: SmallRng) -> Engine {
+        let snapshot = ConfigSnapshot::new(config.load());
+        let cond = snapshot.condition.clone();
+        let state_rng = SmallRng::from_rng(&mut rng).expect("SmallRng never fails to be seeded");
+        // The dedicated RNGs are derived from a fork of `rng` so that `rng` and `state_rng` keep
+        // their sequences for the seed. A new one is appended at the end to keep the others too.
+        let mut fork = SmallRng::seed_from_u64(rng.clone().gen::<u64>() ^ FORK_SALT);
+        let error_rng = SmallRng::from_rng(&mut fork).expect("SmallRng never fails to be seeded");
+        let short_io_rng =
+            SmallRng::from_rng(&mut fork).expect("SmallRng never fails to be seeded");
+        let latency_rng = SmallRng::from_rng(&mut fork).expect("SmallRng never fails to be seeded");
+
+        Engine {
+            config,
+            state_manager: StateManager::with_rng(cond, state_rng),
+            profiles: Vec::new(),
+            clock: Arc::new(SystemClock),
+            rng,
+            sampled_speeds: HashMap::new(),
+            error_rng,
+            short_io_rng,
+            latency_rng,
+            chosen_operations: Vec::new(),
+            handle_operations: HashMap::new(),
+            read_transfers: HashMap::new(),
+            access_offsets: HashMap::new(),
+            high_water_offsets: HashMap::new(),
+            appending_handles: HashSet::new(),
+            window_operation_count: 0,
+            written_bytes: 0,
+            window_errors_left: 0,
+            window_error_ops: 0,
+            window_error_estimate: 0,
+            window_length: None,
+            last_window_error_ops: 0,
+            last_window_length: None,
+            window_burst_bytes: 0,
+            buckets: HashMap::new(),
+            iops_buckets: HashMap::new(),
+            delay_debts: HashMap::new(),
+            delay_reserved_until: HashMap::new(),
+            path_filter: PathFilter::default(),
+            path_filter_globs: (Vec::new(), Vec::new()),
+            file_extension: None,
+            time_of_day: Box::new(local_time_of_day),
+            controls: &CONTROLS,
+            event_log: None,
+            log_level: LogLevel::default(),
+            subscribers: Subscribers::new(),
+            unstable_begin_time: None,
+            window_configured_bps: None,
+            stats: Arc::new(StatsCounters::new()),
+            baseline: None,
+            logger: Logger::root(Discard, o!()),
+            shutdown: Arc::new(Shutdown::new()),
+            started_at: None,
+            passthrough: AtomicBool::new(false),
+            passthrough_key: None,
+            forced: None,
+            trigger_polled: None,
+            forced_by_trigger: false,
+            snapshot,
+        }
+    }
+
+    /// Replaces the clock (`SystemClock` by default).
+    /// Call `init` after this to begin the state with the clock.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.state_manager.set_clock(Arc::clone(&clock));
+        for (_, m) in &mut self.profiles {
+            m.set_clock(Arc::clone(&clock));
+        }
+        self.clock = clock;
+    }
+
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// Returns the current config.
+    pub fn config(&self) -> Arc<Config> {
+        self.config.load()
+    }
+
+    /// Replaces the source of the time of day (the local time by default).
+    pub fn set_time_of_day<F>(&mut self, f: F)
+    where
+        F: Fn() -> u32 + Send + 'static,
+    {
+        self.time_of_day = Box::new(f);
+    }
+
+    /// Replaces the controls (`signal::CONTROLS` by default).
+    pub fn set_controls(&mut self, controls: &'static Controls) {
+        self.controls = controls;
+    }
+
+    /// Replaces the logger (which discards all by default).
+    pub fn set_logger(&mut self, logger: Logger) {
+        self.logger = logger;
+    }
+
+    /// Emits the events to the log whenever an unstable period starts or ends.
+    pub fn set_event_log(&mut self, event_log: EventLog) {
+        self.event_log = Some(event_log);
+    }
+
+    /// Sets which events are emitted to the event log (`LogLevel::Windows` by default).
+    /// The subscribers of `subscribe` receive all events regardless of it.
+    pub fn set_log_level(&mut self, level: LogLevel) {
+        self.log_level = level;
+    }
+
+    /// Returns the receiver of the events from now on: the starts and the ends of the unstable periods,
+    /// the injected errors and the delays of the operations.
+    /// The channel is unbounded (i.e., the engine never blocks or drops the events), so receive them
+    /// or drop the receiver not to buffer them indefinitely.
+    pub fn subscribe(&self) -> Receiver<subscription::Event> {
+        self.subscribers.subscribe()
+    }
+
+    /// Pins the engine in an unstable period at `speed` for the target operations of the config until `clear_force`,
+    /// e.g., to reproduce a slowdown deterministically.
+    ///
+    /// The forced state takes precedence over the schedule (i.e., `Config::condition`, `Config::active_window`,
+    /// `Config::window_burst`, the profiles, the ramp and the decay), then over the paused flag of the controls.
+    /// Only `shutdown` takes precedence over it. The schedule keeps running behind it.
+    pub fn force_active(&mut self, speed: Speed) {
+        self.forced_by_trigger = false;
+        self.set_forced(Some(Forced::Active(speed)));
+    }
+
+    /// Pins the engine out of the unstable periods until `clear_force`: no operation is throttled nor fails
+    /// by the error injection, with the same precedence as `force_active`.
+    pub fn force_idle(&mut self) {
+        self.forced_by_trigger = false;
+        self.set_forced(Some(Forced::Idle));
+    }
+
+    /// Clears `force_active` or `force_idle` to restore the state by the schedule, which is kept meanwhile.
+    pub fn clear_force(&mut self) {
+        self.forced_by_trigger = false;
+        self.set_forced(None);
+    }
+
+    /// Polls the file of `Config::trigger` if `poll_interval` has passed since the last poll, and forces the state
+    /// when the file is created or removed: `force_active` at `Config::speed` of the time while it exists,
+    /// and `clear_force` (or `force_idle` if `idle_when_absent`) while it is absent.
+    /// So `force_active` and `force_idle` by the others hold until the file is created or removed.
+    fn poll_trigger(&mut self, config: &Config, now: Instant) {
+        let trigger = match config.trigger {
+            Some(ref trigger) => trigger,
+            None => {
+                // The trigger is removed from the config.
+                self.trigger_polled = None;
+                if self.forced_by_trigger {
+                    self.clear_force();
+                }
+                return;
+            }
+        };
+
+        // The trigger which is replaced is polled afresh.
+        let prev = match self.trigger_polled {
+            Some((ref t, at, _))
+                if t == trigger && now.saturating_duration_since(at) < trigger.poll_interval =>
+            {
+                return
+            }
+            Some((ref t, _, exists)) if t == trigger => Some(exists),
+            _ => None,
+        };
+        let exists = trigger.path.exists();
+        self.trigger_polled = Some((trigger.clone(), now, exists));
+        if prev == Some(exists) {
+            return;
+        }
+
+        if exists {
+            self.force_active(config.speed.clone());
+            self.forced_by_trigger = true;
+        } else if trigger.idle_when_absent {
+            self.force_idle();
+            self.forced_by_trigger = true;
+        } else if self.forced_by_trigger {
+            self.clear_force();
+        }
+    }
+
+    fn set_forced(&mut self, forced: Option<Forced>) {
+        if self.forced == forced {
+            return;
+        }
+        self.forced = forced;
+        // The speeds and the buckets begin afresh as a new period.
+        self.buckets.clear();
+        self.iops_buckets.clear();
+        self.delay_debts.clear();
+        self.delay_reserved_until.clear();
+        self.sampled_speeds.clear();
+        self.passthrough_key = None;
+        let config = self.load_config();
+        if let Some(Forced::Active(_)) = self.forced {
+            self.schedule_window_errors(&config);
+        }
+        self.refresh_passthrough(&config);
+    }
+
+    /// Stops throttling: no more unstable periods begin, the following operations pass through
+    /// and the operations sleeping in `sleep` wake up.
+    pub fn shutdown(&self) {
+        self.shutdown.trigger();
+    }
+
+    /// Returns the flag which `shutdown` triggers to shut down from the other threads.
+    pub fn shutdown_handle(&self) -> Arc<Shutdown> {
+        Arc::clone(&self.shutdown)
+    }
+
+    /// Returns the delay of an operation at `speed` (see `Speed::sample_latency`), which is drawn with
+    /// the seeded RNG for the random latencies.
+    pub fn sample_latency(&mut self, speed: &Speed) -> Option<Duration> {
+        speed.sample_latency(&mut self.latency_rng)
+    }
+
+    /// Returns the time left until `Config::run_for` elapses since `init`, which is zero once it elapses.
+    /// It is `None` if `run_for` is not set or before `init`.
+    pub fn run_for_remaining(&self) -> Option<Duration> {
+        let run_for = self.config.load().run_for?;
+        let started_at = self.started_at?;
+        Some(run_for.saturating_sub(self.clock.now().saturating_duration_since(started_at)))
+    }
+
+    /// Shuts down if `Config::run_for` has elapsed, and returns true if shut down by it.
+    pub fn shutdown_if_run_over(&self) -> bool {
+        if self.shutdown.is_triggered() || self.run_for_remaining() != Some(Duration::default()) {
+            return false;
+        }
+
+        info!(self.logger, "run_for elapsed: shutting down");
+        self.shutdown();
+        true
+    }
+
+    /// Sleeps for `d` by the clock to throttle the operation until `shutdown` is called.
+    pub fn sleep(&self, d: Duration) {
+        self.clock.sleep_unless_shutdown(d, &self.shutdown);
+    }
+
+    /// Sets the measured throughput in bytes per second which `Speed::Percent` is relative to.
+    /// `Config::baseline` takes precedence over it if set.
+    pub fn set_baseline(&mut self, bps: usize) {
+        self.baseline = Some(bps);
+    }
+
+    pub fn init(&mut self) {
+        self.started_at = Some(self.clock.now());
+        let config = self.load_config();
+        self.configure_state_manager(&config);
+        self.state_manager.init();
+        self.profiles.clear();
+        self.sync_profiles(&config);
+        self.chosen_operations = choose_operations(&config, &mut self.rng);
+        if self.is_throttling(&config) {
+            self.schedule_window_errors(&config);
+        }
+
+        // Only the first activations are postponed.
+        self.state_manager.postpone_activation(config.warmup);
+        for (_, m) in &mut self.profiles {
+            m.postpone_activation(config.warmup);
+        }
+    }
+
+    /// Begins an unstable period of `Condition::Periodic` now instead of after the stable period
+    /// (and `Config::warmup`), e.g., to check the slowdown manually right after the start.
+    /// Unlike `force_active`, the period ends after `duration` and the cycle continues as usual.
+    /// It is expected after `init`, and it does nothing for `Condition::Always` and `Condition::Script`.
+    pub fn activate_now(&mut self) {
+        let now = self.clock.now();
+        let prev_state = self.state().clone();
+        self.state_manager.begin_unstable_at(now);
+
+        let config = self.load_config();
+        let state = self.state().clone();
+        if prev_state != state {
+            self.on_state_changed(&state, &config, now);
+        }
+        self.refresh_passthrough(&config);
+    }
+
+    /// Begins the cycles of the profiles if they are changed.
+    fn sync_profiles(&mut self, config: &Config) {
+        if self.profiles.len() == config.profiles.len()
+            && self
+                .profiles
+                .iter()
+                .zip(&config.profiles)
+                .all(|((p, _), q)| p == q)
+        {
+            return;
+        }
+
+        let mut profiles = Vec::with_capacity(config.profiles.len());
+        for p in &config.profiles {
+            let rng = SmallRng::from_rng(&mut self.rng).expect("SmallRng never fails to be seeded");
+            let mut m = StateManager::with_rng(p.condition(), rng);
+            m.set_clock(Arc::clone(&self.clock));
+            m.init();
+            profiles.push((p.clone(), m));
+        }
+        self.profiles = profiles;
+        self.passthrough_key = None;
+    }
+
+    pub fn state(&self) -> &State {
+        self.state_manager.state()
+    }
+
+    /// Returns whether the operations are throttled now without updating the state.
+    /// The state is updated only by the operations, so it can be behind the clock.
+    pub fn current_state(&self) -> ThrottleState {
+        let config = self.config.load();
+        let now = self.clock.now();
+        // The throttling ends when the stable period begins if `Config::inverted`, and vice versa.
+        let (end, begin) = if config.inverted {
+            (
+                self.state_manager.next_activation_at(now),
+                self.state_manager.remaining_at(now),
+            )
+        } else {
+            (
+                self.state_manager.remaining_at(now),
+                self.state_manager.next_activation_at(now),
+            )
+        };
+        if let (Some(Forced::Active(ref speed)), true) = (&self.forced, self.is_active(&config)) {
+            ThrottleState::Active {
+                operations: self.target_operations(&config),
+                speed: speed.clone(),
+                multiplier: 1.0,
+                remaining: None,
+            }
+        } else if self.is_active(&config) {
+            ThrottleState::Active {
+                operations: self.target_operations(&config),
+                speed: config.speed.clone(),
+                multiplier: self.speed_multiplier(),
+                remaining: end,
+            }
+        } else if self.shutdown.is_triggered() || self.forced.is_some() {
+            ThrottleState::Idle {
+                next_activation: None,
+            }
+        } else {
+            ThrottleState::Idle {
+                next_activation: begin,
+            }
+        }
+    }
+
+    /// Returns the times when the next `n` unstable periods begin without advancing the state
+    /// (see `StateManager::upcoming_activations`). The jitter, the warmup and the probability are projected
+    /// with the seeded RNG as the periods actually are. It is empty once shut down.
+    pub fn upcoming_activations(&self, n: usize) -> Vec<Instant> {
+        if self.shutdown.is_triggered() {
+            return Vec::new();
+        }
+        self.state_manager.upcoming_activations(n)
+    }
+
+    /// Updates the state on the operation and returns the new state if it is changed.
+    pub fn on_operated(&mut self, op: Operation) -> Result<Option<State>, String> {
+        let now = self.clock.now();
+        self.on_operated_at(op, now)
+    }
+
+    /// Same as `on_operated` but the current time is given as `now`.
+    pub fn on_operated_at(&mut self, op: Operation, now: Instant) -> Result<Option<State>, String> {
+        self.shutdown_if_run_over();
+        if self.shutdown.is_triggered() {
+            return Ok(None);
+        }
+
+        let prev_state = self.state_manager.state().clone();
+
+        let config = self.load_config();
+        self.poll_trigger(&config, now);
+        self.configure_state_manager(&config);
+        if self.controls.take_reset_request() {
+            self.state_manager.begin_unstable_at(now);
+        }
+        self.sync_profiles(&config);
+        for (p, m) in &mut self.profiles {
+            let prev = m.state().clone();
+            if *m.on_operated_at(op.clone(), &p.condition(), now)? != prev {
+                self.passthrough_key = None;
+            }
+        }
+        let state = self
+            .state_manager
+            .on_operated_at(op.clone(), &config.condition, now)?
+            .clone();
+        let state = self.count_window_operation(&config, &op, &prev_state, state, now);
+        self.refresh_passthrough(&config);
+
+        if prev_state == state {
+            return Ok(None);
+        }
+
+        self.on_state_changed(&state, &config, now);
+        Ok(Some(state))
+    }
+
+    /// Counts the target operation in the unstable period for `Config::window_operations`, and ends the period
+    /// early at the operation after the limit, which is then serviced in the stable period.
+    /// Returns the state after it.
+    fn count_window_operation(
+        &mut self,
+        config: &Config,
+        op: &Operation,
+        prev_state: &State,
+        state: State,
+        now: Instant,
+    ) -> State {
+        if *prev_state != state {
+            self.window_operation_count = 0;
+        }
+        let limit = match config.window_operations {
+            Some(limit) if state == State::Unstable && self.is_target(config, op) => limit,
+            _ => return state,
+        };
+
+        if self.window_operation_count < limit {
+            self.window_operation_count += 1;
+            return state;
+        }
+        self.state_manager.end_unstable_at(now);
+        self.state_manager.state().clone()
+    }
+
+    fn on_state_changed(&mut self, state: &State, config: &Config, now: Instant) {
+        // Empty the disk again for the next unstable period.
+        self.written_bytes = 0;
+        self.window_burst_bytes = 0;
+        self.buckets.clear();
+        self.iops_buckets.clear();
+        self.delay_debts.clear();
+        self.delay_reserved_until.clear();
+
+        if (*state == State::Unstable) != config.inverted {
+            // Pick a new speed and the weighted operations for this throttling state.
+            self.sampled_speeds.clear();
+            self.chosen_operations = choose_operations(config, &mut self.rng);
+            self.passthrough_key = None;
+            self.schedule_window_errors(config);
+        }
+        if *state == State::Unstable {
+            self.stats.increment_active_windows();
+        }
+        self.emit_event(state, config, now);
+    }
+
+    /// Returns a copy of the live config to `restore_config` later.
+    pub fn snapshot_config(&self) -> Config {
+        (*self.config.load()).clone()
+    }
+
+    /// Returns true if `new` differs from the live config, i.e., `restore_config` with it restarts the cycle.
+    pub fn would_change(&self, new: &Config) -> bool {
+        *self.config.load() != *new
+    }
+
+    /// Replaces the live config and restarts the cycle of the state by the condition of `config`.
+    /// The current unstable period ends immediately (i.e., `Condition::Periodic` begins a stable period).
+    /// It does nothing if `config` is the same as the live config, and changes nothing if `config` is invalid.
+    pub fn restore_config(&mut self, config: Config) -> Result<(), ConfigError> {
+        config.validate()?;
+        if !self.would_change(&config) {
+            return Ok(());
+        }
+
+        let prev_state = self.state().clone();
+        self.config.store(config);
+
+        let config = self.load_config();
+        self.configure_state_manager(&config);
+        self.state_manager.change_condition(&config.condition);
+        self.profiles.clear();
+        self.sync_profiles(&config);
+
+        // Always a fresh start even if the state is not changed.
+        let state = self.state().clone();
+        if prev_state == state {
+            self.written_bytes = 0;
+            self.window_burst_bytes = 0;
+            self.buckets.clear();
+            self.iops_buckets.clear();
+            self.delay_debts.clear();
+            self.delay_reserved_until.clear();
+            self.sampled_speeds.clear();
+        } else {
+            let now = self.clock.now();
+            self.on_state_changed(&state, &config, now);
+        }
+        self.window_operation_count = 0;
+        Ok(())
+    }
+
+    /// Returns the speed of the current unstable period, i.e., of the window of `Condition::Script`
+    /// or `Config::speed`.
+    fn window_speed<'a>(&'a self, config: &'a Config) -> &'a Speed {
+        self.state_manager
+            .script_window()
+            .map_or(&config.speed, |w| &w.speed)
+    }
+
+    /// Returns the nominal byte rate of the current unstable period for `Stats::last_window`,
+    /// which is resolved and decayed as `speed` does.
+    fn configured_bps(&self, config: &Config) -> Option<u64> {
+        let speed = match config.baseline.or(self.baseline) {
+            Some(baseline) => self.window_speed(config).resolve(baseline as u64),
+            None => self.window_speed(config).clone(),
+        };
+        speed.scale(self.speed_multiplier()).bytes_per_sec()
+    }
+
+    fn emit_event(&mut self, state: &State, config: &Config, now: Instant) {
+        let (kind, duration, achieved_bps) = match *state {
+            State::Unstable => {
+                self.unstable_begin_time = Some(now);
+                self.window_configured_bps = self.configured_bps(config);
+                self.stats.begin_window();
+                let duration = match config.condition {
+                    Condition::Periodic { duration, .. } => Some(duration),
+                    Condition::Script { .. } => {
+                        self.state_manager.script_window().map(|w| w.length)
+                    }
+                    Condition::Always(_) => None,
+                };
+                (EventKind::Start, duration, None)
+            }
+            State::Stable => {
+                let duration = self
+                    .unstable_begin_time
+                    .take()
+                    .map(|t| now.saturating_duration_since(t));
+                let throughput =
+                    duration.map(|d| self.stats.end_window(self.window_configured_bps.take(), d));
+                (
+                    EventKind::End,
+                    duration,
+                    throughput.and_then(|t| t.achieved_bps()),
+                )
+            }
+        };
+
+        // The start/end events are emitted unless `LogLevel::Off`.
+        let event_log = self
+            .event_log
+            .as_ref()
+            .filter(|_| self.log_level >= LogLevel::Windows);
+        if let Some(log) = event_log {
+            let event = Event {
+                event: kind,
+                timestamp: event::now_rfc3339(),
+                speed: self.window_speed(config).clone(),
+                operations: self.target_operations(config),
+                duration_ms: duration.map(saturating_millis),
+                achieved_bps,
+            };
+            // The events are best-effort and never affect the operations.
+            let _ = log.emit(&event);
+        }
+
+        self.subscribers.send(&match *state {
+            State::Unstable => subscription::Event::WindowStarted {
+                speed: self.window_speed(config).clone(),
+            },
+            State::Stable => subscription::Event::WindowEnded,
+        });
+    }
+
+    fn configure_state_manager(&mut self, config: &Config) {
+        self.state_manager
+            .set_probability(config.probability.unwrap_or(1.0));
+        self.state_manager
+            .set_frequency_jitter(config.frequency_jitter);
+        self.state_manager
+            .set_max_activations(config.max_activations);
+        self.state_manager
+            .set_hysteresis(config.min_active, config.min_idle);
+    }
+
+    /// Loads the live config and refreshes the snapshot if the config is replaced.
+    fn load_config(&mut self) -> Arc<Config> {
+        let config = self.config.load();
+        if !self.snapshot.is_of(&config) {
+            self.snapshot = ConfigSnapshot::new(Arc::clone(&config));
+        }
+        config
+    }
+
+    /// Returns the snapshot of the config which the operations look up.
+    /// It is refreshed by `on_operated` and `speed`, so it can be older than `config` until then.
+    pub fn config_snapshot(&self) -> &ConfigSnapshot {
+        &self.snapshot
+    }
+
+    /// Returns true if no operation is throttled for now.
+    /// It is cached and refreshed by `on_operated` and `speed`.
+    pub fn is_passthrough(&self) -> bool {
+        self.passthrough.load(Ordering::Relaxed)
+    }
+
+    fn refresh_passthrough(&mut self, config: &Arc<Config>) {
+        if let Some(ref forced) = self.forced {
+            let passthrough = match *forced {
+                Forced::Active(ref speed) => {
+                    speed.is_passthrough() || self.target_operations(config).is_empty()
+                }
+                Forced::Idle => true,
+            };
+            self.passthrough.store(passthrough, Ordering::Relaxed);
+            return;
+        }
+
+        let paused = self.controls.is_paused();
+        let state = self.state();
+        if let Some((ref c, p, ref s)) = self.passthrough_key {
+            if Arc::ptr_eq(c, config) && p == paused && s == state {
+                return;
+            }
+        }
+
+        // The active window is checked in `speed` because it changes without any trigger.
+        // The empty operations pass through regardless of the state.
+        let speeds_passthrough = match self.state_manager.script_window() {
+            Some(w) => w.speed.is_passthrough(),
+            None => {
+                config
+                    .operations
+                    .iter()
+                    .filter(|op| self.is_target(config, op))
+                    .all(|op| config.speed_for(op).is_passthrough())
+                    && config.extension_speeds.values().all(Speed::is_passthrough)
+                    && (!self.is_target(config, &Operation::Write)
+                        || config
+                            .append_speed
+                            .as_ref()
+                            .is_none_or(Speed::is_passthrough))
+            }
+        };
+        let passthrough = paused
+            || ((!self.is_throttling(config) || speeds_passthrough)
+                && self
+                    .profiles
+                    .iter()
+                    .all(|(p, m)| *m.state() != State::Unstable || p.speed.is_passthrough()));
+        self.passthrough.store(passthrough, Ordering::Relaxed);
+        self.passthrough_key = Some((Arc::clone(config), paused, state.clone()));
+    }
+
+    /// Returns true if the operation is throttled in unstable periods.
+    /// The weighted operations are throttled only if chosen for the current period.
+    fn is_target(&self, config: &Config, op: &Operation) -> bool {
+        config.operations.contains(op)
+            && (!config.operation_weights.contains_key(op) || self.chosen_operations.contains(op))
+    }
+
+    fn target_operations(&self, config: &Config) -> Vec<Operation> {
+        config
+            .operations
+            .iter()
+            .filter(|op| self.is_target(config, op))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns true if the state throttles the operations, i.e., it is unstable (or stable if `Config::inverted`)
+    /// or forced to be active.
+    fn is_throttling(&self, config: &Config) -> bool {
+        match self.forced {
+            Some(Forced::Active(_)) => true,
+            Some(Forced::Idle) => false,
+            None => (*self.state() == State::Unstable) != config.inverted,
+        }
+    }
+
+    /// Returns true if the state throttles the operations and the current time of day is in
+    /// `Config::active_window`. It is always false while the throttling is paused or after the shutdown.
+    fn is_active(&self, config: &Config) -> bool {
+        self.is_throttling(config) && self.is_enabled(config)
+    }
+
+    /// Same as `is_active` regardless of the state.
+    /// The forced state is not paused nor limited to the active window.
+    fn is_enabled(&self, config: &Config) -> bool {
+        if self.shutdown.is_triggered() {
+            return false;
+        }
+        match self.forced {
+            Some(Forced::Active(_)) => return true,
+            Some(Forced::Idle) => return false,
+            None if self.controls.is_paused() => return false,
+            None => {}
+        }
+
+        match config.active_window {
+            Some(ref w) => w.contains((self.time_of_day)()),
+            None => true,
+        }
+    }
+
+    /// Returns true if the operation passes through by `Controls::pause_operation`.
+    /// The forced state is not paused as for `Controls::set_paused`.
+    fn is_operation_paused(&self, op: &Operation) -> bool {
+        self.forced.is_none() && self.controls.is_operation_paused(op)
+    }
+
+    /// Returns the factor of `Config::speed_decay` by which the speed of the current unstable period is scaled.
+    /// It is clamped between `MIN_SPEED_MULTIPLIER` and `MAX_SPEED_MULTIPLIER`.
+    pub fn speed_multiplier(&self) -> f64 {
+        // The first unstable period is at the base speed.
+        let n = self.state_manager.activations().saturating_sub(1);
+        self.snapshot
+            .speed_decay
+            .powi(i32::try_from(n).unwrap_or(i32::MAX))
+            .clamp(MIN_SPEED_MULTIPLIER, MAX_SPEED_MULTIPLIER)
+    }
+
+    /// Returns the speed to apply if the state is unstable (stable if `Config::inverted`) and the operation is the target.
+    /// The unstable profiles of the operation take precedence over the state
+    /// (and `Config::ramp` and `Config::speed_decay` are not for them).
+    /// The speed of `Config::extension_speeds` for the file of the last `is_target_file` takes precedence over
+    /// `Config::per_operation` and `Config::speed`, and the speed of the window of `Condition::Script` over them.
+    /// The speed is sampled once per unstable state or when the configured speed is changed.
+    /// Out of the active window, the operations are not throttled regardless of the state.
+    /// `Speed::PassThrough` is never returned, and all operations take the fast path while `is_passthrough`.
+    /// The zero rates (see `Speed::is_stalled`) of the config which is not validated pass through too
+    /// instead of stalling the operations forever.
+    pub fn speed(&mut self, op: Operation) -> Option<Speed> {
+        self.speed_of(op, false)
+    }
+
+    /// Same as `speed` for the operation on the file handle. The write is at `Config::append_speed`
+    /// if the last write recorded by `on_handle_written` on the handle is an append.
+    pub fn handle_speed(&mut self, fh: u64, op: Operation) -> Option<Speed> {
+        let appending = op == Operation::Write && self.appending_handles.contains(&fh);
+        self.speed_of(op, appending)
+    }
+
+    fn speed_of(&mut self, op: Operation, appending: bool) -> Option<Speed> {
+        let config = self.load_config();
+        self.refresh_passthrough(&config);
+        if self.is_passthrough() || !self.is_enabled(&config) {
+            return None;
+        }
+        if let Some(Forced::Active(speed)) = self.forced.clone() {
+            return self.forced_speed(&config, op, &speed);
+        }
+        if self.is_operation_paused(&op) {
+            return None;
+        }
+
+        let profile = self.profile_speed(&op);
+        let scripted = self.state_manager.script_window().map(|w| w.speed.clone());
+        let speed = match profile {
+            Some(ref speed) => speed,
+            None if self.is_throttling(&config) && self.is_target(&config, &op) => match scripted {
+                Some(ref speed) => speed,
+                None => match config.append_speed {
+                    Some(ref speed) if appending => speed,
+                    _ => config.speed_for_extension(&op, self.file_extension.as_deref()),
+                },
+            },
+            None => return None,
+        };
+        if speed.is_stalled() {
+            return None;
+        }
+        if let (Some(WindowBurst::Duration(d)), None) = (config.window_burst, &profile) {
+            if self.state_manager.elapsed_at(self.clock.now()) < d {
+                return None;
+            }
+        }
+        let speed = self.sample_speed(&config, op, speed)?;
+
+        let speed = match self.speed_multiplier() {
+            _ if profile.is_some() => speed,
+            m if MAX_SPEED_MULTIPLIER <= m => return None,
+            m => speed.scale(m),
+        };
+
+        let speed = match (
+            config.ramp,
+            self.state_manager.unstable_elapsed_at(self.clock.now()),
+        ) {
+            (Some(ramp), Some(elapsed)) if elapsed < ramp && profile.is_none() => {
+                speed.interpolate(elapsed.as_secs_f64() / ramp.as_secs_f64())
+            }
+            _ => speed,
+        };
+        Some(self.share(&config, speed)).filter(|s| !s.is_passthrough())
+    }
+
+    /// Divides the speed by the number of the opened file handles if `Config::share_among_handles`.
+    fn share(&self, config: &Config, speed: Speed) -> Speed {
+        match self.open_handles() as u64 {
+            n if config.share_among_handles && 1 < n => speed / n,
+            _ => speed,
+        }
+    }
+
+    /// Returns the speed of `Engine::force_active` for the target operation, which is sampled and resolved
+    /// as the configured one but not ramped nor decayed.
+    fn forced_speed(&mut self, config: &Config, op: Operation, speed: &Speed) -> Option<Speed> {
+        if !self.is_target(config, &op) || speed.is_stalled() {
+            return None;
+        }
+        self.sample_speed(config, op, speed)
+            .map(|s| self.share(config, s))
+            .filter(|s| !s.is_passthrough())
+    }
+
+    /// Samples the speed once per unstable state (or when `speed` is changed) and resolves `Speed::Percent`.
+    /// It returns `None` to pass through if the baseline is unknown.
+    fn sample_speed(&mut self, config: &Config, op: Operation, speed: &Speed) -> Option<Speed> {
+        let sampled = match self.sampled_speeds.get(&op) {
+            Some((configured, sampled)) if configured == speed => sampled.clone(),
+            _ => {
+                let sampled = speed.sample(&mut self.rng);
+                self.sampled_speeds
+                    .insert(op, (speed.clone(), sampled.clone()));
+                sampled
+            }
+        };
+
+        match sampled {
+            Speed::Percent(_) => Some(sampled.resolve(config.baseline.or(self.baseline)? as u64)),
+            s => Some(s),
+        }
+    }
+
+    /// Returns the speed of the first unstable profile of the operation.
+    fn profile_speed(&self, op: &Operation) -> Option<Speed> {
+        self.profiles
+            .iter()
+            .find(|(p, m)| *m.state() == State::Unstable && p.operations.contains(op))
+            .map(|(p, _)| p.speed.clone())
+    }
+
+    /// Passes the bytes through within the budget of `WindowBurst::Bytes` and returns the rest to throttle.
+    /// `Config::throttle_after_bytes` is the same budget.
+    fn consume_window_burst(&mut self, op: &Operation, size: usize) -> usize {
+        let budget = match self.snapshot.window_burst {
+            Some(WindowBurst::Bytes(n)) => Some(n),
+            _ => self.snapshot.throttle_after_bytes,
+        };
+        let budget = match budget {
+            Some(n) if self.forced.is_none() && self.profile_speed(op).is_none() => n,
+            _ => return size,
+        };
+
+        let passed = budget
+            .saturating_sub(self.window_burst_bytes)
+            .min(size as u64);
+        self.window_burst_bytes += passed;
+        size - passed as usize
+    }
+
+    /// Consumes `size` bytes from the token bucket of the operation and returns the duration to wait.
+    /// The bucket is of the current thread if `ThrottleScope::PerThread`.
+    /// Returns `None` if `Config::burst` is not set, and then the legacy throttle is expected.
+    /// The bucket of each throttling state begins at `Config::burst_start_fill` of the capacity.
+    pub fn reserve_bytes(
+        &mut self,
+        op: Operation,
+        bps: u64,
+        size: usize,
+        now: Instant,
+    ) -> Option<Duration> {
+        let burst = self.snapshot.burst?;
+        let fill = self.snapshot.burst_start_fill;
+
+        let bucket = self
+            .buckets
+            .entry(bucket_key(op, self.snapshot.scope))
+            .or_insert_with(|| TokenBucket::with_fill(bps, burst, fill, now));
+        if bucket.rate() != bps || bucket.capacity() != burst {
+            // The speed or the burst is changed.
+            *bucket = TokenBucket::new(bps, burst, now);
+        }
+        warn_backward_jump(&self.logger, bucket, now);
+
+        Some(bucket.reserve(size, now))
+    }
+
+    /// Returns the duration to wait to limit the read/write of `size` bytes to `bps`.
+    /// `elapsed` is the time which the operation itself took.
+    /// The bytes are consumed from the token bucket if `Config::burst` is set.
+    /// The bytes within `WindowBurst::Bytes` are not delayed (nor counted as throttled).
+    /// A transfer of zero bytes (e.g., a read at EOF or an empty write) is free: it neither waits
+    /// nor pays the debts of the token bucket and the rounding.
+    pub fn throttle_delay(
+        &mut self,
+        op: Operation,
+        bps: u64,
+        size: usize,
+        elapsed: Duration,
+    ) -> Duration {
+        let size = self.consume_window_burst(&op, size);
+        if size == 0 {
+            return Duration::default();
+        }
+        self.on_throttled(&op, size);
+
+        let now = self.clock.now();
+        if let Some(wait) = self.reserve_bytes(op.clone(), bps, size, now) {
+            return wait;
+        }
+
+        let key = bucket_key(op, self.snapshot.scope);
+        let debt = self.delay_debts.entry(key.clone()).or_insert(0.0);
+        let delay = compute_sleep_duration_with_debt(bps, size, saturating_millis(elapsed), debt);
+        self.reserve_delay(key, delay, now)
+    }
+
+    /// Reserves `delay` after the ones reserved by the other operations of the key (e.g., of the other threads
+    /// in `ThrottleScope::Global`) and returns the duration to wait until it ends.
+    /// The reservations of the sequential operations have already passed, so they wait for their own delays only.
+    /// Nothing is reserved in `Config::dry_run`, which does not wait, and with `Config::share_among_handles`,
+    /// of which speed is already the share of a handle.
+    fn reserve_delay(&mut self, key: BucketKey, delay: Duration, now: Instant) -> Duration {
+        if self.snapshot.dry_run || self.snapshot.share_among_handles {
+            return delay;
+        }
+
+        // The reservation is of the delay which the operation waits actually.
+        let max = self
+            .snapshot
+            .max_op_delay
+            .map_or(MAX_OP_DELAY, |d| d.min(MAX_OP_DELAY));
+        let reserved_until = self.delay_reserved_until.entry(key).or_insert(now);
+        *reserved_until = (*reserved_until).max(now) + delay.min(max);
+        reserved_until.saturating_duration_since(now)
+    }
+
+    /// Sleeps to throttle the read/write of `size` bytes and returns the duration slept.
+    /// It is zero if the byte rate is not active, in `Config::dry_run` or for zero bytes.
+    /// The fixed latency of `Speed::Latency` and `Speed::BpsWithLatency` is not of the bytes,
+    /// so it is applied to the zero-byte operations by the caller before the transfer as well.
+    /// The state is expected to be updated by `on_operated` before.
+    pub fn throttle(&mut self, op: Operation, size: usize) -> Duration {
+        self.throttle_vectored(op, &[size])
+    }
+
+    /// Same as `throttle` but for the vectored read/write of the buffers of `lengths` bytes.
+    /// The throttle is charged the total bytes of all the buffers.
+    pub fn throttle_vectored(&mut self, op: Operation, lengths: &[usize]) -> Duration {
+        let size = lengths.iter().fold(0usize, |sum, n| sum.saturating_add(*n));
+        let wait = self.byte_rate_delay(op.clone(), size);
+        let wait = self.apply_delay(&op, size, wait);
+        self.sleep(wait);
+        wait
+    }
+
+    /// Returns the sleep of tokio to throttle the read/write of `size` bytes.
+    /// The delay is the same as `throttle`.
+    #[cfg(feature = "async")]
+    pub fn throttle_async(&mut self, op: Operation, size: usize) -> ::tokio::time::Sleep {
+        let wait = self.byte_rate_delay(op.clone(), size);
+        ::tokio::time::sleep(self.apply_delay(&op, size, wait))
+    }
+
+    fn byte_rate_delay(&mut self, op: Operation, size: usize) -> Duration {
+        let speed = self.speed(op.clone());
+        self.speed_delay(op, speed, size)
+    }
+
+    fn speed_delay(&mut self, op: Operation, speed: Option<Speed>, size: usize) -> Duration {
+        match (
+            speed.as_ref().and_then(|s| s.bps()),
+            speed.as_ref().and_then(|s| s.per_byte()),
+        ) {
+            (Some(bps), _) => self.throttle_delay(op, bps, size, Duration::default()),
+            (None, Some(per_byte)) => self.per_byte_delay(op, per_byte, size, Duration::default()),
+            (None, None) => Duration::default(),
+        }
+    }
+
+    /// Charges `size` bytes to the byte rate of the operation without waiting, e.g., for a read coalesced
+    /// into the transfer by `Config::read_coalesce`. The bytes are consumed from the token bucket if
+    /// `Config::burst` is set, or owed as the debt of the rounding otherwise, so the following transfers pay them.
+    /// They are counted as throttled. `Speed::PerByte` has no debt, so nothing is charged at it.
+    pub fn charge_bytes(&mut self, op: Operation, size: usize) {
+        let bps = match self.speed(op.clone()).and_then(|s| s.bps()) {
+            Some(bps) => bps,
+            None => return,
+        };
+        let size = self.consume_window_burst(&op, size);
+        if size == 0 {
+            return;
+        }
+        self.on_throttled(&op, size);
+
+        let now = self.clock.now();
+        if self.reserve_bytes(op.clone(), bps, size, now).is_none() {
+            let key = bucket_key(op, self.snapshot.scope);
+            *self.delay_debts.entry(key).or_insert(0.0) += size as f64;
+        }
+    }
+
+    /// Same as `throttle_delay` but for `Speed::PerByte`, of which each byte takes `per_byte`.
+    /// The token buckets of `Config::burst` do not limit it.
+    pub fn per_byte_delay(
+        &mut self,
+        op: Operation,
+        per_byte: Duration,
+        size: usize,
+        elapsed: Duration,
+    ) -> Duration {
+        let rest = self.consume_window_burst(&op, size);
+        if rest == 0 {
+            return Duration::default();
+        }
+        self.on_throttled(&op, rest);
+
+        compute_delay(&Speed::PerByte(per_byte), rest, 0.0).saturating_sub(elapsed)
+    }
+
+    /// Sleeps for `wait` to delay the operation of `size` bytes and returns true if it slept.
+    /// In `Config::dry_run`, it only logs the delay and returns false.
+    pub fn delay(&self, op: &Operation, size: usize, wait: Duration) -> bool {
+        let wait = self.apply_delay(op, size, wait);
+        self.sleep(wait);
+        wait != Duration::default()
+    }
+
+    /// Counts the delay and returns the duration to sleep actually, which is capped at `Config::max_op_delay`
+    /// (and `MAX_OP_DELAY`) and is zero in `Config::dry_run`.
+    fn apply_delay(&self, op: &Operation, size: usize, wait: Duration) -> Duration {
+        if wait == Duration::default() {
+            return wait;
+        }
+        let max = self
+            .snapshot
+            .max_op_delay
+            .map_or(MAX_OP_DELAY, |d| d.min(MAX_OP_DELAY));
+        let wait = match max {
+            max if max < wait => {
+                debug!(
+                    self.logger,
+                    "the delay of {} of {} bytes ({:?}) is capped at {:?}", op, size, wait, max
+                );
+                max
+            }
+            _ => wait,
+        };
+        self.emit_throttle_event(op, wait);
+        self.subscribers
+            .send(&subscription::Event::OperationThrottled {
+                op: op.clone(),
+                bytes: size,
+                delay: wait,
+            });
+
+        if self.snapshot.dry_run {
+            info!(
+                self.logger,
+                "dry run: {} of {} bytes would be delayed by {:?}", op, size, wait
+            );
+            self.stats.increment_ops_would_delay();
+            return Duration::default();
+        }
+
+        self.on_delayed(op);
+        self.stats.record_delay(wait);
+        wait
+    }
+
+    /// Emits the event of the delay at `LogLevel::Operations` if it is sampled by `EventLog::set_sample_rate`.
+    fn emit_throttle_event(&self, op: &Operation, wait: Duration) {
+        if self.log_level < LogLevel::Operations {
+            return;
+        }
+        if let Some(ref log) = self.event_log {
+            let event = Event {
+                event: EventKind::Throttle,
+                timestamp: event::now_rfc3339(),
+                speed: match self.state_manager.script_window() {
+                    Some(w) => w.speed.clone(),
+                    None => self
+                        .snapshot
+                        .speed_for_extension(op, self.file_extension.as_deref())
+                        .clone(),
+                },
+                operations: vec![op.clone()],
+                duration_ms: Some(saturating_millis(wait)),
+                achieved_bps: None,
+            };
+            let _ = log.emit_sampled(&event);
+        }
+    }
+
+    /// Consumes an operation from the token bucket for `Speed::Iops` and returns the duration to wait.
+    pub fn reserve_operation(&mut self, op: Operation, iops: u64, now: Instant) -> Duration {
+        // The capacity saturates on 32-bit targets, where it is far more than enough.
+        let capacity = usize::try_from(iops).unwrap_or(usize::MAX);
+        let bucket = self
+            .iops_buckets
+            .entry(bucket_key(op, self.snapshot.scope))
+            .or_insert_with(|| TokenBucket::new(iops, capacity, now));
+        if bucket.rate() != iops {
+            *bucket = TokenBucket::new(iops, capacity, now);
+        }
+        warn_backward_jump(&self.logger, bucket, now);
+
+        bucket.reserve(1, now)
+    }
+
+    /// Returns the error code if the operation has to fail by `Config::error_injection`.
+    /// `size` is the number of bytes to write and the others ignore it.
+    /// In `Config::dry_run`, it only logs the error and returns `None`.
+    pub fn injected_error(&mut self, op: Operation, size: usize) -> Option<c_int> {
+        let code = self.decide_error(op.clone(), size)?;
+        if self.snapshot.dry_run {
+            info!(
+                self.logger,
+                "dry run: {} of {} bytes would fail with {}", op, size, code
+            );
+            self.stats.increment_errors_would_inject();
+            return None;
+        }
+
+        self.stats.increment_errors_injected();
+        self.subscribers
+            .send(&subscription::Event::ErrorInjected { op, errno: code });
+        Some(code)
+    }
+
+    /// Estimates the target operations in the unstable period which begins now to spread the errors of
+    /// `ErrorInjection::errors_per_window` over them.
+    fn schedule_window_errors(&mut self, config: &Config) {
+        // The periods without the target operations (e.g., idle) do not estimate the next one.
+        if 0 < self.window_error_ops {
+            self.last_window_error_ops = self.window_error_ops;
+            self.last_window_length = self.window_length;
+        }
+        self.window_error_ops = 0;
+        self.window_length = self.state_manager.remaining_at(self.clock.now());
+
+        self.window_errors_left = match config.error_injection {
+            Some(ErrorInjection {
+                errors_per_window: Some(n),
+                ..
+            }) => n as usize,
+            _ => 0,
+        };
+        // The operations of the last period are scaled by the lengths (e.g., of the script windows).
+        let ops = match (self.window_length, self.last_window_length) {
+            (Some(length), Some(last)) if last != Duration::default() => {
+                let ratio = length.as_secs_f64() / last.as_secs_f64();
+                (self.last_window_error_ops as f64 * ratio).round() as u64
+            }
+            _ => self.last_window_error_ops,
+        };
+        self.window_error_estimate = ops.max(self.window_errors_left as u64);
+    }
+
+    /// Decides whether the next target operation fails by `ErrorInjection::errors_per_window`.
+    /// Each one fails by the probability of the errors left over the operations left in the estimate,
+    /// so that exactly the budget fails over the estimated operations. The operations beyond the estimate
+    /// fail while any are left.
+    fn decide_window_error(&mut self) -> bool {
+        let ops_left = self
+            .window_error_estimate
+            .saturating_sub(self.window_error_ops)
+            .max(self.window_errors_left as u64);
+        self.window_error_ops += 1;
+        if self.window_errors_left == 0 {
+            return false;
+        }
+
+        let p = self.window_errors_left as f64 / ops_left as f64;
+        if 1.0 <= p || self.error_rng.gen_bool(p) {
+            self.window_errors_left -= 1;
+            return true;
+        }
+        false
+    }
+
+    fn decide_error(&mut self, op: Operation, size: usize) -> Option<c_int> {
+        let config = Arc::clone(self.snapshot.config());
+        let e = match config.error_injection {
+            Some(ref e) => e,
+            None => return None,
+        };
+
+        // `ErrorInjection::continuous` injects the errors by the probability out of the unstable periods too.
+        let active = self.is_active(&config);
+        if !(active || e.continuous && self.is_enabled(&config)) || self.is_operation_paused(&op) {
+            return None;
+        }
+
+        if let (true, Some(_)) = (active, e.errors_per_window) {
+            if e.operations.contains(&op) && self.decide_window_error() {
+                return Some(choose_error(&e.errors, &mut self.error_rng));
+            }
+        } else if e.operations.contains(&op)
+            && (1.0 <= e.probability
+                || (0.0 < e.probability && self.error_rng.gen_bool(e.probability)))
+        {
+            return Some(choose_error(&e.errors, &mut self.error_rng));
+        }
+
+        if !active {
+            return None;
+        }
+
+        if let (Operation::Write, Some(threshold)) = (op, e.disk_full_after) {
+            let written = self.written_bytes.saturating_add(size);
+            if threshold < written {
+                return Some(libc::ENOSPC);
+            }
+            self.written_bytes = written;
+        }
+
+        None
+    }
+
+    /// Counts the bytes read/written under the speed limit.
+    pub fn on_throttled(&self, op: &Operation, size: usize) {
+        self.stats.add_throttled_bytes(op, size as u64);
+    }
+
+    /// Counts the operation which slept to be throttled.
+    pub fn on_delayed(&self, op: &Operation) {
+        self.stats.increment_ops_delayed(op);
+    }
+
+    /// Returns the snapshot of the runtime counters.
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot()
+    }
+
+    /// Returns the runtime counters themselves, e.g., to export them as the metrics while running.
+    pub fn stats_counters(&self) -> &Arc<StatsCounters> {
+        &self.stats
+    }
+
+    /// Returns the report of the run so far from `stats`, e.g., to print at the shutdown.
+    /// The current unstable period is counted in the byte rates as if it ended now.
+    pub fn summary(&self) -> RunSummary {
+        let stats = match self.unstable_begin_time {
+            Some(begin) => self.stats.snapshot_with_open_window(
+                self.window_configured_bps,
+                self.clock.now().saturating_duration_since(begin),
+            ),
+            None => self.stats(),
+        };
+        RunSummary::from(&stats)
+    }
+
+    /// Returns true if the file is selected to throttle by the glob patterns and the size.
+    /// `path` is relative to the original directory and `file_size` is called only if `Config::min_file_size` is set.
+    /// The extension of `path` selects the speed of `Config::extension_speeds` for the following lookups
+    /// of the operation (e.g., `speed`).
+    pub fn is_target_file<F>(&mut self, path: &Path, file_size: F) -> bool
+    where
+        F: FnOnce() -> u64,
+    {
+        let config = Arc::clone(self.snapshot.config());
+        self.file_extension = if config.extension_speeds.is_empty() {
+            None
+        } else {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .map(str::to_string)
+        };
+        if self.path_filter_globs.0 != config.include_globs
+            || self.path_filter_globs.1 != config.exclude_globs
+        {
+            // Keep the previous filter if the patterns are invalid (they are rejected by `Config::validate` though).
+            if let Ok(f) = config.path_filter() {
+                self.path_filter = f;
+            }
+            self.path_filter_globs = (config.include_globs.clone(), config.exclude_globs.clone());
+        }
+
+        if !self.path_filter.matches(path) {
+            return false;
+        }
+
+        match config.min_file_size {
+            Some(min) => min <= file_size(),
+            None => true,
+        }
+    }
+
+    /// Services the beginning of the operation of `size` bytes in the order of `Mizumochi`: it sleeps for the latency
+    /// (or the IOPS limit) and then returns the number of bytes to read/write actually (see `io_size`),
+    /// or the error code to inject. `written` is the bytes to count for ENOSPC (see `injected_error`).
+    /// A write is shortened before the error injection, so ENOSPC is judged on the bytes to write actually.
+    /// The state is expected to be updated by `on_operated` before, and `throttle` is expected with
+    /// the transferred bytes after the operation.
+    ///
+    /// The features of the file handles (`Config::passthrough_first_op`, `Config::read_coalesce`,
+    /// `Config::seek_penalty` and `Config::allocation_penalty`) are not applied since it has no handles.
+    pub fn begin_operation(
+        &mut self,
+        op: Operation,
+        size: usize,
+        written: usize,
+    ) -> Result<usize, c_int> {
+        let wait = match self.speed(op.clone()) {
+            Some(Speed::Iops(iops)) => {
+                let now = self.clock.now();
+                self.reserve_operation(op.clone(), iops, now)
+            }
+            Some(s) => self.sample_latency(&s).unwrap_or_default(),
+            None => Duration::default(),
+        };
+        self.delay(&op, 0, wait);
+
+        if op == Operation::Write {
+            let size = self.io_size(op.clone(), size);
+            return match self.injected_error(op, written.min(size)) {
+                Some(code) => Err(code),
+                None => Ok(size),
+            };
+        }
+        if let Some(code) = self.injected_error(op.clone(), written) {
+            return Err(code);
+        }
+        Ok(self.io_size(op, size))
+    }
+
+    /// Returns the number of bytes to read/write actually for the request of `size` bytes by `Config::short_io`.
+    /// It is one byte at least unless `size` is zero.
+    /// In `Config::dry_run`, it only logs the shortened size and returns `size`.
+    pub fn io_size(&mut self, op: Operation, size: usize) -> usize {
+        let config = Arc::clone(self.snapshot.config());
+        if size == 0 || !self.is_active(&config) || self.is_operation_paused(&op) {
+            return size;
+        }
+
+        let clamped = match (&op, &config.short_io) {
+            (&Operation::Read, &Some(ref s)) | (&Operation::Write, &Some(ref s)) => {
+                clamp_io_size(s, size, &mut self.short_io_rng)
+            }
+            _ => size,
+        };
+        if config.dry_run && clamped < size {
+            info!(
+                self.logger,
+                "dry run: {} of {} bytes would be shortened to {} bytes", op, size, clamped
+            );
+            return size;
+        }
+        clamped
+    }
+
+    /// Returns the number of the file handles which are opened by `on_opened` and not yet released.
+    pub fn open_handles(&self) -> usize {
+        self.handle_operations.len()
+    }
+
+    /// Begins to count the operations on the opened file handle.
+    /// It shares the speed with the other opened ones if `Config::share_among_handles`.
+    pub fn on_opened(&mut self, fh: u64) {
+        self.handle_operations.insert(fh, 0);
+    }
+
+    /// Stops counting the operations on the released file handle.
+    pub fn on_released(&mut self, fh: u64) {
+        self.handle_operations.remove(&fh);
+        self.read_transfers.remove(&fh);
+        self.access_offsets.remove(&fh);
+        self.high_water_offsets.remove(&fh);
+        self.appending_handles.remove(&fh);
+    }
+
+    /// Counts an operation on the file handle and returns true if it passes through as the first one
+    /// by `Config::passthrough_first_op`. The handles which are not opened by `on_opened` are not counted.
+    pub fn on_handle_operated(&mut self, fh: u64) -> bool {
+        match self.handle_operations.get_mut(&fh) {
+            Some(n) => {
+                *n = n.saturating_add(1);
+                *n == 1 && self.snapshot.passthrough_first_op
+            }
+            None => false,
+        }
+    }
+
+    /// Same as `throttle` at `handle_speed` but the first operation on the file handle passes through
+    /// if `Config::passthrough_first_op` is set.
+    pub fn throttle_handle(&mut self, fh: u64, op: Operation, size: usize) -> Duration {
+        if self.on_handle_operated(fh) {
+            return Duration::default();
+        }
+        let speed = self.handle_speed(fh, op.clone());
+        let wait = self.speed_delay(op.clone(), speed, size);
+        let wait = self.apply_delay(&op, size, wait);
+        self.sleep(wait);
+        wait
+    }
+
+    /// Returns true if the read of `size` bytes at `offset` on the file handle continues the last transfer
+    /// of the handle (i.e., it begins at the end of the last read) within `Config::read_coalesce` after the
+    /// first read of the transfer completed, and then it is charged to the transfer.
+    /// Otherwise, it begins a new transfer, and `on_read_completed` is expected after its delay.
+    /// The handles which are not opened by `on_opened` are never coalesced.
+    pub fn on_handle_read(&mut self, fh: u64, offset: u64, size: usize) -> bool {
+        let window = match self.snapshot.read_coalesce {
+            Some(window) if self.handle_operations.contains_key(&fh) => window,
+            _ => return false,
+        };
+
+        let now = self.clock.now();
+        let end = offset.saturating_add(size as u64);
+        if let Some(&mut (completed, ref mut last_end)) = self.read_transfers.get_mut(&fh) {
+            if *last_end == offset && now.saturating_duration_since(completed) < window {
+                *last_end = end;
+                return true;
+            }
+        }
+        self.read_transfers.insert(fh, (now, end));
+        false
+    }
+
+    /// Marks the end of the delay of the first read of the transfer on the file handle, from which
+    /// `Config::read_coalesce` is measured.
+    pub fn on_read_completed(&mut self, fh: u64) {
+        let now = self.clock.now();
+        if let Some(&mut (ref mut completed, _)) = self.read_transfers.get_mut(&fh) {
+            *completed = now;
+        }
+    }
+
+    /// Same as `throttle_handle` for the read of `size` bytes at `offset`, but the reads which are coalesced
+    /// by `Config::read_coalesce` pass through and their bytes are charged to the transfer (see `charge_bytes`).
+    pub fn throttle_read(&mut self, fh: u64, offset: u64, size: usize) -> Duration {
+        if self.on_handle_read(fh, offset, size) {
+            self.on_handle_operated(fh);
+            self.charge_bytes(Operation::Read, size);
+            return Duration::default();
+        }
+        let wait = self.throttle_handle(fh, Operation::Read, size);
+        self.on_read_completed(fh);
+        wait
+    }
+
+    /// Returns true if the read/write at `offset` on the file handle is a random access for `Config::seek_penalty`,
+    /// i.e., it does not begin at the end of the last one recorded by `on_handle_accessed`.
+    /// The first access on a handle is sequential, and the handles which are not opened by `on_opened`
+    /// (or without `seek_penalty`) are never tracked.
+    pub fn is_random_access(&self, fh: u64, offset: u64) -> bool {
+        self.tracks_accesses(fh)
+            && self
+                .access_offsets
+                .get(&fh)
+                .is_some_and(|&last_end| last_end != offset)
+    }
+
+    /// Records the read/write of `size` bytes at `offset` on the file handle for `is_random_access`.
+    /// It is expected after the transfer with the bytes transferred actually (e.g., of a short read at EOF),
+    /// so that the next one continues from the end of them.
+    pub fn on_handle_accessed(&mut self, fh: u64, offset: u64, size: usize) {
+        if self.tracks_accesses(fh) {
+            self.access_offsets
+                .insert(fh, offset.saturating_add(size as u64));
+        }
+    }
+
+    fn tracks_accesses(&self, fh: u64) -> bool {
+        self.snapshot.seek_penalty.is_some() && self.handle_operations.contains_key(&fh)
+    }
+
+    /// Returns `Config::seek_penalty` to delay a random access of `op` if the operation is throttled now.
+    /// The state is expected to be updated by `on_operated` before.
+    pub fn seek_penalty(&mut self, op: &Operation) -> Duration {
+        let config = &self.snapshot;
+        match config.seek_penalty {
+            Some(penalty)
+                if self.is_active(config)
+                    && self.is_target(config, op)
+                    && !self.is_operation_paused(op) =>
+            {
+                penalty
+            }
+            _ => Duration::default(),
+        }
+    }
+
+    /// Sleeps for `Config::seek_penalty` if the read/write of `size` bytes at `offset` on the file handle is
+    /// a random access, and returns the duration slept. The byte rate is not applied by this.
+    pub fn throttle_seek(&mut self, fh: u64, op: Operation, offset: u64, size: usize) -> Duration {
+        let random = self.is_random_access(fh, offset);
+        self.on_handle_accessed(fh, offset, size);
+        if !random {
+            return Duration::default();
+        }
+        let wait = self.seek_penalty(&op);
+        let wait = self.apply_delay(&op, size, wait);
+        self.sleep(wait);
+        wait
+    }
+
+    /// Sets the size of the file of the opened handle as its high-water offset for `Config::allocation_penalty`
+    /// and `Config::append_speed`. The writes of a handle whose size is not set allocate from the offset zero.
+    pub fn set_handle_size(&mut self, fh: u64, size: u64) {
+        if self.tracks_high_water() && self.handle_operations.contains_key(&fh) {
+            self.high_water_offsets.insert(fh, size);
+        }
+    }
+
+    /// Returns true if the high-water offsets of the handles are needed by the config.
+    pub fn tracks_high_water(&self) -> bool {
+        self.snapshot.allocation_penalty.is_some() || self.snapshot.append_speed.is_some()
+    }
+
+    /// Records the write of `size` bytes at `offset` on the file handle and returns true if it allocates
+    /// for `Config::allocation_penalty`, i.e., it ends beyond the high-water offset, which is raised to it.
+    /// The overwrites in place do not allocate, and the handles which are not opened by `on_opened`
+    /// (or without `allocation_penalty` and `append_speed`) are never tracked.
+    ///
+    /// The write which begins at the high-water offset is an append, and the following lookups of the writes
+    /// on the handle (i.e., `handle_speed`) are at `Config::append_speed` until its next write is recorded.
+    pub fn on_handle_written(&mut self, fh: u64, offset: u64, size: usize) -> bool {
+        self.appending_handles.remove(&fh);
+        if !self.tracks_high_water() || !self.handle_operations.contains_key(&fh) {
+            return false;
+        }
+
+        let end = offset.saturating_add(size as u64);
+        let high_water = self.high_water_offsets.entry(fh).or_insert(0);
+        if self.snapshot.append_speed.is_some() && offset == *high_water {
+            self.appending_handles.insert(fh);
+        }
+        if end <= *high_water {
+            return false;
+        }
+        *high_water = end;
+        self.snapshot.allocation_penalty.is_some()
+    }
+
+    /// Returns `Config::allocation_penalty` to delay an allocating write if the writes are throttled now.
+    /// The state is expected to be updated by `on_operated` before.
+    pub fn allocation_penalty(&mut self) -> Duration {
+        let config = &self.snapshot;
+        let op = Operation::Write;
+        match config.allocation_penalty {
+            Some(penalty)
+                if self.is_active(config)
+                    && self.is_target(config, &op)
+                    && !self.is_operation_paused(&op) =>
+            {
+                penalty
+            }
+            _ => Duration::default(),
+        }
+    }
+
+    /// Sleeps for `Config::allocation_penalty` if the write of `size` bytes at `offset` on the file handle
+    /// allocates, and returns the duration slept. The byte rate is not applied by this.
+    pub fn throttle_allocation(&mut self, fh: u64, offset: u64, size: usize) -> Duration {
+        if !self.on_handle_written(fh, offset, size) {
+            return Duration::default();
+        }
+        let wait = self.allocation_penalty();
+        let wait = self.apply_delay(&Operation::Write, size, wait);
+        self.sleep(wait);
+        wait
+    }
+
+    /// Blocks until the next unstable period begins and returns the speed which becomes effective then.
+    /// It keeps waiting while paused, and returns `None` once `shutdown` is called or if no unstable periods
+    /// are scheduled (see `StateManager::next_activation_at`).
+    /// The state is not updated by this, so the period begins at the next operation.
+    pub fn wait_for_next_activation(&self) -> Option<Speed> {
+        let mut remaining = self.state_manager.next_activation_at(self.clock.now());
+        loop {
+            match self.activation_step(&mut remaining, None) {
+                Ok(speed) => return speed,
+                Err(wait) => self.sleep(wait),
+            }
+        }
+    }
+
+    /// Same as `wait_for_next_activation` but sleeps by tokio.
+    /// The shutdown is checked every `ACTIVATION_POLL_INTERVAL` because the sleep of tokio does not wake by it.
+    #[cfg(feature = "async")]
+    pub fn wait_for_next_activation_async(&self) -> WaitForActivation<'_> {
+        WaitForActivation {
+            engine: self,
+            remaining: self.state_manager.next_activation_at(self.clock.now()),
+            sleep: None,
+        }
+    }
+
+    /// Returns the speed if the wait for the next activation ends, otherwise the duration to wait more.
+    /// `remaining` is the duration to wait until the activation, which is decreased by the returned duration.
+    fn activation_step(
+        &self,
+        remaining: &mut Option<Duration>,
+        max_wait: Option<Duration>,
+    ) -> Result<Option<Speed>, Duration> {
+        let left = match *remaining {
+            Some(d) if !self.shutdown.is_triggered() => d,
+            _ => return Ok(None),
+        };
+
+        if Duration::default() < left {
+            let wait = max_wait.map_or(left, |m| m.min(left));
+            *remaining = Some(left - wait);
+            Err(wait)
+        } else if self.controls.is_paused() {
+            Err(ACTIVATION_POLL_INTERVAL)
+        } else {
+            Ok(Some(self.config.load().speed.clone()))
+        }
+    }
+}
+
+/// The interval to check the pause flag (and the shutdown in async) while waiting for the next activation.
+pub const ACTIVATION_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// `WaitForActivation` is the future of `Engine::wait_for_next_activation_async`.
+#[cfg(feature = "async")]
+pub struct WaitForActivation<'a> {
+    engine: &'a Engine,
+    remaining: Option<Duration>,
+    sleep: Option<Pin<Box<::tokio::time::Sleep>>>,
+}
+
+#[cfg(feature = "async")]
+impl<'a> Future for WaitForActivation<'a> {
+    type Output = Option<Speed>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Speed>> {
+        let this = &mut *self;
+        loop {
+            if let Some(ref mut sleep) = this.sleep {
+                if sleep.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+            }
+
+            match this
+                .engine
+                .activation_step(&mut this.remaining, Some(ACTIVATION_POLL_INTERVAL))
+            {
+                Ok(speed) => return Poll::Ready(speed),
+                Err(wait) => this.sleep = Some(Box::pin(::tokio::time::sleep(wait))),
+            }
+        }
+    }
+}
+
+/// Logs the backward jump of the clock if `now` is before the last refill of the bucket,
+/// which restarts the refill from `now` (see `TokenBucket::refill`).
+fn warn_backward_jump(logger: &Logger, bucket: &TokenBucket, now: Instant) {
+    let last = bucket.last_refill_time();
+    if now < last {
+        warn!(
+            logger,
+            "the clock jumped backward by {:?}: the token bucket refills from now",
+            last - now
+        );
+    }
+}
+
+fn bucket_key(op: Operation, scope: ThrottleScope) -> BucketKey {
+    match scope {
+        ThrottleScope::Global => (op, None),
+        ThrottleScope::PerThread => (op, Some(thread::current().id())),
+    }
+}
+
+/// Chooses the weighted operations in the order of `Config::operations`. Each one is chosen independently
+/// with the probability of its share of the total weight, so any subset of them (even none) can be chosen.
+/// The RNG is untouched if no weights are given.
+fn choose_operations<R: Rng>(config: &Config, rng: &mut R) -> Vec<Operation> {
+    let weighted = config
+        .operations
+        .iter()
+        .filter_map(|op| {
+            config
+                .operation_weights
+                .get(op)
+                .map(|w| (op, u64::from(*w)))
+        })
+        .collect::<Vec<_>>();
+    let total = weighted.iter().map(|&(_, w)| w).sum::<u64>();
+    if total == 0 {
+        return Vec::new();
+    }
+
+    weighted
+        .into_iter()
+        .filter(|&(_, w)| rng.gen_bool(w as f64 / total as f64))
+        .map(|(op, _)| op.clone())
+        .collect()
+}
+
+/// Chooses an error by the weights, which is EIO if no errors are given.
+/// The RNG is untouched if there is only one error.
+fn choose_error<R: Rng>(errors: &[WeightedError], rng: &mut R) -> c_int {
+    match *errors {
+        [] => return libc::EIO,
+        [ref e] => return e.error.code(),
+        _ => {}
+    }
+
+    let total = errors.iter().map(|e| u64::from(e.weight)).sum::<u64>();
+    let mut x = rng.gen_range(0, total.max(1));
+    for e in errors {
+        if x < u64::from(e.weight) {
+            return e.error.code();
+        }
+        x -= u64::from(e.weight);
+    }
+    errors[0].error.code()
+}
+
+fn clamp_io_size<R: Rng>(short_io: &ShortIo, size: usize, rng: &mut R) -> usize {
+    let clamped = match *short_io {
+        ShortIo::Fraction(x) => (size as f64 * x) as usize,
+        ShortIo::Random => rng.gen_range(1, size + 1),
+    };
+
+    clamped.max(1).min(size)
+}
+
+fn local_time_of_day() -> u32 {
+    let tm = time::now();
+    (tm.tm_hour * 60 * 60 + tm.tm_min * 60 + tm.tm_sec) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::MockClock;
+    use config::{ActiveWindow, ConfigBuilder, ErrorInjection, InjectedError, ScriptWindow};
+    use serde_json;
+    use stats::{WindowThroughput, DELAY_BUCKETS};
+    use std::env;
+    use std::fs;
+    use std::io::{self, Write};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use throttle::compute_sleep_duration_to_adjust_speed;
+
+    fn config() -> Arc<AtomicImmut<Config>> {
+        Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Range {
+                min: 1,
+                max: 1 << 20,
+            },
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            probability: Some(0.5),
+            ..Default::default()
+        }))
+    }
+
+    /// Runs the engine through the cycles and records the decisions.
+    fn decisions(mut engine: Engine) -> String {
+        engine.init();
+        let base = Instant::now();
+
+        let mut decisions = String::new();
+        for i in 0..1000 {
+            let now = base + Duration::from_secs(i * 7);
+            engine.on_operated_at(Operation::Read, now).unwrap();
+            let speed = engine.speed(Operation::Read);
+            decisions += &format!("{:?} {:?}\n", engine.state(), speed);
+        }
+
+        decisions
+    }
+
+    #[test]
+    fn test_engine_reproducible() {
+        let a = decisions(Engine::with_seed(config(), 42));
+        let b = decisions(Engine::with_seed(config(), 42));
+        assert_eq!(a.as_bytes(), b.as_bytes());
+
+        // The decisions are random actually.
+        assert!(a.lines().any(|l| l.starts_with("Unstable")));
+        assert!(a.lines().any(|l| l.starts_with("Stable")));
+        assert_ne!(a, decisions(Engine::with_seed(config(), 43)));
+
+        // The seed in the config is used.
+        let config = config();
+        let mut c = (*config.load()).clone();
+        c.seed = Some(42);
+        config.store(c);
+        assert_eq!(a, decisions(Engine::new(config)));
+    }
+
+    #[test]
+    fn test_engine_seed_sequences() {
+        // `rng` continues from the seed of `state_rng` as before the dedicated RNGs were added.
+        let mut rng = SmallRng::seed_from_u64(42);
+        SmallRng::from_rng(&mut rng).unwrap();
+        let engine = Engine::with_seed(config(), 42);
+        assert_eq!(rng.gen::<u64>(), engine.rng.clone().gen::<u64>());
+        assert_ne!(
+            engine.rng.clone().gen::<u64>(),
+            engine.error_rng.clone().gen::<u64>()
+        );
+    }
+
+    #[test]
+    fn test_engine_speed() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Always(State::Unstable),
+            ..Default::default()
+        }));
+        let mut engine = Engine::with_seed(Arc::clone(&config), 0);
+        engine.init();
+
+        assert_eq!(Ok(None), engine.on_operated(Operation::Read));
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Read));
+        // Not the target.
+        assert_eq!(None, engine.speed(Operation::Stat));
+
+        let mut c = (*config.load()).clone();
+        c.condition = Condition::Always(State::Stable);
+        config.store(c);
+        assert_eq!(Ok(Some(State::Stable)), engine.on_operated(Operation::Read));
+        assert_eq!(None, engine.speed(Operation::Read));
+    }
+
+    #[test]
+    fn test_engine_stalled_speed() {
+        // The zero rates are rejected by `Config::validate`, but they never hang the operations anyway.
+        for speed in [
+            Speed::Bps(0),
+            Speed::Iops(0),
+            Speed::Range { min: 0, max: 0 },
+            Speed::BpsWithLatency {
+                bps: 0,
+                latency: Duration::from_secs(1),
+            },
+        ] {
+            let config = Config {
+                speed: speed.clone(),
+                condition: Condition::Always(State::Unstable),
+                ..Default::default()
+            };
+            assert!(config.validate().is_err(), "{}", speed);
+
+            let clock = Arc::new(MockClock::new());
+            let mut engine = Engine::with_seed(Arc::new(AtomicImmut::new(config)), 0);
+            engine.set_clock(clock.clone());
+            engine.init();
+
+            assert_eq!(None, engine.speed(Operation::Read), "{}", speed);
+            assert_eq!(Duration::default(), engine.throttle(Operation::Read, 1024));
+            assert_eq!(Duration::default(), clock.elapsed());
+        }
+    }
+
+    #[test]
+    fn test_engine_per_byte() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::PerByte(Duration::from_secs(2)),
+            condition: Condition::Always(State::Unstable),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(Arc::clone(&config), 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        assert_eq!(Duration::from_secs(6), engine.throttle(Operation::Read, 3));
+        assert_eq!(Duration::from_secs(6), clock.elapsed());
+        assert_eq!(3, engine.stats().bytes_read_throttled);
+    }
+
+    #[test]
+    fn test_engine_zero_bytes() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Always(State::Unstable),
+            burst: Some(1024),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(Arc::clone(&config), 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        // The bucket owes a second.
+        assert_eq!(
+            Duration::from_secs(1),
+            engine.throttle_delay(Operation::Read, 1024, 2048, Duration::default())
+        );
+        // But zero bytes do not wait for it.
+        assert_eq!(Duration::default(), engine.throttle(Operation::Read, 0));
+        assert_eq!(Duration::default(), clock.elapsed());
+
+        let mut c = (*config.load()).clone();
+        c.speed = Speed::Latency(Duration::from_millis(10));
+        config.store(c);
+        // The latency is for the operation, not for the bytes.
+        assert_eq!(Duration::default(), engine.throttle(Operation::Read, 0));
+        assert_eq!(
+            Some(Duration::from_millis(10)),
+            engine.speed(Operation::Read).and_then(|s| s.latency())
+        );
+    }
+
+    #[test]
+    fn test_engine_active_window() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Always(State::Unstable),
+            ..Default::default()
+        }));
+        let mut engine = Engine::with_seed(Arc::clone(&config), 0);
+        let clock = Arc::new(AtomicUsize::new(0));
+        {
+            let clock = Arc::clone(&clock);
+            engine.set_time_of_day(move || clock.load(Ordering::SeqCst) as u32);
+        }
+        engine.init();
+        let set_clock = |h: usize, m: usize| clock.store(h * 60 * 60 + m * 60, Ordering::SeqCst);
+
+        // No window means the operations are throttled all day.
+        set_clock(3, 0);
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Read));
+
+        let mut c = (*config.load()).clone();
+        c.active_window = Some("09:00-17:00".parse().unwrap());
+        config.store(c);
+
+        set_clock(12, 0);
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Read));
+        set_clock(17, 0);
+        assert_eq!(None, engine.speed(Operation::Read));
+        set_clock(3, 0);
+        assert_eq!(None, engine.speed(Operation::Read));
+
+        // The window wraps past midnight.
+        let mut c = (*config.load()).clone();
+        c.active_window = Some(ActiveWindow {
+            start: 22 * 60 * 60,
+            end: 2 * 60 * 60,
+        });
+        config.store(c);
+
+        set_clock(23, 0);
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Read));
+        set_clock(1, 0);
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Read));
+        set_clock(12, 0);
+        assert_eq!(None, engine.speed(Operation::Read));
+
+        // The state is kept out of the window.
+        assert_eq!(&State::Unstable, engine.state());
+    }
+
+    #[test]
+    fn test_engine_injected_error() {
+        let error_config = |probability| {
+            Arc::new(AtomicImmut::new(Config {
+                condition: Condition::Always(State::Unstable),
+                error_injection: Some(ErrorInjection {
+                    probability,
+                    operations: vec![Operation::Read],
+                    disk_full_after: None,
+                    errors: vec![],
+                    continuous: false,
+                    errors_per_window: None,
+                }),
+                ..Default::default()
+            }))
+        };
+
+        let mut engine = Engine::with_seed(error_config(1.0), 0);
+        engine.init();
+        for _ in 0..1000 {
+            assert_eq!(Some(libc::EIO), engine.injected_error(Operation::Read, 0));
+            // Not the target.
+            assert_eq!(None, engine.injected_error(Operation::Write, 0));
+        }
+
+        let mut engine = Engine::with_seed(error_config(0.0), 0);
+        engine.init();
+        for _ in 0..1000 {
+            assert_eq!(None, engine.injected_error(Operation::Read, 0));
+        }
+
+        let mut engine = Engine::with_seed(error_config(0.5), 0);
+        engine.init();
+        let n = (0..1000)
+            .filter(|_| engine.injected_error(Operation::Read, 0).is_some())
+            .count();
+        assert!(300 < n && n < 700, "{}", n);
+
+        // No errors are injected in stable state.
+        let config = error_config(1.0);
+        let mut c = (*config.load()).clone();
+        c.condition = Condition::Always(State::Stable);
+        config.store(c);
+        let mut engine = Engine::with_seed(config, 0);
+        engine.init();
+        assert_eq!(None, engine.injected_error(Operation::Read, 0));
+    }
+
+    #[test]
+    fn test_engine_continuous_error_injection() {
+        static CONTROLS: Controls = Controls::new();
+        // Whether a stat fails in the stable period, and after the throttling is paused.
+        let injected = |continuous| {
+            let config = Arc::new(AtomicImmut::new(Config {
+                speed: Speed::Bps(1024),
+                condition: Condition::Periodic {
+                    duration: Duration::from_secs(10),
+                    frequency: Duration::from_secs(30),
+                },
+                error_injection: Some(ErrorInjection {
+                    probability: 1.0,
+                    operations: vec![Operation::Stat],
+                    disk_full_after: Some(0),
+                    errors: vec![],
+                    continuous,
+                    errors_per_window: None,
+                }),
+                ..Default::default()
+            }));
+            let clock = Arc::new(MockClock::new());
+            let mut engine = Engine::with_seed(config, 0);
+            engine.set_clock(clock.clone());
+            engine.set_controls(&CONTROLS);
+            engine.init();
+
+            CONTROLS.set_paused(false);
+            engine.on_operated(Operation::Stat).unwrap();
+            assert_eq!(&State::Stable, engine.state());
+            assert!(engine.is_passthrough());
+            let stable = engine.injected_error(Operation::Stat, 0);
+            // The disk is never full out of the unstable periods.
+            assert_eq!(None, engine.injected_error(Operation::Write, 1));
+
+            CONTROLS.set_paused(true);
+            let paused = engine.injected_error(Operation::Stat, 0);
+            (stable, paused)
+        };
+
+        assert_eq!((None, None), injected(false));
+        assert_eq!((Some(libc::EIO), None), injected(true));
+    }
+
+    #[test]
+    fn test_engine_injected_error_codes() {
+        let error_config = |errors: Vec<WeightedError>| {
+            Arc::new(AtomicImmut::new(Config {
+                condition: Condition::Always(State::Unstable),
+                error_injection: Some(ErrorInjection {
+                    probability: 1.0,
+                    operations: vec![Operation::Read],
+                    disk_full_after: None,
+                    errors,
+                    continuous: false,
+                    errors_per_window: None,
+                }),
+                ..Default::default()
+            }))
+        };
+
+        let mut engine = Engine::with_seed(error_config(vec![InjectedError::Eagain.into()]), 0);
+        engine.init();
+        for _ in 0..1000 {
+            assert_eq!(
+                Some(libc::EAGAIN),
+                engine.injected_error(Operation::Read, 0)
+            );
+        }
+
+        let errors = vec![
+            WeightedError {
+                error: InjectedError::Eacces,
+                weight: 1,
+            },
+            WeightedError {
+                error: InjectedError::Etimedout,
+                weight: 3,
+            },
+            WeightedError {
+                error: InjectedError::Erofs,
+                weight: 0,
+            },
+        ];
+        let mut engine = Engine::with_seed(error_config(errors), 0);
+        engine.init();
+        let mut counts = HashMap::new();
+        for _ in 0..1000 {
+            let e = engine.injected_error(Operation::Read, 0).unwrap();
+            *counts.entry(e).or_insert(0) += 1;
+        }
+        assert_eq!(2, counts.len(), "{:?}", counts);
+        let (eacces, etimedout) = (counts[&libc::EACCES], counts[&libc::ETIMEDOUT]);
+        assert!(150 < eacces && eacces < 350, "{}", eacces);
+        assert_eq!(1000, eacces + etimedout);
+    }
+
+    #[test]
+    fn test_engine_begin_operation_short_write() {
+        use config::ShortIo;
+
+        let config = Arc::new(AtomicImmut::new(Config {
+            condition: Condition::Always(State::Unstable),
+            short_io: Some(ShortIo::Fraction(0.5)),
+            error_injection: Some(ErrorInjection {
+                probability: 0.0,
+                operations: vec![],
+                disk_full_after: Some(1000),
+                errors: vec![],
+                continuous: false,
+                errors_per_window: None,
+            }),
+            ..Default::default()
+        }));
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(Arc::new(MockClock::new()));
+        engine.init();
+
+        // The shortened write is counted for ENOSPC as `Mizumochi` counts it, not the requested one.
+        assert_eq!(
+            Ok(750),
+            engine.begin_operation(Operation::Write, 1500, 1500)
+        );
+        assert_eq!(Ok(200), engine.begin_operation(Operation::Write, 400, 400));
+        assert_eq!(
+            Err(libc::ENOSPC),
+            engine.begin_operation(Operation::Write, 200, 200)
+        );
+        assert_eq!(Ok(512), engine.begin_operation(Operation::Read, 1024, 0));
+    }
+
+    #[test]
+    fn test_engine_disk_full() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            error_injection: Some(ErrorInjection {
+                probability: 0.0,
+                operations: vec![],
+                disk_full_after: Some(1000),
+                errors: vec![],
+                continuous: false,
+                errors_per_window: None,
+            }),
+            ..Default::default()
+        }));
+        let mut engine = Engine::with_seed(config, 0);
+        engine.init();
+        let base = Instant::now();
+
+        // Not injected in the stable period.
+        engine.on_operated_at(Operation::Write, base).unwrap();
+        assert_eq!(None, engine.injected_error(Operation::Write, 2000));
+
+        let unstable = base + Duration::from_secs(31);
+        assert_eq!(
+            Ok(Some(State::Unstable)),
+            engine.on_operated_at(Operation::Write, unstable)
+        );
+        // Below the threshold.
+        for _ in 0..10 {
+            assert_eq!(None, engine.injected_error(Operation::Write, 100));
+        }
+        // Above the threshold.
+        assert_eq!(
+            Some(libc::ENOSPC),
+            engine.injected_error(Operation::Write, 1)
+        );
+        assert_eq!(
+            Some(libc::ENOSPC),
+            engine.injected_error(Operation::Write, 100)
+        );
+        // The reads are not affected.
+        assert_eq!(None, engine.injected_error(Operation::Read, 100));
+
+        // The counter is reset after the unstable period.
+        let stable = unstable + Duration::from_secs(11);
+        assert_eq!(
+            Ok(Some(State::Stable)),
+            engine.on_operated_at(Operation::Write, stable)
+        );
+        let unstable = stable + Duration::from_secs(31);
+        assert_eq!(
+            Ok(Some(State::Unstable)),
+            engine.on_operated_at(Operation::Write, unstable)
+        );
+        assert_eq!(None, engine.injected_error(Operation::Write, 1000));
+        assert_eq!(
+            Some(libc::ENOSPC),
+            engine.injected_error(Operation::Write, 1)
+        );
+    }
+
+    #[test]
+    fn test_engine_io_size() {
+        let short_io_config = |short_io| {
+            Arc::new(AtomicImmut::new(Config {
+                condition: Condition::Always(State::Unstable),
+                short_io: Some(short_io),
+                ..Default::default()
+            }))
+        };
+
+        let mut engine = Engine::with_seed(short_io_config(ShortIo::Fraction(0.5)), 0);
+        engine.init();
+        assert_eq!(2048, engine.io_size(Operation::Read, 4096));
+        assert_eq!(50, engine.io_size(Operation::Write, 100));
+        // The size never becomes zero.
+        assert_eq!(1, engine.io_size(Operation::Read, 1));
+        assert_eq!(0, engine.io_size(Operation::Read, 0));
+        // Not the target.
+        assert_eq!(100, engine.io_size(Operation::Stat, 100));
+
+        let sizes = |seed| {
+            let mut engine = Engine::with_seed(short_io_config(ShortIo::Random), seed);
+            engine.init();
+            (1..1000)
+                .map(|size| (size, engine.io_size(Operation::Read, size)))
+                .collect::<Vec<_>>()
+        };
+        let a = sizes(0);
+        for &(size, n) in &a {
+            assert!(1 <= n && n <= size, "{} {}", size, n);
+        }
+        assert!(a.iter().any(|&(size, n)| n < size));
+        // The same seed gives the same sizes.
+        assert_eq!(a, sizes(0));
+
+        // Not clamped in stable state.
+        let config = short_io_config(ShortIo::Fraction(0.5));
+        let mut c = (*config.load()).clone();
+        c.condition = Condition::Always(State::Stable);
+        config.store(c);
+        let mut engine = Engine::with_seed(config, 0);
+        engine.init();
+        assert_eq!(4096, engine.io_size(Operation::Read, 4096));
+    }
+
+    #[test]
+    fn test_engine_is_target_file() {
+        let config = Arc::new(AtomicImmut::new(Config::default()));
+        let mut engine = Engine::with_seed(Arc::clone(&config), 0);
+
+        assert!(engine.is_target_file(Path::new("a.txt"), || panic!("the size is unused")));
+
+        let mut c = (*config.load()).clone();
+        c.include_globs = vec!["data/**".to_string()];
+        c.exclude_globs = vec!["**/*.log".to_string()];
+        config.store(c);
+        // The new config is seen from the next operation.
+        engine.on_operated(Operation::Read).unwrap();
+        assert!(engine.is_target_file(Path::new("data/a.bin"), || 0));
+        assert!(!engine.is_target_file(Path::new("data/a.log"), || 0));
+        assert!(!engine.is_target_file(Path::new("a.txt"), || 0));
+    }
+
+    #[test]
+    fn test_engine_extension_speeds() {
+        let mut config = Config {
+            speed: Speed::Bps(4096),
+            condition: Condition::Always(State::Unstable),
+            ..Default::default()
+        };
+        config
+            .per_operation
+            .insert(Operation::Write, Speed::Bps(2048));
+        config
+            .extension_speeds
+            .insert("db".to_string(), Speed::Bps(1024));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(Arc::new(AtomicImmut::new(config)), 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        // 4KB of a .db file at 1KBps.
+        engine.on_operated(Operation::Read).unwrap();
+        assert!(engine.is_target_file(Path::new("data/a.db"), || 0));
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Read));
+        assert_eq!(
+            Duration::from_secs(4),
+            engine.throttle(Operation::Read, 4096)
+        );
+        // It overrides `per_operation`, and the extension is not case-sensitive.
+        assert!(engine.is_target_file(Path::new("B.DB"), || 0));
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Write));
+
+        // A .txt file is at the global speed (and `per_operation`).
+        assert!(engine.is_target_file(Path::new("a.txt"), || 0));
+        assert_eq!(
+            Duration::from_secs(1),
+            engine.throttle(Operation::Read, 4096)
+        );
+        assert_eq!(Some(Speed::Bps(2048)), engine.speed(Operation::Write));
+        // So is a file without the extension.
+        assert!(engine.is_target_file(Path::new("db"), || 0));
+        assert_eq!(Some(Speed::Bps(4096)), engine.speed(Operation::Read));
+        assert_eq!(Duration::from_secs(5), clock.elapsed());
+
+        // Only the files of the extension are throttled if the others pass through.
+        let mut config = Config {
+            condition: Condition::Always(State::Unstable),
+            ..Default::default()
+        };
+        config
+            .extension_speeds
+            .insert("db".to_string(), Speed::Bps(1024));
+        let mut engine = Engine::with_seed(Arc::new(AtomicImmut::new(config)), 0);
+        engine.init();
+        engine.on_operated(Operation::Read).unwrap();
+        assert!(!engine.is_passthrough());
+        assert!(engine.is_target_file(Path::new("a.txt"), || 0));
+        assert_eq!(None, engine.speed(Operation::Read));
+        assert!(engine.is_target_file(Path::new("a.db"), || 0));
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Read));
+    }
+
+    #[test]
+    fn test_engine_min_file_size() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Always(State::Unstable),
+            min_file_size: Some(1 << 20),
+            ..Default::default()
+        }));
+        let mut engine = Engine::with_seed(config, 0);
+        engine.init();
+
+        let path = Path::new("a.bin");
+        // A small file is never throttled.
+        assert!(!engine.is_target_file(path, || 4 << 10));
+        // The threshold is inclusive.
+        assert!(engine.is_target_file(path, || 1 << 20));
+        assert!(engine.is_target_file(path, || 10 << 20));
+    }
+
+    #[test]
+    fn test_engine_reserve_bytes() {
+        let config = Arc::new(AtomicImmut::new(Config::default()));
+        let mut engine = Engine::with_seed(Arc::clone(&config), 0);
+        let now = Instant::now();
+
+        // The legacy throttle is used without the burst.
+        assert_eq!(None, engine.reserve_bytes(Operation::Read, 1024, 1024, now));
+
+        let mut c = (*config.load()).clone();
+        c.burst = Some(2048);
+        config.store(c);
+        engine.on_operated(Operation::Read).unwrap();
+        let zero = Some(Duration::from_secs(0));
+        assert_eq!(zero, engine.reserve_bytes(Operation::Read, 1024, 2048, now));
+        assert_eq!(
+            Some(Duration::from_secs(1)),
+            engine.reserve_bytes(Operation::Read, 1024, 1024, now)
+        );
+        // Each operation has its own bucket.
+        assert_eq!(
+            zero,
+            engine.reserve_bytes(Operation::Write, 1024, 1024, now)
+        );
+        // The bucket is renewed by a new speed.
+        assert_eq!(zero, engine.reserve_bytes(Operation::Read, 2048, 2048, now));
+    }
+
+    #[test]
+    fn test_engine_reserve_operation() {
+        let config = Arc::new(AtomicImmut::new(Config::default()));
+        let mut engine = Engine::with_seed(config, 0);
+        let now = Instant::now();
+
+        // 100 operations pass in a second.
+        for _ in 0..100 {
+            assert_eq!(
+                Duration::from_secs(0),
+                engine.reserve_operation(Operation::Stat, 100, now)
+            );
+        }
+        // The 101st waits.
+        assert_eq!(
+            Duration::from_millis(10),
+            engine.reserve_operation(Operation::Stat, 100, now)
+        );
+
+        // Refilled after a second.
+        let now = now + Duration::from_secs(1);
+        let waits = (0..100)
+            .filter(|_| {
+                engine.reserve_operation(Operation::Stat, 100, now) != Duration::from_secs(0)
+            })
+            .count();
+        assert_eq!(1, waits);
+    }
+
+    #[test]
+    fn test_engine_mock_clock() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(Ok(None), engine.on_operated(Operation::Read));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(
+            Ok(Some(State::Unstable)),
+            engine.on_operated(Operation::Read)
+        );
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(Ok(Some(State::Stable)), engine.on_operated(Operation::Read));
+    }
+
+    #[test]
+    fn test_engine_paused() {
+        static CONTROLS: Controls = Controls::new();
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Always(State::Unstable),
+            error_injection: Some(ErrorInjection {
+                probability: 1.0,
+                operations: vec![Operation::Read],
+                disk_full_after: None,
+                errors: vec![],
+                continuous: false,
+                errors_per_window: None,
+            }),
+            short_io: Some(ShortIo::Fraction(0.5)),
+            ..Default::default()
+        }));
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_controls(&CONTROLS);
+        engine.init();
+
+        CONTROLS.set_paused(true);
+        assert_eq!(None, engine.speed(Operation::Read));
+        assert_eq!(None, engine.injected_error(Operation::Read, 0));
+        assert_eq!(10, engine.io_size(Operation::Read, 10));
+        // The state is kept while paused.
+        assert_eq!(&State::Unstable, engine.state());
+
+        CONTROLS.set_paused(false);
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Read));
+    }
+
+    #[test]
+    fn test_engine_paused_operation() {
+        static CONTROLS: Controls = Controls::new();
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Always(State::Unstable),
+            error_injection: Some(ErrorInjection {
+                probability: 1.0,
+                operations: vec![Operation::Write],
+                disk_full_after: None,
+                errors: vec![],
+                continuous: false,
+                errors_per_window: None,
+            }),
+            short_io: Some(ShortIo::Fraction(0.5)),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.set_controls(&CONTROLS);
+        engine.init();
+
+        // The writes pass through while the reads are throttled in the active window.
+        CONTROLS.pause_operation(&Operation::Write);
+        engine.on_operated(Operation::Write).unwrap();
+        assert_eq!(None, engine.speed(Operation::Write));
+        assert_eq!(Duration::default(), engine.throttle(Operation::Write, 4096));
+        assert_eq!(None, engine.injected_error(Operation::Write, 0));
+        assert_eq!(10, engine.io_size(Operation::Write, 10));
+        assert_eq!(
+            Duration::from_secs(2),
+            engine.throttle(Operation::Read, 2048)
+        );
+        assert_eq!(5, engine.io_size(Operation::Read, 10));
+
+        // The forced state is not paused.
+        engine.force_active(Speed::Bps(2048));
+        assert_eq!(Some(Speed::Bps(2048)), engine.speed(Operation::Write));
+        engine.clear_force();
+
+        CONTROLS.resume_operation(&Operation::Write);
+        assert_eq!(
+            Duration::from_secs(4),
+            engine.throttle(Operation::Write, 4096)
+        );
+        assert!(engine.injected_error(Operation::Write, 0).is_some());
+        assert_eq!(Duration::from_secs(6), clock.elapsed());
+    }
+
+    #[test]
+    fn test_engine_force_active() {
+        static CONTROLS: Controls = Controls::new();
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            error_injection: Some(ErrorInjection {
+                probability: 1.0,
+                operations: vec![Operation::Write],
+                disk_full_after: None,
+                errors: vec![],
+                continuous: false,
+                errors_per_window: None,
+            }),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.set_controls(&CONTROLS);
+        engine.init();
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(None, engine.speed(Operation::Read));
+
+        // It throttles in the stable period, even while paused.
+        engine.force_active(Speed::Bps(2048));
+        CONTROLS.set_paused(true);
+        assert!(!engine.is_passthrough());
+        assert_eq!(Some(Speed::Bps(2048)), engine.speed(Operation::Read));
+        assert_eq!(
+            Duration::from_secs(1),
+            engine.throttle(Operation::Read, 2048)
+        );
+        assert_eq!(Some(libc::EIO), engine.injected_error(Operation::Write, 0));
+        match engine.current_state() {
+            ThrottleState::Active {
+                speed, remaining, ..
+            } => {
+                assert_eq!(Speed::Bps(2048), speed);
+                assert_eq!(None, remaining);
+            }
+            s => panic!("{:?}", s),
+        }
+        // The operations which are not the target pass through.
+        assert_eq!(None, engine.speed(Operation::Stat));
+        CONTROLS.set_paused(false);
+
+        // It is kept active across the cycles.
+        for _ in 0..6 {
+            clock.advance(Duration::from_secs(10));
+            engine.on_operated(Operation::Read).unwrap();
+            assert_eq!(Some(Speed::Bps(2048)), engine.speed(Operation::Read));
+        }
+
+        // The shutdown takes precedence.
+        engine.shutdown();
+        assert_eq!(None, engine.speed(Operation::Read));
+    }
+
+    #[test]
+    fn test_engine_trigger() {
+        let path = env::temp_dir().join(format!("mizumochi-trigger-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Always(State::Stable),
+            trigger: Some(Trigger {
+                poll_interval: Duration::from_secs(1),
+                ..Trigger::new(path.clone())
+            }),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(Arc::clone(&config), 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(None, engine.speed(Operation::Read));
+
+        // It is found at the next poll.
+        fs::File::create(&path).unwrap();
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(None, engine.speed(Operation::Read));
+        clock.advance(Duration::from_secs(1));
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Read));
+
+        // It follows the schedule again without the file.
+        fs::remove_file(&path).unwrap();
+        clock.advance(Duration::from_secs(1));
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(None, engine.speed(Operation::Read));
+
+        // The explicit force is kept while the file is absent.
+        engine.force_active(Speed::Bps(2048));
+        clock.advance(Duration::from_secs(1));
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(Some(Speed::Bps(2048)), engine.speed(Operation::Read));
+        engine.clear_force();
+
+        // Or it stays idle even in the unstable periods.
+        let mut c = (*config.load()).clone();
+        c.condition = Condition::Always(State::Unstable);
+        c.trigger.as_mut().unwrap().idle_when_absent = true;
+        config.store(c);
+        clock.advance(Duration::from_secs(1));
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(None, engine.speed(Operation::Read));
+        fs::File::create(&path).unwrap();
+        clock.advance(Duration::from_secs(1));
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Read));
+
+        // Removing the trigger from the config clears its force.
+        fs::remove_file(&path).unwrap();
+        clock.advance(Duration::from_secs(1));
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(None, engine.speed(Operation::Read));
+        let mut c = (*config.load()).clone();
+        c.trigger = None;
+        config.store(c);
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Read));
+    }
+
+    #[test]
+    fn test_engine_script() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1 << 20),
+            condition: Condition::Script {
+                windows: vec![
+                    ScriptWindow {
+                        offset: Duration::from_secs(10),
+                        length: Duration::from_secs(5),
+                        speed: Speed::Bps(1024),
+                    },
+                    ScriptWindow {
+                        offset: Duration::from_secs(30),
+                        length: Duration::from_secs(10),
+                        speed: Speed::Latency(Duration::from_millis(50)),
+                    },
+                ],
+                repeat: false,
+            },
+            per_operation: vec![(Operation::Read, Speed::Bps(4096))]
+                .into_iter()
+                .collect(),
+            warmup: Duration::from_secs(60),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+        let rx = engine.subscribe();
+
+        // Each window begins exactly at its offset at its speed (over `per_operation` and `speed`).
+        let mut started = Vec::new();
+        for t in 0..60 {
+            if engine.on_operated(Operation::Read).unwrap() == Some(State::Unstable) {
+                started.push((t, engine.speed(Operation::Read)));
+            }
+            clock.advance(Duration::from_secs(1));
+        }
+        assert_eq!(
+            vec![
+                (10, Some(Speed::Bps(1024))),
+                (30, Some(Speed::Latency(Duration::from_millis(50))))
+            ],
+            started
+        );
+        assert_eq!(
+            vec![
+                subscription::Event::WindowStarted {
+                    speed: Speed::Bps(1024)
+                },
+                subscription::Event::WindowEnded,
+                subscription::Event::WindowStarted {
+                    speed: Speed::Latency(Duration::from_millis(50))
+                },
+                subscription::Event::WindowEnded,
+            ],
+            rx.try_iter().collect::<Vec<_>>()
+        );
+
+        // It idles after the script ends.
+        assert_eq!(&State::Stable, engine.state());
+        assert_eq!(None, engine.speed(Operation::Read));
+        assert_eq!(None, engine.state_manager.next_activation_at(clock.now()));
+        assert_eq!(2, engine.stats().active_windows);
+    }
+
+    #[test]
+    fn test_engine_summary() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            condition: Condition::Script {
+                windows: vec![
+                    ScriptWindow {
+                        offset: Duration::from_secs(10),
+                        length: Duration::from_secs(5),
+                        speed: Speed::Bps(1024),
+                    },
+                    ScriptWindow {
+                        offset: Duration::from_secs(30),
+                        length: Duration::from_secs(10),
+                        speed: Speed::Bps(2048),
+                    },
+                ],
+                repeat: false,
+            },
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        let mut delays = Vec::new();
+        while clock.elapsed() < Duration::from_secs(60) {
+            engine.on_operated(Operation::Read).unwrap();
+            engine.on_operated(Operation::Write).unwrap();
+            delays.push(engine.throttle(Operation::Read, 512));
+            delays.push(engine.throttle(Operation::Write, 256));
+            clock.advance(Duration::from_secs(1));
+        }
+        assert_eq!(&State::Stable, engine.state());
+
+        let stats = engine.stats();
+        let summary = engine.summary();
+        let delays = delays
+            .into_iter()
+            .filter(|d| *d != Duration::default())
+            .collect::<Vec<_>>();
+        assert_eq!(2, summary.activations);
+        assert_eq!(stats.active_windows, summary.activations);
+        assert_eq!(stats.bytes_read_throttled, summary.bytes_read_throttled);
+        assert_eq!(
+            stats.bytes_written_throttled,
+            summary.bytes_written_throttled
+        );
+        assert_eq!(0, summary.errors_injected);
+        assert_eq!(delays.len() as u64, summary.ops_delayed);
+        assert_eq!(
+            summary.ops_delayed,
+            summary.delay_histogram.iter().sum::<u64>()
+        );
+        let total: Duration = delays.iter().sum();
+        assert_eq!(total.as_millis(), u128::from(summary.total_delay_ms));
+
+        // The configured rate is the mean of the windows weighted by their lengths,
+        // and the achieved one is almost the same as the delays are applied exactly.
+        let windows = stats.byte_rate_windows.unwrap();
+        let bytes = summary.bytes_read_throttled + summary.bytes_written_throttled;
+        assert_eq!(bytes, windows.bytes);
+        let configured = summary.configured_bps.unwrap();
+        assert!(1024 < configured && configured < 2048, "{}", configured);
+        let achieved = summary.achieved_bps.unwrap();
+        assert_eq!(windows.achieved_bps(), Some(achieved));
+        assert!(achieved <= configured, "{} > {}", achieved, configured);
+        assert!(summary.to_string().starts_with("activations: 2\n"));
+    }
+
+    #[test]
+    fn test_engine_summary_open_window() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            condition: Condition::Script {
+                windows: vec![ScriptWindow {
+                    offset: Duration::from_secs(10),
+                    length: Duration::from_secs(10),
+                    speed: Speed::Bps(1024),
+                }],
+                repeat: false,
+            },
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        // Shut down in the middle of the window.
+        while clock.elapsed() < Duration::from_secs(15) {
+            engine.on_operated(Operation::Read).unwrap();
+            engine.throttle(Operation::Read, 512);
+            clock.advance(Duration::from_secs(1));
+        }
+        assert_eq!(&State::Unstable, engine.state());
+        assert_eq!(None, engine.stats().byte_rate_windows);
+
+        // The open window is counted as if it ended now.
+        let summary = engine.summary();
+        assert_eq!(Some(1024), summary.configured_bps);
+        let achieved = summary.achieved_bps.unwrap();
+        assert!(0 < achieved && achieved <= 1024, "{}", achieved);
+        assert_eq!(None, engine.stats().byte_rate_windows);
+    }
+
+    #[test]
+    fn test_engine_activate_now() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            warmup: Duration::from_secs(60),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+        let rx = engine.subscribe();
+        engine.activate_now();
+
+        // Active at t=0 without the stable period nor the warmup.
+        assert_eq!(None, engine.on_operated(Operation::Read).unwrap());
+        assert_eq!(&State::Unstable, engine.state());
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Read));
+        assert_eq!(1, engine.stats().active_windows);
+        assert_eq!(
+            Some(subscription::Event::WindowStarted {
+                speed: Speed::Bps(1024)
+            }),
+            rx.try_recv().ok()
+        );
+
+        // Idle after the duration.
+        clock.advance(Duration::from_secs(11));
+        assert_eq!(
+            Some(State::Stable),
+            engine.on_operated(Operation::Read).unwrap()
+        );
+        assert_eq!(None, engine.speed(Operation::Read));
+
+        // Then the cycle continues without the warmup.
+        clock.advance(Duration::from_secs(29));
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(&State::Stable, engine.state());
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(
+            Some(State::Unstable),
+            engine.on_operated(Operation::Read).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_engine_force_idle() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        engine.force_idle();
+        clock.advance(Duration::from_secs(31));
+        engine.on_operated(Operation::Read).unwrap();
+        // The schedule keeps running behind it.
+        assert_eq!(&State::Unstable, engine.state());
+        assert!(engine.is_passthrough());
+        assert_eq!(None, engine.speed(Operation::Read));
+        assert_eq!(
+            ThrottleState::Idle {
+                next_activation: None
+            },
+            engine.current_state()
+        );
+
+        // Clearing restores the scheduled state.
+        engine.clear_force();
+        assert!(!engine.is_passthrough());
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Read));
+
+        engine.force_active(Speed::Bps(2048));
+        assert_eq!(Some(Speed::Bps(2048)), engine.speed(Operation::Read));
+        engine.clear_force();
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Read));
+        clock.advance(Duration::from_secs(10));
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(None, engine.speed(Operation::Read));
+    }
+
+    #[test]
+    fn test_engine_reset_request() {
+        static CONTROLS: Controls = Controls::new();
+        let config = Arc::new(AtomicImmut::new(Config {
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.set_controls(&CONTROLS);
+        engine.init();
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(Ok(None), engine.on_operated(Operation::Read));
+
+        CONTROLS.request_reset();
+        assert_eq!(
+            Ok(Some(State::Unstable)),
+            engine.on_operated(Operation::Read)
+        );
+        // The unstable period lasts the duration from the reset.
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(Ok(None), engine.on_operated(Operation::Read));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(Ok(Some(State::Stable)), engine.on_operated(Operation::Read));
+    }
+
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_engine_event_log() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.set_event_log(EventLog::new(SharedBuffer(Arc::clone(&buffer))));
+        engine.init();
+
+        for _ in 0..50 {
+            clock.advance(Duration::from_secs(1));
+            engine.on_operated(Operation::Read).unwrap();
+        }
+
+        let buffer = buffer.lock().unwrap();
+        let events = String::from_utf8_lossy(&buffer)
+            .lines()
+            .map(|l| serde_json::from_str::<Event>(l).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(2, events.len());
+
+        let (start, end) = (&events[0], &events[1]);
+        assert_eq!(EventKind::Start, start.event);
+        assert_eq!(Some(10_000), start.duration_ms);
+        assert_eq!(EventKind::End, end.event);
+        assert_eq!(Some(10_000), end.duration_ms);
+        for e in &events {
+            assert_eq!(Speed::Bps(1024), e.speed);
+            assert_eq!(vec![Operation::Read, Operation::Write], e.operations);
+            assert!(e.timestamp.ends_with('Z'), "{}", e.timestamp);
+        }
+        assert!(String::from_utf8_lossy(&buffer).starts_with(r#"{"event":"start","#));
+    }
+
+    #[test]
+    fn test_engine_window_throughput() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(4096),
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.set_event_log(EventLog::new(SharedBuffer(Arc::clone(&buffer))));
+        engine.init();
+        assert_eq!(None, engine.stats().last_window);
+
+        // Read continuously through an unstable period, which takes a second per 4KB.
+        clock.advance(Duration::from_secs(31));
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(&State::Unstable, engine.state());
+        while engine.on_operated(Operation::Read).unwrap().is_none() {
+            engine.throttle(Operation::Read, 1024);
+        }
+
+        let throughput = engine.stats().last_window.unwrap();
+        assert_eq!(Some(4096), throughput.configured_bps);
+        assert!(36 << 10 <= throughput.bytes, "{:?}", throughput);
+        let ratio = throughput.ratio().unwrap();
+        assert!(0.95 < ratio && ratio <= 1.0, "{:?}", throughput);
+
+        // The end event has the achieved rate too.
+        let buffer = buffer.lock().unwrap();
+        let events = String::from_utf8_lossy(&buffer)
+            .lines()
+            .map(|l| serde_json::from_str::<Event>(l).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(None, events[0].achieved_bps);
+        assert_eq!(EventKind::End, events[1].event);
+        assert_eq!(throughput.achieved_bps(), events[1].achieved_bps);
+        assert!(!String::from_utf8_lossy(&buffer)
+            .lines()
+            .next()
+            .unwrap()
+            .contains("achieved_bps"));
+    }
+
+    #[test]
+    fn test_engine_log_level() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            ..Default::default()
+        }));
+        // Runs one cycle (i.e., an unstable period and the stable one after it) delaying the operations.
+        let run_cycle = |level: Option<LogLevel>| {
+            let buffer = Arc::new(Mutex::new(Vec::new()));
+            let mut log = EventLog::new(SharedBuffer(Arc::clone(&buffer)));
+            log.set_sample_rate(Some(1));
+
+            let clock = Arc::new(MockClock::new());
+            let mut engine = Engine::with_seed(Arc::clone(&config), 0);
+            engine.set_clock(clock.clone());
+            engine.set_event_log(log);
+            if let Some(level) = level {
+                engine.set_log_level(level);
+            }
+            engine.init();
+            let rx = engine.subscribe();
+            for _ in 0..40 {
+                clock.advance(Duration::from_secs(1));
+                engine.on_operated(Operation::Read).unwrap();
+                engine.throttle(Operation::Read, 1);
+            }
+            // The subscribers are not affected by the level.
+            assert!(rx.try_iter().any(|e| e == subscription::Event::WindowEnded));
+
+            let buffer = buffer.lock().unwrap();
+            String::from_utf8_lossy(&buffer)
+                .lines()
+                .map(|l| serde_json::from_str::<Event>(l).unwrap().event)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(run_cycle(Some(LogLevel::Off)).is_empty());
+        // The default is `LogLevel::Windows`, which ignores the sample rate.
+        assert_eq!(vec![EventKind::Start, EventKind::End], run_cycle(None));
+        assert_eq!(
+            vec![EventKind::Start, EventKind::End],
+            run_cycle(Some(LogLevel::Windows))
+        );
+
+        // The delays in the unstable period are between the start and the end.
+        let events = run_cycle(Some(LogLevel::Operations));
+        assert!(2 < events.len(), "{:?}", events);
+        assert_eq!(EventKind::Start, events[0]);
+        assert_eq!(Some(&EventKind::End), events.last());
+        assert!(events[1..events.len() - 1]
+            .iter()
+            .all(|&e| e == EventKind::Throttle));
+    }
+
+    #[test]
+    fn test_engine_errors_per_window() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            error_injection: Some(ErrorInjection {
+                probability: 0.0,
+                operations: vec![Operation::Read],
+                disk_full_after: None,
+                errors: vec![],
+                continuous: false,
+                errors_per_window: Some(3),
+            }),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        let window = |engine: &mut Engine, ops: usize| {
+            clock.advance(Duration::from_secs(31));
+            engine.on_operated(Operation::Read).unwrap();
+            assert_eq!(&State::Unstable, engine.state());
+            let failed = (0..ops)
+                .map(|_| engine.injected_error(Operation::Read, 0))
+                .collect::<Vec<_>>();
+            // The other operations are not the target.
+            assert_eq!(None, engine.injected_error(Operation::Write, 0));
+            clock.advance(Duration::from_secs(10));
+            engine.on_operated(Operation::Read).unwrap();
+            failed
+        };
+
+        // The first period fails the first operations.
+        let failed = window(&mut engine, 10);
+        assert_eq!(vec![Some(libc::EIO); 3], failed[..3].to_vec());
+        assert_eq!(vec![None; 7], failed[3..].to_vec());
+
+        // The next ones spread the errors over as many operations as the last one.
+        let mut spread = false;
+        for _ in 0..5 {
+            let failed = window(&mut engine, 10);
+            assert_eq!(3, failed.iter().filter(|e| e.is_some()).count());
+            spread |= failed[..3].iter().any(|e| e.is_none());
+        }
+        assert!(spread);
+
+        // More operations than the last period fail up to the budget.
+        let failed = window(&mut engine, 20);
+        assert_eq!(3, failed.iter().filter(|e| e.is_some()).count());
+
+        // Nothing fails in the stable period.
+        assert_eq!(&State::Stable, engine.state());
+        assert_eq!(None, engine.injected_error(Operation::Read, 0));
+    }
+
+    #[test]
+    fn test_engine_errors_per_window_shorter() {
+        let window = |offset, length| ScriptWindow {
+            offset: Duration::from_secs(offset),
+            length: Duration::from_secs(length),
+            speed: Speed::PassThrough,
+        };
+        let config = Arc::new(AtomicImmut::new(Config {
+            condition: Condition::Script {
+                windows: vec![window(10, 10), window(30, 10), window(50, 4)],
+                repeat: false,
+            },
+            error_injection: Some(ErrorInjection {
+                probability: 0.0,
+                operations: vec![Operation::Read],
+                disk_full_after: None,
+                errors: vec![],
+                continuous: false,
+                errors_per_window: Some(3),
+            }),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        // An operation per second.
+        let mut windows: Vec<Vec<Option<c_int>>> = Vec::new();
+        for _ in 0..60 {
+            if engine.on_operated(Operation::Read).unwrap() == Some(State::Unstable) {
+                windows.push(Vec::new());
+            }
+            if engine.state() == &State::Unstable {
+                let e = engine.injected_error(Operation::Read, 0);
+                windows.last_mut().unwrap().push(e);
+            }
+            clock.advance(Duration::from_secs(1));
+        }
+        assert_eq!(
+            vec![10, 10, 4],
+            windows.iter().map(|w| w.len()).collect::<Vec<_>>()
+        );
+
+        // Exactly the budget fails in each window including the last one shorter than the others.
+        for failed in &windows {
+            assert_eq!(
+                3,
+                failed.iter().filter(|e| e.is_some()).count(),
+                "{:?}",
+                failed
+            );
+        }
+    }
+
+    #[test]
+    fn test_engine_subscribe() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            error_injection: Some(ErrorInjection {
+                probability: 1.0,
+                operations: vec![Operation::Write],
+                disk_full_after: None,
+                errors: vec![],
+                continuous: false,
+                errors_per_window: None,
+            }),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        let events = engine.subscribe();
+        engine.init();
+
+        // Nothing happens in the stable period.
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(Duration::default(), engine.throttle(Operation::Read, 1024));
+        assert_eq!(None, engine.injected_error(Operation::Write, 0));
+        assert!(events.try_recv().is_err());
+
+        clock.advance(Duration::from_secs(31));
+        engine.on_operated(Operation::Read).unwrap();
+        engine.throttle(Operation::Read, 2048);
+        engine.on_operated(Operation::Write).unwrap();
+        assert_eq!(Some(libc::EIO), engine.injected_error(Operation::Write, 0));
+        clock.advance(Duration::from_secs(10));
+        engine.on_operated(Operation::Read).unwrap();
+
+        use subscription::Event::*;
+        assert_eq!(
+            vec![
+                WindowStarted {
+                    speed: Speed::Bps(1024)
+                },
+                OperationThrottled {
+                    op: Operation::Read,
+                    bytes: 2048,
+                    delay: Duration::from_secs(2),
+                },
+                ErrorInjected {
+                    op: Operation::Write,
+                    errno: libc::EIO,
+                },
+                WindowEnded,
+            ],
+            events.try_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_engine_stats() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            error_injection: Some(ErrorInjection {
+                probability: 1.0,
+                operations: vec![Operation::Stat],
+                disk_full_after: None,
+                errors: vec![],
+                continuous: false,
+                errors_per_window: None,
+            }),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+        assert_eq!(Stats::default(), engine.stats());
+
+        // Stable.
+        assert_eq!(None, engine.injected_error(Operation::Stat, 0));
+
+        // Two unstable periods.
+        for _ in 0..2 {
+            clock.advance(Duration::from_secs(31));
+            engine.on_operated(Operation::Read).unwrap();
+            assert_eq!(&State::Unstable, engine.state());
+
+            engine.on_throttled(&Operation::Read, 100);
+            engine.on_throttled(&Operation::Write, 10);
+            engine.on_throttled(&Operation::Stat, 1);
+            engine.on_delayed(&Operation::Read);
+            assert!(engine.injected_error(Operation::Stat, 0).is_some());
+            assert_eq!(None, engine.injected_error(Operation::Read, 0));
+
+            clock.advance(Duration::from_secs(10));
+            engine.on_operated(Operation::Read).unwrap();
+            assert_eq!(&State::Stable, engine.state());
+        }
+
+        assert_eq!(
+            Stats {
+                bytes_read_throttled: 200,
+                bytes_written_throttled: 20,
+                ops_delayed: 2,
+                errors_injected: 2,
+                active_windows: 2,
+                ops_would_delay: 0,
+                errors_would_inject: 0,
+                delay_histogram: [0; DELAY_BUCKETS],
+                last_window: Some(WindowThroughput {
+                    configured_bps: Some(1024),
+                    bytes: 110,
+                    duration_ms: 10_000,
+                }),
+                total_delay_ms: 0,
+                byte_rate_windows: Some(WindowThroughput {
+                    configured_bps: Some(1024),
+                    bytes: 220,
+                    duration_ms: 20_000,
+                }),
+            },
+            engine.stats()
+        );
+    }
+
+    #[test]
+    fn test_engine_max_activations() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            max_activations: Some(1),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        let mut transitions = Vec::new();
+        let mut throttled = 0;
+        for _ in 0..1000 {
+            clock.advance(Duration::from_secs(1));
+            if let Some(state) = engine.on_operated(Operation::Read).unwrap() {
+                transitions.push(state);
+            }
+            if engine.speed(Operation::Read).is_some() {
+                throttled += 1;
+            }
+        }
+
+        assert_eq!(vec![State::Unstable, State::Stable], transitions);
+        assert_eq!(10, throttled);
+        assert_eq!(1, engine.stats().active_windows);
+    }
+
+    #[test]
+    fn test_engine_speed_decay() {
+        // Returns the speeds and the multipliers of the first `n` unstable periods.
+        let windows = |speed_decay, n| {
+            let config = Arc::new(AtomicImmut::new(Config {
+                speed: Speed::Bps(1024),
+                condition: Condition::Periodic {
+                    duration: Duration::from_secs(10),
+                    frequency: Duration::from_secs(30),
+                },
+                speed_decay,
+                ..Default::default()
+            }));
+            let clock = Arc::new(MockClock::new());
+            let mut engine = Engine::with_seed(config, 0);
+            engine.set_clock(clock.clone());
+            engine.init();
+
+            // The middle of the first unstable period, then of the following ones.
+            clock.advance(Duration::from_secs(35));
+            let mut windows = Vec::new();
+            for _ in 0..n {
+                engine.on_operated(Operation::Read).unwrap();
+                let multiplier = match engine.current_state() {
+                    ThrottleState::Active { multiplier, .. } => multiplier,
+                    s => panic!("unexpected state: {}", s),
+                };
+                windows.push((engine.speed(Operation::Read), multiplier));
+                // Through the stable period.
+                clock.advance(Duration::from_secs(6));
+                engine.on_operated(Operation::Read).unwrap();
+                clock.advance(Duration::from_secs(34));
+            }
+            windows
+        };
+
+        // Constant.
+        assert_eq!(vec![(Some(Speed::Bps(1024)), 1.0); 3], windows(1.0, 3));
+        // Degrading down to the floor.
+        assert_eq!(
+            vec![
+                (Some(Speed::Bps(1024)), 1.0),
+                (Some(Speed::Bps(512)), 0.5),
+                (Some(Speed::Bps(256)), 0.25),
+                (Some(Speed::Bps(128)), 0.125),
+            ],
+            windows(0.5, 4)
+        );
+        assert_eq!(
+            (Some(Speed::Bps(1)), MIN_SPEED_MULTIPLIER),
+            windows(0.25, 7)[6]
+        );
+        // Recovering up to the pass-through.
+        assert_eq!(
+            vec![
+                (Some(Speed::Bps(1024)), 1.0),
+                (Some(Speed::Bps(4096)), 4.0),
+                (Some(Speed::Bps(16384)), 16.0),
+                (Some(Speed::Bps(65536)), 64.0),
+                (Some(Speed::Bps(262_144)), 256.0),
+                (None, MAX_SPEED_MULTIPLIER),
+                (None, MAX_SPEED_MULTIPLIER),
+            ],
+            windows(4.0, 7)
+        );
+    }
+
+    #[test]
+    fn test_engine_inverted() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            inverted: true,
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        // Throttled out of the unstable period.
+        clock.advance(Duration::from_secs(5));
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(&State::Stable, engine.state());
+        assert!(!engine.is_passthrough());
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Read));
+        assert_eq!(
+            ThrottleState::Active {
+                operations: vec![Operation::Read, Operation::Write],
+                speed: Speed::Bps(1024),
+                multiplier: 1.0,
+                remaining: Some(Duration::from_secs(25)),
+            },
+            engine.current_state()
+        );
+
+        // Passes through in the unstable period.
+        clock.advance(Duration::from_secs(30));
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(&State::Unstable, engine.state());
+        assert!(engine.is_passthrough());
+        assert_eq!(None, engine.speed(Operation::Read));
+        assert_eq!(
+            ThrottleState::Idle {
+                next_activation: Some(Duration::from_secs(5)),
+            },
+            engine.current_state()
+        );
+
+        // Throttled again after it.
+        clock.advance(Duration::from_secs(6));
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(&State::Stable, engine.state());
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Write));
+    }
+
+    #[test]
+    fn test_engine_window_burst() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            window_burst: Some(WindowBurst::Bytes(4096)),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(Arc::clone(&config), 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        for _ in 0..2 {
+            clock.advance(Duration::from_secs(31));
+            assert_eq!(
+                Ok(Some(State::Unstable)),
+                engine.on_operated(Operation::Write)
+            );
+
+            // The first 4KB of the period are not delayed.
+            assert_eq!(Duration::default(), engine.throttle(Operation::Write, 3072));
+            assert_eq!(Duration::default(), engine.throttle(Operation::Read, 512));
+            // Only the bytes over the budget are throttled.
+            assert_eq!(
+                Duration::from_millis(500),
+                engine.throttle(Operation::Write, 1024)
+            );
+            assert_eq!(
+                Duration::from_secs(1),
+                engine.throttle(Operation::Write, 1024)
+            );
+
+            // The budget is given again in the next period.
+            clock.advance(Duration::from_secs(10));
+            assert_eq!(
+                Ok(Some(State::Stable)),
+                engine.on_operated(Operation::Write)
+            );
+        }
+        assert_eq!(
+            1024 + 512 + 1024 + 512,
+            engine.stats().bytes_written_throttled
+        );
+
+        // The beginning of the period by the duration.
+        let mut c = (*config.load()).clone();
+        c.window_burst = Some(WindowBurst::Duration(Duration::from_secs(5)));
+        let mut engine = Engine::with_seed(Arc::new(AtomicImmut::new(c)), 0);
+        let clock = Arc::new(MockClock::new());
+        engine.set_clock(clock.clone());
+        engine.init();
+        clock.advance(Duration::from_secs(31));
+        engine.on_operated(Operation::Write).unwrap();
+        assert_eq!(None, engine.speed(Operation::Write));
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(None, engine.speed(Operation::Write));
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Write));
+    }
+
+    #[test]
+    fn test_engine_throttle_after_bytes() {
+        let config = ConfigBuilder::new()
+            .speed(Speed::Bps(1024))
+            .duration(Duration::from_secs(10))
+            .frequency(Duration::from_secs(30))
+            .throttle_after_bytes(2048)
+            .build();
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(Arc::new(AtomicImmut::new(config)), 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        for _ in 0..2 {
+            clock.advance(Duration::from_secs(31));
+            engine.on_operated(Operation::Read).unwrap();
+
+            // The transfers below the quota pass through, and the later ones are throttled.
+            assert_eq!(Duration::default(), engine.throttle(Operation::Read, 1024));
+            assert_eq!(Duration::default(), engine.throttle(Operation::Read, 1024));
+            assert_eq!(
+                Duration::from_secs(1),
+                engine.throttle(Operation::Read, 1024)
+            );
+
+            // The quota resets at the start of the next period.
+            clock.advance(Duration::from_secs(10));
+            assert_eq!(Ok(Some(State::Stable)), engine.on_operated(Operation::Read));
+        }
+        assert_eq!(2048, engine.stats().bytes_read_throttled);
+    }
+
+    #[test]
+    fn test_engine_current_state() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        let idle = |secs| ThrottleState::Idle {
+            next_activation: Some(Duration::from_secs(secs)),
+        };
+        let active = |secs| ThrottleState::Active {
+            operations: vec![Operation::Read, Operation::Write],
+            speed: Speed::Bps(1024),
+            multiplier: 1.0,
+            remaining: Some(Duration::from_secs(secs)),
+        };
+        assert_eq!(idle(30), engine.current_state());
+
+        for t in 1..=30 {
+            clock.advance(Duration::from_secs(1));
+            engine.on_operated(Operation::Read).unwrap();
+            assert_eq!(idle(30 - t), engine.current_state());
+        }
+
+        // The unstable period began at 30s and the state is updated at 31s.
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(idle(0), engine.current_state());
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(active(9), engine.current_state());
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(active(8), engine.current_state());
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(active(3), engine.current_state());
+        assert_eq!(
+            "Active: throttling Read,Write at 1KBps for 3s remaining",
+            engine.current_state().to_string()
+        );
+
+        // Back to stable at 41s.
+        clock.advance(Duration::from_secs(4));
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(idle(29), engine.current_state());
+        assert_eq!(
+            "Idle: next activation in 29s",
+            engine.current_state().to_string()
+        );
+    }
+
+    #[test]
+    fn test_engine_throttle_delay() {
+        let mut engine = Engine::with_seed(config(), 0);
+        engine.set_clock(Arc::new(MockClock::new()));
+
+        // The same as the legacy throttle without the burst.
+        assert_eq!(
+            compute_sleep_duration_to_adjust_speed(1024, 2048, 500),
+            engine.throttle_delay(Operation::Write, 1024, 2048, Duration::from_millis(500))
+        );
+        assert_eq!(2048, engine.stats().bytes_written_throttled);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_engine_throttle_async() {
+        use tokio::runtime::Builder;
+        use tokio::time::Instant;
+
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            operations: vec![Operation::Write],
+            condition: Condition::Always(State::Unstable),
+            ..Default::default()
+        }));
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(Arc::new(MockClock::new()));
+        engine.init();
+
+        let runtime = Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap();
+        let _guard = runtime.enter();
+
+        let start = Instant::now();
+        runtime.block_on(engine.throttle_async(Operation::Write, 2048));
+        assert_eq!(
+            compute_sleep_duration_to_adjust_speed(1024, 2048, 0),
+            start.elapsed()
+        );
+        assert_eq!(1, engine.stats().ops_delayed);
+
+        // The operation which is not the target does not wait.
+        let start = Instant::now();
+        runtime.block_on(engine.throttle_async(Operation::Read, 2048));
+        assert_eq!(Duration::default(), start.elapsed());
+    }
+
+    #[test]
+    fn test_engine_wait_for_next_activation() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1 << 20),
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(Some(Speed::Bps(1 << 20)), engine.wait_for_next_activation());
+        assert_eq!(Duration::from_secs(30), clock.elapsed());
+
+        // The period begins at the next operation.
+        assert_eq!(None, engine.speed(Operation::Read));
+        clock.advance(Duration::from_millis(1));
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(Some(Speed::Bps(1 << 20)), engine.speed(Operation::Read));
+
+        // The next one is after the current unstable period and the stable period.
+        assert_eq!(Some(Speed::Bps(1 << 20)), engine.wait_for_next_activation());
+        assert_eq!(Duration::from_secs(70), clock.elapsed());
+
+        engine.shutdown();
+        assert_eq!(None, engine.wait_for_next_activation());
+        assert_eq!(Duration::from_secs(70), clock.elapsed());
+    }
+
+    #[test]
+    fn test_engine_wait_for_next_activation_paused() {
+        static CONTROLS: Controls = Controls::new();
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.set_controls(&CONTROLS);
+        engine.init();
+        CONTROLS.set_paused(true);
+
+        // Resume in the other thread while the engine waits.
+        let resumer = thread::spawn(|| {
+            thread::sleep(Duration::from_millis(10));
+            CONTROLS.set_paused(false);
+        });
+        assert_eq!(Some(Speed::Bps(1024)), engine.wait_for_next_activation());
+        resumer.join().unwrap();
+        assert!(Duration::from_secs(30) < clock.elapsed());
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_engine_wait_for_next_activation_async() {
+        use tokio::runtime::Builder;
+        use tokio::time::Instant;
+
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+        clock.advance(Duration::from_secs(5));
+
+        let runtime = Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap();
+        let _guard = runtime.enter();
+
+        let start = Instant::now();
+        assert_eq!(
+            Some(Speed::Bps(1024)),
+            runtime.block_on(engine.wait_for_next_activation_async())
+        );
+        assert_eq!(Duration::from_secs(25), start.elapsed());
+
+        engine.shutdown();
+        assert_eq!(
+            None,
+            runtime.block_on(engine.wait_for_next_activation_async())
+        );
+    }
+
+    #[test]
+    fn test_engine_run_for() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Always(State::Unstable),
+            run_for: Some(Duration::from_secs(60)),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        assert_eq!(None, engine.run_for_remaining());
+        engine.init();
+
+        clock.advance(Duration::from_secs(59));
+        engine.on_operated(Operation::Write).unwrap();
+        assert_eq!(Some(Duration::from_secs(1)), engine.run_for_remaining());
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Write));
+
+        clock.advance(Duration::from_secs(1));
+        engine.on_operated(Operation::Write).unwrap();
+        assert_eq!(Some(Duration::default()), engine.run_for_remaining());
+        assert!(engine.shutdown_handle().is_triggered());
+        // The operations pass through after that.
+        assert_eq!(None, engine.speed(Operation::Write));
+        assert_eq!(Duration::default(), engine.throttle(Operation::Write, 4096));
+        assert!(!engine.shutdown_if_run_over());
+    }
+
+    #[test]
+    fn test_engine_shutdown() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1),
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        clock.advance(Duration::from_secs(31));
+        engine.on_operated(Operation::Write).unwrap();
+        assert_eq!(Some(Speed::Bps(1)), engine.speed(Operation::Write));
+
+        engine.shutdown();
+
+        // A large write passes through.
+        assert_eq!(None, engine.speed(Operation::Write));
+        engine.sleep(Duration::from_secs(60));
+        assert_eq!(Duration::from_secs(31), clock.elapsed());
+        assert_eq!(
+            ThrottleState::Idle {
+                next_activation: None
+            },
+            engine.current_state()
+        );
+
+        // No more unstable periods begin.
+        for _ in 0..10 {
+            clock.advance(Duration::from_secs(10));
+            assert_eq!(None, engine.on_operated(Operation::Write).unwrap());
+            assert_eq!(None, engine.speed(Operation::Write));
+        }
+        assert_eq!(1, engine.stats().active_windows);
+    }
+
+    #[test]
+    fn test_engine_percent_speed() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Percent(10),
+            condition: Condition::Always(State::Unstable),
+            ..Default::default()
+        }));
+        let mut engine = Engine::with_seed(Arc::clone(&config), 0);
+        engine.init();
+
+        // Not throttled without the baseline.
+        assert_eq!(None, engine.speed(Operation::Read));
+
+        engine.set_baseline(100 << 20);
+        assert_eq!(Some(Speed::Bps(10 << 20)), engine.speed(Operation::Read));
+
+        // The configured baseline overrides the measured one.
+        config.store(Config {
+            baseline: Some(200 << 20),
+            ..(*config.load()).clone()
+        });
+        assert_eq!(Some(Speed::Bps(20 << 20)), engine.speed(Operation::Read));
+    }
+
+    #[test]
+    fn test_engine_ramp() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Always(State::Unstable),
+            ramp: Some(Duration::from_secs(10)),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        // Advances the clock to `secs` since the unstable state began.
+        let mut speed_at = |secs: u64| {
+            clock.advance(Duration::from_secs(secs) - clock.elapsed());
+            engine.speed(Operation::Read)
+        };
+        // 0%, 10%, 50% and 100% of the ramp.
+        assert_eq!(None, speed_at(0));
+        assert_eq!(Some(Speed::Bps(1024 * 10)), speed_at(1));
+        assert_eq!(Some(Speed::Bps(2048)), speed_at(5));
+        assert_eq!(Some(Speed::Bps(1024)), speed_at(10));
+        assert_eq!(Some(Speed::Bps(1024)), speed_at(15));
+    }
+
+    #[test]
+    fn test_engine_throttle_small_operations() {
+        // Returns the time which the one-byte writes take in total.
+        let elapsed = |burst| {
+            let config = Arc::new(AtomicImmut::new(Config {
+                speed: Speed::Bps(3000),
+                condition: Condition::Always(State::Unstable),
+                burst,
+                ..Default::default()
+            }));
+            let clock = Arc::new(MockClock::new());
+            let mut engine = Engine::with_seed(config, 0);
+            engine.set_clock(clock.clone());
+            engine.init();
+
+            for _ in 0..10_000 {
+                engine.throttle(Operation::Write, 1);
+            }
+            clock.elapsed()
+        };
+
+        let expected = Duration::from_secs(10_000) / 3000;
+        for &burst in &[None, Some(1)] {
+            let elapsed = elapsed(burst);
+            assert!(
+                elapsed.abs_diff(expected) <= Duration::from_millis(1),
+                "{:?}: {:?}",
+                burst,
+                elapsed
+            );
+        }
+    }
+
+    #[test]
+    fn test_engine_null() {
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::null();
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        for i in 0..1000 {
+            clock.advance(Duration::from_secs(1));
+            for op in &[Operation::Read, Operation::Write, Operation::Stat] {
+                assert_eq!(None, engine.on_operated(op.clone()).unwrap());
+                assert_eq!(None, engine.speed(op.clone()));
+                assert_eq!(None, engine.injected_error(op.clone(), 4096));
+                assert_eq!(4096, engine.io_size(op.clone(), 4096));
+                assert_eq!(Duration::default(), engine.throttle(op.clone(), i << 10));
+            }
+        }
+        assert_eq!(State::Stable, *engine.state());
+        assert_eq!(
+            ThrottleState::Idle {
+                next_activation: None
+            },
+            engine.current_state()
+        );
+        assert_eq!(None, engine.wait_for_next_activation());
+        assert_eq!(Stats::default(), engine.stats());
+        // No timers slept.
+        assert_eq!(Duration::from_secs(1000), clock.elapsed());
+    }
+
+    #[test]
+    fn test_engine_passthrough_first_op() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Always(State::Unstable),
+            passthrough_first_op: true,
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(Arc::clone(&config), 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        engine.on_opened(1);
+        engine.on_opened(2);
+        let second = Duration::from_secs(1);
+        assert_eq!(
+            Duration::default(),
+            engine.throttle_handle(1, Operation::Read, 1024)
+        );
+        assert_eq!(second, engine.throttle_handle(1, Operation::Read, 1024));
+        // Each handle has its own first operation.
+        assert_eq!(
+            Duration::default(),
+            engine.throttle_handle(2, Operation::Write, 1024)
+        );
+        assert_eq!(second, engine.throttle_handle(2, Operation::Read, 1024));
+
+        // The released handles are forgotten, and the handle numbers can be reused.
+        engine.on_released(1);
+        engine.on_released(2);
+        assert!(engine.handle_operations.is_empty());
+        assert_eq!(second, engine.throttle_handle(1, Operation::Read, 1024));
+        engine.on_opened(1);
+        assert_eq!(
+            Duration::default(),
+            engine.throttle_handle(1, Operation::Read, 1024)
+        );
+
+        // Disabled.
+        config.update(|c| Config {
+            passthrough_first_op: false,
+            ..c.clone()
+        });
+        engine.on_operated(Operation::Read).unwrap();
+        engine.on_opened(3);
+        assert_eq!(second, engine.throttle_handle(3, Operation::Read, 1024));
+    }
+
+    #[test]
+    fn test_engine_read_coalesce() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Always(State::Unstable),
+            read_coalesce: Some(Duration::from_millis(100)),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(Arc::clone(&config), 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+        engine.on_opened(1);
+
+        // Three sequential reads back to back are charged as one transfer.
+        let second = Duration::from_secs(1);
+        assert_eq!(second, engine.throttle_read(1, 0, 1024));
+        assert_eq!(Duration::default(), engine.throttle_read(1, 1024, 1024));
+        assert_eq!(Duration::default(), engine.throttle_read(1, 2048, 1024));
+        let stats = engine.stats();
+        assert_eq!(1, stats.ops_delayed);
+        assert_eq!(3072, stats.bytes_read_throttled);
+
+        // The transfer ends at the window after the first read, or at the read which does not continue it.
+        // The next transfer pays the coalesced bytes of the last one.
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(3 * second, engine.throttle_read(1, 3072, 1024));
+        assert_eq!(second, engine.throttle_read(1, 0, 1024));
+        // The other handles have their own transfers, and the handles which are not opened are never coalesced.
+        engine.on_opened(2);
+        assert_eq!(second, engine.throttle_read(2, 1024, 1024));
+        assert_eq!(second, engine.throttle_read(3, 0, 1024));
+        assert_eq!(second, engine.throttle_read(3, 1024, 1024));
+
+        // Disabled by default.
+        config.update(|c| Config {
+            read_coalesce: None,
+            ..c.clone()
+        });
+        assert_eq!(second, engine.throttle_read(2, 2048, 1024));
+
+        engine.on_released(1);
+        engine.on_released(2);
+        assert!(engine.read_transfers.is_empty());
+
+        // The coalesced bytes are consumed from the token bucket too.
+        config.update(|c| Config {
+            read_coalesce: Some(Duration::from_millis(100)),
+            burst: Some(1024),
+            burst_start_fill: 1.0,
+            ..c.clone()
+        });
+        engine.on_operated(Operation::Read).unwrap();
+        engine.on_opened(4);
+        assert_eq!(Duration::default(), engine.throttle_read(4, 0, 1024));
+        assert_eq!(Duration::default(), engine.throttle_read(4, 1024, 2048));
+        clock.advance(Duration::from_millis(100));
+        assert!(2 * second < engine.throttle_read(4, 0, 1024));
+    }
+
+    #[test]
+    fn test_engine_seek_penalty() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            condition: Condition::Always(State::Unstable),
+            seek_penalty: Some(Duration::from_millis(8)),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(Arc::clone(&config), 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+        engine.on_operated(Operation::Read).unwrap();
+        engine.on_opened(1);
+
+        let penalty = Duration::from_millis(8);
+        let zero = Duration::default();
+        // The first access and the contiguous ones are sequential.
+        assert_eq!(zero, engine.throttle_seek(1, Operation::Read, 0, 4096));
+        assert_eq!(zero, engine.throttle_seek(1, Operation::Read, 4096, 4096));
+        // The far-apart offsets are random each.
+        assert_eq!(
+            penalty,
+            engine.throttle_seek(1, Operation::Read, 1 << 30, 4096)
+        );
+        assert_eq!(penalty, engine.throttle_seek(1, Operation::Write, 0, 4096));
+        assert_eq!(
+            penalty,
+            engine.throttle_seek(1, Operation::Read, 1 << 30, 4096)
+        );
+        assert_eq!(
+            zero,
+            engine.throttle_seek(1, Operation::Read, (1 << 30) + 4096, 4096)
+        );
+        assert_eq!(penalty * 3, clock.elapsed());
+
+        // The next access continues from the bytes transferred actually, e.g., of a short read.
+        assert!(!engine.is_random_access(1, (1 << 30) + 8192));
+        engine.on_handle_accessed(1, (1 << 30) + 8192, 100);
+        assert!(engine.is_random_access(1, (1 << 30) + 8192 + 4096));
+        assert!(!engine.is_random_access(1, (1 << 30) + 8192 + 100));
+
+        // The handles which are not opened are not tracked.
+        assert_eq!(zero, engine.throttle_seek(2, Operation::Read, 0, 4096));
+        assert_eq!(
+            zero,
+            engine.throttle_seek(2, Operation::Read, 1 << 30, 4096)
+        );
+
+        // It is not penalized out of the unstable periods (but still tracked).
+        config.update(|c| Config {
+            condition: Condition::Always(State::Stable),
+            ..c.clone()
+        });
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(zero, engine.throttle_seek(1, Operation::Read, 0, 4096));
+        assert_eq!(penalty * 3, clock.elapsed());
+
+        engine.on_released(1);
+        assert!(engine.access_offsets.is_empty());
+    }
+
+    #[test]
+    fn test_engine_allocation_penalty() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            condition: Condition::Always(State::Unstable),
+            allocation_penalty: Some(Duration::from_millis(4)),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(Arc::clone(&config), 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+        engine.on_operated(Operation::Write).unwrap();
+        engine.on_opened(1);
+        engine.set_handle_size(1, 8192);
+
+        let penalty = Duration::from_millis(4);
+        let zero = Duration::default();
+        // The overwrites in place do not allocate.
+        assert_eq!(zero, engine.throttle_allocation(1, 0, 4096));
+        assert_eq!(zero, engine.throttle_allocation(1, 4096, 4096));
+        // The writes past the size allocate, and raise the high-water offset.
+        assert_eq!(penalty, engine.throttle_allocation(1, 6144, 4096));
+        assert_eq!(zero, engine.throttle_allocation(1, 8192, 2048));
+        assert_eq!(penalty, engine.throttle_allocation(1, 1 << 30, 4096));
+        assert_eq!(zero, engine.throttle_allocation(1, 1 << 20, 4096));
+        assert_eq!(penalty * 2, clock.elapsed());
+
+        // The handles which are not opened are not tracked.
+        assert_eq!(zero, engine.throttle_allocation(2, 1 << 30, 4096));
+        // The size of a handle is zero unless it is set.
+        engine.on_opened(3);
+        assert_eq!(penalty, engine.throttle_allocation(3, 0, 1));
+
+        // It is not penalized out of the unstable periods (but still tracked).
+        config.update(|c| Config {
+            condition: Condition::Always(State::Stable),
+            ..c.clone()
+        });
+        engine.on_operated(Operation::Write).unwrap();
+        assert!(engine.on_handle_written(1, 1 << 31, 4096));
+        assert_eq!(zero, engine.allocation_penalty());
+        assert_eq!(penalty * 3, clock.elapsed());
+
+        engine.on_released(1);
+        engine.on_released(3);
+        assert!(engine.high_water_offsets.is_empty());
+    }
+
+    #[test]
+    fn test_engine_allocation_penalty_disabled() {
+        let mut engine = Engine::new(Arc::new(AtomicImmut::new(Config {
+            condition: Condition::Always(State::Unstable),
+            ..Default::default()
+        })));
+        engine.init();
+        engine.on_operated(Operation::Write).unwrap();
+        engine.on_opened(1);
+        engine.set_handle_size(1, 0);
+
+        assert!(!engine.on_handle_written(1, 1 << 30, 4096));
+        assert_eq!(Duration::default(), engine.allocation_penalty());
+        assert!(engine.high_water_offsets.is_empty());
+    }
+
+    #[test]
+    fn test_engine_window_operations() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(600),
+                frequency: Duration::from_secs(30),
+            },
+            window_operations: Some(1000),
+            ..Default::default()
+        }));
+        let mut engine = Engine::with_seed(config, 0);
+        engine.init();
+        let base = Instant::now();
+        engine.on_operated_at(Operation::Read, base).unwrap();
+
+        // The operation starting the period is the first one.
+        let unstable = base + Duration::from_secs(31);
+        assert_eq!(
+            Ok(Some(State::Unstable)),
+            engine.on_operated_at(Operation::Read, unstable)
+        );
+        for i in 1..1000 {
+            let op = if i % 2 == 0 {
+                Operation::Read
+            } else {
+                Operation::Write
+            };
+            assert_eq!(Ok(None), engine.on_operated_at(op, unstable));
+            // The operations which are not the target are not counted.
+            assert_eq!(Ok(None), engine.on_operated_at(Operation::Stat, unstable));
+        }
+        assert_eq!(&State::Unstable, engine.state());
+
+        // Idle after exactly 1000 operations, long before the duration.
+        assert_eq!(
+            Ok(Some(State::Stable)),
+            engine.on_operated_at(Operation::Read, unstable)
+        );
+        assert!(engine.is_passthrough());
+
+        // The next period starts after the frequency, and counts afresh.
+        let next = unstable + Duration::from_secs(31);
+        assert_eq!(
+            Ok(Some(State::Unstable)),
+            engine.on_operated_at(Operation::Read, next)
+        );
+        for _ in 1..1000 {
+            assert_eq!(Ok(None), engine.on_operated_at(Operation::Write, next));
+        }
+        assert_eq!(
+            Ok(Some(State::Stable)),
+            engine.on_operated_at(Operation::Write, next)
+        );
+    }
+
+    #[test]
+    fn test_engine_append_speed() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            operations: vec![Operation::Write],
+            condition: Condition::Always(State::Unstable),
+            append_speed: Some(Speed::Bps(4096)),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(Arc::clone(&config), 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+        engine.on_operated(Operation::Write).unwrap();
+        engine.on_opened(1);
+        engine.set_handle_size(1, 8192);
+
+        // The appends at the end of the file are at the append speed.
+        assert!(!engine.on_handle_written(1, 8192, 4096));
+        assert_eq!(
+            Some(Speed::Bps(4096)),
+            engine.handle_speed(1, Operation::Write)
+        );
+        assert_eq!(
+            Duration::from_secs(1),
+            engine.throttle_handle(1, Operation::Write, 4096)
+        );
+        engine.on_handle_written(1, 12288, 4096);
+        assert_eq!(
+            Some(Speed::Bps(4096)),
+            engine.handle_speed(1, Operation::Write)
+        );
+        // The lookups without the handle are not of the appends.
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Write));
+
+        // The writes in the middle of the file are at the normal speed, as those beyond the end.
+        engine.on_handle_written(1, 0, 4096);
+        assert_eq!(
+            Some(Speed::Bps(1024)),
+            engine.handle_speed(1, Operation::Write)
+        );
+        assert_eq!(
+            Duration::from_secs(4),
+            engine.throttle_handle(1, Operation::Write, 4096)
+        );
+        engine.on_handle_written(1, 1 << 20, 4096);
+        assert_eq!(
+            Some(Speed::Bps(1024)),
+            engine.handle_speed(1, Operation::Write)
+        );
+
+        // Each handle has its own decision: the write of the other handle does not end the append of the handle,
+        // and the handles which are not opened are never appending.
+        engine.on_opened(2);
+        engine.on_handle_written(1, (1 << 20) + 4096, 4096);
+        engine.on_handle_written(2, 0, 4096);
+        engine.on_handle_written(3, 0, 4096);
+        assert_eq!(
+            Some(Speed::Bps(4096)),
+            engine.handle_speed(1, Operation::Write)
+        );
+        assert_eq!(
+            Some(Speed::Bps(4096)),
+            engine.handle_speed(2, Operation::Write)
+        );
+        assert_eq!(
+            Some(Speed::Bps(1024)),
+            engine.handle_speed(3, Operation::Write)
+        );
+        engine.on_handle_written(2, 0, 4096);
+        assert_eq!(
+            Some(Speed::Bps(1024)),
+            engine.handle_speed(2, Operation::Write)
+        );
+        engine.on_released(2);
+        assert!(!engine.appending_handles.contains(&2));
+
+        // The appends can be exempted even if the others are not throttled.
+        config.update(|c| Config {
+            speed: Speed::PassThrough,
+            ..c.clone()
+        });
+        engine.on_operated(Operation::Write).unwrap();
+        engine.on_handle_written(1, (1 << 20) + 8192, 4096);
+        assert!(!engine.is_passthrough());
+        assert_eq!(
+            Some(Speed::Bps(4096)),
+            engine.handle_speed(1, Operation::Write)
+        );
+        config.update(|c| Config {
+            speed: Speed::Bps(1024),
+            append_speed: Some(Speed::PassThrough),
+            ..c.clone()
+        });
+        engine.on_operated(Operation::Write).unwrap();
+        engine.on_handle_written(1, (1 << 20) + 12288, 4096);
+        assert_eq!(None, engine.handle_speed(1, Operation::Write));
+        engine.on_handle_written(1, 0, 4096);
+        assert_eq!(
+            Some(Speed::Bps(1024)),
+            engine.handle_speed(1, Operation::Write)
+        );
+    }
+
+    #[test]
+    fn test_engine_share_among_handles() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(2048),
+            condition: Condition::Always(State::Unstable),
+            share_among_handles: true,
+            ..Default::default()
+        }));
+        let mut engine = Engine::with_seed(Arc::clone(&config), 0);
+        engine.init();
+        engine.on_operated(Operation::Read).unwrap();
+
+        assert_eq!(Some(Speed::Bps(2048)), engine.speed(Operation::Read));
+        engine.on_opened(1);
+        assert_eq!(Some(Speed::Bps(2048)), engine.speed(Operation::Read));
+        engine.on_opened(2);
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Read));
+        engine.on_opened(3);
+        engine.on_opened(4);
+        assert_eq!(4, engine.open_handles());
+        assert_eq!(Some(Speed::Bps(512)), engine.speed(Operation::Read));
+
+        engine.on_released(4);
+        engine.on_released(3);
+        engine.on_released(2);
+        assert_eq!(1, engine.open_handles());
+        assert_eq!(Some(Speed::Bps(2048)), engine.speed(Operation::Read));
+
+        // The latencies are not divided.
+        engine.on_opened(2);
+        config.update(|c| Config {
+            speed: Speed::BpsWithLatency {
+                bps: 2048,
+                latency: Duration::from_millis(10),
+            },
+            ..c.clone()
+        });
+        assert_eq!(
+            Some(Speed::BpsWithLatency {
+                bps: 1024,
+                latency: Duration::from_millis(10),
+            }),
+            engine.speed(Operation::Read)
+        );
+
+        // It is off by default.
+        config.update(|c| Config {
+            share_among_handles: false,
+            ..c.clone()
+        });
+        assert_eq!(
+            Some(Speed::BpsWithLatency {
+                bps: 2048,
+                latency: Duration::from_millis(10),
+            }),
+            engine.speed(Operation::Read)
+        );
+    }
+
+    #[test]
+    fn test_engine_share_among_handles_concurrent() {
+        let engine = Arc::new(Mutex::new(Engine::with_seed(
+            Arc::new(AtomicImmut::new(Config {
+                speed: Speed::Bps(64 << 10),
+                condition: Condition::Always(State::Unstable),
+                share_among_handles: true,
+                ..Default::default()
+            })),
+            0,
+        )));
+        {
+            let mut engine = engine.lock().unwrap();
+            engine.init();
+            engine.on_operated(Operation::Read).unwrap();
+            engine.on_opened(1);
+            engine.on_opened(2);
+        }
+
+        // Each stream reads 16KB in 1KB chunks and sleeps out of the lock as concurrent reads do.
+        let streams = (0..2)
+            .map(|_| {
+                let engine = Arc::clone(&engine);
+                thread::spawn(move || {
+                    let start = Instant::now();
+                    for _ in 0..16 {
+                        let wait = engine
+                            .lock()
+                            .unwrap()
+                            .byte_rate_delay(Operation::Read, 1024);
+                        thread::sleep(wait);
+                    }
+                    (16 << 10) as f64 / start.elapsed().as_secs_f64()
+                })
+            })
+            .collect::<Vec<_>>();
+        for s in streams {
+            // Roughly a half of 64KBps.
+            let bps = s.join().unwrap();
+            assert!(
+                (16 << 10) as f64 <= bps && bps <= (34 << 10) as f64,
+                "{}",
+                bps
+            );
+        }
+    }
+
+    #[test]
+    fn test_engine_clock_backward_jump() {
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(
+            Arc::new(AtomicImmut::new(Config {
+                speed: Speed::Bps(1024),
+                condition: Condition::Always(State::Unstable),
+                burst: Some(1024),
+                ..Default::default()
+            })),
+            0,
+        );
+        engine.set_clock(clock.clone());
+        engine.init();
+        engine.on_operated(Operation::Read).unwrap();
+
+        clock.advance(Duration::from_secs(100));
+        assert_eq!(Duration::default(), engine.throttle(Operation::Read, 1024));
+
+        // The delays after the jump are of the bytes without the debt of the jumped time.
+        clock.rewind(Duration::from_secs(60));
+        assert_eq!(
+            Duration::from_secs(1),
+            engine.throttle(Operation::Read, 1024)
+        );
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(Duration::default(), engine.throttle(Operation::Read, 1024));
+        assert_eq!(
+            Duration::from_secs(1),
+            engine.throttle(Operation::Read, 1024)
+        );
+    }
+
+    #[test]
+    fn test_engine_max_op_delay() {
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(
+            Arc::new(AtomicImmut::new(Config {
+                speed: Speed::Bps(1024),
+                condition: Condition::Always(State::Unstable),
+                max_op_delay: Some(Duration::from_secs(5)),
+                ..Default::default()
+            })),
+            0,
+        );
+        engine.set_clock(clock.clone());
+        engine.init();
+        engine.on_operated(Operation::Write).unwrap();
+
+        // 1MB at 1KBps would take 1024s.
+        assert_eq!(
+            Duration::from_secs(5),
+            engine.throttle(Operation::Write, 1 << 20)
+        );
+        assert_eq!(Duration::from_secs(5), clock.elapsed());
+
+        // The delays below the cap are as computed.
+        assert_eq!(
+            Duration::from_secs(2),
+            engine.throttle(Operation::Write, 2048)
+        );
+        assert_eq!(Duration::from_secs(7), clock.elapsed());
+    }
+
+    #[test]
+    fn test_engine_huge_operation() {
+        let speeds = vec![
+            Speed::Bps(1),
+            Speed::PerByte(Duration::from_secs(1)),
+            Speed::BpsWithLatency {
+                bps: 1,
+                latency: Duration::from_secs(1),
+            },
+        ];
+        for (speed, burst) in speeds.into_iter().zip(vec![None, None, Some(1)]) {
+            for &max_op_delay in &[None, Some(Duration::from_secs(30))] {
+                let clock = Arc::new(MockClock::new());
+                let mut engine = Engine::with_seed(
+                    Arc::new(AtomicImmut::new(Config {
+                        speed: speed.clone(),
+                        condition: Condition::Always(State::Unstable),
+                        burst,
+                        max_op_delay,
+                        ..Default::default()
+                    })),
+                    0,
+                );
+                engine.set_clock(clock.clone());
+                engine.init();
+                engine.on_operated(Operation::Write).unwrap();
+
+                // It saturates without panicking nor wrapping.
+                let max = max_op_delay.unwrap_or(MAX_OP_DELAY);
+                assert_eq!(
+                    max,
+                    engine.throttle(Operation::Write, usize::MAX),
+                    "{}",
+                    speed
+                );
+                assert_eq!(
+                    max,
+                    engine.throttle_vectored(Operation::Write, &[usize::MAX, usize::MAX]),
+                    "{}",
+                    speed
+                );
+                assert_eq!(max * 2, clock.elapsed());
+            }
+        }
+    }
+
+    #[test]
+    fn test_engine_config_snapshot() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Always(State::Unstable),
+            error_injection: Some(ErrorInjection {
+                probability: 1.0,
+                operations: vec![Operation::Write],
+                disk_full_after: None,
+                errors: vec![],
+                continuous: false,
+                errors_per_window: None,
+            }),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(Arc::clone(&config), 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+        engine.on_operated(Operation::Write).unwrap();
+        assert!(engine.config_snapshot().is_of(&config.load()));
+
+        config.update(|c| Config {
+            dry_run: true,
+            ..c.clone()
+        });
+        assert!(!engine.config_snapshot().is_of(&config.load()));
+        // The lookups of the current operation keep reading the snapshot without loading the new config.
+        assert!(engine.injected_error(Operation::Write, 0).is_some());
+        assert!(engine.delay(&Operation::Write, 0, Duration::from_secs(1)));
+        assert_eq!(Duration::from_secs(1), clock.elapsed());
+
+        // The next operation refreshes it.
+        engine.on_operated(Operation::Write).unwrap();
+        assert!(engine.config_snapshot().is_of(&config.load()));
+        assert!(engine.config_snapshot().dry_run);
+        assert_eq!(None, engine.injected_error(Operation::Write, 0));
+        assert!(!engine.delay(&Operation::Write, 0, Duration::from_secs(1)));
+        assert_eq!(Duration::from_secs(1), clock.elapsed());
+
+        // It is kept as long as the config is not replaced.
+        let snapshot = Arc::clone(engine.config_snapshot().config());
+        engine.on_operated(Operation::Write).unwrap();
+        assert!(engine.config_snapshot().is_of(&snapshot));
+
+        // `restore_config` refreshes it at once.
+        engine.restore_config(Config::default()).unwrap();
+        assert!(engine.config_snapshot().is_of(&config.load()));
+        assert!(!engine.config_snapshot().dry_run);
+    }
+
+    #[test]
+    fn test_engine_throttle_vectored() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(4096),
+            operations: vec![Operation::Write],
+            condition: Condition::Always(State::Unstable),
+            burst: Some(12 << 10),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        // All the buffers are charged, which empties the bucket.
+        assert_eq!(
+            Duration::default(),
+            engine.throttle_vectored(Operation::Write, &[4 << 10; 3])
+        );
+        assert_eq!(12 << 10, engine.stats().bytes_written_throttled);
+        assert_eq!(
+            Duration::from_secs(1),
+            engine.throttle(Operation::Write, 4 << 10)
+        );
+        assert_eq!(16 << 10, engine.stats().bytes_written_throttled);
+        // Only the delay of 1s is counted in the bucket of [512ms, 1024ms).
+        let mut histogram = [0; DELAY_BUCKETS];
+        histogram[10] = 1;
+        assert_eq!(histogram, engine.stats().delay_histogram);
+    }
+
+    #[test]
+    fn test_engine_burst_start_fill() {
+        // Returns the delays of the first and the second reads of 1KB in an unstable period after the idle.
+        let delays = |fill, window_burst| {
+            let config = Arc::new(AtomicImmut::new(Config {
+                speed: Speed::Bps(1024),
+                condition: Condition::Periodic {
+                    duration: Duration::from_secs(10),
+                    frequency: Duration::from_secs(30),
+                },
+                burst: Some(4096),
+                burst_start_fill: fill,
+                window_burst,
+                ..Default::default()
+            }));
+            let clock = Arc::new(MockClock::new());
+            let mut engine = Engine::with_seed(config, 0);
+            engine.set_clock(clock.clone());
+            engine.init();
+
+            clock.advance(Duration::from_secs(31));
+            engine.on_operated(Operation::Read).unwrap();
+            assert_eq!(State::Unstable, *engine.state());
+            (
+                engine.throttle(Operation::Read, 1024),
+                engine.throttle(Operation::Read, 1024),
+            )
+        };
+
+        // The full bucket lets the burst through for free.
+        assert_eq!(
+            (Duration::default(), Duration::default()),
+            delays(1.0, None)
+        );
+        // The empty bucket throttles from the first operation.
+        assert_eq!(
+            (Duration::from_secs(1), Duration::from_secs(1)),
+            delays(0.0, None)
+        );
+        assert_eq!(
+            (Duration::default(), Duration::from_secs(1)),
+            delays(0.25, None)
+        );
+        // The budget of the window burst passes before the bucket.
+        assert_eq!(
+            (Duration::default(), Duration::from_secs(1)),
+            delays(0.0, Some(WindowBurst::Bytes(1024)))
+        );
+    }
+
+    #[test]
+    fn test_engine_scope() {
+        const THREADS: usize = 4;
+        const OPS: usize = 4;
+
+        // Returns the bytes per second which all threads proceed at in total.
+        let throughput = |scope, burst| {
+            let config = Arc::new(AtomicImmut::new(Config {
+                burst,
+                scope,
+                ..Default::default()
+            }));
+            let clock = Arc::new(MockClock::new());
+            let mut engine = Engine::with_seed(config, 0);
+            engine.set_clock(clock.clone());
+            let engine = Arc::new(Mutex::new(engine));
+
+            let threads = (0..THREADS)
+                .map(|_| {
+                    let engine = Arc::clone(&engine);
+                    thread::spawn(move || {
+                        (0..OPS)
+                            .map(|_| {
+                                let mut engine = engine.lock().unwrap();
+                                engine.throttle_delay(
+                                    Operation::Write,
+                                    1024,
+                                    1024,
+                                    Duration::default(),
+                                )
+                            })
+                            .max()
+                            .unwrap()
+                    })
+                })
+                .collect::<Vec<_>>();
+            let wait = threads
+                .into_iter()
+                .map(|t| t.join().unwrap())
+                .max()
+                .unwrap();
+
+            (THREADS * OPS * 1024) as f64 / wait.as_secs_f64()
+        };
+
+        // The rate is shared (the burst exceeds it a little).
+        let global = throughput(ThrottleScope::Global, Some(1024));
+        assert!((1024.0..1024.0 * 1.2).contains(&global), "{}", global);
+        // The concurrent delays without the burst are reserved in turn.
+        let global = throughput(ThrottleScope::Global, None);
+        assert!((1024.0..1024.0 * 1.2).contains(&global), "{}", global);
+
+        // Each thread proceeds at the rate.
+        for &burst in &[Some(1024), None] {
+            let per_thread = throughput(ThrottleScope::PerThread, burst);
+            assert!((THREADS * 1024) as f64 <= per_thread, "{}", per_thread);
+        }
+    }
+
+    #[test]
+    fn test_engine_reload_per_operation() {
+        const THREADS: usize = 4;
+        const LOOKUPS: usize = 10_000;
+
+        let config_at = |bps| {
+            let mut config = Config {
+                speed: Speed::Bps(bps),
+                operations: vec![Operation::Read, Operation::Write, Operation::Stat],
+                condition: Condition::Always(State::Unstable),
+                ..Default::default()
+            };
+            for op in &config.operations {
+                config.per_operation.insert(op.clone(), Speed::Bps(bps));
+            }
+            config
+        };
+        let config = Arc::new(AtomicImmut::new(config_at(1024)));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let swapper = {
+            let config = Arc::clone(&config);
+            let done = Arc::clone(&done);
+            thread::spawn(move || {
+                let mut swaps = 0;
+                while !done.load(Ordering::SeqCst) {
+                    let bps = if swaps % 2 == 0 { 2048 } else { 1024 };
+                    config.store(config_at(bps));
+                    swaps += 1;
+                }
+                swaps
+            })
+        };
+        let readers = (0..THREADS)
+            .map(|i| {
+                let config = Arc::clone(&config);
+                thread::spawn(move || {
+                    let mut engine = Engine::with_seed(Arc::clone(&config), i as u64);
+                    engine.init();
+                    for _ in 0..LOOKUPS {
+                        let speed = engine.speed(Operation::Write);
+                        assert!(
+                            speed == Some(Speed::Bps(1024)) || speed == Some(Speed::Bps(2048)),
+                            "{:?}",
+                            speed
+                        );
+
+                        // All speeds in a snapshot are of the same config.
+                        let snapshot = config.load();
+                        assert!(snapshot
+                            .per_operation
+                            .values()
+                            .all(|s| *s == snapshot.speed));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        done.store(true, Ordering::SeqCst);
+        assert!(0 < swapper.join().unwrap());
+    }
+
+    #[test]
+    fn test_engine_dry_run() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Always(State::Unstable),
+            error_injection: Some(ErrorInjection {
+                probability: 1.0,
+                operations: vec![Operation::Read],
+                disk_full_after: None,
+                errors: vec![],
+                continuous: false,
+                errors_per_window: None,
+            }),
+            dry_run: true,
+            ..Default::default()
+        }));
+        let mut engine = Engine::with_seed(config, 0);
+        engine.init();
+
+        // A large write returns at once with the real clock.
+        let start = Instant::now();
+        assert_eq!(
+            Duration::default(),
+            engine.throttle(Operation::Write, 100 << 20)
+        );
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        assert_eq!(None, engine.injected_error(Operation::Read, 0));
+        // The short I/O is not applied either.
+        engine.config.update(|c| Config {
+            short_io: Some(ShortIo::Fraction(0.5)),
+            ..c.clone()
+        });
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(4096, engine.io_size(Operation::Read, 4096));
+
+        let stats = engine.stats();
+        assert_eq!(1, stats.ops_would_delay);
+        assert_eq!(0, stats.ops_delayed);
+        assert_eq!(1, stats.errors_would_inject);
+        assert_eq!(0, stats.errors_injected);
+    }
+
+    #[test]
+    fn test_engine_restore_config() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(Arc::clone(&config), 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        let a = engine.snapshot_config();
+        clock.advance(Duration::from_secs(31));
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Read));
+
+        let b = Config {
+            speed: Speed::Bps(4096),
+            condition: Condition::Always(State::Unstable),
+            ..Default::default()
+        };
+        engine.restore_config(b.clone()).unwrap();
+        assert_eq!(State::Unstable, *engine.state());
+        assert_eq!(Some(Speed::Bps(4096)), engine.speed(Operation::Read));
+        assert_eq!(b.speed, engine.snapshot_config().speed);
+        assert_eq!(b.condition, config.load().condition);
+
+        // The invalid one changes nothing.
+        let inverted = Config {
+            speed: Speed::Range {
+                min: 4096,
+                max: 1024,
+            },
+            ..b.clone()
+        };
+        match engine.restore_config(inverted) {
+            Err(ConfigError::ValidationFailed(_)) => {}
+            e => panic!("{:?}", e),
+        }
+        assert_eq!(b, *config.load());
+        assert_eq!(Some(Speed::Bps(4096)), engine.speed(Operation::Read));
+
+        // The window ends at once and the cycle restarts.
+        engine.restore_config(a).unwrap();
+        assert_eq!(State::Stable, *engine.state());
+        assert_eq!(None, engine.speed(Operation::Read));
+
+        clock.advance(Duration::from_secs(30));
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(None, engine.speed(Operation::Read));
+        clock.advance(Duration::from_secs(1));
+        engine.on_operated(Operation::Read).unwrap();
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Read));
+        assert_eq!(2, engine.stats().active_windows);
+    }
+
+    #[test]
+    fn test_engine_would_change() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(Arc::clone(&config), 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        let same = engine.snapshot_config();
+        assert!(!engine.would_change(&same));
+        let mut changed = same.clone();
+        changed.speed = Speed::Bps(2048);
+        assert!(engine.would_change(&changed));
+
+        // The identical config keeps the timer.
+        clock.advance(Duration::from_secs(20));
+        engine.restore_config(same).unwrap();
+        assert_eq!(
+            ThrottleState::Idle {
+                next_activation: Some(Duration::from_secs(10))
+            },
+            engine.current_state()
+        );
+
+        // The changed one restarts the cycle.
+        engine.restore_config(changed).unwrap();
+        assert_eq!(
+            ThrottleState::Idle {
+                next_activation: Some(Duration::from_secs(30))
+            },
+            engine.current_state()
+        );
+        assert_eq!(Speed::Bps(2048), config.load().speed);
+    }
+
+    #[test]
+    fn test_engine_passthrough() {
+        static CONTROLS: Controls = Controls::new();
+        let config = Arc::new(AtomicImmut::new(Config {
+            condition: Condition::Always(State::Unstable),
+            ..Default::default()
+        }));
+        let mut engine = Engine::with_seed(Arc::clone(&config), 0);
+        engine.set_controls(&CONTROLS);
+        engine.init();
+
+        engine.on_operated(Operation::Read).unwrap();
+        assert!(engine.is_passthrough());
+        assert_eq!(None, engine.speed(Operation::Read));
+
+        // The config is changed.
+        config.update(|c| Config {
+            speed: Speed::Bps(1024),
+            ..c.clone()
+        });
+        assert_eq!(Some(Speed::Bps(1024)), engine.speed(Operation::Read));
+        assert!(!engine.is_passthrough());
+
+        // The speed of an operation is enough to throttle.
+        config.update(|c| {
+            let mut c = Config {
+                speed: Speed::PassThrough,
+                ..c.clone()
+            };
+            c.per_operation.insert(Operation::Write, Speed::Bps(1));
+            c
+        });
+        engine.on_operated(Operation::Read).unwrap();
+        assert!(!engine.is_passthrough());
+        assert_eq!(None, engine.speed(Operation::Read));
+        assert_eq!(Some(Speed::Bps(1)), engine.speed(Operation::Write));
+
+        CONTROLS.set_paused(true);
+        engine.on_operated(Operation::Read).unwrap();
+        assert!(engine.is_passthrough());
+        CONTROLS.set_paused(false);
+        engine.on_operated(Operation::Read).unwrap();
+        assert!(!engine.is_passthrough());
+
+        // The state is changed.
+        config.update(|c| Config {
+            condition: Condition::Always(State::Stable),
+            ..c.clone()
+        });
+        engine.on_operated(Operation::Read).unwrap();
+        assert!(engine.is_passthrough());
+    }
+
+    #[test]
+    fn test_engine_profiles() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            condition: Condition::Always(State::Stable),
+            profiles: vec![
+                Profile {
+                    speed: Speed::Bps(1024),
+                    operations: vec![Operation::Read],
+                    duration: Duration::from_secs(10),
+                    frequency: Duration::from_secs(30),
+                },
+                Profile {
+                    speed: Speed::Bps(2048),
+                    operations: vec![Operation::Write],
+                    duration: Duration::from_secs(10),
+                    frequency: Duration::from_secs(60),
+                },
+            ],
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        let mut speeds_at = |secs: u64| {
+            clock.advance(Duration::from_secs(secs));
+            engine.on_operated(Operation::Read).unwrap();
+            (
+                engine.speed(Operation::Read),
+                engine.speed(Operation::Write),
+            )
+        };
+        // Read is unstable in 30s-40s and 70s-80s, and write is unstable in 60s-70s.
+        assert_eq!((None, None), speeds_at(0));
+        assert_eq!((Some(Speed::Bps(1024)), None), speeds_at(31));
+        assert_eq!((None, None), speeds_at(10));
+        assert_eq!((None, Some(Speed::Bps(2048))), speeds_at(20));
+        assert_eq!((Some(Speed::Bps(1024)), None), speeds_at(10));
+        assert_eq!((None, None), speeds_at(10));
+
+        // The global state is kept stable.
+        assert_eq!(State::Stable, *engine.state());
+    }
+
+    #[test]
+    fn test_throttle_state_display() {
+        let active = ThrottleState::Active {
+            operations: vec![Operation::Read, Operation::Write],
+            speed: Speed::Bps(1 << 20),
+            multiplier: 1.0,
+            remaining: Some(Duration::from_secs(9 * 60)),
+        };
+        assert_eq!(
+            "Active: throttling Read,Write at 1MBps for 9m remaining",
+            active.to_string()
+        );
+
+        let active = ThrottleState::Active {
+            operations: vec![Operation::Stat],
+            speed: Speed::Iops(10),
+            multiplier: 1.0,
+            remaining: None,
+        };
+        assert_eq!("Active: throttling Stat at 10iops", active.to_string());
+
+        let active = ThrottleState::Active {
+            operations: vec![Operation::Write],
+            speed: Speed::Bps(1 << 20),
+            multiplier: 0.5,
+            remaining: None,
+        };
+        assert_eq!("Active: throttling Write at 1MBps x0.5", active.to_string());
+
+        let idle = ThrottleState::Idle {
+            next_activation: None,
+        };
+        assert_eq!("Idle", idle.to_string());
+    }
+
+    #[test]
+    fn test_engine_empty_operations() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            operations: vec![],
+            condition: Condition::Always(State::Unstable),
+            burst: Some(1024),
+            ..Default::default()
+        }));
+        assert!(config.load().validate().is_ok());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.init();
+
+        for op in [Operation::Read, Operation::Write].iter().cloned() {
+            engine.on_operated(op.clone()).unwrap();
+            assert!(engine.is_passthrough());
+            assert_eq!(None, engine.speed(op.clone()));
+            assert_eq!(Duration::default(), engine.throttle(op.clone(), 1 << 20));
+            assert_eq!(4096, engine.io_size(op.clone(), 4096));
+            assert_eq!(None, engine.injected_error(op, 4096));
+        }
+        let stats = engine.stats();
+        assert_eq!(
+            0,
+            stats.bytes_read_throttled + stats.bytes_written_throttled
+        );
+        assert_eq!(0, stats.ops_delayed);
+    }
+
+    #[test]
+    fn test_engine_event_log_sample_rate() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Latency(Duration::from_millis(1)),
+            condition: Condition::Always(State::Unstable),
+            ..Default::default()
+        }));
+        let throttle_events = |sample_rate| {
+            let buffer = Arc::new(Mutex::new(Vec::new()));
+            let mut log = EventLog::new(SharedBuffer(Arc::clone(&buffer)));
+            log.set_sample_rate(sample_rate);
+
+            let mut engine = Engine::with_seed(Arc::clone(&config), 0);
+            engine.set_clock(Arc::new(MockClock::new()));
+            engine.set_event_log(log);
+            engine.set_log_level(LogLevel::Operations);
+            engine.init();
+            for _ in 0..100 {
+                assert!(engine.delay(&Operation::Read, 0, Duration::from_millis(1)));
+            }
+            // The counters reflect all operations regardless of the sampling.
+            assert_eq!(100, engine.stats().ops_delayed);
+
+            let buffer = buffer.lock().unwrap();
+            String::from_utf8_lossy(&buffer)
+                .lines()
+                .map(|l| serde_json::from_str::<Event>(l).unwrap())
+                .filter(|e| e.event == EventKind::Throttle)
+                .collect::<Vec<_>>()
+        };
+
+        let events = throttle_events(Some(10));
+        assert_eq!(10, events.len());
+        for e in &events {
+            assert_eq!(vec![Operation::Read], e.operations);
+            assert_eq!(Some(1), e.duration_ms);
+        }
+        assert_eq!(100, throttle_events(Some(1)).len());
+        assert_eq!(0, throttle_events(Some(0)).len());
+        assert_eq!(0, throttle_events(None).len());
+    }
+
+    #[test]
+    fn test_engine_hysteresis() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(1),
+                frequency: Duration::from_secs(2),
+            },
+            min_active: Duration::from_secs(10),
+            min_idle: Duration::from_secs(5),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        // The periods are stretched to 5s stable and then 10s unstable.
+        assert_eq!(
+            ThrottleState::Idle {
+                next_activation: Some(Duration::from_secs(5))
+            },
+            engine.current_state()
+        );
+        let mut active = Vec::new();
+        for t in 1..=30 {
+            clock.advance(Duration::from_secs(1));
+            engine.on_operated(Operation::Read).unwrap();
+            if let ThrottleState::Active { remaining, .. } = engine.current_state() {
+                if t == 6 {
+                    assert_eq!(Some(Duration::from_secs(9)), remaining);
+                }
+                active.push(t);
+            }
+        }
+        assert_eq!((6..=15).chain(21..=30).collect::<Vec<_>>(), active);
+    }
+
+    #[test]
+    fn test_engine_operation_weights() {
+        let mut config = Config {
+            speed: Speed::Bps(1024),
+            operations: vec![Operation::Read, Operation::Write, Operation::Stat],
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            ..Default::default()
+        };
+        config.operation_weights.insert(Operation::Read, 1);
+        config.operation_weights.insert(Operation::Write, 3);
+        assert!(config.validate().is_ok());
+
+        // Returns the throttled operations of each unstable period.
+        let windows = |config: &Config, seed| {
+            let clock = Arc::new(MockClock::new());
+            let mut engine = Engine::with_seed(Arc::new(AtomicImmut::new(config.clone())), seed);
+            engine.set_clock(clock.clone());
+            engine.init();
+
+            // Every period is checked at 5 seconds after it begins.
+            clock.advance(Duration::from_secs(35));
+            (0..8)
+                .map(|_| {
+                    engine.on_operated(Operation::Read).unwrap();
+                    let ops = config
+                        .operations
+                        .iter()
+                        .filter(|op| engine.speed((*op).clone()).is_some())
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    assert_eq!(
+                        ThrottleState::Active {
+                            operations: ops.clone(),
+                            speed: Speed::Bps(1024),
+                            multiplier: 1.0,
+                            remaining: Some(Duration::from_secs(5)),
+                        },
+                        engine.current_state()
+                    );
+                    clock.advance(Duration::from_secs(6));
+                    engine.on_operated(Operation::Read).unwrap();
+                    clock.advance(Duration::from_secs(34));
+                    ops
+                })
+                .collect::<Vec<_>>()
+        };
+
+        use self::Operation::{Read, Stat, Write};
+        // The unweighted operation is always throttled and each weighted one is chosen independently,
+        // so both of them or neither can be throttled together.
+        let expected = vec![
+            vec![Stat],
+            vec![Write, Stat],
+            vec![Write, Stat],
+            vec![Read, Write, Stat],
+            vec![Read, Stat],
+            vec![Write, Stat],
+            vec![Write, Stat],
+            vec![Write, Stat],
+        ];
+        assert_eq!(expected, windows(&config, 1));
+        assert!(expected.iter().any(|ops| ops.len() == 3));
+        assert_eq!(windows(&config, 2), windows(&config, 2));
+
+        // Without the weights, all operations are throttled.
+        config.operation_weights.clear();
+        assert!(windows(&config, 0)
+            .iter()
+            .all(|ops| *ops == config.operations));
+    }
+
+    #[test]
+    fn test_engine_warmup() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            warmup: Duration::from_secs(60),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(Arc::clone(&config), 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+        assert_eq!(
+            ThrottleState::Idle {
+                next_activation: Some(Duration::from_secs(90))
+            },
+            engine.current_state()
+        );
+
+        // The first period begins at `warmup + frequency` and the cycle proceeds as usual after it.
+        let mut active = Vec::new();
+        for t in 1..=150 {
+            clock.advance(Duration::from_secs(1));
+            engine.on_operated(Operation::Read).unwrap();
+            if engine.speed(Operation::Read).is_some() {
+                active.push(t);
+            }
+        }
+        assert_eq!((91..=100).chain(131..=140).collect::<Vec<_>>(), active);
+
+        // A restart of the cycle does not warm up again.
+        let mut c = engine.snapshot_config();
+        c.speed = Speed::Bps(2048);
+        engine.restore_config(c).unwrap();
+        assert_eq!(
+            ThrottleState::Idle {
+                next_activation: Some(Duration::from_secs(30))
+            },
+            engine.current_state()
+        );
+    }
+
+    #[test]
+    fn test_engine_sub_second_period() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Periodic {
+                duration: Duration::from_millis(500),
+                frequency: Duration::from_secs(2),
+            },
+            ..Default::default()
+        }));
+        assert!(config.load().validate().is_ok());
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(config, 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        // The unstable period is in 2.0s-2.5s.
+        let mut active = Vec::new();
+        for i in 1..=100 {
+            clock.advance(Duration::from_millis(30));
+            engine.on_operated(Operation::Read).unwrap();
+            if let ThrottleState::Active { remaining, .. } = engine.current_state() {
+                let remaining = remaining.unwrap();
+                assert!(Duration::default() < remaining, "{}", i * 30);
+                assert!(!engine
+                    .current_state()
+                    .to_string()
+                    .ends_with(" 0s remaining"));
+                active.push(i * 30);
+            }
+        }
+        assert_eq!((2010..2500).step_by(30).collect::<Vec<_>>(), active);
+        assert_eq!(
+            "Active: throttling Read,Write at 1KBps for 1ms remaining",
+            ThrottleState::Active {
+                operations: vec![Operation::Read, Operation::Write],
+                speed: Speed::Bps(1024),
+                multiplier: 1.0,
+                remaining: Some(Duration::from_micros(100)),
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn test_engine_upcoming_activations() {
+        let config = Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            frequency_jitter: Duration::from_secs(20),
+            probability: Some(0.7),
+            warmup: Duration::from_secs(60),
+            ..Default::default()
+        }));
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(Arc::clone(&config), 7);
+        engine.set_clock(clock.clone());
+        engine.init();
+
+        let projected = engine.upcoming_activations(5);
+        assert_eq!(5, projected.len());
+        // The projection does not advance the state.
+        assert_eq!(projected, engine.upcoming_activations(5));
+        assert_eq!(projected[..3], engine.upcoming_activations(3)[..]);
+        assert!(clock.now() + Duration::from_secs(60) < projected[0]);
+
+        // The activations realized by the operations every 100ms.
+        let mut realized = Vec::new();
+        let mut from_unstable = Vec::new();
+        while realized.len() < projected.len() {
+            clock.advance(Duration::from_millis(100));
+            let now = clock.now();
+            engine.on_operated(Operation::Read).unwrap();
+            let begin = now - engine.state_manager.elapsed_at(now);
+            if engine.speed(Operation::Read).is_some() && realized.last() != Some(&begin) {
+                realized.push(begin);
+                if realized.len() == 1 {
+                    // The projection from the beginning of an unstable period.
+                    from_unstable = engine.upcoming_activations(4);
+                }
+            }
+        }
+        assert_eq!(projected, realized);
+        assert_eq!(projected[1..], from_unstable[..]);
+
+        // No more activations.
+        config.update(|c| Config {
+            max_activations: Some(realized.len() as u32),
+            ..c.clone()
+        });
+        engine.on_operated(Operation::Read).unwrap();
+        assert!(engine.upcoming_activations(3).is_empty());
+        config.update(|c| Config {
+            max_activations: None,
+            ..c.clone()
+        });
+        engine.on_operated(Operation::Read).unwrap();
+        assert!(!engine.upcoming_activations(3).is_empty());
+        engine.shutdown();
+        assert!(engine.upcoming_activations(3).is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+use config::{Operation, Speed};
+use serde_json;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use time;
+
+/// `Event` is emitted when an unstable period starts or ends, and when an operation is delayed if sampled.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub event: EventKind,
+    /// RFC 3339 in UTC.
+    pub timestamp: String,
+    pub speed: Speed,
+    pub operations: Vec<Operation>,
+    /// The configured duration of the period for `EventKind::Start` (it is `None` for `Condition::Always`),
+    /// the actual duration of the period for `EventKind::End`, or the delay of the operation for
+    /// `EventKind::Throttle`.
+    pub duration_ms: Option<u64>,
+    /// The bytes throttled in the period over its duration for `EventKind::End` (see `stats::WindowThroughput`),
+    /// which is omitted for the other events.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub achieved_bps: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Start,
+    End,
+    Throttle,
+}
+
+/// Formats the kind as same as the serialized one (e.g., "start").
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EventKind::Start => write!(f, "start"),
+            EventKind::End => write!(f, "end"),
+            EventKind::Throttle => write!(f, "throttle"),
+        }
+    }
+}
+
+/// `LogLevel` decides which events `Engine` writes to its `EventLog`, regardless of the level of the logger.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    /// No events are written.
+    Off,
+    /// Only the starts and the ends of the unstable periods are written.
+    #[default]
+    Windows,
+    /// The events of the delayed operations are written too (one in `EventLog::set_sample_rate`).
+    Operations,
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    /// Parses "off", "windows" or "operations" as `LogLevel`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" | "Off" => Ok(LogLevel::Off),
+            "windows" | "Windows" => Ok(LogLevel::Windows),
+            "operations" | "Operations" => Ok(LogLevel::Operations),
+            _ => Err(format!(
+                "log level accepts off, windows or operations: {}",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LogLevel::Off => write!(f, "off"),
+            LogLevel::Windows => write!(f, "windows"),
+            LogLevel::Operations => write!(f, "operations"),
+        }
+    }
+}
+
+/// `EventLog` writes the events as JSON lines.
+/// The events of the operations (i.e., `EventKind::Throttle`) are written one in `sample_rate` not to flood the log.
+pub struct EventLog {
+    writer: Mutex<Box<dyn Write + Send>>,
+    sample_rate: Option<u32>,
+    // The number of the sampled events so far.
+    sampled: AtomicU64,
+}
+
+impl EventLog {
+    pub fn new<W: Write + Send + 'static>(writer: W) -> EventLog {
+        EventLog {
+            writer: Mutex::new(Box::new(writer)),
+            sample_rate: None,
+            sampled: AtomicU64::new(0),
+        }
+    }
+
+    /// Sets the rate to write the events of the operations (e.g., 1 writes all and 10 writes one in ten).
+    /// `None` or zero (the default) writes none of them, but the start/end events are written anyway.
+    /// `Engine` emits the events of the operations only at `LogLevel::Operations`.
+    pub fn set_sample_rate(&mut self, sample_rate: Option<u32>) {
+        self.sample_rate = sample_rate;
+    }
+
+    pub fn stderr() -> EventLog {
+        EventLog::new(io::stderr())
+    }
+
+    pub fn emit(&self, event: &Event) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        serde_json::to_writer(&mut *writer, event)?;
+        writer.write_all(b"\n")?;
+        writer.flush()
+    }
+
+    /// Writes the event if it is sampled by the sample rate and returns true if written.
+    pub fn emit_sampled(&self, event: &Event) -> io::Result<bool> {
+        let rate = match self.sample_rate {
+            Some(rate) if 0 < rate => u64::from(rate),
+            _ => return Ok(false),
+        };
+        if !self
+            .sampled
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(rate)
+        {
+            return Ok(false);
+        }
+
+        self.emit(event).map(|_| true)
+    }
+}
+
+impl Default for EventLog {
+    fn default() -> EventLog {
+        EventLog::stderr()
+    }
+}
+
+/// Returns the current time in RFC 3339 for `Event::timestamp`.
+pub fn now_rfc3339() -> String {
+    time::now_utc().rfc3339().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_level_from_str() {
+        assert_eq!(Ok(LogLevel::Off), LogLevel::from_str("off"));
+        assert_eq!(Ok(LogLevel::Operations), LogLevel::from_str("Operations"));
+        assert!(LogLevel::from_str("").is_err());
+        assert!(LogLevel::from_str("ops").is_err());
+
+        for s in ["off", "windows", "operations"].iter() {
+            assert_eq!(*s, LogLevel::from_str(s).unwrap().to_string());
+        }
+        assert_eq!(LogLevel::Windows, LogLevel::default());
+    }
+}
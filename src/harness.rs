@@ -0,0 +1,165 @@
+//! An in-memory file system which routes the reads and the writes through `Engine` in the order of `Mizumochi`,
+//! so that the tests can assert the throttling, the error injection and the schedule without mounting anything.
+//!
+//! It has no file handles, so `Config::passthrough_first_op`, `Config::read_coalesce`,
+//! `Config::seek_penalty` and `Config::allocation_penalty` are not modeled.
+//!
+//! It is enabled by the `test-harness` feature.
+use clock::Clock;
+use config::Operation;
+use engine::Engine;
+use libc::{self, c_int};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// `IoResult` is the result of an operation of `Harness` and the time which it took by the clock of `Engine`
+/// (i.e., the delays of the throttling).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IoResult<T> {
+    pub result: Result<T, c_int>,
+    pub elapsed: Duration,
+}
+
+/// `Harness` is the files in memory and the engine to throttle the operations on them.
+///
+/// # Examples
+/// ```
+/// extern crate atomic_immut;
+/// extern crate mizumochi;
+///
+/// use atomic_immut::AtomicImmut;
+/// use mizumochi::clock::MockClock;
+/// use mizumochi::config::{Condition, Config, Speed};
+/// use mizumochi::engine::Engine;
+/// use mizumochi::harness::Harness;
+/// use mizumochi::state::State;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// # fn main() {
+/// let config = Config {
+///     speed: Speed::Bps(1024),
+///     condition: Condition::Always(State::Unstable),
+///     ..Default::default()
+/// };
+/// let mut engine = Engine::with_seed(Arc::new(AtomicImmut::new(config)), 0);
+/// engine.set_clock(Arc::new(MockClock::new()));
+/// engine.init();
+///
+/// let mut harness = Harness::new(engine);
+/// assert_eq!(Ok(2048), harness.write("a.txt", &[0; 2048]).result);
+/// let read = harness.read("a.txt", 1024);
+/// assert_eq!(Ok(vec![0; 1024]), read.result);
+/// assert_eq!(Duration::from_secs(1), read.elapsed);
+/// # }
+/// ```
+pub struct Harness {
+    engine: Engine,
+    files: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl Harness {
+    /// Creates the harness without files.
+    /// The engine is expected to be initialized (and to have `MockClock` for the deterministic timings).
+    pub fn new(engine: Engine) -> Harness {
+        Harness {
+            engine,
+            files: HashMap::new(),
+        }
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    pub fn engine_mut(&mut self) -> &mut Engine {
+        &mut self.engine
+    }
+
+    /// Returns the content of the file if exists without any operation.
+    pub fn content<P: AsRef<Path>>(&self, path: P) -> Option<&[u8]> {
+        self.files.get(path.as_ref()).map(|f| &f[..])
+    }
+
+    /// Reads `len` bytes (or less at the end) from the beginning of the file.
+    /// It fails with ENOENT if the file does not exist.
+    pub fn read<P: AsRef<Path>>(&mut self, path: P, len: usize) -> IoResult<Vec<u8>> {
+        let path = path.as_ref();
+        self.operate(Operation::Read, path, len, 0, |files, len| {
+            let f = files.get(path).ok_or(libc::ENOENT)?;
+            let data = f[..len.min(f.len())].to_vec();
+            let n = data.len();
+            Ok((data, n))
+        })
+    }
+
+    /// Appends `buf` to the file, which is created if it does not exist, and returns the written bytes.
+    pub fn write<P: AsRef<Path>>(&mut self, path: P, buf: &[u8]) -> IoResult<usize> {
+        let path = path.as_ref();
+        self.operate(
+            Operation::Write,
+            path,
+            buf.len(),
+            buf.len(),
+            |files, len| {
+                files
+                    .entry(path.to_path_buf())
+                    .or_default()
+                    .extend_from_slice(&buf[..len]);
+                Ok((len, len))
+            },
+        )
+    }
+
+    /// Services the operation of `size` bytes on the file by `Engine::begin_operation`: the latency (or the IOPS limit),
+    /// the short read/write and the error injection, the operation itself by `f` with the actual size,
+    /// and then the byte rate for the bytes which `f` transferred. `written` is the bytes to count for ENOSPC.
+    fn operate<T, F>(
+        &mut self,
+        op: Operation,
+        path: &Path,
+        size: usize,
+        written: usize,
+        f: F,
+    ) -> IoResult<T>
+    where
+        F: FnOnce(&mut HashMap<PathBuf, Vec<u8>>, usize) -> Result<(T, usize), c_int>,
+    {
+        let clock = Arc::clone(self.engine.clock());
+        let start = clock.now();
+        let elapsed = |clock: &Arc<dyn Clock>| clock.now().saturating_duration_since(start);
+
+        // The errors of the state are only logged by `Mizumochi`.
+        let _ = self.engine.on_operated(op.clone());
+        let file_size = self.files.get(path).map_or(0, |f| f.len() as u64);
+        let target = self.engine.is_target_file(path, || file_size);
+
+        let size = if target {
+            match self.engine.begin_operation(op.clone(), size, written) {
+                Ok(size) => size,
+                Err(code) => {
+                    return IoResult {
+                        result: Err(code),
+                        elapsed: elapsed(&clock),
+                    }
+                }
+            }
+        } else {
+            size
+        };
+
+        let result = f(&mut self.files, size).map(|(value, transferred)| {
+            if target {
+                self.engine.throttle(op, transferred);
+            }
+            value
+        });
+
+        IoResult {
+            result,
+            elapsed: elapsed(&clock),
+        }
+    }
+}
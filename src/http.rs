@@ -1,13 +1,16 @@
 use atomic_immut::AtomicImmut;
-use bytecodec::json_codec::{JsonDecoder, JsonEncoder};
+use bytecodec::bytes::Utf8Decoder;
+use bytecodec::json_codec::JsonEncoder;
 use bytecodec::null::{NullDecoder, NullEncoder};
 use config::Config;
 use fibers::{Executor, InPlaceExecutor, Spawn};
+#[cfg(feature = "metrics")]
 use fibers_http_server::metrics::MetricsHandler;
 use fibers_http_server::{HandleRequest, Reply, Req, Res, ServerBuilder, Status};
 use futures::future::ok;
 use futures::Future;
 use httpcodec::{BodyDecoder, BodyEncoder};
+#[cfg(feature = "metrics")]
 use prometrics;
 use slog::Logger;
 use std;
@@ -26,8 +29,12 @@ pub fn start_server(
     builder.add_handler(PutConfigHandler { logger, config })?;
 
     // Enables process metrics and registers a HTTP endpoint for exporting metrics
-    prometrics::default_registry().register(prometrics::metrics::ProcessMetricsCollector::new());
-    builder.add_handler(MetricsHandler)?;
+    #[cfg(feature = "metrics")]
+    {
+        prometrics::default_registry()
+            .register(prometrics::metrics::ProcessMetricsCollector::new());
+        builder.add_handler(MetricsHandler)?;
+    }
 
     // Starts HTTP server
     let http_server = builder.finish(executor.handle());
@@ -65,17 +72,175 @@ impl HandleRequest for PutConfigHandler {
     const METHOD: &'static str = "PUT";
     const PATH: &'static str = "/config";
 
-    type ReqBody = Config;
+    // The body is decoded by `Config::from_json_str` to log the unknown operations which it skips.
+    type ReqBody = String;
     type ResBody = ();
-    type Decoder = BodyDecoder<JsonDecoder<Config>>;
+    type Decoder = BodyDecoder<Utf8Decoder>;
     type Encoder = BodyEncoder<NullEncoder>;
     type Reply = Reply<Self::ResBody>;
 
     fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
-        let config = req.into_body();
-        self.config.store(config.clone());
-        info!(self.logger, "new config: {:?}", config);
+        let config = match Config::from_json_str(req.body(), &self.logger) {
+            Ok(config) => config,
+            Err(error) => {
+                warn!(self.logger, "rejected config: {}", error);
+                return Box::new(ok(Res::new(Status::BadRequest, ())));
+            }
+        };
+
+        // Keep the live config (and the cycles which compare it) if nothing is changed.
+        if *self.config.load() == config {
+            info!(self.logger, "config unchanged");
+        } else {
+            self.config.store(config.clone());
+            info!(self.logger, "new config: {:?}", config);
+        }
 
         Box::new(ok(Res::new(Status::Ok, ())))
     }
 }
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::*;
+    use clock::MockClock;
+    use config::{Condition, ErrorInjection, Operation, Speed};
+    use engine::Engine;
+    use metrics::Metrics;
+    use slog::Discard;
+    use state::State;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::{Duration, Instant};
+
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    fn request(port: u16, method: &str, path: &str, body: &str) -> String {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            if let Ok(mut stream) = TcpStream::connect(("127.0.0.1", port)) {
+                stream
+                    .set_read_timeout(Some(Duration::from_millis(500)))
+                    .unwrap();
+                write!(
+                    stream,
+                    "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+                    method,
+                    path,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+
+                // Read until the connection is closed or idle.
+                let mut res = Vec::new();
+                let mut buf = [0; 4096];
+                while let Ok(n) = stream.read(&mut buf) {
+                    if n == 0 {
+                        break;
+                    }
+                    res.extend_from_slice(&buf[..n]);
+                }
+                return String::from_utf8_lossy(&res).into_owned();
+            }
+
+            assert!(Instant::now() < deadline, "the server is not ready");
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn test_put_config_unknown_operation() {
+        use slog::{Drain, Never, OwnedKVList, Record};
+        use std::sync::Mutex;
+
+        struct Collect(Arc<Mutex<Vec<String>>>);
+        impl Drain for Collect {
+            type Ok = ();
+            type Err = Never;
+            fn log(&self, record: &Record, _: &OwnedKVList) -> Result<(), Never> {
+                self.0.lock().unwrap().push(record.msg().to_string());
+                Ok(())
+            }
+        }
+
+        let port = free_port();
+        let config = Arc::new(AtomicImmut::new(Config::default()));
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let logger = Logger::root(Collect(Arc::clone(&messages)), o!());
+        start_server(logger, port, Arc::clone(&config)).unwrap();
+
+        let json = r#"{"speed":"1KBps","operations":["Write","Rename"],"condition":{"Always":"Unstable"}}"#;
+        let res = request(port, "PUT", "/config", json);
+        assert!(res.starts_with("HTTP/1.1 200"), "{}", res);
+        assert_eq!(vec![Operation::Write], config.load().operations);
+        assert!(
+            messages
+                .lock()
+                .unwrap()
+                .contains(&"skipped the unknown operation: Rename".to_string()),
+            "{:?}",
+            messages
+        );
+
+        // The invalid config is rejected and the live one is kept.
+        let json = r#"{"speed":"0Bps","operations":["Read"],"condition":{"Always":"Unstable"}}"#;
+        let res = request(port, "PUT", "/config", json);
+        assert!(res.starts_with("HTTP/1.1 400"), "{}", res);
+        assert_eq!(vec![Operation::Write], config.load().operations);
+    }
+
+    #[test]
+    fn test_metrics_endpoint() {
+        let port = free_port();
+        let config = Arc::new(AtomicImmut::new(Config::default()));
+        start_server(Logger::root(Discard, o!()), port, config).unwrap();
+
+        // Run the throttled operations through an engine, of which the counters are exported.
+        let mut engine = Engine::new(Arc::new(AtomicImmut::new(Config {
+            speed: Speed::Bps(1 << 30),
+            condition: Condition::Always(State::Unstable),
+            error_injection: Some(ErrorInjection {
+                probability: 1.0,
+                operations: vec![Operation::Write],
+                disk_full_after: None,
+                errors: vec![],
+                continuous: false,
+                errors_per_window: None,
+            }),
+            ..Default::default()
+        })));
+        engine.set_clock(Arc::new(MockClock::new()));
+        engine.init();
+        let _metrics = Metrics::new(engine.stats_counters());
+        for _ in 0..3 {
+            engine.throttle(Operation::Read, 1 << 20);
+        }
+        engine.throttle(Operation::Write, 1 << 20);
+        assert!(engine.injected_error(Operation::Write, 0).is_some());
+
+        // The other tests may export their counters in parallel, so the lines of this engine are looked for.
+        let res = request(port, "GET", "/metrics", "");
+        assert!(res.starts_with("HTTP/1.1 200"), "{}", res);
+        for line in &[
+            format!("mizumochi_throttled_bytes_total {}", 4 << 20),
+            "mizumochi_delayed_operations_total{operation=\"read\"} 3".to_string(),
+            "mizumochi_delayed_operations_total{operation=\"write\"} 1".to_string(),
+            "mizumochi_delayed_operations_total{operation=\"stat\"} 0".to_string(),
+            "mizumochi_injected_errors_total 1".to_string(),
+        ] {
+            assert!(res.lines().any(|l| l == line), "{} is not in {}", line, res);
+        }
+
+        // It is not exported after the engine is dropped.
+        drop(engine);
+        let res = request(port, "GET", "/metrics", "");
+        assert!(!res.contains(&format!("mizumochi_throttled_bytes_total {}", 4 << 20)));
+    }
+}
@@ -0,0 +1,49 @@
+extern crate atomic_immut;
+extern crate bytecodec;
+#[cfg(feature = "cli")]
+extern crate clap;
+extern crate fibers;
+extern crate fibers_http_server;
+extern crate fuse;
+extern crate futures;
+extern crate glob;
+extern crate httpcodec;
+extern crate libc;
+extern crate prometrics;
+extern crate rand;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate time;
+#[cfg(feature = "async")]
+extern crate tokio;
+extern crate toml;
+#[macro_use]
+extern crate slog;
+
+pub mod benchmark;
+pub mod calibrate;
+#[cfg(feature = "cli")]
+pub mod cli;
+pub mod clock;
+pub mod config;
+#[cfg(unix)]
+pub mod control;
+pub mod engine;
+pub mod event;
+#[cfg(feature = "test-harness")]
+pub mod harness;
+pub mod http;
+mod localfile;
+mod metrics;
+pub mod mizumochi;
+pub mod shutdown;
+pub mod signal;
+pub mod state;
+pub mod stats;
+pub mod subscription;
+pub mod throttle;
+pub mod throttled;
+
+pub use mizumochi::Mizumochi;
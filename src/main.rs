@@ -1,47 +1,101 @@
 extern crate atomic_immut;
-extern crate bytecodec;
 #[macro_use]
 extern crate clap;
-extern crate fibers;
-extern crate fibers_http_server;
-extern crate fuse;
-extern crate futures;
-extern crate httpcodec;
-extern crate libc;
-extern crate prometrics;
-extern crate serde;
-#[macro_use]
-extern crate serde_derive;
-extern crate time;
+extern crate mizumochi;
 #[macro_use]
 extern crate slog;
 extern crate slog_async;
 extern crate slog_term;
 
-mod config;
-mod http;
-mod localfile;
-mod metrics;
-mod mizumochi;
-mod state;
-
 use atomic_immut::AtomicImmut;
-use clap::{Arg, SubCommand};
-use config::*;
-use mizumochi::Mizumochi;
+use clap::{AppSettings, Arg, ErrorKind, SubCommand};
+use mizumochi::config::*;
+use mizumochi::event::{EventLog, LogLevel};
+use mizumochi::{benchmark, calibrate, cli, control, http, signal, Mizumochi};
 use slog::{Drain, Level};
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::process;
 use std::sync::Arc;
-use std::time::Duration;
 
 fn main() -> Result<(), Box<std::error::Error>> {
     let matches = app_from_crate!()
+        // The directories are checked below because `validate` does not need them.
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .arg(
+            Arg::with_name("CONFIG")
+                .short("c")
+                .long("config")
+                .value_name("FILE")
+                .help("Loads config from the TOML file (the other options override it)")
+                .long_help("Loads config from the TOML file (the other options override it)\nMIZUMOCHI_SPEED, MIZUMOCHI_OPERATIONS, MIZUMOCHI_DURATION, MIZUMOCHI_FREQUENCY and MIZUMOCHI_TARGET override the file too")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("SPEED")
                 .short("s")
                 .long("speed")
                 .value_name("BytePerSecond")
                 .help("Sets byte per second to limit file operations")
-                .long_help("you can put suffixes (KBps, MBps, GBps) at the tail (examples: 1024Bps, 4096KBps, 5Mbps)\nthe default is Bps")
+                .long_help("you can put suffixes (KBps, MBps, GBps, TBps, PBps) at the tail (examples: 1024Bps, 4096KBps, 5MBps)\nthe aliases (e.g., 1KiBps, 1kB/s, 5MiB/s) are accepted too\na range (e.g., 1MBps..5MBps) picks a random speed in it on each activation\nlatency:<duration> (e.g., latency:50ms) delays each operation by the fixed duration instead\nlatency:<min>..<max> (e.g., latency:10ms..200ms) or latency:exp:<mean> (e.g., latency:exp:50ms) draws the delay of each operation randomly\n<speed>+<duration> (e.g., 1MBps+50ms) delays each operation by the duration and limits the speed too\n<n>iops (e.g., 100iops) limits the number of operations per second regardless of their sizes\nthe lowercase suffixes (bps, Kbps, Mbps, Gbps) mean bit per second\nthe rates which overflow are clamped with a warning\nthe default is Bps")
+                .takes_value(true)
+                .validator(cli::validate_speed),
+        )
+        .arg(cli::operations_arg("OPERATION"))
+        .arg(
+            Arg::with_name("SI")
+                .long("si")
+                .help("Interprets the speed suffixes in SI units (1KBps = 1000Bps) instead of binary units (1KBps = 1024Bps)"),
+        )
+        .arg(
+            Arg::with_name("DRY_RUN")
+                .long("dry-run")
+                .help("Logs the delays and the errors which would be applied without applying them"),
+        )
+        .arg(
+            Arg::with_name("ACTIVATE_NOW")
+                .long("activate-now")
+                .help("Begins the first unstable period at startup without waiting (then continues the cycle)"),
+        )
+        .arg(
+            Arg::with_name("PRINT_CONFIG_JSON")
+                .long("print-config-json")
+                .help("Prints the effective config as a JSON object in a line to stdout at startup"),
+        )
+        .arg(
+            Arg::with_name("PRINT_SUMMARY_JSON")
+                .long("print-summary-json")
+                .help("Prints the summary of the run as a JSON object in a line to stdout at the unmount"),
+        )
+        .arg(
+            Arg::with_name("PRINT_DEFAULT_CONFIG")
+                .long("print-default-config")
+                .help("Prints the default config as an annotated TOML file to stdout and exits"),
+        )
+        .arg(
+            Arg::with_name("BENCHMARK")
+                .long("benchmark")
+                .help("Measures the overhead of mizumochi per operation in PassThrough, prints it to stdout and exits"),
+        )
+        .arg(
+            Arg::with_name("SEED")
+                .long("seed")
+                .value_name("SEED")
+                .help("Seeds the random decisions to make them reproducible")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ACTIVE_WINDOW")
+                .long("active-window")
+                .value_name("HH:MM-HH:MM")
+                .help("Limits the time of day when the operations can be throttled (e.g., 09:00-17:00 or 22:00-02:00)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("RUN_FOR")
+                .long("run-for")
+                .value_name("DURATION")
+                .help("Stops throttling and unmounts to exit after the duration (e.g., 2h)")
                 .takes_value(true),
         )
         .arg(
@@ -52,16 +106,46 @@ fn main() -> Result<(), Box<std::error::Error>> {
                 .takes_value(true)
                 .default_value("33133"),
         )
+        .arg(
+            Arg::with_name("CONTROL_SOCKET")
+                .long("control-socket")
+                .value_name("PATH")
+                .help("Listens on the Unix domain socket to change the config at runtime (e.g., `set speed 512KBps`)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("EVENT_LOG")
+                .long("event-log")
+                .value_name("FILE")
+                .help("Appends a JSON line to the file whenever an unstable period starts or ends (`-` means stderr)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("EVENT_LOG_SAMPLE_RATE")
+                .long("event-log-sample-rate")
+                .value_name("N")
+                .help("Appends a JSON line to the event log for one in N delayed operations (0 means none)")
+                .takes_value(true)
+                .requires("EVENT_LOG"),
+        )
+        .arg(
+            Arg::with_name("EVENT_LOG_LEVEL")
+                .long("event-log-level")
+                .value_name("LEVEL")
+                .help("Selects the events to append to the event log (operations by --event-log-sample-rate, windows otherwise)")
+                .possible_values(&["off", "windows", "operations"])
+                .takes_value(true)
+                .requires("EVENT_LOG"),
+        )
         .arg(
             Arg::with_name("ORIGINAL_DIR")
                 .help("Sets a directory has original files")
-                .required(true)
+                .required_unless_one(&["PRINT_DEFAULT_CONFIG", "BENCHMARK"])
                 .index(1),
         )
         .arg(
             Arg::with_name("MOUNTPOINT")
-                .help("Mountpoint directory")
-                .required(true)
+                .help("Mountpoint directory (defaults to the target of the config)")
                 .index(2),
         )
          .subcommand(
@@ -83,48 +167,143 @@ fn main() -> Result<(), Box<std::error::Error>> {
                          .takes_value(true)
                          .default_value("60m")
                          .required(true)
-                         .help("Sets frequency of making operations unstable"),
+                         .help("Sets frequency of making operations unstable (a duration, or a rate such as 2/h or 4/d)"),
+                 )
+                 .arg(
+                     Arg::with_name("JITTER")
+                         .short("j")
+                         .long("jitter")
+                         .takes_value(true)
+                         .help("Randomizes each frequency within frequency ± jitter"),
+                 )
+                 .arg(
+                     Arg::with_name("MAX_ACTIVATIONS")
+                         .short("n")
+                         .long("max-activations")
+                         .takes_value(true)
+                         .help("Stops after the number of the unstable periods (e.g., 1 for a one-shot slowdown)"),
                  ))
+        .subcommand(
+            SubCommand::with_name("validate")
+                .about("Checks the config file and prints it without mounting")
+                .arg(
+                    Arg::with_name("FILE")
+                        .help("Sets the TOML config file to check")
+                        .required(true)
+                        .index(1),
+                ),
+        )
         .get_matches();
 
-    let original_dir = matches.value_of("ORIGINAL_DIR").unwrap();
-    let mountpoint = matches.value_of("MOUNTPOINT").unwrap();
+    if let Some(matches) = matches.subcommand_matches("validate") {
+        validate_config(Path::new(matches.value_of("FILE").unwrap()));
+    }
+
+    if matches.is_present("PRINT_DEFAULT_CONFIG") {
+        print!("{}", Config::default_template());
+        return Ok(());
+    }
+
+    if matches.is_present("BENCHMARK") {
+        println!("{}", benchmark::measure_overhead(benchmark::ITERATIONS));
+        return Ok(());
+    }
+
+    let original_dir = match matches.value_of("ORIGINAL_DIR") {
+        Some(original_dir) => original_dir,
+        None => clap::Error::with_description(
+            "The following required arguments were not provided: <ORIGINAL_DIR>",
+            ErrorKind::MissingRequiredArgument,
+        )
+        .exit(),
+    };
     let http_port: u16 = matches.value_of("HTTP_PORT").unwrap().parse()?;
 
-    let mut config: Config = Default::default();
+    // The sources override the former in the order of the defaults, the config file,
+    // the environment variables and the command line options.
+    let mut sources = Vec::new();
+    if let Some(path) = matches.value_of("CONFIG") {
+        sources.push(Config::partial_from_toml_path(Path::new(path))?);
+    }
+    sources.push(Config::partial_from_env()?);
+
+    let si = matches.is_present("SI");
+    let mut cli_config = PartialConfig {
+        speed: cli::speed_of(&matches, "SPEED", si).unwrap_or_else(|e| e.exit()),
+        operations: cli::operations_of(&matches, "OPERATION").unwrap_or_else(|e| e.exit()),
+        warnings: cli::speed_warning_of(&matches, "SPEED", si)
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
 
-    // Override the config if there are given options.
-    if let Some(speed) = matches.value_of("SPEED") {
-        config.speed = speed.parse()?;
+    if let Some(mountpoint) = matches.value_of("MOUNTPOINT") {
+        cli_config.target = Some(mountpoint.into());
     }
 
-    if let Some(matches) = matches.subcommand_matches("periodic") {
-        let mut p = config::Condition::default_periodic();
+    if matches.is_present("DRY_RUN") {
+        cli_config.dry_run = Some(true);
+    }
 
-        if let Some(duration) = matches.value_of("DURATION") {
-            let secs = parse_time(String::from(duration))?;
+    if let Some(seed) = matches.value_of("SEED") {
+        cli_config.seed = Some(seed.parse()?);
+    }
 
-            if let Condition::Periodic {
-                ref mut duration, ..
-            } = p
-            {
-                *duration = Duration::from_secs(secs);
-            }
+    if let Some(window) = matches.value_of("ACTIVE_WINDOW") {
+        cli_config.active_window = Some(window.parse()?);
+    }
+
+    if let Some(d) = matches.value_of("RUN_FOR") {
+        cli_config.run_for = Some(parse_duration(d)?);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("periodic") {
+        if let Some(duration) = matches.value_of("DURATION") {
+            cli_config.duration = Some(parse_duration(duration)?);
         }
 
         if let Some(frequency) = matches.value_of("FREQUENCY") {
-            let secs = parse_time(String::from(frequency))?;
+            cli_config.frequency = Some(parse_frequency(frequency)?);
+        }
 
-            if let Condition::Periodic {
-                ref mut frequency, ..
-            } = p
-            {
-                *frequency = Duration::from_secs(secs);
-            }
+        if let Some(jitter) = matches.value_of("JITTER") {
+            cli_config.frequency_jitter = Some(parse_duration(jitter)?);
         }
 
-        config.condition = p;
+        if let Some(n) = matches.value_of("MAX_ACTIVATIONS") {
+            cli_config.max_activations = Some(n.parse()?);
+        }
+    } else if !sources
+        .iter()
+        .any(|s| s.duration.is_some() || s.frequency.is_some() || s.script.is_some())
+    {
+        // Without the subcommand, it toggles periodically by the default condition
+        // unless the config file or the environment variables set the condition.
+        if let Condition::Periodic {
+            duration,
+            frequency,
+        } = Condition::default_periodic()
+        {
+            cli_config.duration = Some(duration);
+            cli_config.frequency = Some(frequency);
+        }
     }
+    sources.push(cli_config);
+
+    let parse_warnings: Vec<String> = sources
+        .iter()
+        .flat_map(|source| source.warnings.clone())
+        .collect();
+    let config = sources.into_iter().fold(Config::default(), Config::merge);
+    config.validate()?;
+    let mountpoint = match config.target.clone() {
+        Some(mountpoint) => mountpoint,
+        None => clap::Error::with_description(
+            "The following required arguments were not provided: <MOUNTPOINT> (or target in the config)",
+            ErrorKind::MissingRequiredArgument,
+        )
+        .exit(),
+    };
 
     let decorator = slog_term::TermDecorator::new().build();
     let drain = slog_term::FullFormat::new(decorator).build().fuse();
@@ -133,18 +312,77 @@ fn main() -> Result<(), Box<std::error::Error>> {
     let logger = slog::Logger::root(drain, o!());
 
     info!(logger, "original directory: {}", original_dir);
-    info!(logger, "mountpoint: {}", mountpoint);
+    info!(logger, "mountpoint: {}", mountpoint.display());
     info!(logger, "config: {}", config);
+    if matches.is_present("PRINT_CONFIG_JSON") {
+        println!("{}", config.to_summary_json());
+    }
+    for line in config.diff_from_default().to_string().lines() {
+        info!(logger, "changed from the default: {}", line);
+    }
+    for warning in parse_warnings.iter().chain(&config.warnings()) {
+        warn!(logger, "{}", warning);
+    }
+
+    signal::install_handlers()?;
+    info!(
+        logger,
+        "SIGUSR1 toggles pausing, SIGUSR2 begins an unstable period and SIGRTMIN+i toggles pausing the i-th of {:?}",
+        signal::OPERATIONS
+    );
+
+    let baseline = if calibrate::is_required(&config) {
+        let bps =
+            calibrate::measure_throughput(Path::new(original_dir), calibrate::CALIBRATION_SIZE)?;
+        info!(logger, "measured baseline: {}", Speed::Bps(bps as u64));
+        Some(bps)
+    } else {
+        None
+    };
 
     let config = Arc::new(AtomicImmut::new(config));
     http::start_server(logger.clone(), http_port, Arc::clone(&config))?;
+    if let Some(path) = matches.value_of("CONTROL_SOCKET") {
+        // The throttling works without the control server.
+        if let Err(e) = control::start_server(logger.clone(), Path::new(path), Arc::clone(&config))
+        {
+            warn!(logger, "control socket is disabled: {}: {}", path, e);
+        }
+    }
 
-    let m = Mizumochi::new(
-        logger.clone(),
-        original_dir.into(),
-        mountpoint.into(),
-        config,
-    );
+    let mut m = Mizumochi::new(logger.clone(), original_dir.into(), mountpoint, config);
+    if let Some(bps) = baseline {
+        m.set_baseline(bps);
+    }
+    if matches.is_present("ACTIVATE_NOW") {
+        m.activate_now();
+    }
+    if matches.is_present("PRINT_SUMMARY_JSON") {
+        m.print_summary_json();
+    }
+    let sample_rate = match matches.value_of("EVENT_LOG_SAMPLE_RATE") {
+        Some(n) => Some(n.parse()?),
+        None => None,
+    };
+    let event_log = match matches.value_of("EVENT_LOG") {
+        Some("-") => Some(EventLog::stderr()),
+        Some(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            Some(EventLog::new(file))
+        }
+        None => None,
+    };
+    // The sample rate implies the events of the operations unless the level is given.
+    let log_level = match matches.value_of("EVENT_LOG_LEVEL") {
+        Some(level) => level.parse()?,
+        None if sample_rate.is_some() => LogLevel::Operations,
+        None => LogLevel::Windows,
+    };
+    if let Some(mut event_log) = event_log {
+        event_log.set_sample_rate(sample_rate);
+        m.set_event_log(event_log);
+        m.set_log_level(log_level);
+    }
 
     if let Err(error) = m.mount() {
         error!(logger, "{}", error);
@@ -154,18 +392,25 @@ fn main() -> Result<(), Box<std::error::Error>> {
     }
 }
 
-fn parse_time(mut input: String) -> Result<u64, Box<std::error::Error>> {
-    let suffix = input.pop().ok_or(std::fmt::Error)?;
-    let t: u64 = input.parse()?;
-
-    use std::io::{Error, ErrorKind};
-    match suffix {
-        's' => Ok(t),
-        'm' => Ok(t * 60),
-        'h' => Ok(t * 60 * 60),
-        _ => Err(Box::new(Error::new(
-            ErrorKind::Other,
-            "time suffix accepts s, m or h",
-        ))),
+/// Loads the config file and prints it if it is valid.
+/// Otherwise, prints the error and exits with 1.
+fn validate_config(path: &Path) -> ! {
+    let parsed = Config::partial_from_toml_path(path).and_then(|partial| {
+        let warnings = partial.warnings.clone();
+        let config = Config::merge(Config::default(), partial);
+        config.validate().map(|()| (config, warnings))
+    });
+    match parsed {
+        Ok((config, warnings)) => {
+            for warning in warnings.iter().chain(&config.warnings()) {
+                eprintln!("{}: warning: {}", path.display(), warning);
+            }
+            println!("{}", config);
+            process::exit(0);
+        }
+        Err(error) => {
+            eprintln!("{}: {}", path.display(), error);
+            process::exit(1);
+        }
     }
 }
@@ -1,4 +1,11 @@
-use prometrics::metrics::{Counter, MetricBuilder};
+use config::Operation;
+use prometrics;
+use prometrics::metric::Metric;
+use prometrics::metrics::{Counter, CounterBuilder, Gauge, MetricBuilder};
+use prometrics::Collect;
+use stats::StatsCounters;
+use std::sync::{Arc, Weak};
+use std::vec;
 
 #[derive(Debug)]
 pub struct Metrics {
@@ -37,9 +44,14 @@ pub struct Metrics {
     pub io_operations_bmap: Counter,
     pub speed_limit_enabled: Counter,
     pub speed_limit_disabled: Counter,
+    pub unstable: Gauge,
 }
 impl Metrics {
-    pub fn new() -> Self {
+    /// Creates the metrics of the file system, and exports `stats` as the metrics of the throttling too
+    /// (see `StatsCollector`).
+    pub fn new(stats: &Arc<StatsCounters>) -> Self {
+        prometrics::default_registry().register(StatsCollector(Arc::downgrade(stats)));
+
         let mut builder = MetricBuilder::new();
         builder.namespace("mizumochi");
         let build_io_operations_metric = |name| {
@@ -94,6 +106,61 @@ impl Metrics {
                 .help("Number of times speed limit has been disabled")
                 .finish()
                 .expect("Never fails"),
+            unstable: builder
+                .gauge("unstable")
+                .help("Whether the operations are unstable (1) or not (0)")
+                .finish()
+                .expect("Never fails"),
         }
     }
 }
+
+/// `StatsCollector` exports the counters of `Engine::stats` at each scrape, so the throttling is counted only
+/// by `StatsCounters`. It is removed from the registry once the engine is dropped.
+struct StatsCollector(Weak<StatsCounters>);
+
+impl Collect for StatsCollector {
+    type Metrics = vec::IntoIter<Metric>;
+
+    fn collect(&mut self) -> Option<Self::Metrics> {
+        let counters = self.0.upgrade()?;
+        let stats = counters.snapshot();
+        let mut metrics = vec![
+            counter(
+                CounterBuilder::new("throttled_bytes_total")
+                    .help("Number of bytes read/written under speed limit"),
+                stats.bytes_read_throttled + stats.bytes_written_throttled,
+            ),
+            counter(
+                CounterBuilder::new("injected_errors_total")
+                    .help("Number of errors injected intentionally"),
+                stats.errors_injected,
+            ),
+        ];
+        for op in &[
+            Operation::Read,
+            Operation::Write,
+            Operation::Stat,
+            Operation::Open,
+            Operation::Readdir,
+            Operation::Fsync,
+        ] {
+            metrics.push(counter(
+                CounterBuilder::new("delayed_operations_total")
+                    .label("operation", &op.to_string().to_lowercase())
+                    .help("Number of operations delayed to throttle"),
+                counters.ops_delayed_of(op),
+            ));
+        }
+        Some(metrics.into_iter())
+    }
+}
+
+fn counter(builder: &mut CounterBuilder, value: u64) -> Metric {
+    let counter = builder
+        .namespace("mizumochi")
+        .finish()
+        .expect("Never fails");
+    counter.add_u64(value);
+    counter.into()
+}
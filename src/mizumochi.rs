@@ -1,12 +1,16 @@
 // FIXME: Refactor error
 use atomic_immut::AtomicImmut;
-use config::{Config, Operation, Speed};
+use config::{format_duration, Config, Operation, Speed};
+use engine::Engine;
+use event::{EventLog, LogLevel};
 use fuse::{self, *};
 use libc;
 use localfile::{Inode, LocalFile};
 use metrics::Metrics;
+use shutdown::Shutdown;
 use slog::Logger;
-use state::{State, StateManager};
+use state::State;
+use stats::RunSummary;
 use std;
 use std::collections::HashMap;
 use std::error::Error;
@@ -17,8 +21,7 @@ use std::mem;
 use std::os::raw::c_int;
 use std::path::{Path, PathBuf};
 use std::result::Result;
-use std::sync::Arc;
-use std::thread::sleep;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use time::{PreciseTime, Timespec};
 
@@ -27,11 +30,13 @@ type FileHandler = u64;
 const TTL: Timespec = Timespec { sec: 1, nsec: 0 };
 const ROOT_DIR_INO: u64 = 1;
 
+// The metadata operations (e.g., stat, open) are regarded as reading a block of this size to throttle them.
+const METADATA_OPERATION_BYTES: usize = 4096;
+
 pub struct Mizumochi {
     logger: Logger,
 
-    state_manager: StateManager,
-    config: Arc<AtomicImmut<Config>>,
+    engine: Engine,
 
     // FIXME: use simple allocator.
     ino_count: Inode,
@@ -44,6 +49,13 @@ pub struct Mizumochi {
     mountpoint: PathBuf,
 
     metrics: Metrics,
+
+    // Begins the first unstable period at the mount (see `Engine::activate_now`).
+    activate_now: bool,
+    // Prints `Engine::summary` as JSON to stdout at the unmount besides logging it.
+    print_summary_json: bool,
+    // `Engine::summary` which `Drop` leaves for `mount` to emit after the session ends.
+    summary: Arc<Mutex<Option<RunSummary>>>,
 }
 
 impl Mizumochi {
@@ -53,14 +65,14 @@ impl Mizumochi {
         mountpoint: PathBuf,
         config: Arc<AtomicImmut<Config>>,
     ) -> Mizumochi {
-        let cond = (&*config.load()).condition.clone();
-        let state_manager = StateManager::new(cond);
+        let mut engine = Engine::new(config);
+        engine.set_logger(logger.clone());
+        let metrics = Metrics::new(engine.stats_counters());
 
         Mizumochi {
             logger,
 
-            state_manager,
-            config,
+            engine,
 
             fh_count: 1,
             // inode number begins from the next of `ROOT_DIR_INO`.
@@ -71,13 +83,92 @@ impl Mizumochi {
             mountpoint,
             original_dir,
 
-            metrics: Metrics::new(),
+            metrics,
+
+            activate_now: false,
+            print_summary_json: false,
+            summary: Arc::new(Mutex::new(None)),
         }
     }
 
+    /// Emits the start/end events of the unstable periods to the log.
+    pub fn set_event_log(&mut self, event_log: EventLog) {
+        self.engine.set_event_log(event_log);
+    }
+
+    /// Sets which events are emitted to the event log (see `Engine::set_log_level`).
+    pub fn set_log_level(&mut self, level: LogLevel) {
+        self.engine.set_log_level(level);
+    }
+
+    /// Sets the measured throughput for `Speed::Percent` (see `Engine::set_baseline`).
+    pub fn set_baseline(&mut self, bps: usize) {
+        self.engine.set_baseline(bps);
+    }
+
+    /// Begins the first unstable period as soon as mounted instead of after the stable period.
+    pub fn activate_now(&mut self) {
+        self.activate_now = true;
+    }
+
+    /// Prints the summary of the run as a JSON object in a line to stdout at the unmount (see `RunSummary::to_json`).
+    pub fn print_summary_json(&mut self) {
+        self.print_summary_json = true;
+    }
+
+    /// Returns the flag to stop throttling while mounted (see `Engine::shutdown`).
+    pub fn shutdown_handle(&self) -> Arc<Shutdown> {
+        self.engine.shutdown_handle()
+    }
+
+    /// Mounts and blocks until unmounted, and then emits the summary of the run to the log
+    /// (and to stdout by `print_summary_json`).
+    /// If `Config::run_for` is set when mounted, it shuts down the engine and unmounts once the time elapses,
+    /// or returns as soon as unmounted externally before it.
     pub fn mount(self) -> Result<(), io::Error> {
+        let logger = self.logger.clone();
+        let print_summary_json = self.print_summary_json;
+        let summary = Arc::clone(&self.summary);
+        self.run_session()?;
+
+        // The session has dropped the file system, which left the summary.
+        if let Some(summary) = summary.lock().unwrap().take() {
+            for line in summary.to_string().lines() {
+                info!(logger, "summary: {}", line);
+            }
+            if print_summary_json {
+                println!("{}", summary.to_json());
+            }
+        }
+        Ok(())
+    }
+
+    fn run_session(self) -> Result<(), io::Error> {
         let mountpoint = self.mountpoint.clone();
-        fuse::mount(self, &mountpoint, &[])
+        let run_for = match self.engine.config().run_for {
+            Some(d) => d,
+            None => return fuse::mount(self, &mountpoint, &[]),
+        };
+
+        let logger = self.logger.clone();
+        let shutdown = self.shutdown_handle();
+        // The session is only dropped in this function, so the file system never outlives its borrows.
+        let session = unsafe { fuse::spawn_mount(self, &mountpoint, &[])? };
+        let start = std::time::Instant::now();
+        // The file system triggers it when dropped by the session, e.g., at an external unmount.
+        shutdown.sleep(run_for);
+        if start.elapsed() < run_for {
+            info!(logger, "unmounted before run_for elapsed");
+        } else {
+            shutdown.trigger();
+            info!(
+                logger,
+                "unmounting after run_for: {}",
+                format_duration(&run_for)
+            );
+        }
+        drop(session);
+        Ok(())
     }
 
     fn init(&mut self) -> Result<(), io::Error> {
@@ -90,8 +181,11 @@ impl Mizumochi {
         }
 
         // Initialize the state.
-        self.state_manager.init();
-        info!(self.logger, "State: {:?}", self.state_manager.state());
+        self.engine.init();
+        if self.activate_now {
+            self.engine.activate_now();
+        }
+        info!(self.logger, "State: {}", self.engine.state());
 
         let path = self.original_dir.clone();
         self.fetch_files_if_not_found(ROOT_DIR_INO, &path)?;
@@ -155,35 +249,196 @@ impl Mizumochi {
         Ok(())
     }
 
-    fn change_state_if_necessary(&mut self, op: Operation) -> &State {
-        let prev_state = self.state_manager.state().clone();
+    fn change_state_if_necessary(&mut self, op: Operation) {
+        match self.engine.on_operated(op) {
+            Ok(Some(State::Unstable)) => {
+                self.metrics.speed_limit_enabled.increment();
+                self.metrics.unstable.set(1.0);
+                info!(self.logger, "--- Enable unstable mode ---");
+                info!(self.logger, "{}", self.engine.current_state())
+            }
+            Ok(Some(State::Stable)) => {
+                self.metrics.speed_limit_disabled.increment();
+                self.metrics.unstable.set(0.0);
+                info!(self.logger, "--- Enable stable mode ---")
+            }
+            Ok(None) => {}
+            Err(e) => crit!(self.logger, "change_state_if_necessary crit: {}", e),
+        }
+    }
 
-        {
-            let cond = &self.config.load().condition;
-            let state = if let Ok(state) = self.state_manager.on_operated_after(op, cond) {
-                state.clone()
-            } else {
-                crit!(
-                    self.logger,
-                    "change_state_if_necessary crit: let the state stable"
-                );
-                State::Stable
-            };
+    /// Returns true if the file is the target to throttle.
+    /// Each operation looks it up once as the file size may be read, and passes it to the helpers below.
+    fn is_target_file(&mut self, ino: Inode) -> bool {
+        let path = match self.file_map.get(&ino) {
+            Some(LocalFile::RegularFile(path)) | Some(LocalFile::Directory(path, _)) => path,
+            None => return false,
+        };
+        let relative_path = path.strip_prefix(&self.original_dir).unwrap_or(path);
 
-            match (prev_state, state) {
-                (State::Stable, State::Unstable) => {
-                    self.metrics.speed_limit_enabled.increment();
-                    info!(self.logger, "--- Enable unstable mode ---")
-                }
-                (State::Unstable, State::Stable) => {
-                    self.metrics.speed_limit_enabled.increment();
-                    info!(self.logger, "--- Enable stable mode ---")
-                }
-                _ => {}
+        // The file of which size is unknown is regarded as empty.
+        self.engine.is_target_file(relative_path, || {
+            fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+        })
+    }
+
+    /// Returns the speed to apply if the state is unstable and the operation is the target.
+    /// The speed is of the file handle if given (see `Engine::handle_speed`).
+    fn active_speed(&mut self, op: Operation, target: bool, fh: Option<u64>) -> Option<Speed> {
+        self.change_state_if_necessary(op.clone());
+        if !target {
+            return None;
+        }
+
+        match fh {
+            Some(fh) => self.engine.handle_speed(fh, op),
+            None => self.engine.speed(op),
+        }
+    }
+
+    /// Returns the error code if the operation has to fail intentionally.
+    /// `size` is the number of bytes to write and the others ignore it.
+    fn inject_error(&mut self, op: Operation, target: bool, size: usize) -> Option<c_int> {
+        self.change_state_if_necessary(op.clone());
+        if !target {
+            return None;
+        }
+
+        let e = self.engine.injected_error(op.clone(), size);
+        if let Some(code) = e {
+            debug!(self.logger, "inject error: {}, code: {}", op, code);
+        }
+
+        e
+    }
+
+    /// Returns the number of bytes to read/write actually.
+    fn io_size(&mut self, op: Operation, target: bool, size: usize) -> usize {
+        if !target {
+            return size;
+        }
+
+        self.engine.io_size(op, size)
+    }
+
+    /// Sleeps before servicing the operation if the latency or the IOPS limit is active.
+    fn inject_latency(&mut self, op: Operation, target: bool, fh: Option<u64>) {
+        let wait = match self.active_speed(op.clone(), target, fh) {
+            Some(Speed::Iops(iops)) => {
+                let now = self.engine.clock().now();
+                self.engine.reserve_operation(op.clone(), iops, now)
             }
+            Some(s) => self.engine.sample_latency(&s).unwrap_or_default(),
+            None => return,
+        };
+
+        self.delay(&op, 0, wait);
+    }
+
+    /// Sleeps for `Config::seek_penalty` for the random access of the operation on the file.
+    fn inject_seek_penalty(&mut self, op: Operation, target: bool) {
+        self.change_state_if_necessary(op.clone());
+        if !target {
+            return;
         }
 
-        self.state_manager.state()
+        let wait = self.engine.seek_penalty(&op);
+        self.delay(&op, 0, wait);
+    }
+
+    /// Sleeps for `Config::allocation_penalty` for the allocating write on the file.
+    fn inject_allocation_penalty(&mut self, target: bool) {
+        self.change_state_if_necessary(Operation::Write);
+        if !target {
+            return;
+        }
+
+        let wait = self.engine.allocation_penalty();
+        self.delay(&Operation::Write, 0, wait);
+    }
+
+    /// Sleeps for `wait`, which `Engine::delay` counts unless it does not sleep.
+    /// `size` is the number of bytes of the operation to log in the dry run.
+    fn delay(&self, op: &Operation, size: usize, wait: Duration) {
+        self.engine.delay(op, size, wait);
+    }
+
+    /// Sleeps to adjust the speed if the byte rate is active.
+    /// `size` is the number of read/written bytes and `start` is the time when the operation began.
+    fn throttle(
+        &mut self,
+        op: Operation,
+        target: bool,
+        fh: Option<u64>,
+        size: usize,
+        start: PreciseTime,
+    ) {
+        let speed = match self.active_speed(op.clone(), target, fh) {
+            Some(speed) if speed.bps().is_some() || speed.per_byte().is_some() => speed,
+            _ => return,
+        };
+
+        // Mesure elapsed time and wait if necessary.
+        let elapsed = start.to(PreciseTime::now()).to_std().unwrap_or_default();
+        let wait = match (speed.bps(), speed.per_byte()) {
+            (Some(bps), _) => self.engine.throttle_delay(op.clone(), bps, size, elapsed),
+            (None, Some(per_byte)) => {
+                self.engine
+                    .per_byte_delay(op.clone(), per_byte, size, elapsed)
+            }
+            (None, None) => return,
+        };
+        self.delay(&op, size, wait);
+    }
+
+    /// Reads `size` bytes at `offset` on the file handle with the throttling, and passes the read bytes
+    /// or the error code to `reply` before it sleeps for the byte rate (as `Filesystem::read` replies).
+    fn read_with<F>(&mut self, ino: Inode, fh: u64, offset: i64, size: u32, reply: F)
+    where
+        F: FnOnce(Result<&[u8], c_int>),
+    {
+        // The first operation on the handle is never delayed by `Config::passthrough_first_op`,
+        // nor the reads which continue the transfer of the last read by `Config::read_coalesce`.
+        let target = self.is_target_file(ino);
+        let coalesced = self.engine.on_handle_read(fh, offset as u64, size as usize);
+        let passthrough = self.engine.on_handle_operated(fh) || coalesced;
+        let random = self.engine.is_random_access(fh, offset as u64);
+        if !passthrough {
+            self.inject_latency(Operation::Read, target, Some(fh));
+            if random {
+                self.inject_seek_penalty(Operation::Read, target);
+            }
+        }
+        if let Some(e) = self.inject_error(Operation::Read, target, 0) {
+            reply(Err(e));
+            return;
+        }
+        let size = self.io_size(Operation::Read, target, size as usize) as u32;
+        let start = PreciseTime::now();
+
+        let mut buffer = vec![0; size as usize];
+
+        match Mizumochi::read(self, fh, &mut buffer, offset, size) {
+            Ok(read_size) => {
+                reply(Ok(&buffer[0..read_size]));
+                self.engine.on_handle_accessed(fh, offset as u64, read_size);
+                if coalesced {
+                    // The coalesced bytes are charged to the transfer as `Engine::throttle_read` does.
+                    if target {
+                        self.engine.charge_bytes(Operation::Read, read_size);
+                    }
+                } else {
+                    if !passthrough {
+                        self.throttle(Operation::Read, target, Some(fh), read_size, start);
+                    }
+                    self.engine.on_read_completed(fh);
+                }
+            }
+            Err(error) => {
+                error!(self.logger, "read error: {}", error);
+                reply(Err(libc::EIO));
+            }
+        }
     }
 
     fn lookup(&mut self, parent: u64, name: &OsStr) -> Result<FileAttr, io::Error> {
@@ -356,6 +611,7 @@ impl Mizumochi {
             self.fh_count += 1;
 
             self.fh_map.insert(fh, file);
+            self.engine.on_opened(fh);
             files.push((ino, name.into()));
 
             (attr, fh, ino, LocalFile::RegularFile(path.clone()))
@@ -367,6 +623,18 @@ impl Mizumochi {
     }
 }
 
+// The kernel does not always send the destroy operation at the unmount (e.g., for the non-block devices),
+// so the summary is taken when the session drops the file system instead, and `mount` emits it.
+// It also wakes `mount` waiting for `Config::run_for` if unmounted externally (e.g., by `fusermount -u`).
+impl Drop for Mizumochi {
+    fn drop(&mut self) {
+        self.shutdown_handle().trigger();
+        if let Ok(mut summary) = self.summary.lock() {
+            *summary = Some(self.engine.summary());
+        }
+    }
+}
+
 impl Filesystem for Mizumochi {
     fn init(&mut self, _req: &Request) -> Result<(), c_int> {
         info!(self.logger, "init");
@@ -397,6 +665,14 @@ impl Filesystem for Mizumochi {
         debug!(self.logger, "getattr: ino: {:?}", ino);
         self.metrics.io_operations_getattr.increment();
 
+        let target = self.is_target_file(ino);
+        self.inject_latency(Operation::Stat, target, None);
+        if let Some(e) = self.inject_error(Operation::Stat, target, 0) {
+            reply.error(e);
+            return;
+        }
+        let start = PreciseTime::now();
+
         match self.file_map.get(&ino) {
             Some(LocalFile::RegularFile(path)) => match fetch_fileattr(ino, path) {
                 Ok(attr) => reply.attr(&TTL, &attr),
@@ -422,6 +698,14 @@ impl Filesystem for Mizumochi {
                 reply.error(libc::ENOENT);
             }
         }
+
+        self.throttle(
+            Operation::Stat,
+            target,
+            None,
+            METADATA_OPERATION_BYTES,
+            start,
+        );
     }
 
     fn readdir(
@@ -438,6 +722,14 @@ impl Filesystem for Mizumochi {
         );
         self.metrics.io_operations_readdir.increment();
 
+        let target = self.is_target_file(ino);
+        self.inject_latency(Operation::Readdir, target, None);
+        if let Some(e) = self.inject_error(Operation::Readdir, target, 0) {
+            reply.error(e);
+            return;
+        }
+        let start = PreciseTime::now();
+
         use self::io::ErrorKind;
         if let Err(error) = self.readdir(req, ino, fh, offset, &mut reply) {
             let e = match error.kind() {
@@ -452,6 +744,14 @@ impl Filesystem for Mizumochi {
         } else {
             reply.ok();
         }
+
+        self.throttle(
+            Operation::Readdir,
+            target,
+            None,
+            METADATA_OPERATION_BYTES,
+            start,
+        );
     }
 
     fn read(
@@ -469,30 +769,10 @@ impl Filesystem for Mizumochi {
         );
         self.metrics.io_operations_read.increment();
 
-        let start = PreciseTime::now();
-
-        let mut buffer = vec![0; size as usize];
-
-        match Mizumochi::read(self, fh, &mut buffer, offset, size) {
-            Ok(read_size) => {
-                reply.data(&buffer[0..read_size]);
-
-                if State::Unstable == *self.change_state_if_necessary(Operation::Read) {
-                    if let Speed::Bps(bps) = self.config.load().speed {
-                        // Mesure elapsed time and wait if necessary.
-                        sleep(compute_sleep_duration_to_adjust_speed(
-                            bps,
-                            read_size,
-                            start.to(PreciseTime::now()).num_milliseconds() as u64,
-                        ));
-                    }
-                }
-            }
-            Err(error) => {
-                error!(self.logger, "read error: {}", error);
-                reply.error(libc::EIO);
-            }
-        }
+        self.read_with(ino, fh, offset, size, |result| match result {
+            Ok(data) => reply.data(data),
+            Err(e) => reply.error(e),
+        });
     }
 
     fn setattr(
@@ -562,20 +842,33 @@ impl Filesystem for Mizumochi {
         );
         self.metrics.io_operations_write.increment();
 
+        let target = self.is_target_file(ino);
+        let passthrough = self.engine.on_handle_operated(fh);
+        let random = self.engine.is_random_access(fh, offset as u64);
+        let allocating = self.engine.on_handle_written(fh, offset as u64, data.len());
+        if !passthrough {
+            self.inject_latency(Operation::Write, target, Some(fh));
+            if random {
+                self.inject_seek_penalty(Operation::Write, target);
+            }
+            if allocating {
+                self.inject_allocation_penalty(target);
+            }
+        }
+        let data = &data[..self.io_size(Operation::Write, target, data.len())];
+        if let Some(e) = self.inject_error(Operation::Write, target, data.len()) {
+            reply.error(e);
+            return;
+        }
         let start = PreciseTime::now();
 
         match Mizumochi::write(self, fh, data, offset) {
             Ok(written_size) => {
                 reply.written(written_size as u32);
-
-                if State::Unstable == *self.change_state_if_necessary(Operation::Write) {
-                    if let Speed::Bps(bps) = self.config.load().speed {
-                        sleep(compute_sleep_duration_to_adjust_speed(
-                            bps,
-                            written_size,
-                            start.to(PreciseTime::now()).num_milliseconds() as u64,
-                        ));
-                    }
+                self.engine
+                    .on_handle_accessed(fh, offset as u64, written_size);
+                if !passthrough {
+                    self.throttle(Operation::Write, target, Some(fh), written_size, start);
                 }
             }
             Err(ecode) => {
@@ -590,6 +883,14 @@ impl Filesystem for Mizumochi {
         info!(self.logger, "open ino: {}, flags: {}", ino, flags);
         self.metrics.io_operations_open.increment();
 
+        let target = self.is_target_file(ino);
+        self.inject_latency(Operation::Open, target, None);
+        if let Some(e) = self.inject_error(Operation::Open, target, 0) {
+            reply.error(e);
+            return;
+        }
+        let start = PreciseTime::now();
+
         match self.file_map.get(&ino) {
             Some(LocalFile::RegularFile(filepath)) => {
                 let mut options = fs::OpenOptions::new();
@@ -600,6 +901,12 @@ impl Filesystem for Mizumochi {
                     Ok(f) => {
                         let fh = self.fh_count;
                         self.fh_count += 1;
+                        self.engine.on_opened(fh);
+                        if self.engine.tracks_high_water() {
+                            if let Ok(metadata) = f.metadata() {
+                                self.engine.set_handle_size(fh, metadata.len());
+                            }
+                        }
                         self.fh_map.insert(fh, f);
 
                         reply.opened(fh, 0);
@@ -619,6 +926,14 @@ impl Filesystem for Mizumochi {
                 reply.error(libc::ENOENT)
             }
         }
+
+        self.throttle(
+            Operation::Open,
+            target,
+            None,
+            METADATA_OPERATION_BYTES,
+            start,
+        );
     }
 
     fn flush(&mut self, _req: &Request, ino: u64, fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
@@ -651,6 +966,7 @@ impl Filesystem for Mizumochi {
         info!(self.logger, "release: ino: {}, fh: {}", ino, fh);
         self.metrics.io_operations_release.increment();
 
+        self.engine.on_released(fh);
         if let Some(f) = self.fh_map.remove(&fh) {
             if let Err(error) = f.sync_data() {
                 error!(self.logger, "sync_data error: {}", error);
@@ -671,6 +987,17 @@ impl Filesystem for Mizumochi {
         );
         self.metrics.io_operations_fsync.increment();
 
+        let target = self.is_target_file(ino);
+        let passthrough = self.engine.on_handle_operated(fh);
+        if !passthrough {
+            self.inject_latency(Operation::Fsync, target, None);
+        }
+        if let Some(e) = self.inject_error(Operation::Fsync, target, 0) {
+            reply.error(e);
+            return;
+        }
+        let start = PreciseTime::now();
+
         if let Some(f) = self.fh_map.get(&fh) {
             if let Err(error) = f.sync_data() {
                 error!(self.logger, "sync_data error: {}", error);
@@ -682,6 +1009,16 @@ impl Filesystem for Mizumochi {
             error!(self.logger, "fsync error: no entry");
             reply.error(libc::ENOENT);
         }
+
+        if !passthrough {
+            self.throttle(
+                Operation::Fsync,
+                target,
+                None,
+                METADATA_OPERATION_BYTES,
+                start,
+            );
+        }
     }
 
     fn getxattr(
@@ -701,6 +1038,7 @@ impl Filesystem for Mizumochi {
     fn destroy(&mut self, _req: &Request) {
         debug!(self.logger, "destroy");
         self.metrics.io_operations_destroy.increment();
+        self.shutdown_handle().trigger();
     }
 
     fn forget(&mut self, _req: &Request, _ino: u64, _nlookup: u64) {
@@ -981,28 +1319,10 @@ fn fetch_fileattr(ino: u64, filepath: &Path) -> Result<FileAttr, io::Error> {
     Ok(attr)
 }
 
-/// `request_bps` means request Byte per seconds (not bit).
-/// `count_byte` is the number of read/written bytes.
-/// `elapsed_ms` is the elapsed time in milliseconds to read/write data.
-fn compute_sleep_duration_to_adjust_speed(
-    request_bps: usize,
-    count_byte: usize,
-    elapsed_ms: u64,
-) -> Duration {
-    if request_bps == 0 {
-        panic!("The given request bps is zero.");
-    }
-
-    let expect_sec = count_byte as f64 / request_bps as f64;
-    let expect_ms = (expect_sec * 1000.0).round() as u64;
-    let wait_ms = expect_ms.saturating_sub(elapsed_ms);
-
-    Duration::from_millis(wait_ms as u64)
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use throttle::compute_sleep_duration_to_adjust_speed;
 
     #[test]
     fn test_compute_sleep_duration_to_adjust_speed() {
@@ -1039,4 +1359,170 @@ mod tests {
             compute_sleep_duration_to_adjust_speed(1024, 512, 1000)
         );
     }
+
+    #[test]
+    fn test_drop_triggers_shutdown() {
+        let m = Mizumochi::new(
+            Logger::root(slog::Discard, o!()),
+            PathBuf::from("/nonexistent"),
+            PathBuf::from("/nonexistent"),
+            Arc::new(AtomicImmut::new(Config::default())),
+        );
+        let shutdown = m.shutdown_handle();
+        let summary = Arc::clone(&m.summary);
+        assert!(!shutdown.is_triggered());
+
+        // The session drops the file system at the unmount, which ends `mount` waiting for `run_for`
+        // and leaves the summary for it.
+        drop(m);
+        assert!(shutdown.is_triggered());
+        assert_eq!(0, summary.lock().unwrap().take().unwrap().activations);
+    }
+
+    #[test]
+    fn test_metrics() {
+        use clock::MockClock;
+        use config::{Condition, ErrorInjection};
+
+        let config = Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            error_injection: Some(ErrorInjection {
+                probability: 1.0,
+                operations: vec![Operation::Write],
+                disk_full_after: None,
+                errors: vec![],
+                continuous: false,
+                errors_per_window: None,
+            }),
+            ..Default::default()
+        };
+        let mut m = Mizumochi::new(
+            Logger::root(slog::Discard, o!()),
+            PathBuf::from("/nonexistent"),
+            PathBuf::from("/nonexistent"),
+            Arc::new(AtomicImmut::new(config)),
+        );
+        let clock = Arc::new(MockClock::new());
+        m.engine.set_clock(clock.clone());
+        m.engine.init();
+        let ino = 2;
+        m.file_map
+            .insert(ino, LocalFile::RegularFile(PathBuf::from("/nonexistent/a")));
+        let target = m.is_target_file(ino);
+        assert!(target);
+
+        // Nothing is counted while stable.
+        m.throttle(Operation::Read, target, None, 1024, PreciseTime::now());
+        assert_eq!(0, m.engine.stats().bytes_read_throttled);
+        assert_eq!(0.0, m.metrics.unstable.value());
+
+        clock.advance(Duration::from_secs(31));
+        m.throttle(Operation::Read, target, None, 1024, PreciseTime::now());
+        m.throttle(Operation::Read, target, None, 1024, PreciseTime::now());
+        assert!(m.inject_error(Operation::Write, target, 512).is_some());
+        assert_eq!(1.0, m.metrics.speed_limit_enabled.value());
+        assert_eq!(1.0, m.metrics.unstable.value());
+        // The throttling is counted by the engine, which the metrics export (see `metrics::StatsCollector`).
+        let stats = m.engine.stats_counters();
+        assert_eq!(2048, stats.snapshot().bytes_read_throttled);
+        assert_eq!(2, stats.ops_delayed_of(&Operation::Read));
+        assert_eq!(0, stats.ops_delayed_of(&Operation::Write));
+        assert_eq!(1, stats.snapshot().errors_injected);
+
+        // The stable period is counted apart from the unstable ones.
+        clock.advance(Duration::from_secs(10));
+        m.throttle(Operation::Read, target, None, 1024, PreciseTime::now());
+        assert_eq!(1.0, m.metrics.speed_limit_enabled.value());
+        assert_eq!(1.0, m.metrics.speed_limit_disabled.value());
+        assert_eq!(0.0, m.metrics.unstable.value());
+        assert_eq!(2048, m.engine.stats().bytes_read_throttled);
+    }
+
+    #[test]
+    fn test_read_coalesce() {
+        use clock::MockClock;
+        use config::Condition;
+        use std::{env, process};
+
+        let dir = env::temp_dir().join(format!("mizumochi-read-coalesce-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a");
+        fs::File::create(&path)
+            .unwrap()
+            .write_all(&[1; 4096])
+            .unwrap();
+
+        let config = Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Always(State::Unstable),
+            read_coalesce: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let mut m = Mizumochi::new(
+            Logger::root(slog::Discard, o!()),
+            dir.clone(),
+            dir.clone(),
+            Arc::new(AtomicImmut::new(config)),
+        );
+        let clock = Arc::new(MockClock::new());
+        m.engine.set_clock(clock.clone());
+        m.engine.init();
+        let (ino, fh) = (2, 1);
+        m.file_map.insert(ino, LocalFile::RegularFile(path.clone()));
+        m.fh_map.insert(fh, fs::File::open(&path).unwrap());
+        m.engine.on_opened(fh);
+
+        let read = |m: &mut Mizumochi, offset| {
+            let mut size = 0;
+            m.read_with(ino, fh, offset, 1024, |r| size = r.unwrap().len());
+            size
+        };
+
+        // The reads which continue the first one do not sleep, but they are charged as one transfer.
+        assert_eq!(1024, read(&mut m, 0));
+        assert_eq!(Duration::from_secs(1), clock.elapsed());
+        assert_eq!(1024, read(&mut m, 1024));
+        assert_eq!(1024, read(&mut m, 2048));
+        assert_eq!(Duration::from_secs(1), clock.elapsed());
+        assert_eq!(3072, m.engine.stats().bytes_read_throttled);
+
+        // The next transfer pays the coalesced bytes of the last one.
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(1024, read(&mut m, 3072));
+        assert_eq!(Duration::from_millis(4100), clock.elapsed());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_bps_with_latency_delay() {
+        let speed: Speed = "1KBps+50ms".parse().unwrap();
+
+        // Emulate an operation on a fake clock in milliseconds which advances only by the sleeps.
+        // The latency is injected before the operation starts and the byte rate is adjusted after it.
+        let delay = |bytes: usize, io_ms: u64| {
+            let mut clock = speed.latency().unwrap();
+            let start = clock;
+            clock += Duration::from_millis(io_ms);
+            let elapsed = clock - start;
+            clock += compute_sleep_duration_to_adjust_speed(
+                speed.bps().unwrap(),
+                bytes,
+                elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis()),
+            );
+            clock
+        };
+
+        // The total delay is the latency plus bytes / bps.
+        assert_eq!(Duration::from_millis(50), delay(0, 0));
+        assert_eq!(Duration::from_millis(50 + 500), delay(512, 0));
+        assert_eq!(Duration::from_millis(50 + 2000), delay(2048, 0));
+        assert_eq!(Duration::from_millis(50 + 1000), delay(1024, 300));
+        // The slow operation itself exceeds the byte rate.
+        assert_eq!(Duration::from_millis(50 + 1500), delay(1024, 1500));
+    }
 }
@@ -0,0 +1,93 @@
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// `Shutdown` is the flag to stop throttling, which wakes the threads sleeping to be throttled.
+#[derive(Debug, Default)]
+pub struct Shutdown {
+    triggered: Mutex<bool>,
+    cond: Condvar,
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown::default()
+    }
+
+    /// Sets the flag and wakes all threads in `sleep`.
+    pub fn trigger(&self) {
+        *self.triggered.lock().unwrap() = true;
+        self.cond.notify_all();
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.triggered.lock().unwrap()
+    }
+
+    /// Sleeps for `d` in the real time, or until `trigger` is called.
+    /// The duration beyond the range of `Instant` (e.g., `Duration::MAX`) sleeps until `trigger`.
+    pub fn sleep(&self, d: Duration) {
+        let deadline = Instant::now().checked_add(d);
+        let mut triggered = self.triggered.lock().unwrap();
+        while !*triggered {
+            triggered = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if deadline <= now {
+                        return;
+                    }
+                    self.cond.wait_timeout(triggered, deadline - now).unwrap().0
+                }
+                None => self.cond.wait(triggered).unwrap(),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_shutdown_sleep() {
+        let shutdown = Shutdown::new();
+        let start = Instant::now();
+        shutdown.sleep(Duration::from_millis(10));
+        assert!(Duration::from_millis(10) <= start.elapsed());
+        assert!(!shutdown.is_triggered());
+    }
+
+    #[test]
+    fn test_shutdown_wakes() {
+        let shutdown = Arc::new(Shutdown::new());
+        let start = Instant::now();
+        let sleeper = {
+            let shutdown = Arc::clone(&shutdown);
+            thread::spawn(move || shutdown.sleep(Duration::from_secs(60)))
+        };
+
+        thread::sleep(Duration::from_millis(10));
+        shutdown.trigger();
+        sleeper.join().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(60));
+
+        // It never sleeps after triggered.
+        let start = Instant::now();
+        shutdown.sleep(Duration::from_secs(60));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_shutdown_sleep_forever() {
+        let shutdown = Arc::new(Shutdown::new());
+        let sleeper = {
+            let shutdown = Arc::clone(&shutdown);
+            thread::spawn(move || shutdown.sleep(Duration::MAX))
+        };
+
+        thread::sleep(Duration::from_millis(10));
+        shutdown.trigger();
+        sleeper.join().unwrap();
+    }
+}
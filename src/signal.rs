@@ -0,0 +1,214 @@
+//! Signal handlers to control the throttling without changing the config.
+//!
+//! - `SIGUSR1` toggles pausing the throttling (i.e., all operations pass through while paused).
+//! - `SIGUSR2` begins an unstable period immediately.
+//! - `SIGRTMIN + i` toggles pausing the i-th operation of `OPERATIONS` (Linux only),
+//!   e.g., `kill -s RTMIN+1` toggles pausing `Write` like `pause Write` and `resume Write` of the control socket.
+use config::Operation;
+#[cfg(unix)]
+use libc;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+/// The operations in the order of the real-time signals toggling them.
+pub const OPERATIONS: [Operation; 6] = [
+    Operation::Read,
+    Operation::Write,
+    Operation::Stat,
+    Operation::Open,
+    Operation::Readdir,
+    Operation::Fsync,
+];
+
+/// `Controls` is the set of the flags changed by the signals (and the control socket).
+pub struct Controls {
+    paused: AtomicBool,
+    // The bits of the paused operations by `operation_bit`.
+    paused_operations: AtomicU8,
+    reset_requested: AtomicBool,
+}
+
+/// The controls which the signal handlers change.
+pub static CONTROLS: Controls = Controls::new();
+
+impl Controls {
+    pub const fn new() -> Controls {
+        Controls {
+            paused: AtomicBool::new(false),
+            paused_operations: AtomicU8::new(0),
+            reset_requested: AtomicBool::new(false),
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// Toggles pausing and returns true if it is paused now.
+    pub fn toggle_paused(&self) -> bool {
+        !self.paused.fetch_xor(true, Ordering::Relaxed)
+    }
+
+    /// Returns true if the operation passes through, i.e., all operations or the operation is paused.
+    pub fn is_operation_paused(&self, op: &Operation) -> bool {
+        self.is_paused() || self.paused_operations.load(Ordering::Relaxed) & operation_bit(op) != 0
+    }
+
+    /// Pauses throttling the operation while the others are throttled as usual.
+    pub fn pause_operation(&self, op: &Operation) {
+        self.paused_operations
+            .fetch_or(operation_bit(op), Ordering::Relaxed);
+    }
+
+    /// Resumes throttling the operation paused by `pause_operation`.
+    /// It is still paused while all operations are paused.
+    pub fn resume_operation(&self, op: &Operation) {
+        self.paused_operations
+            .fetch_and(!operation_bit(op), Ordering::Relaxed);
+    }
+
+    /// Toggles pausing the operation and returns true if it is paused now.
+    pub fn toggle_operation_paused(&self, op: &Operation) -> bool {
+        let bit = operation_bit(op);
+        self.paused_operations.fetch_xor(bit, Ordering::Relaxed) & bit == 0
+    }
+
+    /// Requests to begin an unstable period immediately.
+    pub fn request_reset(&self) {
+        self.reset_requested.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true if the reset is requested and clears the request.
+    pub fn take_reset_request(&self) -> bool {
+        self.reset_requested.swap(false, Ordering::Relaxed)
+    }
+}
+
+fn operation_bit(op: &Operation) -> u8 {
+    1 << (op.clone() as u8)
+}
+
+impl Default for Controls {
+    fn default() -> Controls {
+        Controls::new()
+    }
+}
+
+/// Installs the handlers of `SIGUSR1`, `SIGUSR2` and the real-time signals to change `CONTROLS`.
+#[cfg(unix)]
+pub fn install_handlers() -> io::Result<()> {
+    extern "C" fn on_sigusr1(_: libc::c_int) {
+        CONTROLS.toggle_paused();
+    }
+
+    extern "C" fn on_sigusr2(_: libc::c_int) {
+        CONTROLS.request_reset();
+    }
+
+    let handlers: [(libc::c_int, extern "C" fn(libc::c_int)); 2] =
+        [(libc::SIGUSR1, on_sigusr1), (libc::SIGUSR2, on_sigusr2)];
+    for &(signum, handler) in &handlers {
+        install_handler(signum, handler)?;
+    }
+
+    #[cfg(target_os = "linux")]
+    install_operation_handlers()?;
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn install_handler(signum: libc::c_int, handler: extern "C" fn(libc::c_int)) -> io::Result<()> {
+    // The handlers only touch the atomics, which is async-signal-safe.
+    let prev = unsafe { libc::signal(signum, handler as libc::sighandler_t) };
+    if prev == libc::SIG_ERR {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// `SIGRTMIN` at installing the handlers since the handler must not call it.
+#[cfg(target_os = "linux")]
+static SIGRT_BASE: ::std::sync::atomic::AtomicI32 = ::std::sync::atomic::AtomicI32::new(0);
+
+#[cfg(target_os = "linux")]
+fn install_operation_handlers() -> io::Result<()> {
+    extern "C" fn on_sigrt(signum: libc::c_int) {
+        let i = signum - SIGRT_BASE.load(Ordering::Relaxed);
+        if 0 <= i && (i as usize) < OPERATIONS.len() {
+            CONTROLS.toggle_operation_paused(&OPERATIONS[i as usize]);
+        }
+    }
+
+    let base = libc::SIGRTMIN();
+    SIGRT_BASE.store(base, Ordering::Relaxed);
+    for i in 0..OPERATIONS.len() as libc::c_int {
+        install_handler(base + i, on_sigrt)?;
+    }
+    Ok(())
+}
+
+/// Does nothing since there are no such signals.
+#[cfg(not(unix))]
+pub fn install_handlers() -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_controls() {
+        let controls = Controls::new();
+
+        assert!(!controls.is_paused());
+        assert!(controls.toggle_paused());
+        assert!(controls.is_paused());
+        assert!(!controls.toggle_paused());
+        assert!(!controls.is_paused());
+
+        assert!(!controls.take_reset_request());
+        controls.request_reset();
+        assert!(controls.take_reset_request());
+        assert!(!controls.take_reset_request());
+    }
+
+    #[test]
+    fn test_controls_paused_operations() {
+        let controls = Controls::new();
+        let ops = OPERATIONS;
+        assert!(ops.iter().all(|op| !controls.is_operation_paused(op)));
+
+        controls.pause_operation(&Operation::Write);
+        controls.pause_operation(&Operation::Fsync);
+        for op in &ops {
+            let paused = *op == Operation::Write || *op == Operation::Fsync;
+            assert_eq!(paused, controls.is_operation_paused(op), "{}", op);
+        }
+        assert!(!controls.is_paused());
+
+        // Pausing all operations takes precedence.
+        controls.set_paused(true);
+        assert!(controls.is_operation_paused(&Operation::Read));
+        controls.set_paused(false);
+
+        controls.resume_operation(&Operation::Write);
+        assert!(!controls.is_operation_paused(&Operation::Write));
+        assert!(controls.is_operation_paused(&Operation::Fsync));
+        // Resuming the one which is not paused does nothing.
+        controls.resume_operation(&Operation::Read);
+        assert!(controls.is_operation_paused(&Operation::Fsync));
+
+        assert!(controls.toggle_operation_paused(&Operation::Read));
+        assert!(controls.is_operation_paused(&Operation::Read));
+        assert!(!controls.toggle_operation_paused(&Operation::Fsync));
+        assert!(!controls.is_operation_paused(&Operation::Fsync));
+        assert!(!controls.toggle_operation_paused(&Operation::Read));
+        assert!(!controls.is_operation_paused(&Operation::Read));
+    }
+}
@@ -1,4 +1,11 @@
-use config::{Condition, Operation};
+use clock::{Clock, SystemClock};
+use config::{Condition, Operation, ScriptWindow};
+use rand::rngs::SmallRng;
+use rand::{FromEntropy, Rng};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -7,6 +14,28 @@ pub enum State {
     Unstable,
 }
 
+impl FromStr for State {
+    type Err = String;
+
+    /// Parses "stable" or "unstable" as `State` case-insensitively.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "stable" => Ok(State::Stable),
+            "unstable" => Ok(State::Unstable),
+            _ => Err(format!("state accepts stable or unstable: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            State::Stable => write!(f, "Stable"),
+            State::Unstable => write!(f, "Unstable"),
+        }
+    }
+}
+
 /// `StateManager` stores information for a condition to toggle stable/unstable.
 pub struct StateManager {
     // Keep current condition to detect changing the condition.
@@ -14,24 +43,74 @@ pub struct StateManager {
     condition: Condition,
     state: State,
     current_state_begin_time: Instant,
+    // Whether the current unstable period actually slows down the operations.
+    // It is decided with `probability` every time an unstable period begins.
+    engaged: bool,
+    probability: f64,
+    // The length of the current stable period of `Condition::Periodic`.
+    // It is sampled within `frequency ± frequency_jitter` every time a stable period begins.
+    frequency: Duration,
+    frequency_jitter: Duration,
+    // The minimum lengths of the unstable/stable periods of `Condition::Periodic`.
+    min_active: Duration,
+    min_idle: Duration,
+    // The number of the engaged unstable periods and its limit.
+    activations: u32,
+    max_activations: Option<u32>,
+    // The beginning of `Condition::Script` (i.e., `init`) and the cycle and the index of its current window.
+    script_began_at: Instant,
+    script_window: Option<(u64, usize)>,
+    // The window of `Condition::Script` which is ended early by `end_unstable_at`, which stays stable.
+    script_ended: Option<(u64, usize)>,
+    rng: SmallRng,
+    clock: Arc<dyn Clock>,
 }
 
 impl StateManager {
     pub fn new(condition: Condition) -> StateManager {
+        StateManager::with_rng(condition, SmallRng::from_entropy())
+    }
+
+    /// Creates `StateManager` with the given RNG to decide whether unstable periods engage.
+    pub fn with_rng(condition: Condition, rng: SmallRng) -> StateManager {
         StateManager {
             condition,
             state: State::Stable,
             current_state_begin_time: Instant::now(),
+            engaged: true,
+            probability: 1.0,
+            frequency: Duration::from_secs(0),
+            frequency_jitter: Duration::from_secs(0),
+            min_active: Duration::from_secs(0),
+            min_idle: Duration::from_secs(0),
+            activations: 0,
+            max_activations: None,
+            script_began_at: Instant::now(),
+            script_window: None,
+            script_ended: None,
+            rng,
+            clock: Arc::new(SystemClock),
         }
     }
 
+    /// Replaces the clock (`SystemClock` by default).
+    /// Call `init` after this to begin the state with the clock.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
     pub fn init(&mut self) {
         match self.condition {
             Condition::Always(ref s) => self.state = s.clone(),
             _ => self.state = State::Stable,
         }
 
-        self.current_state_begin_time = Instant::now();
+        self.current_state_begin_time = self.clock.now();
+        self.script_began_at = self.current_state_begin_time;
+        self.script_window = None;
+        self.script_ended = None;
+        self.engaged = true;
+        self.frequency = self.next_frequency();
     }
 
     pub fn change_condition(&mut self, c: &Condition) {
@@ -39,26 +118,269 @@ impl StateManager {
         self.init()
     }
 
+    /// Sets the probability that an unstable period of `Condition::Periodic` engages.
+    /// It takes effect from the next unstable period.
+    pub fn set_probability(&mut self, probability: f64) {
+        self.probability = probability;
+    }
+
+    /// Sets the jitter of the frequency of `Condition::Periodic`.
+    /// It takes effect from the next stable period.
+    pub fn set_frequency_jitter(&mut self, jitter: Duration) {
+        self.frequency_jitter = jitter;
+    }
+
+    /// Sets the minimum lengths of the unstable and stable periods of `Condition::Periodic`.
+    /// They override the shorter duration and the jittered frequency.
+    pub fn set_hysteresis(&mut self, min_active: Duration, min_idle: Duration) {
+        self.min_active = min_active;
+        self.min_idle = min_idle;
+    }
+
+    /// Returns the lengths of the unstable and stable periods of the current cycle.
+    fn periods(&self, duration: Duration) -> (Duration, Duration) {
+        (
+            duration.max(self.min_active),
+            self.frequency.max(self.min_idle),
+        )
+    }
+
+    /// Extends the current stable period of `Condition::Periodic` by `d`.
+    /// It does nothing during an unstable period or for `Condition::Always`.
+    pub fn postpone_activation(&mut self, d: Duration) {
+        if let Condition::Periodic { .. } = self.condition {
+            if self.state == State::Stable {
+                self.frequency += d;
+            }
+        }
+    }
+
+    /// Sets the number of the unstable periods of `Condition::Periodic` to engage.
+    /// No more unstable periods begin once the number of them engaged.
+    pub fn set_max_activations(&mut self, max_activations: Option<u32>) {
+        self.max_activations = max_activations;
+    }
+
+    /// Returns the number of the engaged unstable periods so far, including the current one.
+    pub fn activations(&self) -> u32 {
+        self.activations
+    }
+
+    fn is_exhausted(&self) -> bool {
+        match (&self.condition, self.max_activations) {
+            (&Condition::Periodic { .. }, Some(max)) => max <= self.activations,
+            _ => false,
+        }
+    }
+
+    /// Returns the effective state.
+    /// It is `State::Stable` during an unstable period which did not engage.
     pub fn state(&self) -> &State {
-        &self.state
+        if self.engaged {
+            &self.state
+        } else {
+            &State::Stable
+        }
+    }
+
+    /// Returns the current window of `Condition::Script` if the state is unstable.
+    pub fn script_window(&self) -> Option<&ScriptWindow> {
+        match (self.script_window, &self.condition) {
+            (Some((_, i)), Condition::Script { windows, .. }) if self.state == State::Unstable => {
+                windows.get(i)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the time elapsed since the current state (stable or unstable) began at `now`.
+    pub fn elapsed_at(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.current_state_begin_time)
+    }
+
+    /// Returns the time elapsed since the current unstable period began at `now`.
+    /// It is `None` if the state is not unstable.
+    pub fn unstable_elapsed_at(&self, now: Instant) -> Option<Duration> {
+        if *self.state() != State::Unstable {
+            return None;
+        }
+
+        Some(now.saturating_duration_since(self.current_state_begin_time))
+    }
+
+    /// Returns the time until the current unstable period ends at `now`.
+    /// It is `None` if the state is not unstable or the period never ends (i.e., `Condition::Always`).
+    pub fn remaining_at(&self, now: Instant) -> Option<Duration> {
+        if *self.state() != State::Unstable {
+            return None;
+        }
+
+        match self.condition {
+            Condition::Periodic { duration, .. } => Some(
+                self.periods(duration)
+                    .0
+                    .saturating_sub(now.saturating_duration_since(self.current_state_begin_time)),
+            ),
+            Condition::Script { .. } => self.script_window().map(|w| {
+                w.length
+                    .saturating_sub(now.saturating_duration_since(self.current_state_begin_time))
+            }),
+            Condition::Always(_) => None,
+        }
+    }
+
+    /// Returns the time until the next unstable period begins at `now`.
+    /// It is `None` if no unstable periods are scheduled (i.e., `Condition::Always` or `max_activations`).
+    /// The jitter of the next stable period is not considered if the current period is unstable.
+    pub fn next_activation_at(&self, now: Instant) -> Option<Duration> {
+        if self.is_exhausted() {
+            return None;
+        }
+
+        let elapsed = now.saturating_duration_since(self.current_state_begin_time);
+        match self.condition {
+            Condition::Periodic { duration, .. } if self.state == State::Stable => {
+                Some(self.periods(duration).1.saturating_sub(elapsed))
+            }
+            Condition::Periodic {
+                duration,
+                frequency,
+            } => Some(
+                self.periods(duration).0.saturating_sub(elapsed) + frequency.max(self.min_idle),
+            ),
+            Condition::Script {
+                ref windows,
+                repeat,
+            } => {
+                let elapsed = now.saturating_duration_since(self.script_began_at);
+                let t = script_position(windows, repeat, elapsed).1;
+                match windows.iter().find(|w| t < w.offset) {
+                    Some(w) => Some(w.offset - t),
+                    None if repeat && !windows.is_empty() => {
+                        Some(script_length(windows) - t + windows[0].offset)
+                    }
+                    None => None,
+                }
+            }
+            Condition::Always(_) => None,
+        }
+    }
+
+    /// Returns the beginnings of the next `n` unstable periods which engage, projected from the current state
+    /// with a copy of the RNG, so that the jittered frequencies and the engagements are the same as the periods
+    /// actually have. The projection assumes that the state is updated (i.e., an operation comes) in every period
+    /// because a cycle without operations is skipped with the same frequency.
+    /// It is empty for `Condition::Always`, and it stops at `max_activations`.
+    /// The windows of `Condition::Script` are listed as they are.
+    pub fn upcoming_activations(&self, n: usize) -> Vec<Instant> {
+        let (duration, frequency) = match self.condition {
+            Condition::Periodic {
+                duration,
+                frequency,
+            } => (duration, frequency),
+            Condition::Script {
+                ref windows,
+                repeat,
+            } => return self.upcoming_script_activations(windows, repeat, n),
+            Condition::Always(_) => return Vec::new(),
+        };
+        if self.probability <= 0.0 || self.probability.is_nan() {
+            return Vec::new();
+        }
+
+        let mut rng = self.rng.clone();
+        let mut begin = self.current_state_begin_time;
+        let mut next_frequency = self.frequency;
+        let mut activations = self.activations;
+        let mut upcoming = Vec::with_capacity(n);
+        let exhausted = |activations| self.max_activations.is_some_and(|max| max <= activations);
+        if self.state == State::Unstable {
+            begin += duration.max(self.min_active);
+            next_frequency = sample_frequency(frequency, self.frequency_jitter, &mut rng);
+        }
+        while upcoming.len() < n && !exhausted(activations) {
+            begin += next_frequency.max(self.min_idle);
+            if engages(self.probability, &mut rng) {
+                activations += 1;
+                upcoming.push(begin);
+            }
+
+            begin += duration.max(self.min_active);
+            next_frequency = sample_frequency(frequency, self.frequency_jitter, &mut rng);
+        }
+
+        upcoming
+    }
+
+    /// Returns the beginnings of the next `n` windows of `Condition::Script` after the current state.
+    fn upcoming_script_activations(
+        &self,
+        windows: &[ScriptWindow],
+        repeat: bool,
+        n: usize,
+    ) -> Vec<Instant> {
+        let length = script_length(windows);
+        if length == Duration::default() {
+            return Vec::new();
+        }
+
+        // The current window has begun, while the next one after a stable period has not yet.
+        let from = self
+            .current_state_begin_time
+            .saturating_duration_since(self.script_began_at);
+        let is_upcoming = |begin: Duration| match self.state {
+            State::Unstable => from < begin,
+            State::Stable => from <= begin,
+        };
+        let (mut cycle, _) = script_position(windows, repeat, from);
+        let mut upcoming = Vec::with_capacity(n);
+        while upcoming.len() < n {
+            let cycle_begin = duration_from_nanos(u128::from(cycle) * length.as_nanos());
+            upcoming.extend(
+                windows
+                    .iter()
+                    .map(|w| cycle_begin + w.offset)
+                    .filter(|&begin| is_upcoming(begin))
+                    .take(n - upcoming.len())
+                    .map(|begin| self.script_began_at + begin),
+            );
+            if !repeat {
+                break;
+            }
+            cycle += 1;
+        }
+
+        upcoming
+    }
+
+    pub fn on_operated_after(&mut self, op: Operation, cond: &Condition) -> Result<&State, String> {
+        let now = self.clock.now();
+        self.on_operated_at(op, cond, now)
     }
 
-    pub fn on_operated_after(&mut self, _: Operation, cond: &Condition) -> Result<&State, String> {
+    /// Same as `on_operated_after` but the current time is given as `now`.
+    pub fn on_operated_at(
+        &mut self,
+        _: Operation,
+        cond: &Condition,
+        now: Instant,
+    ) -> Result<&State, String> {
         if self.condition != *cond {
             self.change_condition(cond);
         }
 
-        use Condition::*;
+        use config::Condition::*;
         match self.condition {
-            Periodic {
-                ref duration,
-                ref frequency,
-            } => {
-                let elapsed = self.current_state_begin_time.elapsed().as_secs();
+            Periodic { .. } if self.state == State::Stable && self.is_exhausted() => {
+                // Stay stable forever.
+            }
+            Periodic { duration, .. } => {
+                let elapsed = now.saturating_duration_since(self.current_state_begin_time);
+                let (duration, frequency) = self.periods(duration);
                 let (next_mode, d) = toggle_mode_if_necessary(
                     self.state == State::Unstable,
-                    duration,
-                    frequency,
+                    &duration,
+                    &frequency,
                     elapsed,
                 );
                 self.current_state_begin_time += d;
@@ -66,52 +388,216 @@ impl StateManager {
                 match (self.state == State::Unstable, next_mode) {
                     (false, true) => {
                         self.state = State::Unstable;
+                        self.engaged = self.engage();
+                        if self.engaged {
+                            self.activations += 1;
+                        }
                     }
                     (true, false) => {
                         self.state = State::Stable;
+                        self.engaged = true;
+                        self.frequency = self.next_frequency();
                     }
                     _ => {}
                 }
             }
+            Script {
+                ref windows,
+                repeat,
+            } => {
+                let elapsed = now.saturating_duration_since(self.script_began_at);
+                let (cycle, t) = script_position(windows, repeat, elapsed);
+                let cycle_begin = self.script_began_at + (elapsed - t);
+                let index = windows
+                    .iter()
+                    .position(|w| w.offset <= t && t < w.end())
+                    .filter(|&i| self.script_ended != Some((cycle, i)));
+                let window = index.map(|i| (cycle, i));
+                if window != self.script_window {
+                    self.current_state_begin_time = match index {
+                        Some(i) => cycle_begin + windows[i].offset,
+                        // The stable period began at the end of the last window (or of the last cycle).
+                        None => {
+                            cycle_begin
+                                + windows
+                                    .iter()
+                                    .rev()
+                                    .find(|w| w.end() <= t)
+                                    .map_or(Duration::default(), ScriptWindow::end)
+                        }
+                    };
+                    if window.is_some() {
+                        self.activations += 1;
+                    }
+                    self.script_window = window;
+                }
+                self.state = if window.is_some() {
+                    State::Unstable
+                } else {
+                    State::Stable
+                };
+            }
             Always(_) => {
                 // Keep the current state,
             }
         }
 
-        Ok(&self.state)
+        Ok(self.state())
+    }
+
+    /// Begins an unstable period of `Condition::Periodic` at `now` regardless of the elapsed time.
+    /// The period always engages. It does nothing for `Condition::Always` and `Condition::Script`.
+    pub fn begin_unstable_at(&mut self, now: Instant) {
+        if let Condition::Periodic { .. } = self.condition {
+            if self.state == State::Stable || !self.engaged {
+                self.activations += 1;
+            }
+            self.state = State::Unstable;
+            self.engaged = true;
+            self.current_state_begin_time = now;
+        }
+    }
+
+    /// Ends the current unstable period of `Condition::Periodic` or `Condition::Script` at `now` regardless of
+    /// its duration (and `min_active`), and a stable period begins. The window of the script stays stable until it
+    /// would end. It does nothing during a stable period or for `Condition::Always`.
+    pub fn end_unstable_at(&mut self, now: Instant) {
+        if self.state != State::Unstable {
+            return;
+        }
+
+        match self.condition {
+            Condition::Periodic { .. } => {
+                self.state = State::Stable;
+                self.engaged = true;
+                self.frequency = self.next_frequency();
+                self.current_state_begin_time = now;
+            }
+            Condition::Script { .. } => {
+                self.state = State::Stable;
+                self.script_ended = self.script_window.take();
+                self.current_state_begin_time = now;
+            }
+            Condition::Always(_) => {}
+        }
+    }
+
+    fn next_frequency(&mut self) -> Duration {
+        match self.condition {
+            Condition::Periodic { frequency, .. } => {
+                sample_frequency(frequency, self.frequency_jitter, &mut self.rng)
+            }
+            Condition::Always(_) | Condition::Script { .. } => Duration::from_secs(0),
+        }
+    }
+
+    fn engage(&mut self) -> bool {
+        engages(self.probability, &mut self.rng)
+    }
+}
+
+/// Decides whether an unstable period engages with `probability`.
+fn engages<R: Rng>(probability: f64, rng: &mut R) -> bool {
+    if 1.0 <= probability {
+        // Keep the RNG untouched if the probability is not used.
+        true
+    } else if probability <= 0.0 || probability.is_nan() {
+        false
+    } else {
+        rng.gen_bool(probability)
     }
 }
 
+/// Picks a frequency randomly within `frequency ± jitter` in milliseconds.
+/// The result is at least one second (or `frequency` if shorter) so that a cycle never collapses.
+fn sample_frequency<R: Rng>(frequency: Duration, jitter: Duration, rng: &mut R) -> Duration {
+    if jitter.as_millis() == 0 {
+        // Keep the RNG untouched if the jitter is not used.
+        return frequency;
+    }
+
+    let frequency = frequency.as_millis() as u64;
+    let jitter = jitter.as_millis() as u64;
+    let min = match frequency.saturating_sub(jitter) {
+        0 => frequency.min(1000),
+        min => min,
+    };
+    Duration::from_millis(rng.gen_range(min, frequency + jitter + 1))
+}
+
+/// Returns the next mode and the time to advance the beginning of the current mode.
+/// The durations are compared in the full precision so that sub-second periods work.
 fn toggle_mode_if_necessary(
     is_unstable: bool,
     duration: &Duration,
     frequency: &Duration,
-    elapsed: u64,
+    elapsed: Duration,
 ) -> (bool, Duration) {
-    let frequency = frequency.as_secs();
-    let duration = duration.as_secs();
-    let one_term = frequency + duration;
+    let one_term = *frequency + *duration;
 
-    let cnt = elapsed / one_term;
-    let elapsed = elapsed % one_term;
+    let cnt = elapsed.as_nanos() / one_term.as_nanos();
+    let cycles = duration_from_nanos(cnt * one_term.as_nanos());
+    let elapsed = elapsed - cycles;
 
-    let t = if !is_unstable { frequency } else { duration };
+    let t = if !is_unstable { *frequency } else { *duration };
 
     if t < elapsed {
         // Toggle the mode if the elapsed time exceeds the current mode duration.
-        (!is_unstable, Duration::from_secs(cnt * one_term + t))
+        (!is_unstable, cycles + t)
     } else {
         // Keep
-        (is_unstable, Duration::from_secs(cnt * one_term))
+        (is_unstable, cycles)
     }
 }
 
+/// Returns the length of a cycle of `Condition::Script`, i.e., the end of the last window.
+fn script_length(windows: &[ScriptWindow]) -> Duration {
+    windows
+        .last()
+        .map_or(Duration::default(), ScriptWindow::end)
+}
+
+/// Returns the cycle of `Condition::Script` and the offset in it at `elapsed` since the script began.
+/// The script which is not repeated stays in the first cycle.
+fn script_position(windows: &[ScriptWindow], repeat: bool, elapsed: Duration) -> (u64, Duration) {
+    let length = script_length(windows).as_nanos();
+    if !repeat || length == 0 {
+        return (0, elapsed);
+    }
+
+    let cycle = elapsed.as_nanos() / length;
+    (
+        u64::try_from(cycle).unwrap_or(u64::MAX),
+        elapsed - duration_from_nanos(cycle * length),
+    )
+}
+
+fn duration_from_nanos(nanos: u128) -> Duration {
+    Duration::new(
+        (nanos / 1_000_000_000) as u64,
+        (nanos % 1_000_000_000) as u32,
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use atomic_immut::AtomicImmut;
-    use config::Config;
-    use std::sync::Arc;
+    use clock::MockClock;
+    use config::{Config, Speed};
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_state_from_str() {
+        assert_eq!(Ok(State::Stable), State::from_str("stable"));
+        assert_eq!(Ok(State::Unstable), State::from_str("UNSTABLE"));
+        assert!(State::from_str("").is_err());
+        assert!(State::from_str("unsteady").is_err());
+
+        for state in [State::Stable, State::Unstable].iter() {
+            assert_eq!(Ok(state.clone()), State::from_str(&state.to_string()));
+        }
+    }
 
     struct TestFileSystem {
         config: Arc<AtomicImmut<Config>>,
@@ -126,7 +612,9 @@ mod tests {
             frequency: Duration::from_secs(30 * 60),
         };
 
-        let stat = StateManager::new(config.condition.clone());
+        let clock = Arc::new(MockClock::new());
+        let mut stat = StateManager::new(config.condition.clone());
+        stat.set_clock(clock.clone());
 
         let mut fs = TestFileSystem {
             config: Arc::new(AtomicImmut::new(config)),
@@ -135,8 +623,8 @@ mod tests {
 
         fs.stat.init();
 
-        // Change the time for test.
-        fs.stat.current_state_begin_time = Instant::now() - Duration::from_secs(5 * 60);
+        // Advance the time for test.
+        clock.advance(Duration::from_secs(5 * 60));
 
         // The state is kept.
         let cond = &fs.config.load().condition;
@@ -145,8 +633,8 @@ mod tests {
             fs.stat.on_operated_after(Operation::Read, cond)
         );
 
-        // Change the time for test.
-        fs.stat.current_state_begin_time = Instant::now() - Duration::from_secs(35 * 60);
+        // Advance the time for test.
+        clock.advance(Duration::from_secs(30 * 60));
 
         // The state is changed to unstable.
         let cond = &fs.config.load().condition;
@@ -167,6 +655,292 @@ mod tests {
         );
     }
 
+    /// Runs `ticks` cycles of stable/unstable periods and returns how many unstable periods engaged.
+    fn count_engaged(probability: f64, seed: u64, ticks: usize) -> usize {
+        let condition = Condition::Periodic {
+            duration: Duration::from_secs(10 * 60),
+            frequency: Duration::from_secs(30 * 60),
+        };
+        let clock = Arc::new(MockClock::new());
+        let mut stat = StateManager::with_rng(condition.clone(), SmallRng::seed_from_u64(seed));
+        stat.set_clock(clock.clone());
+        stat.set_probability(probability);
+        stat.init();
+
+        // Operate a minute after the beginning of each period.
+        clock.advance(Duration::from_secs(60));
+
+        let mut engaged = 0;
+        for _ in 0..ticks {
+            // Enter the unstable period.
+            clock.advance(Duration::from_secs(30 * 60));
+            if Ok(&State::Unstable) == stat.on_operated_after(Operation::Read, &condition) {
+                engaged += 1;
+            }
+            assert_eq!(State::Unstable, stat.state);
+
+            // Back to the stable period.
+            clock.advance(Duration::from_secs(10 * 60));
+            assert_eq!(
+                Ok(&State::Stable),
+                stat.on_operated_after(Operation::Read, &condition)
+            );
+        }
+
+        engaged
+    }
+
+    #[test]
+    fn test_state_manager_probability() {
+        assert_eq!(0, count_engaged(0.0, 0, 1000));
+        assert_eq!(1000, count_engaged(1.0, 0, 1000));
+
+        let n = count_engaged(0.5, 0, 1000);
+        assert!(300 < n && n < 700, "{}", n);
+        // The same seed gives the same decisions.
+        assert_eq!(n, count_engaged(0.5, 0, 1000));
+    }
+
+    #[test]
+    fn test_sample_frequency() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let frequency = Duration::from_secs(30 * 60);
+        let jitter = Duration::from_secs(5 * 60);
+
+        let n = 10000;
+        let mut sum = 0;
+        for _ in 0..n {
+            let f = sample_frequency(frequency, jitter, &mut rng).as_secs();
+            assert!((25 * 60..=35 * 60).contains(&f), "{}", f);
+            sum += f;
+        }
+        let mean = sum / n;
+        assert!(29 * 60 < mean && mean < 31 * 60, "{}", mean);
+
+        assert_eq!(
+            frequency,
+            sample_frequency(frequency, Duration::from_secs(0), &mut rng)
+        );
+        // A cycle never collapses.
+        for _ in 0..100 {
+            let f = sample_frequency(Duration::from_secs(2), Duration::from_secs(2), &mut rng);
+            assert!(Duration::from_secs(1) <= f, "{:?}", f);
+        }
+    }
+
+    #[test]
+    fn test_state_manager_frequency_jitter() {
+        let condition = Condition::Periodic {
+            duration: Duration::from_secs(10),
+            frequency: Duration::from_secs(60),
+        };
+        let mut stat = StateManager::with_rng(condition.clone(), SmallRng::seed_from_u64(0));
+        stat.set_frequency_jitter(Duration::from_secs(20));
+        stat.init();
+
+        // Step the time by a second and measure the stable intervals.
+        let base = stat.current_state_begin_time;
+        let mut intervals = Vec::new();
+        let mut stable_begin = 0;
+        let mut prev = State::Stable;
+        for t in 0..100 * 90 {
+            let now = base + Duration::from_secs(t);
+            let state = stat
+                .on_operated_at(Operation::Read, &condition, now)
+                .unwrap()
+                .clone();
+            match (&prev, &state) {
+                (State::Stable, State::Unstable) => intervals.push(t - stable_begin),
+                (State::Unstable, State::Stable) => stable_begin = t,
+                _ => {}
+            }
+            prev = state;
+        }
+
+        assert!(50 < intervals.len(), "{}", intervals.len());
+        // An interval differs by a second at most because the state toggles after the period elapsed.
+        for i in &intervals {
+            assert!((40..=81).contains(i), "{}", i);
+        }
+        let mean = intervals.iter().sum::<u64>() as f64 / intervals.len() as f64;
+        assert!(56.0 < mean && mean < 66.0, "{}", mean);
+        // The intervals are jittered actually.
+        assert!(intervals.iter().any(|i| *i != intervals[0]));
+    }
+
+    fn script(repeat: bool) -> Condition {
+        Condition::Script {
+            windows: vec![
+                ScriptWindow {
+                    offset: Duration::from_secs(10),
+                    length: Duration::from_secs(5),
+                    speed: Speed::Bps(1024),
+                },
+                ScriptWindow {
+                    offset: Duration::from_secs(30),
+                    length: Duration::from_secs(10),
+                    speed: Speed::Bps(2048),
+                },
+            ],
+            repeat,
+        }
+    }
+
+    #[test]
+    fn test_state_manager_end_unstable() {
+        let condition = Condition::Periodic {
+            duration: Duration::from_secs(10),
+            frequency: Duration::from_secs(30),
+        };
+        let clock = Arc::new(MockClock::new());
+        let mut stat = StateManager::new(condition.clone());
+        stat.set_clock(clock.clone());
+        stat.init();
+        let base = stat.current_state_begin_time;
+        let at = |secs| base + Duration::from_secs(secs);
+
+        stat.end_unstable_at(at(1));
+        assert_eq!(State::Stable, *stat.state());
+        assert_eq!(
+            State::Unstable,
+            *stat
+                .on_operated_at(Operation::Read, &condition, at(31))
+                .unwrap()
+        );
+        // The stable period begins at the early end.
+        stat.end_unstable_at(at(33));
+        assert_eq!(None, stat.remaining_at(at(33)));
+        assert_eq!(
+            Some(Duration::from_secs(30)),
+            stat.next_activation_at(at(33))
+        );
+        for &(secs, ref state) in &[
+            (35, State::Stable),
+            (63, State::Stable),
+            (64, State::Unstable),
+        ] {
+            assert_eq!(
+                state,
+                stat.on_operated_at(Operation::Read, &condition, at(secs))
+                    .unwrap()
+            );
+        }
+
+        // The ended window of the script stays stable, and the next one begins as usual.
+        let condition = script(false);
+        let mut stat = StateManager::new(condition.clone());
+        stat.set_clock(clock.clone());
+        stat.init();
+        let operate = |stat: &mut StateManager, secs| {
+            stat.on_operated_at(Operation::Read, &condition, at(secs))
+                .unwrap()
+                .clone()
+        };
+        assert_eq!(State::Unstable, operate(&mut stat, 10));
+        stat.end_unstable_at(at(11));
+        assert_eq!(None, stat.script_window());
+        assert_eq!(vec![at(30)], stat.upcoming_activations(5));
+        assert_eq!(State::Stable, operate(&mut stat, 12));
+        assert_eq!(State::Stable, operate(&mut stat, 14));
+        assert_eq!(State::Unstable, operate(&mut stat, 30));
+        assert_eq!(2, stat.activations());
+    }
+
+    #[test]
+    fn test_state_manager_script() {
+        let condition = script(false);
+        let clock = Arc::new(MockClock::new());
+        let mut stat = StateManager::new(condition.clone());
+        stat.set_clock(clock.clone());
+        // The randomness and the limits of `Condition::Periodic` are ignored.
+        stat.set_probability(0.0);
+        stat.set_max_activations(Some(1));
+        stat.postpone_activation(Duration::from_secs(60));
+        stat.init();
+
+        let base = stat.current_state_begin_time;
+        let at = |secs| base + Duration::from_secs(secs);
+        let mut operate = |secs| {
+            stat.on_operated_at(Operation::Read, &condition, at(secs))
+                .unwrap()
+                .clone()
+        };
+        assert_eq!(State::Stable, operate(0));
+        assert_eq!(State::Stable, operate(9));
+        assert_eq!(State::Unstable, operate(10));
+        assert_eq!(State::Unstable, operate(14));
+        assert_eq!(State::Stable, operate(15));
+        assert_eq!(State::Unstable, operate(35));
+        assert_eq!(State::Stable, operate(40));
+        assert_eq!(State::Stable, operate(1000));
+        assert_eq!(2, stat.activations());
+
+        // The state begins at the window, not at the operation.
+        let mut stat = StateManager::new(condition.clone());
+        stat.set_clock(clock.clone());
+        stat.init();
+        assert_eq!(vec![at(10), at(30)], stat.upcoming_activations(5));
+        assert_eq!(Some(Duration::from_secs(8)), stat.next_activation_at(at(2)));
+        stat.on_operated_at(Operation::Read, &condition, at(32))
+            .unwrap();
+        assert_eq!(
+            Some(&Speed::Bps(2048)),
+            stat.script_window().map(|w| &w.speed)
+        );
+        assert_eq!(Duration::from_secs(2), stat.elapsed_at(at(32)));
+        assert_eq!(Some(Duration::from_secs(8)), stat.remaining_at(at(32)));
+        assert!(stat.upcoming_activations(5).is_empty());
+        assert_eq!(None, stat.next_activation_at(at(32)));
+
+        stat.on_operated_at(Operation::Read, &condition, at(40))
+            .unwrap();
+        assert_eq!(None, stat.script_window());
+        assert_eq!(None, stat.remaining_at(at(40)));
+    }
+
+    #[test]
+    fn test_state_manager_script_repeat() {
+        let condition = script(true);
+        let mut stat = StateManager::new(condition.clone());
+        stat.init();
+
+        // The script restarts at the end of the last window every 40 seconds.
+        let base = stat.current_state_begin_time;
+        let at = |secs| base + Duration::from_secs(secs);
+        assert_eq!(
+            vec![at(10), at(30), at(50), at(70), at(90)],
+            stat.upcoming_activations(5)
+        );
+        assert_eq!(
+            Some(Duration::from_secs(15)),
+            stat.next_activation_at(at(35))
+        );
+
+        let mut speeds = Vec::new();
+        for t in 0..120 {
+            stat.on_operated_at(Operation::Read, &condition, at(t))
+                .unwrap();
+            if let Some(w) = stat.script_window() {
+                if stat.elapsed_at(at(t)) == Duration::default() {
+                    speeds.push((t, w.speed.clone()));
+                }
+            }
+        }
+        assert_eq!(
+            vec![
+                (10, Speed::Bps(1024)),
+                (30, Speed::Bps(2048)),
+                (50, Speed::Bps(1024)),
+                (70, Speed::Bps(2048)),
+                (90, Speed::Bps(1024)),
+                (110, Speed::Bps(2048)),
+            ],
+            speeds
+        );
+        assert_eq!(6, stat.activations());
+        assert_eq!(vec![at(130), at(150)], stat.upcoming_activations(2));
+    }
+
     #[test]
     fn test_toggle_mode() {
         let is_unstable = true;
@@ -176,35 +950,60 @@ mod tests {
         let mut elapsed = 0;
 
         // Keep.
-        let (is_unstable, d) = toggle_mode_if_necessary(is_unstable, duration, frequency, elapsed);
+        let (is_unstable, d) = toggle_mode_if_necessary(
+            is_unstable,
+            duration,
+            frequency,
+            Duration::from_secs(elapsed),
+        );
         elapsed -= d.as_secs();
         assert_eq!(true, is_unstable);
         assert_eq!(0, elapsed);
 
         // Change it to stable.
         elapsed += 11;
-        let (is_unstable, d) = toggle_mode_if_necessary(is_unstable, duration, frequency, elapsed);
+        let (is_unstable, d) = toggle_mode_if_necessary(
+            is_unstable,
+            duration,
+            frequency,
+            Duration::from_secs(elapsed),
+        );
         elapsed -= d.as_secs();
         assert_eq!(false, is_unstable);
         assert_eq!(1, elapsed);
 
         // Change it to unstable.
         elapsed += 60;
-        let (is_unstable, d) = toggle_mode_if_necessary(is_unstable, duration, frequency, elapsed);
+        let (is_unstable, d) = toggle_mode_if_necessary(
+            is_unstable,
+            duration,
+            frequency,
+            Duration::from_secs(elapsed),
+        );
         elapsed -= d.as_secs();
         assert_eq!(true, is_unstable);
         assert_eq!(1, elapsed);
 
         // Keep unstable.
         elapsed += 10 + 60;
-        let (is_unstable, d) = toggle_mode_if_necessary(is_unstable, duration, frequency, elapsed);
+        let (is_unstable, d) = toggle_mode_if_necessary(
+            is_unstable,
+            duration,
+            frequency,
+            Duration::from_secs(elapsed),
+        );
         elapsed -= d.as_secs();
         assert_eq!(true, is_unstable);
         assert_eq!(1, elapsed);
 
         // Change it to stable.
         elapsed += 10;
-        let (is_unstable, d) = toggle_mode_if_necessary(is_unstable, duration, frequency, elapsed);
+        let (is_unstable, d) = toggle_mode_if_necessary(
+            is_unstable,
+            duration,
+            frequency,
+            Duration::from_secs(elapsed),
+        );
         elapsed -= d.as_secs();
         assert_eq!(false, is_unstable);
         assert_eq!(1, elapsed);
@@ -217,13 +1016,23 @@ mod tests {
         let frequency = &Duration::from_secs(60);
 
         let mut elapsed = 60 + 1;
-        let (f, d) = toggle_mode_if_necessary(is_unstable, duration, frequency, elapsed);
+        let (f, d) = toggle_mode_if_necessary(
+            is_unstable,
+            duration,
+            frequency,
+            Duration::from_secs(elapsed),
+        );
         elapsed -= d.as_secs();
         assert_eq!(true, f);
         assert_eq!(1, elapsed);
 
         let mut elapsed = 60 + 10 + 60 + 1;
-        let (f, d) = toggle_mode_if_necessary(is_unstable, duration, frequency, elapsed);
+        let (f, d) = toggle_mode_if_necessary(
+            is_unstable,
+            duration,
+            frequency,
+            Duration::from_secs(elapsed),
+        );
         elapsed -= d.as_secs();
         assert_eq!(true, f);
         assert_eq!(1, elapsed);
@@ -236,13 +1045,23 @@ mod tests {
         let frequency = &Duration::from_secs(60);
 
         let mut elapsed = 10 + 1;
-        let (f, d) = toggle_mode_if_necessary(is_unstable, duration, frequency, elapsed);
+        let (f, d) = toggle_mode_if_necessary(
+            is_unstable,
+            duration,
+            frequency,
+            Duration::from_secs(elapsed),
+        );
         elapsed -= d.as_secs();
         assert_eq!(false, f);
         assert_eq!(1, elapsed);
 
         let mut elapsed = 10 + 60 + 10 + 1;
-        let (f, d) = toggle_mode_if_necessary(is_unstable, duration, frequency, elapsed);
+        let (f, d) = toggle_mode_if_necessary(
+            is_unstable,
+            duration,
+            frequency,
+            Duration::from_secs(elapsed),
+        );
         elapsed -= d.as_secs();
         assert_eq!(false, f);
         assert_eq!(1, elapsed);
@@ -255,19 +1074,34 @@ mod tests {
         let frequency = &Duration::from_secs(60);
 
         let mut elapsed = 1;
-        let (f, d) = toggle_mode_if_necessary(is_unstable, duration, frequency, elapsed);
+        let (f, d) = toggle_mode_if_necessary(
+            is_unstable,
+            duration,
+            frequency,
+            Duration::from_secs(elapsed),
+        );
         elapsed -= d.as_secs();
         assert_eq!(true, f);
         assert_eq!(1, elapsed);
 
         let mut elapsed = 8;
-        let (f, d) = toggle_mode_if_necessary(is_unstable, duration, frequency, elapsed);
+        let (f, d) = toggle_mode_if_necessary(
+            is_unstable,
+            duration,
+            frequency,
+            Duration::from_secs(elapsed),
+        );
         elapsed -= d.as_secs();
         assert_eq!(true, f);
         assert_eq!(8, elapsed);
 
         let mut elapsed = 10 + 60 + 1;
-        let (f, d) = toggle_mode_if_necessary(is_unstable, duration, frequency, elapsed);
+        let (f, d) = toggle_mode_if_necessary(
+            is_unstable,
+            duration,
+            frequency,
+            Duration::from_secs(elapsed),
+        );
         elapsed -= d.as_secs();
         assert_eq!(true, f);
         assert_eq!(1, elapsed);
@@ -280,19 +1114,34 @@ mod tests {
         let frequency = &Duration::from_secs(60);
 
         let mut elapsed = 1;
-        let (f, d) = toggle_mode_if_necessary(is_unstable, duration, frequency, elapsed);
+        let (f, d) = toggle_mode_if_necessary(
+            is_unstable,
+            duration,
+            frequency,
+            Duration::from_secs(elapsed),
+        );
         elapsed -= d.as_secs();
         assert_eq!(false, f);
         assert_eq!(1, elapsed);
 
         let mut elapsed = 8;
-        let (f, d) = toggle_mode_if_necessary(is_unstable, duration, frequency, elapsed);
+        let (f, d) = toggle_mode_if_necessary(
+            is_unstable,
+            duration,
+            frequency,
+            Duration::from_secs(elapsed),
+        );
         elapsed -= d.as_secs();
         assert_eq!(false, f);
         assert_eq!(8, elapsed);
 
         let mut elapsed = 60 + 10 + 60 + 10 + 1;
-        let (f, d) = toggle_mode_if_necessary(is_unstable, duration, frequency, elapsed);
+        let (f, d) = toggle_mode_if_necessary(
+            is_unstable,
+            duration,
+            frequency,
+            Duration::from_secs(elapsed),
+        );
         elapsed -= d.as_secs();
         assert_eq!(false, f);
         assert_eq!(1, elapsed);
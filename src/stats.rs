@@ -0,0 +1,500 @@
+use config::{format_duration, Operation, Speed};
+use serde_json;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The number of the buckets of `Stats::delay_histogram`.
+pub const DELAY_BUCKETS: usize = 16;
+
+/// `Stats` is a snapshot of the runtime counters of `Engine`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stats {
+    /// The bytes read under the speed limit.
+    pub bytes_read_throttled: u64,
+    /// The bytes written under the speed limit.
+    pub bytes_written_throttled: u64,
+    /// The operations which slept to be throttled.
+    pub ops_delayed: u64,
+    /// The operations which failed intentionally.
+    pub errors_injected: u64,
+    /// The unstable periods which began.
+    pub active_windows: u64,
+    /// The operations which would sleep but did not in `Config::dry_run`.
+    pub ops_would_delay: u64,
+    /// The operations which would fail but did not in `Config::dry_run`.
+    pub errors_would_inject: u64,
+    /// The numbers of the delays by the milliseconds in powers of two (see `delay_bucket`).
+    pub delay_histogram: [u64; DELAY_BUCKETS],
+    /// The throughput of the last unstable period which ended.
+    pub last_window: Option<WindowThroughput>,
+    /// The total time of the delays in milliseconds.
+    #[serde(default)]
+    pub total_delay_ms: u64,
+    /// The throughput of all the unstable periods at byte rates which ended, of which `configured_bps` is
+    /// the mean weighted by the lengths of the periods.
+    #[serde(default)]
+    pub byte_rate_windows: Option<WindowThroughput>,
+}
+
+/// `WindowThroughput` compares the configured byte rate of an unstable period with the achieved one,
+/// e.g., to see how much the caps of the delays (see `Config::max_op_delay`) or the scheduling slow it down.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowThroughput {
+    /// The nominal byte rate of the period (see `Speed::bytes_per_sec`), which is `None` for the speeds
+    /// which are not byte rates.
+    pub configured_bps: Option<u64>,
+    /// The bytes read/written under the speed limit in the period.
+    pub bytes: u64,
+    /// The length of the period in milliseconds.
+    pub duration_ms: u64,
+}
+
+impl WindowThroughput {
+    /// Returns the achieved byte rate, i.e., the bytes over the length of the period.
+    /// It is `None` for the period shorter than a millisecond.
+    pub fn achieved_bps(&self) -> Option<u64> {
+        match self.duration_ms {
+            0 => None,
+            ms => Some(
+                u64::try_from(u128::from(self.bytes) * 1000 / u128::from(ms)).unwrap_or(u64::MAX),
+            ),
+        }
+    }
+
+    /// Returns the achieved byte rate over the configured one (e.g., 0.5 for a half of the rate).
+    pub fn ratio(&self) -> Option<f64> {
+        match (self.achieved_bps(), self.configured_bps) {
+            (Some(achieved), Some(configured)) if 0 < configured => {
+                Some(achieved as f64 / configured as f64)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Returns the bucket of `Stats::delay_histogram` for the delay.
+/// The bucket 0 is below 1ms, the bucket `i` is `[2^(i-1), 2^i)` ms and the last one has all the longer delays.
+pub fn delay_bucket(d: Duration) -> usize {
+    let millis = d.as_millis();
+    if millis == 0 {
+        return 0;
+    }
+
+    // The number of the bits is `i` for `[2^(i-1), 2^i)`.
+    let bits = (128 - millis.leading_zeros()) as usize;
+    bits.min(DELAY_BUCKETS - 1)
+}
+
+/// `RunSummary` is the report of the whole run consolidated from `Stats` (e.g., at the shutdown).
+/// `Display` writes it as a human-readable block, and `to_json` as a JSON object for automation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunSummary {
+    /// The unstable periods which began.
+    pub activations: u64,
+    pub bytes_read_throttled: u64,
+    pub bytes_written_throttled: u64,
+    pub errors_injected: u64,
+    pub ops_delayed: u64,
+    pub total_delay_ms: u64,
+    /// The numbers of the delays in the buckets of `delay_bucket`.
+    pub delay_histogram: [u64; DELAY_BUCKETS],
+    /// The mean of the configured byte rates of the unstable periods (see `Stats::byte_rate_windows`),
+    /// including the current one as if it ended at the summary.
+    pub configured_bps: Option<u64>,
+    /// The byte rate achieved in the same periods.
+    pub achieved_bps: Option<u64>,
+}
+
+impl RunSummary {
+    /// Returns the summary as a JSON object in a line.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("RunSummary is always serializable")
+    }
+}
+
+impl<'a> From<&'a Stats> for RunSummary {
+    fn from(stats: &'a Stats) -> RunSummary {
+        let windows = stats.byte_rate_windows;
+        RunSummary {
+            activations: stats.active_windows,
+            bytes_read_throttled: stats.bytes_read_throttled,
+            bytes_written_throttled: stats.bytes_written_throttled,
+            errors_injected: stats.errors_injected,
+            ops_delayed: stats.ops_delayed,
+            total_delay_ms: stats.total_delay_ms,
+            delay_histogram: stats.delay_histogram,
+            configured_bps: windows.and_then(|w| w.configured_bps),
+            achieved_bps: windows.and_then(|w| w.achieved_bps()),
+        }
+    }
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "activations: {}", self.activations)?;
+        writeln!(
+            f,
+            "throttled: Read {} bytes, Write {} bytes",
+            self.bytes_read_throttled, self.bytes_written_throttled
+        )?;
+        writeln!(f, "injected errors: {}", self.errors_injected)?;
+        writeln!(
+            f,
+            "delays: {} (total {})",
+            self.ops_delayed,
+            format_duration(&Duration::from_millis(self.total_delay_ms))
+        )?;
+
+        let buckets = self
+            .delay_histogram
+            .iter()
+            .enumerate()
+            .filter(|&(_, n)| 0 < *n)
+            .map(|(i, n)| format!("{}: {}", bucket_label(i), n))
+            .collect::<Vec<_>>();
+        if !buckets.is_empty() {
+            writeln!(f, "delay histogram: {}", buckets.join(", "))?;
+        }
+
+        match (self.achieved_bps, self.configured_bps) {
+            (Some(achieved), Some(configured)) if 0 < configured => write!(
+                f,
+                "throughput: {} of {} ({:.1}%)",
+                Speed::Bps(achieved),
+                Speed::Bps(configured),
+                achieved as f64 * 100.0 / configured as f64
+            ),
+            _ => write!(f, "throughput: none at byte rates"),
+        }
+    }
+}
+
+/// Returns the range of the bucket of `delay_bucket` (e.g., "<1ms", "2-4ms" and ">=16384ms").
+fn bucket_label(i: usize) -> String {
+    match i {
+        0 => "<1ms".to_string(),
+        _ if i == DELAY_BUCKETS - 1 => format!(">={}ms", 1u64 << (i - 1)),
+        _ => format!("{}-{}ms", 1u64 << (i - 1), 1u64 << i),
+    }
+}
+
+// The totals of the unstable periods at byte rates for `Stats::byte_rate_windows`.
+#[derive(Debug, Default, Clone, Copy)]
+struct ByteRateTotals {
+    // The bytes which the configured rates allow in the periods, i.e., the sum of the rates times the lengths.
+    configured_bytes: u128,
+    bytes: u64,
+    duration_ms: u64,
+}
+
+impl ByteRateTotals {
+    fn add(&mut self, bps: u64, throughput: &WindowThroughput) {
+        self.configured_bytes += u128::from(bps) * u128::from(throughput.duration_ms) / 1000;
+        self.bytes = self.bytes.saturating_add(throughput.bytes);
+        self.duration_ms = self.duration_ms.saturating_add(throughput.duration_ms);
+    }
+}
+
+/// `StatsCounters` holds the counters of `Stats`.
+#[derive(Debug, Default)]
+pub struct StatsCounters {
+    bytes_read_throttled: AtomicU64,
+    bytes_written_throttled: AtomicU64,
+    // The delayed operations indexed by `Operation`, of which the sum is `Stats::ops_delayed`.
+    ops_delayed: [AtomicU64; 6],
+    errors_injected: AtomicU64,
+    active_windows: AtomicU64,
+    ops_would_delay: AtomicU64,
+    errors_would_inject: AtomicU64,
+    delay_histogram: [AtomicU64; DELAY_BUCKETS],
+    total_delay_nanos: AtomicU64,
+    // The bytes throttled since the current unstable period began.
+    window_bytes: AtomicU64,
+    last_window: Mutex<Option<WindowThroughput>>,
+    byte_rate_totals: Mutex<ByteRateTotals>,
+}
+
+impl StatsCounters {
+    pub fn new() -> StatsCounters {
+        StatsCounters::default()
+    }
+
+    /// Counts the bytes of the throttled read/write. The other operations are ignored.
+    pub fn add_throttled_bytes(&self, op: &Operation, size: u64) {
+        let counter = match *op {
+            Operation::Read => &self.bytes_read_throttled,
+            Operation::Write => &self.bytes_written_throttled,
+            _ => return,
+        };
+        counter.fetch_add(size, Ordering::Relaxed);
+        self.window_bytes.fetch_add(size, Ordering::Relaxed);
+    }
+
+    /// Begins to count the bytes of an unstable period for `Stats::last_window`.
+    pub fn begin_window(&self) {
+        self.window_bytes.store(0, Ordering::Relaxed);
+    }
+
+    /// Ends the unstable period of `duration` at the byte rate, and returns its throughput.
+    pub fn end_window(&self, configured_bps: Option<u64>, duration: Duration) -> WindowThroughput {
+        let throughput = WindowThroughput {
+            configured_bps,
+            bytes: self.window_bytes.swap(0, Ordering::Relaxed),
+            duration_ms: u64::try_from(duration.as_millis()).unwrap_or(u64::MAX),
+        };
+        *self.last_window.lock().unwrap() = Some(throughput);
+        if let Some(bps) = configured_bps {
+            self.byte_rate_totals.lock().unwrap().add(bps, &throughput);
+        }
+        throughput
+    }
+
+    pub fn increment_ops_delayed(&self, op: &Operation) {
+        self.ops_delayed[op.clone() as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of the delayed operations of `op`.
+    pub fn ops_delayed_of(&self, op: &Operation) -> u64 {
+        self.ops_delayed[op.clone() as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn increment_errors_injected(&self) {
+        self.errors_injected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_active_windows(&self) {
+        self.active_windows.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_ops_would_delay(&self) {
+        self.ops_would_delay.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_errors_would_inject(&self) {
+        self.errors_would_inject.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts the delay in the bucket of `delay_bucket`, and adds it to the total.
+    pub fn record_delay(&self, d: Duration) {
+        self.delay_histogram[delay_bucket(d)].fetch_add(1, Ordering::Relaxed);
+        let nanos = u64::try_from(d.as_nanos()).unwrap_or(u64::MAX);
+        self.total_delay_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    /// Returns the throughput of all the ended unstable periods at byte rates and the current one of `open`
+    /// (i.e., its configured rate and length so far) if given, or `None` if there are none.
+    fn byte_rate_windows(&self, open: Option<(u64, Duration)>) -> Option<WindowThroughput> {
+        let mut totals = *self.byte_rate_totals.lock().unwrap();
+        if let Some((bps, elapsed)) = open {
+            let throughput = WindowThroughput {
+                configured_bps: Some(bps),
+                bytes: self.window_bytes.load(Ordering::Relaxed),
+                duration_ms: u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX),
+            };
+            totals.add(bps, &throughput);
+        }
+        let configured_bps = match totals.duration_ms {
+            0 => return None,
+            ms => {
+                u64::try_from(totals.configured_bytes * 1000 / u128::from(ms)).unwrap_or(u64::MAX)
+            }
+        };
+        Some(WindowThroughput {
+            configured_bps: Some(configured_bps),
+            bytes: totals.bytes,
+            duration_ms: totals.duration_ms,
+        })
+    }
+
+    /// Returns the numbers of the delays in the buckets of `delay_bucket`.
+    pub fn delay_histogram(&self) -> [u64; DELAY_BUCKETS] {
+        let mut histogram = [0; DELAY_BUCKETS];
+        for (n, counter) in histogram.iter_mut().zip(&self.delay_histogram) {
+            *n = counter.load(Ordering::Relaxed);
+        }
+        histogram
+    }
+
+    pub fn snapshot(&self) -> Stats {
+        Stats {
+            bytes_read_throttled: self.bytes_read_throttled.load(Ordering::Relaxed),
+            bytes_written_throttled: self.bytes_written_throttled.load(Ordering::Relaxed),
+            ops_delayed: self
+                .ops_delayed
+                .iter()
+                .map(|n| n.load(Ordering::Relaxed))
+                .sum(),
+            errors_injected: self.errors_injected.load(Ordering::Relaxed),
+            active_windows: self.active_windows.load(Ordering::Relaxed),
+            ops_would_delay: self.ops_would_delay.load(Ordering::Relaxed),
+            errors_would_inject: self.errors_would_inject.load(Ordering::Relaxed),
+            delay_histogram: self.delay_histogram(),
+            last_window: *self.last_window.lock().unwrap(),
+            total_delay_ms: self.total_delay_nanos.load(Ordering::Relaxed) / 1_000_000,
+            byte_rate_windows: self.byte_rate_windows(None),
+        }
+    }
+
+    /// Same as `snapshot` but the current unstable period, which began `elapsed` ago at `configured_bps`,
+    /// is counted in `Stats::byte_rate_windows` as if it ended now (e.g., for the summary at the shutdown).
+    /// The counters are not changed.
+    pub fn snapshot_with_open_window(
+        &self,
+        configured_bps: Option<u64>,
+        elapsed: Duration,
+    ) -> Stats {
+        Stats {
+            byte_rate_windows: self.byte_rate_windows(configured_bps.map(|bps| (bps, elapsed))),
+            ..self.snapshot()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_bucket() {
+        let ms = Duration::from_millis;
+        assert_eq!(0, delay_bucket(Duration::from_micros(999)));
+        assert_eq!(1, delay_bucket(ms(1)));
+        assert_eq!(2, delay_bucket(ms(2)));
+        assert_eq!(2, delay_bucket(ms(3)));
+        assert_eq!(3, delay_bucket(ms(4)));
+        assert_eq!(10, delay_bucket(ms(1000)));
+        assert_eq!(13, delay_bucket(ms(8191)));
+        assert_eq!(14, delay_bucket(ms(8192)));
+        assert_eq!(15, delay_bucket(ms(16384)));
+        assert_eq!(15, delay_bucket(Duration::from_secs(60 * 60)));
+    }
+
+    #[test]
+    fn test_window_throughput() {
+        let stats = StatsCounters::new();
+        stats.add_throttled_bytes(&Operation::Read, 100);
+        stats.begin_window();
+        stats.add_throttled_bytes(&Operation::Read, 4096);
+        stats.add_throttled_bytes(&Operation::Write, 1024);
+        stats.add_throttled_bytes(&Operation::Stat, 1);
+
+        let throughput = stats.end_window(Some(1024), Duration::from_secs(10));
+        assert_eq!(Some(throughput), stats.snapshot().last_window);
+        assert_eq!(5120, throughput.bytes);
+        assert_eq!(Some(512), throughput.achieved_bps());
+        assert_eq!(Some(0.5), throughput.ratio());
+
+        let throughput = stats.end_window(None, Duration::from_micros(999));
+        assert_eq!(0, throughput.bytes);
+        assert_eq!(None, throughput.achieved_bps());
+        assert_eq!(None, throughput.ratio());
+    }
+
+    #[test]
+    fn test_delay_histogram() {
+        let stats = StatsCounters::new();
+        for &millis in &[0, 1, 3, 3, 100, 1 << 20] {
+            stats.record_delay(Duration::from_millis(millis));
+        }
+
+        let mut expected = [0; DELAY_BUCKETS];
+        expected[0] = 1;
+        expected[1] = 1;
+        expected[2] = 2;
+        expected[7] = 1;
+        expected[DELAY_BUCKETS - 1] = 1;
+        assert_eq!(expected, stats.delay_histogram());
+        assert_eq!(expected, stats.snapshot().delay_histogram);
+        assert_eq!((1 << 20) + 107, stats.snapshot().total_delay_ms);
+    }
+
+    #[test]
+    fn test_byte_rate_windows() {
+        let stats = StatsCounters::new();
+        assert_eq!(None, stats.snapshot().byte_rate_windows);
+
+        stats.begin_window();
+        stats.add_throttled_bytes(&Operation::Read, 1000);
+        stats.end_window(Some(1000), Duration::from_secs(2));
+        // The periods which are not at byte rates are not counted.
+        stats.begin_window();
+        stats.add_throttled_bytes(&Operation::Read, 1 << 20);
+        stats.end_window(None, Duration::from_secs(1));
+        stats.begin_window();
+        stats.add_throttled_bytes(&Operation::Write, 3000);
+        stats.end_window(Some(4000), Duration::from_secs(2));
+
+        let windows = stats.snapshot().byte_rate_windows.unwrap();
+        assert_eq!(
+            WindowThroughput {
+                configured_bps: Some(2500),
+                bytes: 4000,
+                duration_ms: 4000,
+            },
+            windows
+        );
+        assert_eq!(Some(1000), windows.achieved_bps());
+
+        // The open period is counted as if it ended now but not ended actually.
+        stats.begin_window();
+        stats.add_throttled_bytes(&Operation::Read, 2000);
+        let windows = stats
+            .snapshot_with_open_window(Some(2500), Duration::from_secs(4))
+            .byte_rate_windows
+            .unwrap();
+        assert_eq!(
+            WindowThroughput {
+                configured_bps: Some(2500),
+                bytes: 6000,
+                duration_ms: 8000,
+            },
+            windows
+        );
+        assert_eq!(4000, stats.snapshot().byte_rate_windows.unwrap().bytes);
+        assert_eq!(
+            stats.snapshot().byte_rate_windows,
+            stats
+                .snapshot_with_open_window(None, Duration::from_secs(4))
+                .byte_rate_windows
+        );
+    }
+
+    #[test]
+    fn test_run_summary() {
+        let stats = StatsCounters::new();
+        assert_eq!(
+            "activations: 0\nthrottled: Read 0 bytes, Write 0 bytes\ninjected errors: 0\ndelays: 0 (total 0s)\nthroughput: none at byte rates",
+            RunSummary::from(&stats.snapshot()).to_string()
+        );
+
+        stats.increment_active_windows();
+        stats.begin_window();
+        stats.add_throttled_bytes(&Operation::Read, 2048);
+        stats.add_throttled_bytes(&Operation::Write, 512);
+        for &(ref op, millis) in &[(Operation::Read, 0), (Operation::Fsync, 1500)] {
+            stats.increment_ops_delayed(op);
+            stats.record_delay(Duration::from_millis(millis));
+        }
+        stats.increment_errors_injected();
+        stats.end_window(Some(1024), Duration::from_secs(5));
+        assert_eq!(1, stats.ops_delayed_of(&Operation::Fsync));
+        assert_eq!(0, stats.ops_delayed_of(&Operation::Write));
+
+        let summary = RunSummary::from(&stats.snapshot());
+        assert_eq!(Some(1024), summary.configured_bps);
+        assert_eq!(Some(512), summary.achieved_bps);
+        assert_eq!(
+            "activations: 1\nthrottled: Read 2048 bytes, Write 512 bytes\ninjected errors: 1\ndelays: 2 (total 1500ms)\ndelay histogram: <1ms: 1, 1024-2048ms: 1\nthroughput: 512Bps of 1KBps (50.0%)",
+            summary.to_string()
+        );
+        assert_eq!(summary, serde_json::from_str(&summary.to_json()).unwrap());
+        assert!(
+            summary.to_json().contains(r#""total_delay_ms":1500"#),
+            "{}",
+            summary.to_json()
+        );
+    }
+}
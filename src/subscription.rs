@@ -0,0 +1,100 @@
+//! The events of `Engine` for the library users to react to them programmatically (see `Engine::subscribe`).
+use config::{Operation, Speed};
+use libc::c_int;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// `Event` is sent to the subscribers of `Engine`.
+/// Unlike `event::Event`, which is written to `EventLog` as JSON, the operations are never sampled.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// An unstable period started at the speed.
+    WindowStarted { speed: Speed },
+    /// The unstable period ended.
+    WindowEnded,
+    /// The operation failed by `Config::error_injection` with the error code.
+    ErrorInjected { op: Operation, errno: c_int },
+    /// The operation of the bytes was delayed (the bytes are zero for the latency and the IOPS limit).
+    OperationThrottled {
+        op: Operation,
+        bytes: usize,
+        delay: Duration,
+    },
+}
+
+/// `Subscribers` sends the events to all the receivers of `subscribe`.
+/// The channels are unbounded, so sending never blocks the operations, and the events are buffered
+/// until received. The receivers which are dropped are removed at the next event.
+#[derive(Default)]
+pub struct Subscribers {
+    senders: Mutex<Vec<Sender<Event>>>,
+    // The length of `senders` to send nothing without the lock when nobody subscribes.
+    len: AtomicUsize,
+}
+
+impl Subscribers {
+    pub fn new() -> Subscribers {
+        Subscribers::default()
+    }
+
+    /// Returns a new receiver of the events sent from now on.
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (tx, rx) = mpsc::channel();
+        let mut senders = self.senders.lock().unwrap();
+        senders.push(tx);
+        self.len.store(senders.len(), Ordering::Relaxed);
+        rx
+    }
+
+    /// Returns true if there is no receiver, i.e., the events do not have to be made.
+    pub fn is_empty(&self) -> bool {
+        self.len.load(Ordering::Relaxed) == 0
+    }
+
+    pub fn send(&self, event: &Event) {
+        if self.is_empty() {
+            return;
+        }
+        let mut senders = self.senders.lock().unwrap();
+        senders.retain(|tx| tx.send(event.clone()).is_ok());
+        self.len.store(senders.len(), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscribers() {
+        let subscribers = Subscribers::new();
+        assert!(subscribers.is_empty());
+        // Nobody receives it.
+        subscribers.send(&Event::WindowEnded);
+
+        let rx1 = subscribers.subscribe();
+        let rx2 = subscribers.subscribe();
+        subscribers.send(&Event::WindowStarted {
+            speed: Speed::Bps(1024),
+        });
+        drop(rx2);
+        subscribers.send(&Event::WindowEnded);
+        assert!(!subscribers.is_empty());
+
+        assert_eq!(
+            vec![
+                Event::WindowStarted {
+                    speed: Speed::Bps(1024)
+                },
+                Event::WindowEnded
+            ],
+            rx1.try_iter().collect::<Vec<_>>()
+        );
+
+        drop(rx1);
+        subscribers.send(&Event::WindowEnded);
+        assert!(subscribers.is_empty());
+    }
+}
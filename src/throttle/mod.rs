@@ -0,0 +1,214 @@
+//! Rate limiters to throttle the operations.
+pub use self::token_bucket::TokenBucket;
+
+use config::Speed;
+use std::time::Duration;
+
+mod token_bucket;
+
+/// Returns the duration for which the operation of `bytes` bytes waits at `speed` without any clock.
+/// `tokens_available` is the bytes which pass without waiting (e.g., the tokens in the bucket,
+/// or the bytes which the elapsed time of the operation covers). It is the operations for `Speed::Iops`.
+///
+/// - `Speed::PassThrough` never waits.
+/// - The byte rates wait for the bytes beyond `tokens_available`, and `Speed::Range` waits at its slowest rate.
+/// - `Speed::Latency` waits for the latency regardless of the bytes, and `Speed::BpsWithLatency` adds it to the byte rate.
+///   So zero bytes wait only for the latency, and are free for the other speeds.
+/// - `Speed::LatencyRange` waits for its longest latency and `Speed::ExpLatency` for its mean, because they are
+///   drawn for each operation by `Speed::sample_latency`.
+/// - `Speed::PerByte` waits for the time of each byte beyond `tokens_available`.
+/// - `Speed::Iops` waits until an operation is available.
+/// - `Speed::Percent` has to be resolved by `Speed::resolve` before, and it never waits otherwise.
+///
+/// A rate of zero waits forever (i.e., `Duration::MAX`).
+pub fn compute_delay(speed: &Speed, bytes: usize, tokens_available: f64) -> Duration {
+    let from_nanos = |nanos: f64| {
+        let nanos = nanos.ceil();
+        if u64::MAX as f64 <= nanos {
+            Duration::MAX
+        } else {
+            Duration::from_nanos(nanos as u64)
+        }
+    };
+    let wait = |n: f64, rate: u64| {
+        if n <= tokens_available {
+            return Duration::default();
+        }
+        if rate == 0 {
+            return Duration::MAX;
+        }
+
+        from_nanos((n - tokens_available) / rate as f64 * 1e9)
+    };
+
+    match *speed {
+        Speed::Bps(bps) | Speed::Range { min: bps, .. } => wait(bytes as f64, bps),
+        Speed::BpsWithLatency { bps, latency } => latency.saturating_add(wait(bytes as f64, bps)),
+        Speed::Latency(latency)
+        | Speed::LatencyRange { max: latency, .. }
+        | Speed::ExpLatency(latency) => latency,
+        Speed::Iops(iops) => wait(1.0, iops),
+        Speed::PerByte(d) => {
+            from_nanos((bytes as f64 - tokens_available).max(0.0) * d.as_nanos() as f64)
+        }
+        Speed::Percent(_) | Speed::PassThrough => Duration::default(),
+    }
+}
+
+/// `request_bps` means request Byte per seconds (not bit).
+/// `count_byte` is the number of read/written bytes.
+/// `elapsed_ms` is the elapsed time in milliseconds to read/write data.
+pub fn compute_sleep_duration_to_adjust_speed(
+    request_bps: u64,
+    count_byte: usize,
+    elapsed_ms: u64,
+) -> Duration {
+    if request_bps == 0 {
+        panic!("The given request bps is zero.");
+    }
+
+    // The elapsed time covers the bytes at the speed.
+    let covered = elapsed_ms as f64 / 1000.0 * request_bps as f64;
+    let wait = compute_delay(&Speed::Bps(request_bps), count_byte, covered);
+    let wait_ms = (wait.as_secs_f64() * 1000.0).round() as u64;
+
+    Duration::from_millis(wait_ms)
+}
+
+/// Same as `compute_sleep_duration_to_adjust_speed` but carries the bytes which the rounding to milliseconds
+/// leaves over to the next operation by `debt`, so that many small operations respect the speed in total.
+/// `debt` is in bytes and negative if the sleeps are ahead of the bytes.
+pub fn compute_sleep_duration_with_debt(
+    request_bps: u64,
+    count_byte: usize,
+    elapsed_ms: u64,
+    debt: &mut f64,
+) -> Duration {
+    if request_bps == 0 {
+        panic!("The given request bps is zero.");
+    }
+
+    let covered = elapsed_ms as f64 / 1000.0 * request_bps as f64;
+    let owed = (count_byte as f64 - covered).max(0.0) + *debt;
+    // The sleep saturates at `u64::MAX` milliseconds, and the debt keeps the rest.
+    let wait_ms = (owed / request_bps as f64 * 1000.0)
+        .round()
+        .clamp(0.0, u64::MAX as f64);
+    *debt = owed - wait_ms / 1000.0 * request_bps as f64;
+
+    Duration::from_millis(wait_ms as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute_delay() {
+        let zero = Duration::default();
+        assert_eq!(zero, compute_delay(&Speed::PassThrough, 1 << 30, 0.0));
+
+        assert_eq!(
+            Duration::from_secs(2),
+            compute_delay(&Speed::Bps(1024), 2048, 0.0)
+        );
+        assert_eq!(
+            Duration::from_millis(500),
+            compute_delay(&Speed::Bps(1024), 2048, 1536.0)
+        );
+        assert_eq!(zero, compute_delay(&Speed::Bps(1024), 2048, 4096.0));
+        // The tokens are in debt.
+        assert_eq!(
+            Duration::from_secs(3),
+            compute_delay(&Speed::Bps(1024), 2048, -1024.0)
+        );
+
+        // A zero-byte operation never waits for the byte rates.
+        assert_eq!(zero, compute_delay(&Speed::Bps(1024), 0, 0.0));
+
+        let per_byte = Speed::PerByte(Duration::from_secs(2));
+        assert_eq!(Duration::from_secs(6), compute_delay(&per_byte, 3, 0.0));
+        assert_eq!(Duration::from_secs(1), compute_delay(&per_byte, 3, 2.5));
+        assert_eq!(zero, compute_delay(&per_byte, 3, 3.0));
+        let latency = Duration::from_millis(50);
+        assert_eq!(latency, compute_delay(&Speed::Latency(latency), 0, 0.0));
+        assert_eq!(
+            latency,
+            compute_delay(&Speed::BpsWithLatency { bps: 1024, latency }, 0, 0.0)
+        );
+        assert_eq!(
+            latency + Duration::from_secs(1),
+            compute_delay(&Speed::BpsWithLatency { bps: 1024, latency }, 1024, 0.0)
+        );
+
+        assert_eq!(
+            Duration::from_secs(2),
+            compute_delay(
+                &Speed::Range {
+                    min: 1024,
+                    max: 4096
+                },
+                2048,
+                0.0
+            )
+        );
+        assert_eq!(
+            Duration::from_millis(100),
+            compute_delay(&Speed::Iops(10), 1 << 20, 0.0)
+        );
+        assert_eq!(zero, compute_delay(&Speed::Iops(10), 1 << 20, 1.0));
+        assert_eq!(Duration::MAX, compute_delay(&Speed::Bps(0), 1, 0.0));
+    }
+
+    #[test]
+    fn test_compute_delay_saturates() {
+        // `usize::MAX` bytes at 1Bps (about 5.8e11 years) saturate instead of overflowing.
+        assert_eq!(
+            Duration::MAX,
+            compute_delay(&Speed::Bps(1), usize::MAX, 0.0)
+        );
+        assert_eq!(
+            Duration::MAX,
+            compute_delay(
+                &Speed::BpsWithLatency {
+                    bps: 1,
+                    latency: Duration::from_secs(1)
+                },
+                usize::MAX,
+                0.0
+            )
+        );
+        assert_eq!(
+            Duration::MAX,
+            compute_delay(&Speed::PerByte(Duration::MAX), usize::MAX, 0.0)
+        );
+
+        let mut debt = 0.0;
+        assert_eq!(
+            Duration::from_millis(u64::MAX),
+            compute_sleep_duration_with_debt(1, usize::MAX, 0, &mut debt)
+        );
+        // The rest is owed.
+        assert!(debt.is_finite() && 0.0 < debt, "{}", debt);
+    }
+
+    #[test]
+    fn test_compute_sleep_duration_with_debt() {
+        let mut debt = 0.0;
+        assert_eq!(
+            compute_sleep_duration_to_adjust_speed(1024, 2048, 500),
+            compute_sleep_duration_with_debt(1024, 2048, 500, &mut debt)
+        );
+        assert_eq!(0.0, debt);
+
+        // A byte at 3000Bps is a third of a millisecond, which is paid every three bytes.
+        let waits = (0..6)
+            .map(|_| compute_sleep_duration_with_debt(3000, 1, 0, &mut debt).as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![0, 1, 0, 0, 1, 0], waits);
+        assert_eq!(
+            Duration::default(),
+            compute_sleep_duration_to_adjust_speed(3000, 1, 0)
+        );
+    }
+}
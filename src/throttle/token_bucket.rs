@@ -0,0 +1,220 @@
+use super::compute_delay;
+use clock::Clock;
+use config::Speed;
+use std::time::{Duration, Instant};
+
+/// `TokenBucket` limits the rate of tokens (e.g., bytes) to consume.
+/// The tokens are refilled at `rate` per second up to `capacity`, so a burst up to `capacity` passes immediately.
+/// The bucket is full at the beginning unless it is made by `with_fill`.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: u64,
+    capacity: usize,
+    // It becomes negative while the consumers wait for the tokens.
+    tokens: f64,
+    last_refill_time: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u64, capacity: usize, now: Instant) -> TokenBucket {
+        TokenBucket::with_fill(rate, capacity, 1.0, now)
+    }
+
+    /// Makes the bucket which holds the fraction of `capacity` at the beginning (e.g., 0.0 is empty).
+    /// The fraction is clamped in `[0, 1]`.
+    pub fn with_fill(rate: u64, capacity: usize, fill: f64, now: Instant) -> TokenBucket {
+        if rate == 0 {
+            panic!("The given rate is zero.");
+        }
+
+        TokenBucket {
+            rate,
+            capacity,
+            tokens: capacity as f64 * fill.clamp(0.0, 1.0),
+            last_refill_time: now,
+        }
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the time which the tokens are refilled until.
+    pub fn last_refill_time(&self) -> Instant {
+        self.last_refill_time
+    }
+
+    /// Refills the tokens for the time elapsed since the last refill.
+    /// If the clock jumps backward (i.e., `now` is before the last refill), the refill restarts from `now`
+    /// instead of stalling until the clock catches up. The refill after a forward jump (e.g., after a suspend)
+    /// is clamped at `capacity` as any long idle, so it never bursts more than `capacity`.
+    fn refill(&mut self, now: Instant) {
+        if now < self.last_refill_time {
+            self.last_refill_time = now;
+            return;
+        }
+
+        let elapsed = now - self.last_refill_time;
+        let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1e-9;
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.capacity as f64);
+        self.last_refill_time = now;
+    }
+
+    /// Consumes `n` tokens if they are available at `now`.
+    /// Returns false without consuming any tokens otherwise.
+    pub fn try_consume(&mut self, n: usize, now: Instant) -> bool {
+        self.refill(now);
+
+        if n as f64 <= self.tokens {
+            self.tokens -= n as f64;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consumes `n` tokens in advance and returns the duration to wait until they are available.
+    /// `n` can exceed `capacity` and then the consumer waits for the tokens over the capacity.
+    pub fn reserve(&mut self, n: usize, now: Instant) -> Duration {
+        self.refill(now);
+        let wait = compute_delay(&Speed::Bps(self.rate), n, self.tokens);
+        self.tokens -= n as f64;
+
+        wait
+    }
+
+    /// Consumes `n` tokens and blocks until they are available on the clock.
+    /// Returns the waited duration.
+    pub fn consume_blocking(&mut self, n: usize, clock: &dyn Clock) -> Duration {
+        let wait = self.reserve(n, clock.now());
+        clock.sleep(wait);
+        wait
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::MockClock;
+
+    #[test]
+    fn test_token_bucket_burst() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(1024, 4096, now);
+
+        // A burst up to the capacity passes immediately.
+        assert!(bucket.try_consume(4096, now));
+        assert!(!bucket.try_consume(1, now));
+
+        // Refilled by the elapsed time.
+        let now = now + Duration::from_millis(500);
+        assert!(!bucket.try_consume(513, now));
+        assert!(bucket.try_consume(512, now));
+
+        // Never exceeds the capacity.
+        let now = now + Duration::from_secs(60);
+        assert!(bucket.try_consume(4096, now));
+        assert!(!bucket.try_consume(1, now));
+    }
+
+    #[test]
+    fn test_token_bucket_with_fill() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::with_fill(1024, 4096, 0.25, now);
+        assert!(!bucket.try_consume(1025, now));
+        assert!(bucket.try_consume(1024, now));
+
+        // An empty one waits from the first token.
+        let mut bucket = TokenBucket::with_fill(1024, 4096, 0.0, now);
+        assert_eq!(Duration::from_millis(500), bucket.reserve(512, now));
+
+        // Refilled up to the capacity as usual.
+        let now = now + Duration::from_secs(60);
+        assert!(bucket.try_consume(4096, now));
+    }
+
+    #[test]
+    fn test_token_bucket_sustained_throughput() {
+        let start = Instant::now();
+        let mut now = start;
+        let mut bucket = TokenBucket::new(1000, 100, now);
+
+        // Consume 100 bytes repeatedly on a fake clock advancing only by the waits.
+        let mut consumed = 0;
+        while consumed < 100_000 {
+            now += bucket.reserve(100, now);
+            consumed += 100;
+        }
+
+        // The initial burst (100 bytes) is free and the rest proceeds at the rate.
+        let elapsed = now - start;
+        assert!(
+            Duration::from_millis(99_899) <= elapsed && elapsed <= Duration::from_millis(99_901),
+            "{:?}",
+            elapsed
+        );
+    }
+
+    #[test]
+    fn test_token_bucket_backward_jump() {
+        let clock = MockClock::new();
+        clock.advance(Duration::from_secs(100));
+        let mut bucket = TokenBucket::new(1024, 1024, clock.now());
+        assert_eq!(Duration::from_secs(0), bucket.reserve(1024, clock.now()));
+
+        // The refill restarts from the time after the jump.
+        clock.rewind(Duration::from_secs(60));
+        assert_eq!(Duration::from_secs(1), bucket.reserve(1024, clock.now()));
+        assert_eq!(clock.now(), bucket.last_refill_time());
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(Duration::from_secs(0), bucket.reserve(1024, clock.now()));
+
+        // A forward jump refills up to the capacity.
+        clock.advance(Duration::from_secs(24 * 60 * 60));
+        assert_eq!(Duration::from_secs(0), bucket.reserve(1024, clock.now()));
+        assert_eq!(Duration::from_secs(1), bucket.reserve(1024, clock.now()));
+    }
+
+    #[test]
+    fn test_token_bucket_reserve() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(1024, 1024, now);
+
+        assert_eq!(Duration::from_secs(0), bucket.reserve(1024, now));
+        assert_eq!(Duration::from_millis(500), bucket.reserve(512, now));
+        // Over the capacity.
+        assert_eq!(Duration::from_millis(2500), bucket.reserve(2048, now));
+        assert_eq!(
+            Duration::from_millis(1500),
+            bucket.reserve(0, now + Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn test_token_bucket_consume_blocking() {
+        let clock = MockClock::new();
+        let mut bucket = TokenBucket::new(1024, 1024, clock.now());
+
+        assert_eq!(
+            Duration::from_secs(0),
+            bucket.consume_blocking(1024, &clock)
+        );
+        assert_eq!(
+            Duration::from_secs(1),
+            bucket.consume_blocking(1024, &clock)
+        );
+        assert_eq!(Duration::from_secs(1), clock.elapsed());
+        assert!(bucket.try_consume(0, clock.now()));
+        assert!(!bucket.try_consume(1, clock.now()));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_token_bucket_zero_rate() {
+        TokenBucket::new(0, 1024, Instant::now());
+    }
+}
@@ -0,0 +1,230 @@
+//! The adapters to throttle any `Read` and `Write` (e.g., a socket or a file) by `Engine` without FUSE.
+//!
+//! They have no file handles, so `Config::passthrough_first_op`, `Config::read_coalesce`,
+//! `Config::seek_penalty` and `Config::allocation_penalty` are not applied.
+use config::Operation;
+use engine::Engine;
+use std::io::{self, Read, Write};
+
+/// `ThrottledReader` reads from the inner reader as `Operation::Read` of `Engine`:
+/// each `read` is delayed by the latency and the speed, and fails by the error injection.
+/// It passes through out of the unstable periods.
+///
+/// # Examples
+/// ```
+/// extern crate atomic_immut;
+/// extern crate mizumochi;
+///
+/// use atomic_immut::AtomicImmut;
+/// use mizumochi::config::{Condition, Config, Speed};
+/// use mizumochi::engine::Engine;
+/// use mizumochi::state::State;
+/// use mizumochi::throttled::ThrottledReader;
+/// use std::io::{Cursor, Read};
+/// use std::sync::Arc;
+///
+/// # fn main() {
+/// let config = Config {
+///     speed: Speed::Bps(1 << 20),
+///     condition: Condition::Always(State::Unstable),
+///     ..Default::default()
+/// };
+/// let mut engine = Engine::new(Arc::new(AtomicImmut::new(config)));
+/// engine.init();
+///
+/// let mut reader = ThrottledReader::new(Cursor::new(vec![0; 1024]), engine);
+/// let mut buf = Vec::new();
+/// reader.read_to_end(&mut buf).unwrap();
+/// assert_eq!(1024, buf.len());
+/// # }
+/// ```
+pub struct ThrottledReader<R> {
+    inner: R,
+    engine: Engine,
+}
+
+impl<R: Read> ThrottledReader<R> {
+    /// Wraps the reader. The engine is expected to be initialized by `Engine::init`.
+    pub fn new(inner: R, engine: Engine) -> ThrottledReader<R> {
+        ThrottledReader { inner, engine }
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    pub fn engine_mut(&mut self) -> &mut Engine {
+        &mut self.engine
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = begin(&mut self.engine, Operation::Read, buf.len(), 0)?;
+        let n = self.inner.read(&mut buf[..size])?;
+        self.engine.throttle(Operation::Read, n);
+        Ok(n)
+    }
+}
+
+/// `ThrottledWriter` writes to the inner writer as `Operation::Write` of `Engine` like `ThrottledReader`.
+/// The written bytes are counted for `ErrorInjection::disk_full_after`, and `flush` is not throttled.
+pub struct ThrottledWriter<W> {
+    inner: W,
+    engine: Engine,
+}
+
+impl<W: Write> ThrottledWriter<W> {
+    /// Wraps the writer. The engine is expected to be initialized by `Engine::init`.
+    pub fn new(inner: W, engine: Engine) -> ThrottledWriter<W> {
+        ThrottledWriter { inner, engine }
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    pub fn engine_mut(&mut self) -> &mut Engine {
+        &mut self.engine
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for ThrottledWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = begin(&mut self.engine, Operation::Write, buf.len(), buf.len())?;
+        let n = self.inner.write(&buf[..size])?;
+        self.engine.throttle(Operation::Write, n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Updates the state and begins the operation (see `Engine::begin_operation`).
+/// The injected error is returned as the OS error of the code.
+fn begin(engine: &mut Engine, op: Operation, size: usize, written: usize) -> io::Result<usize> {
+    // The errors of the state are not of the I/O, and the state is kept as it was.
+    let _ = engine.on_operated(op.clone());
+    engine
+        .begin_operation(op, size, written)
+        .map_err(io::Error::from_raw_os_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atomic_immut::AtomicImmut;
+    use clock::MockClock;
+    use config::{Condition, Config, ErrorInjection, Speed};
+    use libc;
+    use state::State;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn engine(config: Config) -> (Engine, Arc<MockClock>) {
+        let clock = Arc::new(MockClock::new());
+        let mut engine = Engine::with_seed(Arc::new(AtomicImmut::new(config)), 0);
+        engine.set_clock(clock.clone());
+        engine.init();
+        (engine, clock)
+    }
+
+    #[test]
+    fn test_throttled_reader() {
+        let (engine, clock) = engine(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Always(State::Unstable),
+            ..Default::default()
+        });
+        let mut reader = ThrottledReader::new(Cursor::new(vec![1; 8192]), engine);
+
+        let mut buf = [0; 2048];
+        assert_eq!(2048, reader.read(&mut buf).unwrap());
+        assert_eq!(Duration::from_secs(2), clock.elapsed());
+
+        // 8KB in total at 1KBps.
+        let mut rest = Vec::new();
+        assert_eq!(6144, reader.read_to_end(&mut rest).unwrap());
+        assert_eq!(Duration::from_secs(8), clock.elapsed());
+        assert_eq!(8192, reader.engine().stats().bytes_read_throttled);
+        assert_eq!(8192, reader.into_inner().position());
+    }
+
+    #[test]
+    fn test_throttled_reader_ignores_target() {
+        // The mountpoint is never touched without FUSE, even if it does not exist.
+        let (engine, clock) = engine(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Always(State::Unstable),
+            target: Some(PathBuf::from("/nonexistent/mizumochi")),
+            ..Default::default()
+        });
+        let mut reader = ThrottledReader::new(Cursor::new(vec![1; 2048]), engine);
+
+        let mut buf = Vec::new();
+        assert_eq!(2048, reader.read_to_end(&mut buf).unwrap());
+        assert_eq!(Duration::from_secs(2), clock.elapsed());
+    }
+
+    #[test]
+    fn test_throttled_writer() {
+        let (engine, clock) = engine(Config {
+            speed: Speed::Bps(1024),
+            condition: Condition::Periodic {
+                duration: Duration::from_secs(10),
+                frequency: Duration::from_secs(30),
+            },
+            error_injection: Some(ErrorInjection {
+                probability: 0.0,
+                operations: vec![],
+                disk_full_after: Some(4096),
+                errors: vec![],
+                continuous: false,
+                errors_per_window: None,
+            }),
+            ..Default::default()
+        });
+        let mut writer = ThrottledWriter::new(Vec::new(), engine);
+
+        // It passes through in the stable period.
+        writer.write_all(&[1; 4096]).unwrap();
+        assert_eq!(Duration::default(), clock.elapsed());
+
+        clock.advance(Duration::from_secs(31));
+        writer.write_all(&[2; 3072]).unwrap();
+        assert_eq!(Duration::from_secs(34), clock.elapsed());
+
+        let e = writer.write(&[3; 2048]).unwrap_err();
+        assert_eq!(Some(libc::ENOSPC), e.raw_os_error());
+        writer.flush().unwrap();
+        assert_eq!(4096 + 3072, writer.get_ref().len());
+    }
+}
@@ -0,0 +1,107 @@
+#![cfg(feature = "test-harness")]
+
+extern crate atomic_immut;
+extern crate libc;
+extern crate mizumochi;
+
+use atomic_immut::AtomicImmut;
+use mizumochi::clock::MockClock;
+use mizumochi::config::{Condition, Config, ErrorInjection, Operation, Speed};
+use mizumochi::engine::Engine;
+use mizumochi::harness::Harness;
+use mizumochi::state::State;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn harness(config: Config) -> (Harness, Arc<MockClock>) {
+    harness_with(Arc::new(AtomicImmut::new(config)))
+}
+
+fn harness_with(config: Arc<AtomicImmut<Config>>) -> (Harness, Arc<MockClock>) {
+    let clock = Arc::new(MockClock::new());
+    let mut engine = Engine::with_seed(config, 0);
+    engine.set_clock(clock.clone());
+    engine.init();
+    (Harness::new(engine), clock)
+}
+
+#[test]
+fn test_harness_periodic() {
+    let (mut harness, clock) = harness(Config {
+        speed: Speed::Bps(1024),
+        condition: Condition::Periodic {
+            duration: Duration::from_secs(10),
+            frequency: Duration::from_secs(30),
+        },
+        ..Default::default()
+    });
+
+    // The first period is stable.
+    clock.advance(Duration::from_secs(6));
+    let write = harness.write("a.txt", &[1; 2048]);
+    assert_eq!(Ok(2048), write.result);
+    assert_eq!(Duration::from_secs(0), write.elapsed);
+
+    // In the unstable period.
+    clock.advance(Duration::from_secs(28));
+    let write = harness.write("a.txt", &[2; 2048]);
+    assert_eq!(Ok(2048), write.result);
+    assert_eq!(Duration::from_secs(2), write.elapsed);
+
+    let content = harness.content("a.txt").unwrap();
+    assert_eq!(4096, content.len());
+    assert_eq!(&[1, 2], &[content[0], content[2048]]);
+
+    // The file does not exist.
+    assert_eq!(Err(libc::ENOENT), harness.read("b.txt", 1).result);
+}
+
+#[test]
+fn test_harness_zero_bytes() {
+    let config = Arc::new(AtomicImmut::new(Config {
+        speed: Speed::Bps(1),
+        condition: Condition::Always(State::Unstable),
+        ..Default::default()
+    }));
+    let (mut harness, _) = harness_with(Arc::clone(&config));
+    assert_eq!(Ok(0), harness.write("a.txt", &[]).result);
+    // The read at EOF returns immediately.
+    let read = harness.read("a.txt", 1024);
+    assert_eq!(Ok(vec![]), read.result);
+    assert_eq!(Duration::default(), read.elapsed);
+
+    let mut c = (*config.load()).clone();
+    c.speed = Speed::Latency(Duration::from_millis(10));
+    config.store(c);
+    // The latency still applies.
+    let read = harness.read("a.txt", 1024);
+    assert_eq!(Ok(vec![]), read.result);
+    assert_eq!(Duration::from_millis(10), read.elapsed);
+}
+
+#[test]
+fn test_harness_error_injection() {
+    let (mut harness, clock) = harness(Config {
+        condition: Condition::Periodic {
+            duration: Duration::from_secs(10),
+            frequency: Duration::from_secs(30),
+        },
+        error_injection: Some(ErrorInjection {
+            probability: 1.0,
+            operations: vec![Operation::Write],
+            disk_full_after: None,
+            errors: vec![],
+            continuous: false,
+            errors_per_window: None,
+        }),
+        ..Default::default()
+    });
+
+    assert_eq!(Ok(3), harness.write("a.txt", b"abc").result);
+
+    clock.advance(Duration::from_secs(34));
+    assert_eq!(Err(libc::EIO), harness.write("a.txt", b"def").result);
+    // The reads are not the target of the injection.
+    assert_eq!(Ok(b"abc".to_vec()), harness.read("a.txt", 8).result);
+    assert_eq!(Some(&b"abc"[..]), harness.content("a.txt"));
+}
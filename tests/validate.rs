@@ -0,0 +1,47 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output};
+
+fn validate(path: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_mizumochi"))
+        .arg("validate")
+        .arg(path)
+        .output()
+        .unwrap()
+}
+
+fn temp_config(name: &str, content: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("mizumochi-{}-{}.toml", name, process::id()));
+    fs::write(&path, content).unwrap();
+    path
+}
+
+#[test]
+fn test_validate_good_config() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/config.toml");
+    let output = validate(&path);
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("config {speed: 1MBps"), "{}", stdout);
+}
+
+#[test]
+fn test_validate_bad_config() {
+    // The duration is longer than the frequency.
+    let path = temp_config("validate-bad", "duration = \"1h\"\nfrequency = \"30m\"\n");
+    let output = validate(&path);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(Some(1), output.status.code());
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("validation failed"), "{}", stderr);
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_validate_missing_config() {
+    let output = validate(Path::new("/nonexistent/mizumochi.toml"));
+    assert_eq!(Some(1), output.status.code());
+}